@@ -0,0 +1,70 @@
+// the table format is shared with the library
+#[cfg(feature = "embedded-country")]
+#[allow(dead_code)]
+#[path = "src/countrydb.rs"]
+mod countrydb;
+
+fn main() {
+    #[cfg(feature = "embedded-country")]
+    embed_country_table();
+
+    // the gRPC service is generated from the message types of src/grpc.rs,
+    // which mirror proto/geoipsed.proto, so that building needs no protoc
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let method = |name: &str, route: &str, input: &str, output: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{input}"))
+                .output_type(format!("crate::grpc::{output}"))
+                .codec_path("tonic_prost::ProstCodec")
+                .client_streaming()
+                .server_streaming()
+                .build()
+        };
+        let service = Service::builder()
+            .name("Enrichment")
+            .package("geoipsed.v1")
+            .method(method(
+                "decorate",
+                "Decorate",
+                "DecorateRequest",
+                "DecorateResponse",
+            ))
+            .method(method(
+                "lookup",
+                "Lookup",
+                "LookupRequest",
+                "LookupResponse",
+            ))
+            .build();
+        Builder::new().compile(&[service]);
+        println!("cargo:rerun-if-changed=build.rs");
+    }
+}
+
+/// Serialize the country CSV named by $GEOIPSED_COUNTRY_CSV into the table
+/// embedded by the embedded-country feature, or an empty table without one
+#[cfg(feature = "embedded-country")]
+fn embed_country_table() {
+    println!("cargo:rerun-if-env-changed=GEOIPSED_COUNTRY_CSV");
+    let table = match std::env::var("GEOIPSED_COUNTRY_CSV") {
+        Ok(path) => {
+            println!("cargo:rerun-if-changed={path}");
+            let text = std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("cannot read {path}: {e}"));
+            countrydb::CountryTable::from_csv(&text).unwrap_or_else(|e| panic!("{path}: {e}"))
+        }
+        Err(_) => {
+            println!(
+                "cargo:warning=GEOIPSED_COUNTRY_CSV is not set, the embedded country table is empty"
+            );
+            countrydb::CountryTable::default()
+        }
+    };
+    let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("country.bin");
+    std::fs::write(out, table.to_bytes()).unwrap();
+}
@@ -0,0 +1,26 @@
+#![no_main]
+
+use geoipsed::entity::{EntityKind, Extractor, PatternSet};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+fn extractor() -> &'static Extractor {
+    static EXTRACTOR: OnceLock<Extractor> = OnceLock::new();
+    EXTRACTOR.get_or_init(|| {
+        PatternSet::new()
+            .kind(EntityKind::Ip)
+            .kind(EntityKind::Mac)
+            .kind(EntityKind::Domain)
+            .build()
+            .unwrap()
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut end = 0;
+    for entity in extractor().find_iter(data) {
+        assert!(end <= entity.start() && entity.start() < entity.end());
+        assert_eq!(&data[entity.range()], entity.as_bytes());
+        end = entity.end();
+    }
+});
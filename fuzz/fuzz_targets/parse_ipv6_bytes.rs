@@ -0,0 +1,15 @@
+#![no_main]
+
+use geoipsed::ipparse::parse_ipv6_bytes;
+use libfuzzer_sys::fuzz_target;
+use std::net::Ipv6Addr;
+
+fuzz_target!(|data: &[u8]| {
+    let parsed = parse_ipv6_bytes(data);
+    // std is the oracle for the inputs it can take
+    if let Ok(s) = std::str::from_utf8(data) {
+        assert_eq!(parsed, s.parse::<Ipv6Addr>().ok(), "{s:?}");
+    } else {
+        assert_eq!(parsed, None);
+    }
+});
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use flate2::read::GzDecoder;
+use globset::GlobSet;
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+/// Kinds of archives whose members are read as inputs
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// The kind of archive at `path` by its extension, or None for other
+    /// files
+    pub fn of(path: &Utf8Path) -> Option<Self> {
+        let name = path.file_name()?.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Invoke `f` with the name and contents of each regular file in the
+/// archive at `path`, in archive order, skipping the members whose names do
+/// not match `glob` if given
+pub fn for_each_member<F>(
+    path: &Utf8Path,
+    kind: ArchiveKind,
+    glob: Option<&GlobSet>,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(&str, &mut dyn Read) -> Result<()>,
+{
+    let file = BufReader::new(File::open(path)?);
+    let wanted = |name: &str| glob.is_none_or(|glob| glob.is_match(name));
+    match kind {
+        ArchiveKind::Tar => for_each_tar_member(path, tar::Archive::new(file), wanted, f),
+        ArchiveKind::TarGz => {
+            for_each_tar_member(path, tar::Archive::new(GzDecoder::new(file)), wanted, f)
+        }
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file)
+                .with_context(|| format!("{path}: invalid zip archive"))?;
+            for i in 0..zip.len() {
+                let mut member = zip.by_index(i)?;
+                if !member.is_file() {
+                    continue;
+                }
+                let name = member.name().to_string();
+                if wanted(&name) {
+                    f(&name, &mut member)?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+fn for_each_tar_member<R, F>(
+    path: &Utf8Path,
+    mut archive: tar::Archive<R>,
+    wanted: impl Fn(&str) -> bool,
+    mut f: F,
+) -> Result<()>
+where
+    R: Read,
+    F: FnMut(&str, &mut dyn Read) -> Result<()>,
+{
+    let entries = archive
+        .entries()
+        .with_context(|| format!("{path}: invalid tar archive"))?;
+    for entry in entries {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        if wanted(&name) {
+            f(&name, &mut entry)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+use crate::entity::{EntityKind, ExtractScratch, PatternSet, SearchProfile};
+use crate::geoip::GeoIPSed;
+use anyhow::Result;
+use clap::Args;
+use std::hint::black_box;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Measure throughput on generated corpora
+#[derive(Args, Debug)]
+pub struct BenchArgs {
+    /// Lines of each generated corpus
+    #[clap(long, value_name = "N", default_value_t = 200_000)]
+    pub lines: usize,
+
+    /// Search profile of the extractors
+    #[clap(long, value_enum, default_value_t = ProfileArg::Throughput)]
+    pub search_profile: ProfileArg,
+}
+
+/// Search profiles selectable by --search-profile
+#[derive(clap::ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProfileArg {
+    Latency,
+    Throughput,
+}
+
+impl From<ProfileArg> for SearchProfile {
+    fn from(profile: ProfileArg) -> Self {
+        match profile {
+            ProfileArg::Latency => SearchProfile::Latency,
+            ProfileArg::Throughput => SearchProfile::Throughput,
+        }
+    }
+}
+
+/// Kinds of generated corpora
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Corpus {
+    /// Several IPs on every line, e.g. firewall or flow logs
+    Dense,
+    /// An IP on one line in twenty, e.g. application logs
+    Sparse,
+    /// Log lines without IPs, the cost of scanning alone
+    NoIp,
+    /// Like sparse, with non-ASCII user names
+    Utf8,
+}
+
+impl Corpus {
+    pub const ALL: [Corpus; 4] = [Corpus::Dense, Corpus::Sparse, Corpus::NoIp, Corpus::Utf8];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Corpus::Dense => "dense",
+            Corpus::Sparse => "sparse",
+            Corpus::NoIp => "no-ip",
+            Corpus::Utf8 => "utf8",
+        }
+    }
+
+    /// Generate `lines` lines of log text. The output is the same for the
+    /// same arguments so that runs are comparable
+    pub fn generate(self, lines: usize) -> Vec<u8> {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        let mut out = Vec::with_capacity(lines * 120);
+        for i in 0..lines {
+            let ts = 1_700_000_000 + i as u64;
+            let _ = write!(
+                out,
+                "{ts} host{} sshd[{}]: ",
+                rng.next() % 64,
+                rng.next() % 65536
+            );
+            match self {
+                Corpus::Dense => {
+                    let _ = write!(
+                        out,
+                        "conn {} -> {} port {} via {}",
+                        ipv4(&mut rng),
+                        ipv4(&mut rng),
+                        rng.next() % 65536,
+                        ipv6(&mut rng)
+                    );
+                }
+                Corpus::Sparse | Corpus::Utf8 if i % 20 == 0 => {
+                    let _ = write!(
+                        out,
+                        "Accepted publickey for admin from {} port {}",
+                        ipv4(&mut rng),
+                        rng.next() % 65536
+                    );
+                }
+                Corpus::Sparse | Corpus::NoIp | Corpus::Utf8 => {
+                    let user = if self == Corpus::Utf8 {
+                        "usuário"
+                    } else {
+                        "u"
+                    };
+                    let _ = write!(
+                        out,
+                        "session opened for user {user}{} by (uid=0) after {}.{}s",
+                        rng.next() % 1000,
+                        rng.next() % 60,
+                        rng.next() % 1000
+                    );
+                }
+            }
+            out.push(b'\n');
+        }
+        out
+    }
+}
+
+/// Throughput of one corpus at each stage of decoration
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    pub corpus: Corpus,
+    pub bytes: usize,
+    pub matches: usize,
+    /// finding the IPs
+    pub extract: Duration,
+    /// finding the IPs and looking up all their geoip fields
+    pub lookup: Duration,
+    /// finding the IPs and rendering their decorations
+    pub render: Duration,
+    /// finding IPs, MACs, and domains with `find_iter`
+    pub kinds: Duration,
+    /// finding IPs, MACs, and domains with `find_iter_with`, reusing one
+    /// scratch for all lines
+    pub kinds_scratch: Duration,
+}
+
+/// Time each stage over each corpus. Lookups are not cached, so the
+/// figures are a lower bound for inputs with repeated IPs
+pub fn measure(
+    args: &BenchArgs,
+    geoipdb: &GeoIPSed,
+    obfuscated_ipv4: bool,
+) -> Result<Vec<Measurement>> {
+    let extractor = PatternSet::new()
+        .search_profile(args.search_profile.into())
+        .obfuscated_ipv4(obfuscated_ipv4)
+        .kind(EntityKind::Ip)
+        .build()?;
+    let all_kinds = PatternSet::new()
+        .search_profile(args.search_profile.into())
+        .obfuscated_ipv4(obfuscated_ipv4)
+        .kind(EntityKind::Ip)
+        .kind(EntityKind::Mac)
+        .kind(EntityKind::Domain)
+        .build()?;
+    let timed = |f: &mut dyn FnMut()| {
+        let start = Instant::now();
+        f();
+        start.elapsed()
+    };
+
+    let mut results = Vec::new();
+    for corpus in Corpus::ALL {
+        let text = corpus.generate(args.lines);
+        let lines = || text.split_inclusive(|&b| b == b'\n');
+        let ips = || {
+            lines().flat_map(|line| extractor.find_iter(line).filter_map(|m| m.canonical_text()))
+        };
+
+        let mut matches = 0;
+        let extract = timed(&mut || matches = black_box(ips().count()));
+        let lookup = timed(&mut || {
+            for ip in ips() {
+                black_box(geoipdb.lookup_record(&ip));
+            }
+        });
+        let render = timed(&mut || {
+            for ip in ips() {
+                black_box(geoipdb.lookup(&ip));
+            }
+        });
+        let kinds = timed(&mut || {
+            for line in lines() {
+                black_box(all_kinds.find_iter(line).count());
+            }
+        });
+        let mut scratch = ExtractScratch::new();
+        let kinds_scratch = timed(&mut || {
+            for line in lines() {
+                black_box(all_kinds.find_iter_with(line, &mut scratch).count());
+            }
+        });
+        results.push(Measurement {
+            corpus,
+            bytes: text.len(),
+            matches,
+            extract,
+            lookup,
+            render,
+            kinds,
+            kinds_scratch,
+        });
+    }
+    Ok(results)
+}
+
+/// Print `results` as a table of MB/s
+pub fn print(out: &mut impl Write, results: &[Measurement]) -> Result<()> {
+    writeln!(
+        out,
+        "{:<8} {:>9} {:>9} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "corpus", "MB", "IPs", "extract", "+lookup", "+render", "all kinds", "+scratch"
+    )?;
+    for r in results {
+        let mb = r.bytes as f64 / 1e6;
+        let rate = |elapsed: Duration| format!("{:.1} MB/s", mb / elapsed.as_secs_f64().max(1e-9));
+        writeln!(
+            out,
+            "{:<8} {:>9.1} {:>9} {:>12} {:>12} {:>12} {:>12} {:>12}",
+            r.corpus.name(),
+            mb,
+            r.matches,
+            rate(r.extract),
+            rate(r.lookup),
+            rate(r.render),
+            rate(r.kinds),
+            rate(r.kinds_scratch)
+        )?;
+    }
+    Ok(())
+}
+
+/// Small deterministic generator for corpus contents
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn ipv4(rng: &mut XorShift) -> std::net::Ipv4Addr {
+    // first octets 1-223 keep addresses out of multicast and reserved space
+    let first = 1 + (rng.next() % 223) as u32;
+    std::net::Ipv4Addr::from(first << 24 | (rng.next() as u32 & 0x00ff_ffff))
+}
+
+fn ipv6(rng: &mut XorShift) -> std::net::Ipv6Addr {
+    // 2000::/3 global unicast
+    let high = 0x2000_0000_0000_0000 | (rng.next() & 0x1fff_ffff_ffff_ffff);
+    std::net::Ipv6Addr::from((high as u128) << 64 | rng.next() as u128)
+}
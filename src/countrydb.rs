@@ -0,0 +1,171 @@
+use std::error::Error;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Magic and version at the start of a serialized [`CountryTable`]
+const MAGIC: &[u8] = b"GCC1";
+
+/// Error returned when a line of a country CSV is not a range of two
+/// addresses of the same version and a two-letter country code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCountryError {
+    pub line: usize,
+}
+
+impl fmt::Display for ParseCountryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: expected \"start_ip,end_ip,country\" with a two-letter country code",
+            self.line
+        )
+    }
+}
+
+impl Error for ParseCountryError {}
+
+/// Compact IP range to country code table, small enough to be embedded in
+/// the binary with the `embedded-country` feature. Ranges are kept sorted
+/// by their start and looked up by binary search.
+///
+/// ```
+/// use geoipsed::countrydb::CountryTable;
+///
+/// let table = CountryTable::from_csv("start_ip,end_ip,country\n81.2.69.0,81.2.69.255,GB\n").unwrap();
+/// assert_eq!(table.lookup("81.2.69.205".parse().unwrap()), Some("GB"));
+/// assert_eq!(table.lookup("81.2.70.1".parse().unwrap()), None);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CountryTable {
+    v4: Vec<(u32, u32, [u8; 2])>,
+    v6: Vec<(u128, u128, [u8; 2])>,
+}
+
+impl CountryTable {
+    /// Parse a CSV whose lines start with "start_ip,end_ip,country", e.g.
+    /// the country.csv of IPinfo's free database. Further columns, a
+    /// header line, and empty lines are ignored
+    pub fn from_csv(text: &str) -> Result<Self, ParseCountryError> {
+        let mut table = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let mut fields = line.trim().split(',').map(|f| f.trim().trim_matches('"'));
+            let (Some(start), Some(end), Some(country)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                match line.trim().is_empty() {
+                    true => continue,
+                    false => return Err(ParseCountryError { line: i + 1 }),
+                }
+            };
+            let code = match country.as_bytes() {
+                [a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                    [a.to_ascii_uppercase(), b.to_ascii_uppercase()]
+                }
+                _ if i == 0 => continue,
+                _ => return Err(ParseCountryError { line: i + 1 }),
+            };
+            match (start.parse::<IpAddr>(), end.parse::<IpAddr>()) {
+                (Ok(IpAddr::V4(start)), Ok(IpAddr::V4(end))) => {
+                    table.v4.push((start.into(), end.into(), code))
+                }
+                (Ok(IpAddr::V6(start)), Ok(IpAddr::V6(end))) => {
+                    table.v6.push((start.into(), end.into(), code))
+                }
+                _ if i == 0 => continue,
+                _ => return Err(ParseCountryError { line: i + 1 }),
+            }
+        }
+        table.v4.sort_unstable();
+        table.v6.sort_unstable();
+        Ok(table)
+    }
+
+    /// Number of ranges
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Country code of the range containing `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        let code = match ip {
+            IpAddr::V4(ip) => find(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => find(&self.v4, u32::from(ip)),
+                None => find(&self.v6, u128::from(ip)),
+            },
+        }?;
+        std::str::from_utf8(code).ok()
+    }
+
+    /// The table as bytes for [`CountryTable::from_bytes`]: a magic, the
+    /// numbers of IPv4 and IPv6 ranges, then the ranges, big-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12 + self.v4.len() * 10 + self.v6.len() * 34);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.v4.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.v6.len() as u32).to_be_bytes());
+        for (start, end, code) in &self.v4 {
+            bytes.extend_from_slice(&start.to_be_bytes());
+            bytes.extend_from_slice(&end.to_be_bytes());
+            bytes.extend_from_slice(code);
+        }
+        for (start, end, code) in &self.v6 {
+            bytes.extend_from_slice(&start.to_be_bytes());
+            bytes.extend_from_slice(&end.to_be_bytes());
+            bytes.extend_from_slice(code);
+        }
+        bytes
+    }
+
+    /// Read a table written by [`CountryTable::to_bytes`], or None if
+    /// `bytes` is not one
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        let count = |at: usize| -> Option<usize> {
+            Some(u32::from_be_bytes(rest.get(at..at + 4)?.try_into().ok()?) as usize)
+        };
+        let (v4_len, v6_len) = (count(0)?, count(4)?);
+        let v4_bytes = rest.get(8..8 + v4_len * 10)?;
+        let v6_bytes = rest.get(8 + v4_len * 10..)?;
+        if v6_bytes.len() != v6_len * 34 {
+            return None;
+        }
+        let v4 = v4_bytes
+            .chunks_exact(10)
+            .map(|entry| {
+                let (start, rest) = entry.split_at(4);
+                let (end, code) = rest.split_at(4);
+                (
+                    u32::from_be_bytes(start.try_into().unwrap()),
+                    u32::from_be_bytes(end.try_into().unwrap()),
+                    [code[0], code[1]],
+                )
+            })
+            .collect();
+        let v6 = v6_bytes
+            .chunks_exact(34)
+            .map(|entry| {
+                let (start, rest) = entry.split_at(16);
+                let (end, code) = rest.split_at(16);
+                (
+                    u128::from_be_bytes(start.try_into().unwrap()),
+                    u128::from_be_bytes(end.try_into().unwrap()),
+                    [code[0], code[1]],
+                )
+            })
+            .collect();
+        Some(Self { v4, v6 })
+    }
+}
+
+/// Code of the last range starting at or before `key`, if it ends at or
+/// after it
+fn find<K: Ord + Copy>(ranges: &[(K, K, [u8; 2])], key: K) -> Option<&[u8; 2]> {
+    let i = ranges.partition_point(|&(start, _, _)| start <= key);
+    let (_, end, code) = ranges.get(i.checked_sub(1)?)?;
+    (key <= *end).then_some(code)
+}
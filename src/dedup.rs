@@ -0,0 +1,91 @@
+use rustc_hash::FxHashMap as HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Error returned when a dedup window is neither a line count nor seconds
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseWindowError(pub(crate) String);
+
+impl fmt::Display for ParseWindowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid dedup window {:?}, expected a number of lines (e.g. 100) or seconds (e.g. 30s)",
+            self.0
+        )
+    }
+}
+
+impl Error for ParseWindowError {}
+
+/// How far back [`Dedup`] remembers, parsed from "N" lines or "Ns" seconds
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DedupWindow {
+    Lines(u64),
+    Time(Duration),
+}
+
+impl FromStr for DedupWindow {
+    type Err = ParseWindowError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseWindowError(s.to_string());
+        let window = match s.strip_suffix('s') {
+            Some(secs) => DedupWindow::Time(Duration::from_secs(secs.parse().map_err(|_| err())?)),
+            None => DedupWindow::Lines(s.parse().map_err(|_| err())?),
+        };
+        match window {
+            DedupWindow::Lines(0) => Err(err()),
+            DedupWindow::Time(d) if d.is_zero() => Err(err()),
+            window => Ok(window),
+        }
+    }
+}
+
+/// Suppresses lines whose set of extracted entities was already seen within
+/// the window, e.g. the same scanner flooding a log being tailed
+pub struct Dedup {
+    window: DedupWindow,
+    /// line number and time each set was last seen
+    seen: HashMap<Vec<Vec<u8>>, (u64, Instant)>,
+    line: u64,
+    /// size of `seen` at which entries outside the window are dropped
+    prune_at: usize,
+}
+
+impl Dedup {
+    pub fn new(window: DedupWindow) -> Self {
+        Self {
+            window,
+            seen: HashMap::default(),
+            line: 0,
+            prune_at: 1024,
+        }
+    }
+
+    /// Whether a line with these entities repeats a line within the window.
+    /// Lines without entities are never repeats
+    pub fn is_repeat<'a>(&mut self, entities: impl IntoIterator<Item = &'a [u8]>) -> bool {
+        self.line += 1;
+        let mut key: Vec<Vec<u8>> = entities.into_iter().map(<[u8]>::to_vec).collect();
+        if key.is_empty() {
+            return false;
+        }
+        key.sort_unstable();
+        key.dedup();
+
+        let (window, line, now) = (self.window, self.line, Instant::now());
+        let within = |(last_line, last_time): (u64, Instant)| match window {
+            DedupWindow::Lines(n) => line - last_line <= n,
+            DedupWindow::Time(d) => now.duration_since(last_time) <= d,
+        };
+        let repeat = self.seen.insert(key, (line, now)).is_some_and(within);
+        if self.seen.len() >= self.prune_at {
+            self.seen.retain(|_, last| within(*last));
+            self.prune_at = (self.seen.len() * 2).max(1024);
+        }
+        repeat
+    }
+}
@@ -0,0 +1,280 @@
+use crate::geoip::{GeoIPSed, SpacePolicy};
+use crate::template::{Fields, Template};
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use std::borrow::Cow;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Dotted hostnames whose last label starts with a letter, so that dotted
+/// quads are not taken for domains. Candidates still have to end in a known
+/// public suffix, see [`is_domain`]
+pub const DOMAIN_PATTERN: &str = r"(?x)
+    \b
+    (?:[A-Za-z0-9](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?\.)+
+    [A-Za-z](?:[A-Za-z0-9-]{0,61}[A-Za-z0-9])?
+    \b";
+
+/// Public suffixes (e.g. "com", "co.uk", "github.io") under which domains
+/// are registered
+pub struct PublicSuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl PublicSuffixList {
+    /// Parse the rules of a list in the format of publicsuffix.org
+    pub fn parse(text: &str) -> Self {
+        let mut list = Self {
+            rules: HashSet::default(),
+            wildcards: HashSet::default(),
+            exceptions: HashSet::default(),
+        };
+        for line in text.lines() {
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let rule = rule.to_ascii_lowercase();
+            if let Some(exception) = rule.strip_prefix('!') {
+                list.exceptions.insert(exception.to_string());
+            } else if let Some(parent) = rule.strip_prefix("*.") {
+                list.wildcards.insert(parent.to_string());
+            } else {
+                list.rules.insert(rule);
+            }
+        }
+        list
+    }
+
+    /// The list built into geoipsed, a subset of the one at publicsuffix.org
+    pub fn embedded() -> &'static Self {
+        static LIST: OnceLock<PublicSuffixList> = OnceLock::new();
+        LIST.get_or_init(|| Self::parse(include_str!("public_suffix_list.dat")))
+    }
+
+    /// The public suffix `domain` ends with, by the longest matching rule.
+    /// Unlike publicsuffix.org there is no implicit "*" rule, so domains
+    /// under unknown top-level domains have none
+    pub fn public_suffix<'d>(&self, domain: &'d str) -> Option<&'d str> {
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        let lower = domain.to_ascii_lowercase();
+        // offsets of the suffixes of one or more labels, longest first
+        let starts = std::iter::once(0).chain(lower.match_indices('.').map(|(i, _)| i + 1));
+        for start in starts {
+            let candidate = &lower[start..];
+            if self.exceptions.contains(candidate) {
+                // an exception is registrable itself, its parent is the suffix
+                return candidate.find('.').map(|dot| &domain[start + dot + 1..]);
+            }
+            let parent = candidate.split_once('.').map(|(_, parent)| parent);
+            if self.rules.contains(candidate)
+                || parent.is_some_and(|parent| self.wildcards.contains(parent))
+            {
+                return Some(&domain[start..]);
+            }
+        }
+        None
+    }
+
+    /// The registered part of `domain`: its public suffix and one more
+    /// label, e.g. "example.co.uk" of "www.example.co.uk"
+    pub fn registrable_domain<'d>(&self, domain: &'d str) -> Option<&'d str> {
+        let domain = domain.strip_suffix('.').unwrap_or(domain);
+        let suffix = self.public_suffix(domain)?;
+        let rest = domain.strip_suffix(suffix)?.strip_suffix('.')?;
+        let start = rest.rfind('.').map_or(0, |dot| dot + 1);
+        Some(&domain[start..])
+    }
+}
+
+/// Whether `s` is a domain registered under a public suffix of the
+/// embedded list. Bare suffixes and names such as "file.txt" are not
+pub fn is_domain(s: &[u8]) -> bool {
+    std::str::from_utf8(s)
+        .is_ok_and(|s| PublicSuffixList::embedded().registrable_domain(s).is_some())
+}
+
+/// Template fields of a domain. Other fields are the geoip fields of the
+/// address the domain resolves to
+struct DomainRecord<'a> {
+    domain: &'a str,
+    registered: &'a str,
+    suffix: &'a str,
+    resolved: Option<&'a dyn Fields>,
+}
+
+impl Fields for DomainRecord<'_> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "domain" => Some(self.domain.into()),
+            "registered" => Some(self.registered.into()),
+            "suffix" => Some(self.suffix.into()),
+            _ => self.resolved?.field(name),
+        }
+    }
+
+    fn write_field_to(&self, name: &str, out: &mut dyn io::Write) -> io::Result<()> {
+        match (name, self.resolved) {
+            ("domain" | "registered" | "suffix", _) | (_, None) => match self.field(name) {
+                Some(value) => out.write_all(value.as_bytes()),
+                None => Ok(()),
+            },
+            (_, Some(resolved)) => resolved.write_field_to(name, out),
+        }
+    }
+}
+
+/// Resolves domains with the system resolver, several at a time and each
+/// within a timeout. Results, including failures, are cached
+pub struct Resolver {
+    timeout: Duration,
+    concurrency: usize,
+    // lookups still running, including abandoned ones that timed out
+    in_flight: Arc<AtomicUsize>,
+    cache: Mutex<HashMap<String, Option<IpAddr>>>,
+}
+
+impl Resolver {
+    /// Resolve with at most `concurrency` lookups running at once, giving
+    /// up on a batch of lookups after `timeout`
+    pub fn new(timeout: Duration, concurrency: usize) -> Self {
+        Self {
+            timeout,
+            concurrency: concurrency.max(1),
+            in_flight: Arc::default(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// First address `domain` resolves to, or None if it does not resolve
+    /// in time
+    pub fn resolve(&self, domain: &str) -> Option<IpAddr> {
+        self.prefetch([domain]);
+        self.cache.lock().ok()?.get(domain).copied().flatten()
+    }
+
+    /// Resolve the domains not cached yet concurrently and cache the
+    /// results, so that a line with several domains waits for the slowest
+    /// lookup rather than for all of them in turn
+    pub fn prefetch<'d>(&self, domains: impl IntoIterator<Item = &'d str>) {
+        let Ok(mut cache) = self.cache.lock() else {
+            return;
+        };
+        let (tx, rx) = mpsc::channel();
+        let deadline = Instant::now() + self.timeout;
+        // wait for one lookup of the batch, caching its result
+        let mut pending = 0;
+        let receive = |cache: &mut HashMap<_, _>, pending: &mut usize| {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            let Ok((domain, ip)) = rx.recv_timeout(wait) else {
+                return false;
+            };
+            cache.insert(domain, ip);
+            *pending -= 1;
+            true
+        };
+        for domain in domains {
+            if cache.contains_key(domain) {
+                continue;
+            }
+            while self.in_flight.load(Ordering::Relaxed) >= self.concurrency
+                && pending > 0
+                && receive(&mut cache, &mut pending)
+            {}
+            // the slots are taken by lookups abandoned after timing out, so
+            // this one is skipped but not cached as failed
+            if self.in_flight.load(Ordering::Relaxed) >= self.concurrency {
+                continue;
+            }
+            self.in_flight.fetch_add(1, Ordering::Relaxed);
+            // mark the domain so that repeats in the batch are looked up once
+            cache.insert(domain.to_string(), None);
+            pending += 1;
+            let (tx, in_flight, domain) = (tx.clone(), self.in_flight.clone(), domain.to_string());
+            thread::spawn(move || {
+                let ip = (domain.as_str(), 0)
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .map(|addr| addr.ip());
+                in_flight.fetch_sub(1, Ordering::Relaxed);
+                // the receiver is gone if the lookup timed out
+                let _ = tx.send((domain, ip));
+            });
+        }
+        while pending > 0 && receive(&mut cache, &mut pending) {}
+    }
+}
+
+/// Decorates domains with their registered domain and, when resolving, the
+/// geoip fields of the address they resolve to
+pub struct DomainDecorator {
+    template: Template,
+    resolver: Option<Resolver>,
+    space_policy: SpacePolicy,
+}
+
+impl DomainDecorator {
+    /// Default template of domain decorations
+    pub const DEFAULT_TEMPLATE: &'static str = "<{domain}|{registered}>";
+
+    /// Default template of domain decorations when resolving
+    pub const RESOLVE_TEMPLATE: &'static str = "{domain}[{ip}|AS{asnnum}|{country_iso}|{city}]";
+
+    /// Decorate with `template`, whose fields are {domain}, {registered},
+    /// {suffix}, and the geoip fields (e.g. {ip}, {country_iso}) of the first
+    /// address the domain resolves to with `resolver`. Without a resolver
+    /// the geoip fields are empty
+    pub fn new(template: &str, resolver: Option<Resolver>) -> Self {
+        Self {
+            template: Template::new(template),
+            resolver,
+            space_policy: SpacePolicy::default(),
+        }
+    }
+
+    /// Write the spaces of decorations as `policy` says, instead of as
+    /// underscores
+    pub fn space_policy(mut self, policy: SpacePolicy) -> Self {
+        self.space_policy = policy;
+        self
+    }
+
+    /// Resolve `domains` ahead of their decoration, see
+    /// [`Resolver::prefetch`]
+    pub fn prefetch<'d>(&self, domains: impl IntoIterator<Item = &'d str>) {
+        if let Some(resolver) = &self.resolver {
+            resolver.prefetch(domains);
+        }
+    }
+
+    /// Render the decoration of `s`, or return it unchanged if it is not a
+    /// registered domain. Geoip fields are empty if it does not resolve
+    pub fn lookup(&self, s: &str, geoipdb: &GeoIPSed) -> String {
+        let psl = PublicSuffixList::embedded();
+        let (Some(registered), Some(suffix)) = (psl.registrable_domain(s), psl.public_suffix(s))
+        else {
+            return s.to_string();
+        };
+        let ip = self
+            .resolver
+            .as_ref()
+            .and_then(|resolver| resolver.resolve(s))
+            .map(|ip| ip.to_string());
+        let record = ip.as_deref().and_then(|ip| geoipdb.lookup_record(ip));
+        let record = DomainRecord {
+            domain: s,
+            registered,
+            suffix,
+            resolved: record.as_ref().map(|r| r as &dyn Fields),
+        };
+        self.space_policy.render(&self.template, &record)
+    }
+}
@@ -0,0 +1,68 @@
+use serde_json::{json, Map, Value};
+
+/// The Elastic Common Schema (ECS) fields of an address and its geoip
+/// record, as found under `source`, `destination`, or `client` in ECS
+/// documents, so that SIEM index mappings and Kibana maps accept them:
+///
+/// ```
+/// use geoipsed::ecs;
+/// use serde_json::json;
+///
+/// let geo = json!({"ip": "81.2.69.205", "asnnum": null, "country_iso": "GB", "city": "London"});
+/// assert_eq!(
+///     ecs::endpoint(&geo),
+///     json!({"ip": "81.2.69.205", "geo": {"city_name": "London", "country_iso_code": "GB"}})
+/// );
+/// ```
+///
+/// Unknown fields are left out rather than written empty
+pub fn endpoint(geo: &Value) -> Value {
+    let field = |name: &str| geo[name].as_str().filter(|v| !v.is_empty());
+    let mut endpoint = Map::new();
+    if let Some(ip) = field("ip") {
+        endpoint.insert("ip".to_string(), ip.into());
+    }
+
+    let mut location = Map::new();
+    for (name, ecs) in [
+        ("city", "city_name"),
+        ("continent", "continent_code"),
+        ("country_iso", "country_iso_code"),
+        ("country_full", "country_name"),
+        ("subdivision_name", "region_name"),
+        ("postal_code", "postal_code"),
+        ("timezone", "timezone"),
+    ] {
+        if let Some(value) = field(name) {
+            location.insert(ecs.to_string(), value.into());
+        }
+    }
+    // ECS region codes are ISO 3166-2, e.g. US-CA
+    if let (Some(country), Some(region)) = (field("country_iso"), field("subdivision_iso")) {
+        location.insert(
+            "region_iso_code".to_string(),
+            format!("{country}-{region}").into(),
+        );
+    }
+    let coord = |name: &str| geo[name].as_f64();
+    match (coord("latitude"), coord("longitude")) {
+        (Some(lat), Some(lon)) if lat != 0.0 || lon != 0.0 => {
+            location.insert("location".to_string(), json!({ "lat": lat, "lon": lon }));
+        }
+        _ => {}
+    }
+    if !location.is_empty() {
+        endpoint.insert("geo".to_string(), location.into());
+    }
+
+    if let Some(number) = geo["asnnum"].as_u64() {
+        if number != 0 {
+            let mut asn = json!({ "number": number });
+            if let Some(org) = field("asnorg") {
+                asn["organization"] = json!({ "name": org });
+            }
+            endpoint.insert("as".to_string(), asn);
+        }
+    }
+    endpoint.into()
+}
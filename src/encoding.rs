@@ -0,0 +1,80 @@
+use clap::ValueEnum;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, Cursor, Read};
+
+/// Bytes of the input looked at to recognize UTF-16 without a BOM
+const SNIFF_LEN: usize = 4096;
+
+/// Text encodings of the input, which is transcoded to UTF-8 before
+/// scanning
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum InputEncoding {
+    /// UTF-16 little endian, e.g. Windows event log exports
+    #[value(name = "utf-16le")]
+    Utf16le,
+    /// UTF-16 big endian
+    #[value(name = "utf-16be")]
+    Utf16be,
+    /// ISO 8859-1, decoded as its superset Windows-1252
+    Latin1,
+    /// By the byte order mark, or by the NUL bytes between the ASCII
+    /// characters of UTF-16. Other input is passed through
+    Auto,
+}
+
+impl InputEncoding {
+    /// Transcode `reader` to UTF-8. A byte order mark overrides the
+    /// encoding and is removed
+    pub fn decode<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        let (encoding, reader): (_, Box<dyn Read + 'a>) = match self {
+            InputEncoding::Utf16le => (UTF_16LE, Box::new(reader)),
+            InputEncoding::Utf16be => (UTF_16BE, Box::new(reader)),
+            InputEncoding::Latin1 => (WINDOWS_1252, Box::new(reader)),
+            InputEncoding::Auto => {
+                let mut prefix = Vec::with_capacity(SNIFF_LEN);
+                let mut reader = reader;
+                (&mut reader)
+                    .take(SNIFF_LEN as u64)
+                    .read_to_end(&mut prefix)?;
+                let encoding = sniff(&prefix);
+                (encoding, Box::new(Cursor::new(prefix).chain(reader)))
+            }
+        };
+        Ok(Box::new(
+            DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .utf8_passthru(true)
+                .bom_override(true)
+                .strip_bom(true)
+                .build(reader),
+        ))
+    }
+}
+
+/// The encoding of text starting with `prefix`: by its byte order mark,
+/// else UTF-16 if most of its even or odd bytes are NUL, else UTF-8
+pub fn sniff(prefix: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(prefix) {
+        return encoding;
+    }
+    let pairs = prefix.len() / 2;
+    let nuls = |offset: usize| {
+        prefix
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .take(pairs)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (nuls(0), nuls(1));
+    // ASCII text in UTF-16 has a NUL in every other byte
+    if pairs > 0 && odd * 4 > pairs * 3 && even * 4 < pairs {
+        UTF_16LE
+    } else if pairs > 0 && even * 4 > pairs * 3 && odd * 4 < pairs {
+        UTF_16BE
+    } else {
+        UTF_8
+    }
+}
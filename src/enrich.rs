@@ -0,0 +1,50 @@
+use crate::prefixtrie::PrefixTrie;
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use std::net::IpAddr;
+
+/// User-supplied labels for networks, e.g. an internal asset inventory
+/// mapping "10.1.2.0/24" to "corp-vpn", exposed as one extra template field
+pub struct EnrichDb {
+    field: String,
+    labels: PrefixTrie<String>,
+}
+
+impl EnrichDb {
+    /// Load a CSV of `cidr,label` lines whose labels are exposed as the
+    /// template field `field`. The most specific network containing an IP
+    /// wins. Lines starting with # are comments, and a first line whose
+    /// first column is not an IP/CIDR is taken to be a header
+    pub fn load(path: &Utf8Path, field: &str) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read enrichment file {path}"))?;
+        let mut labels = PrefixTrie::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            let (cidr, label) = line.split_once(',').unwrap_or((line, ""));
+            let unquote = |s: &str| s.trim().trim_matches('"').to_string();
+            if labels.insert_cidr(&unquote(cidr), unquote(label)).is_err() {
+                if i == 0 {
+                    continue;
+                }
+                bail!("{path}:{}: expected \"cidr,label\"", i + 1);
+            }
+        }
+        Ok(Self {
+            field: field.to_string(),
+            labels,
+        })
+    }
+
+    /// Name of the template field the labels are exposed as
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Label of the most specific network containing `ip`
+    pub fn label(&self, ip: IpAddr) -> Option<&str> {
+        self.labels.lookup(ip).map(String::as_str)
+    }
+}
@@ -0,0 +1,670 @@
+use crate::geoip::is_public;
+use crate::ipparse::{parse_ip_bytes, parse_obfuscated_ipv4};
+use regex::bytes::{CaptureLocations, Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// MAC addresses in colon ("00:1a:2b:3c:4d:5e"), dash ("00-1A-2B-3C-4D-5E"),
+/// or Cisco dot ("001a.2b3c.4d5e") notation
+pub const MAC_PATTERN: &str = r"(?x)
+    \b(?:
+        (?:[0-9A-Fa-f]{2}:){5}[0-9A-Fa-f]{2}
+        |
+        (?:[0-9A-Fa-f]{2}-){5}[0-9A-Fa-f]{2}
+        |
+        (?:[0-9A-Fa-f]{4}\.){2}[0-9A-Fa-f]{4}
+    )\b";
+
+/// Kinds of entities that can be extracted from text
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EntityKind {
+    /// IPv4 and IPv6 addresses
+    Ip,
+    /// MAC addresses
+    Mac,
+    /// Domain names under a public suffix
+    Domain,
+    /// Entities of a pattern registered with [`PatternSet`]
+    Custom(String),
+}
+
+impl EntityKind {
+    /// Name of the kind, e.g. "ip" or the name of a custom kind
+    pub fn name(&self) -> &str {
+        match self {
+            EntityKind::Ip => "ip",
+            EntityKind::Mac => "mac",
+            EntityKind::Domain => "domain",
+            EntityKind::Custom(name) => name,
+        }
+    }
+}
+
+/// One entity found in a haystack
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entity<'e, 'h> {
+    kind: &'e EntityKind,
+    haystack: &'h [u8],
+    start: usize,
+    end: usize,
+    // span of the url the entity is in, if the extractor looks for them
+    url: Option<(usize, usize)>,
+    // the address of an obfuscated IPv4 entity
+    dotted: Option<Ipv4Addr>,
+}
+
+impl<'e, 'h> Entity<'e, 'h> {
+    /// What kind of entity this is
+    pub fn kind(&self) -> &'e EntityKind {
+        self.kind
+    }
+
+    /// Offset of the first byte of the entity
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Offset just past the last byte of the entity
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Byte range of the entity within the haystack
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The text of the entity
+    pub fn as_bytes(&self) -> &'h [u8] {
+        &self.haystack[self.start..self.end]
+    }
+
+    /// The address of an IP entity, or None if the entity is not one or
+    /// its text is not a valid address. Obfuscated IPv4 notations are
+    /// parsed if the extractor was built with
+    /// [`PatternSet::obfuscated_ipv4`]
+    pub fn ip(&self) -> Option<IpAddr> {
+        if *self.kind != EntityKind::Ip {
+            return None;
+        }
+        self.dotted
+            .map(IpAddr::V4)
+            .or_else(|| parse_ip_bytes(self.as_bytes()))
+    }
+
+    /// The text of the entity, with an obfuscated IPv4 address in its
+    /// dotted-quad form so that it is looked up and decorated as one. None
+    /// if the text is not UTF-8
+    pub fn canonical_text(&self) -> Option<Cow<'h, str>> {
+        match self.dotted {
+            Some(ip) => Some(ip.to_string().into()),
+            None => std::str::from_utf8(self.as_bytes()).ok().map(Cow::from),
+        }
+    }
+
+    /// The `scheme://…` URL the entity is part of, e.g. all of
+    /// "http://1.2.3.4:8080/path" for its address. None if the entity is
+    /// not in a URL or the extractor was not built with
+    /// [`PatternSet::url_context`]
+    pub fn url(&self) -> Option<&'h [u8]> {
+        self.url.map(|(start, end)| &self.haystack[start..end])
+    }
+}
+
+/// Error returned when an [`Extractor`] cannot be built
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum BuildError {
+    /// No pattern was added, so nothing would ever be extracted
+    NoPatterns,
+    /// A pattern is not a valid regex, or the patterns together are too big
+    Regex(regex::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::NoPatterns => write!(f, "no entity patterns selected"),
+            BuildError::Regex(_) => write!(f, "invalid entity pattern"),
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::NoPatterns => None,
+            BuildError::Regex(err) => Some(err),
+        }
+    }
+}
+
+impl From<regex::Error> for BuildError {
+    fn from(err: regex::Error) -> Self {
+        BuildError::Regex(err)
+    }
+}
+
+/// Checks a candidate match, e.g. a checksum or a lookup in a list of
+/// known values. Rejected candidates are skipped
+pub type Validator = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Why a match of a pattern is not extracted, see [`Extractor::candidates`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Rejection {
+    /// An IP match that is part of a longer token, e.g. the "1.2.3.4" of
+    /// "v1.2.3.4" or "1.2.3.4.5"
+    Boundary,
+    /// The validator of the pattern turned the match down
+    Validator,
+    /// An address that is not globally routable, with
+    /// [`PatternSet::public_only`]
+    PublicOnly,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Boundary => write!(f, "not delimited from the surrounding text"),
+            Rejection::Validator => write!(f, "rejected by the validator"),
+            Rejection::PublicOnly => write!(f, "not a globally routable address"),
+        }
+    }
+}
+
+/// Trade-off of the search of an [`Extractor`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SearchProfile {
+    /// Unicode-aware classes and word boundaries, as the regex engine
+    /// defaults to. The lazy DFA that makes searches fast cannot handle a
+    /// Unicode `\b` next to non-ASCII bytes, so lines with them are
+    /// searched by a slower engine, up to a hundred times slower
+    #[default]
+    Latency,
+    /// ASCII-only `\b`, `\w`, `\d`, and `\s`, so every line is searched by
+    /// the lazy DFA whatever its bytes. Non-ASCII letters are not word
+    /// characters, and patterns with Unicode classes such as `\p{L}` fail
+    /// to build
+    Throughput,
+}
+
+/// A registered entity pattern
+struct Pattern {
+    kind: EntityKind,
+    // capture group index of the pattern's alternative
+    group: usize,
+    validate: Option<Validator>,
+}
+
+/// Builder of an [`Extractor`] from built-in and custom entity patterns.
+///
+/// ```
+/// use geoipsed::entity::{EntityKind, PatternSet};
+///
+/// let extractor = PatternSet::new()
+///     .kind(EntityKind::Ip)
+///     .validated(
+///         EntityKind::Custom("md5".to_string()),
+///         r"\b[0-9a-f]{32}\b",
+///         |hash| hash.iter().any(u8::is_ascii_alphabetic),
+///     )
+///     .build()
+///     .unwrap();
+///
+/// let line = b"10.1.2.3 fetched 00000000000000000000000000000000 d41d8cd98f00b204e9800998ecf8427e";
+/// let kinds: Vec<_> = extractor.find_iter(line).map(|m| m.kind().clone()).collect();
+/// assert_eq!(kinds, [EntityKind::Ip, EntityKind::Custom("md5".to_string())]);
+/// ```
+#[derive(Default)]
+pub struct PatternSet {
+    patterns: Vec<(EntityKind, String, Option<Validator>)>,
+    profile: SearchProfile,
+    public_only: bool,
+    greedy_recovery: bool,
+    url_context: bool,
+    obfuscated_ipv4: bool,
+}
+
+impl PatternSet {
+    /// Empty set of patterns, the same as [`PatternSet::permissive`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Empty set of patterns extracting every address matched, including
+    /// private, loopback, and broadcast ones
+    pub fn permissive() -> Self {
+        Self::default()
+    }
+
+    /// Empty set of patterns extracting only globally routable addresses,
+    /// i.e. not private, loopback, broadcast, link-local, documentation,
+    /// multicast, or reserved ones. Other kinds are not affected
+    pub fn strict() -> Self {
+        Self::default().public_only(true)
+    }
+
+    /// Whether to extract only globally routable addresses, false by
+    /// default. See [`PatternSet::strict`]
+    pub fn public_only(mut self, public_only: bool) -> Self {
+        self.public_only = public_only;
+        self
+    }
+
+    /// Add the built-in pattern of `kind`. Custom kinds have none and are
+    /// ignored
+    pub fn kind(self, kind: EntityKind) -> Self {
+        match kind {
+            EntityKind::Ip => self.pattern(kind, crate::geoip::REGEX_PATTERN),
+            EntityKind::Mac => self.pattern(kind, MAC_PATTERN),
+            EntityKind::Domain => self.validated(
+                kind,
+                crate::domain::DOMAIN_PATTERN,
+                crate::domain::is_domain,
+            ),
+            EntityKind::Custom(_) => self,
+        }
+    }
+
+    /// Add a pattern whose matches are entities of `kind`
+    pub fn pattern(mut self, kind: EntityKind, pattern: impl Into<String>) -> Self {
+        self.patterns.push((kind, pattern.into(), None));
+        self
+    }
+
+    /// Add a pattern whose matches are entities of `kind` only if accepted
+    /// by `validate`
+    pub fn validated<F>(mut self, kind: EntityKind, pattern: impl Into<String>, validate: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + Send + Sync + 'static,
+    {
+        self.patterns
+            .push((kind, pattern.into(), Some(Box::new(validate))));
+        self
+    }
+
+    /// Whether to recover addresses from tokens they do not stand on their
+    /// own in, false by default. Normally an IP match glued to a longer
+    /// token, e.g. the "1.2.3.4" of "1.2.3.4.5" or "10.0.0.0" of
+    /// "310.0.0.0", is skipped. With recovery, the longest part of the
+    /// match, from its start, that is an address in standard notation is
+    /// extracted instead, for best-effort matches in corrupted or oddly
+    /// delimited text
+    pub fn greedy_recovery(mut self, greedy_recovery: bool) -> Self {
+        self.greedy_recovery = greedy_recovery;
+        self
+    }
+
+    /// Whether IP patterns also match IPv4 addresses written as decimal,
+    /// hex, or octal numbers or dotted quads with hex or octal parts, e.g.
+    /// "3232235777", "0xC0A80101", or "0300.0250.0001.0001", false by
+    /// default as plain numbers are easily mistaken for addresses. Their
+    /// dotted quads are given by [`Entity::canonical_text`]
+    pub fn obfuscated_ipv4(mut self, obfuscated_ipv4: bool) -> Self {
+        self.obfuscated_ipv4 = obfuscated_ipv4;
+        self
+    }
+
+    /// Whether to find the URL each entity is part of, false by default.
+    /// See [`Entity::url`]
+    pub fn url_context(mut self, url_context: bool) -> Self {
+        self.url_context = url_context;
+        self
+    }
+
+    /// Set the trade-off of the search, [`SearchProfile::Latency`] by
+    /// default. The built-in patterns only match ASCII, so
+    /// [`SearchProfile::Throughput`] finds the same entities with them
+    /// except next to non-ASCII letters, which no longer join words
+    pub fn search_profile(mut self, profile: SearchProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    /// Compile the patterns, in the order they were added, into one
+    /// [`Extractor`]. Fails if there are none, or with the error of the
+    /// first invalid pattern
+    pub fn build(self) -> Result<Extractor, BuildError> {
+        if self.patterns.is_empty() {
+            return Err(BuildError::NoPatterns);
+        }
+        // groups scope the flags (e.g. verbose mode) of each pattern to it
+        let group_name = |i: usize| format!("__entity{i}");
+        let alternatives: Vec<String> = self
+            .patterns
+            .iter()
+            .enumerate()
+            .map(|(i, (kind, pattern, _))| match kind {
+                // obfuscated forms are tried first so that e.g.
+                // "0300.0250.0001.0001" is not matched piecemeal
+                EntityKind::Ip if self.obfuscated_ipv4 => format!(
+                    "(?P<{}>(?:{})|(?:{pattern}))",
+                    group_name(i),
+                    crate::geoip::OBFUSCATED_IPV4_PATTERN
+                ),
+                _ => format!("(?P<{}>{pattern})", group_name(i)),
+            })
+            .collect();
+        let re = RegexBuilder::new(&alternatives.join("|"))
+            .unicode(self.profile == SearchProfile::Latency)
+            .build()?;
+        let names: Vec<Option<&str>> = re.capture_names().collect();
+        let patterns = self
+            .patterns
+            .into_iter()
+            .enumerate()
+            .map(|(i, (kind, _, validate))| {
+                let name = group_name(i);
+                let group = names
+                    .iter()
+                    .position(|n| *n == Some(name.as_str()))
+                    .expect("every pattern has a group");
+                Pattern {
+                    kind,
+                    group,
+                    validate,
+                }
+            })
+            .collect();
+        // identifies the extractor to the scratch buffers sized for it
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Ok(Extractor {
+            re,
+            patterns,
+            public_only: self.public_only,
+            greedy_recovery: self.greedy_recovery,
+            url_context: self.url_context,
+            obfuscated_ipv4: self.obfuscated_ipv4,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+}
+
+/// Finds entities of several kinds in a single pass. The patterns of all
+/// kinds are compiled into one regex of alternatives, each in a named
+/// group, so the haystack is scanned once whatever the number of kinds. At
+/// any position the earliest registered kind whose pattern matches wins.
+/// Extractors are immutable once built and `Send + Sync`, so one can be
+/// shared by threads behind an `Arc`
+pub struct Extractor {
+    re: Regex,
+    patterns: Vec<Pattern>,
+    public_only: bool,
+    greedy_recovery: bool,
+    url_context: bool,
+    obfuscated_ipv4: bool,
+    id: u64,
+}
+
+impl Extractor {
+    /// Compile an extractor for `(kind, pattern)` pairs, in priority order.
+    /// See [`PatternSet`] for built-in patterns and validation
+    pub fn new(patterns: &[(EntityKind, &str)]) -> Result<Self, BuildError> {
+        patterns
+            .iter()
+            .fold(PatternSet::new(), |set, (kind, pattern)| {
+                set.pattern(kind.clone(), *pattern)
+            })
+            .build()
+    }
+
+    /// The kinds this extractor finds, in priority order
+    pub fn kinds(&self) -> impl Iterator<Item = &EntityKind> {
+        self.patterns.iter().map(|p| &p.kind)
+    }
+
+    /// Iterate the non-overlapping entities of `haystack`, left to right.
+    /// Any bytes are accepted, including invalid UTF-8 and NULs, without
+    /// panicking
+    pub fn find_iter<'e, 'h>(
+        &'e self,
+        haystack: &'h [u8],
+    ) -> Box<dyn Iterator<Item = Entity<'e, 'h>> + 'e>
+    where
+        'h: 'e,
+    {
+        Box::new(
+            self.candidates(haystack)
+                .filter_map(|(entity, rejection)| rejection.is_none().then_some(entity)),
+        )
+    }
+
+    /// Like [`Extractor::find_iter`], but the search state is kept in
+    /// `scratch` and reused by later calls rather than allocated for each
+    /// match. Worth it in tight loops over many short lines when several
+    /// kinds are extracted; see `geoipsed bench` for the difference.
+    ///
+    /// ```
+    /// use geoipsed::entity::{EntityKind, ExtractScratch, PatternSet};
+    ///
+    /// let extractor = PatternSet::new().kind(EntityKind::Ip).kind(EntityKind::Mac).build().unwrap();
+    /// let mut scratch = ExtractScratch::new();
+    /// for line in [&b"10.0.0.1 00:1a:2b:3c:4d:5e"[..], b"fe80::1"] {
+    ///     let found: Vec<_> = extractor.find_iter(line).map(|m| m.range()).collect();
+    ///     let reused: Vec<_> = extractor.find_iter_with(line, &mut scratch).map(|m| m.range()).collect();
+    ///     assert_eq!(found, reused);
+    /// }
+    /// ```
+    pub fn find_iter_with<'e, 'h, 's>(
+        &'e self,
+        haystack: &'h [u8],
+        scratch: &'s mut ExtractScratch,
+    ) -> impl Iterator<Item = Entity<'e, 'h>> + 's
+    where
+        'e: 's,
+        'h: 's,
+    {
+        if scratch.locs.as_ref().is_none_or(|(id, _)| *id != self.id) {
+            scratch.locs = Some((self.id, self.re.capture_locations()));
+        }
+        let (_, locs) = scratch.locs.as_mut().expect("set above");
+        let mut at = 0;
+        let mut last_end = None;
+        std::iter::from_fn(move || loop {
+            if at > haystack.len() {
+                return None;
+            }
+            let (pattern, range) = match self.patterns.as_slice() {
+                [pattern] => (pattern, self.re.find_at(haystack, at)?.range()),
+                patterns => {
+                    self.re.captures_read_at(locs, haystack, at)?;
+                    patterns.iter().find_map(|p| {
+                        let (start, end) = locs.get(p.group)?;
+                        Some((p, start..end))
+                    })?
+                }
+            };
+            // as the regex iterators do, step past empty matches and skip
+            // one right after the previous match
+            if range.is_empty() {
+                at = range.end + 1;
+                if last_end == Some(range.end) {
+                    continue;
+                }
+            } else {
+                at = range.end;
+            }
+            last_end = Some(range.end);
+            if let (entity, None) = self.candidate(pattern, haystack, range) {
+                return Some(entity);
+            }
+        })
+    }
+
+    /// Iterate every match of the patterns in `haystack`, left to right,
+    /// with why it was rejected, if it was. Rejected matches are not
+    /// entities, but explain why some text was not extracted
+    pub fn candidates<'e, 'h>(
+        &'e self,
+        haystack: &'h [u8],
+    ) -> Box<dyn Iterator<Item = (Entity<'e, 'h>, Option<Rejection>)> + 'e>
+    where
+        'h: 'e,
+    {
+        // with a single kind there is no need to find out which group matched
+        if let [pattern] = self.patterns.as_slice() {
+            return Box::new(
+                self.re
+                    .find_iter(haystack)
+                    .map(move |m| self.candidate(pattern, haystack, m.range())),
+            );
+        }
+        Box::new(self.re.captures_iter(haystack).filter_map(move |caps| {
+            let (pattern, m) = self
+                .patterns
+                .iter()
+                .find_map(|p| caps.get(p.group).map(|m| (p, m)))?;
+            Some(self.candidate(pattern, haystack, m.range()))
+        }))
+    }
+
+    /// The match of `pattern` at `range` of `haystack`, with why it was
+    /// rejected, if it was. With greedy recovery, an IP match that is not
+    /// delimited is narrowed to the address it starts with
+    fn candidate<'e, 'h>(
+        &self,
+        pattern: &'e Pattern,
+        haystack: &'h [u8],
+        mut range: Range<usize>,
+    ) -> (Entity<'e, 'h>, Option<Rejection>) {
+        let mut delimited = true;
+        if pattern.kind == EntityKind::Ip && !ip_delimited(haystack, &range) {
+            match self.greedy_recovery.then(|| recover_ip(haystack, &range)) {
+                Some(Some(recovered)) => range = recovered,
+                _ => delimited = false,
+            }
+        }
+        let text = &haystack[range.clone()];
+        let rejection = if !delimited {
+            Some(Rejection::Boundary)
+        } else if pattern
+            .validate
+            .as_ref()
+            .is_some_and(|validate| !validate(text))
+        {
+            Some(Rejection::Validator)
+        } else if pattern.kind == EntityKind::Ip
+            && self.public_only
+            && !is_public_match(text, self.obfuscated_ipv4)
+        {
+            Some(Rejection::PublicOnly)
+        } else {
+            None
+        };
+        let entity = Entity {
+            kind: &pattern.kind,
+            haystack,
+            start: range.start,
+            end: range.end,
+            url: self
+                .url_context
+                .then(|| enclosing_url(haystack, &range))
+                .flatten(),
+            dotted: None,
+        };
+        let entity = match self.obfuscated_ipv4 && pattern.kind == EntityKind::Ip {
+            true if parse_ip_bytes(text).is_none() => Entity {
+                dotted: parse_obfuscated_ipv4(text),
+                ..entity
+            },
+            _ => entity,
+        };
+        (entity, rejection)
+    }
+}
+
+/// Search state reused by [`Extractor::find_iter_with`] across calls. A
+/// scratch can be given any extractor, but is only reused while it is
+/// given the same one; keep one per thread and extractor
+#[derive(Debug, Default)]
+pub struct ExtractScratch {
+    // capture locations and the id of the extractor they are for
+    locs: Option<(u64, CaptureLocations)>,
+}
+
+impl ExtractScratch {
+    /// Empty scratch, sized on first use
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// The longest part of the IP match at `range`, from its start, that is an
+/// address in standard notation
+fn recover_ip(haystack: &[u8], range: &Range<usize>) -> Option<Range<usize>> {
+    (range.start + 1..=range.end)
+        .rev()
+        .map(|end| range.start..end)
+        .find(|sub| parse_ip_bytes(&haystack[sub.clone()]).is_some())
+}
+
+/// Span of the `scheme://…` URL that the text at `range` of `haystack` is
+/// part of. A URL runs until whitespace, a quote, or an angle bracket, less
+/// any punctuation ending a sentence or closing a parenthesis
+fn enclosing_url(haystack: &[u8], range: &Range<usize>) -> Option<(usize, usize)> {
+    let in_url = |b: &u8| !b.is_ascii_whitespace() && !b"\"'<>".contains(b);
+    let token_start = haystack[..range.start]
+        .iter()
+        .rposition(|b| !in_url(b))
+        .map_or(0, |i| i + 1);
+    // the last scheme separator before the entity, or at its start
+    let prefix = &haystack[token_start..range.start];
+    let sep = token_start + prefix.windows(3).rposition(|w| w == b"://")?;
+    let scheme = haystack[token_start..sep]
+        .iter()
+        .rposition(|b| !(b.is_ascii_alphanumeric() || b"+.-".contains(b)))
+        .map_or(token_start, |i| token_start + i + 1);
+    // schemes start with a letter
+    let scheme = scheme
+        + haystack[scheme..sep]
+            .iter()
+            .position(u8::is_ascii_alphabetic)?;
+    let end = haystack[range.end..]
+        .iter()
+        .position(|b| !in_url(b))
+        .map_or(haystack.len(), |i| range.end + i);
+    let end = range.end
+        + haystack[range.end..end]
+            .iter()
+            .rposition(|b| !b".,;:!?)".contains(b))
+            .map_or(0, |i| i + 1);
+    Some((scheme, end))
+}
+
+/// Whether the matched address `ip` is globally routable. Obfuscated IPv4
+/// forms are matched only if the extractor was built for them
+fn is_public_match(ip: &[u8], obfuscated_ipv4: bool) -> bool {
+    parse_ip_bytes(ip)
+        .or_else(|| {
+            obfuscated_ipv4
+                .then(|| parse_obfuscated_ipv4(ip).map(IpAddr::V4))
+                .flatten()
+        })
+        .is_some_and(|ip| is_public(&ip))
+}
+
+/// Whether the IP address at `range` of `haystack` stands on its own rather
+/// than being the middle of a longer token, e.g. "10.0.0.0" of "310.0.0.0"
+/// or "1.2.3.4" of "1.2.3.4.5". Punctuation such as the colon of
+/// "1.2.3.4:80" or the period ending a sentence is a delimiter. A leading
+/// period is too, so the address ending a long dotted run is still found
+fn ip_delimited(haystack: &[u8], range: &Range<usize>) -> bool {
+    let before = range.start.checked_sub(1).map(|i| haystack[i]);
+    let after = haystack.get(range.end).copied();
+    // colons delimit IPv4 addresses but are part of IPv6 ones
+    let ipv6 = haystack[range.clone()].contains(&b':');
+    let continues = |b: u8| b.is_ascii_alphanumeric() || (ipv6 && b == b':');
+    // a trailing period continues the token if a digit is past it
+    let period = after == Some(b'.')
+        && haystack
+            .get(range.end + 1)
+            .is_some_and(|b| b.is_ascii_digit());
+    !before.is_some_and(continues) && !after.is_some_and(continues) && !period
+}
@@ -0,0 +1,77 @@
+use crate::template::TemplateError;
+use camino::Utf8PathBuf;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Errors of opening and configuring a [`GeoIPSed`](crate::geoip::GeoIPSed)
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A database file does not exist
+    DatabaseNotFound(Utf8PathBuf),
+    /// The database directory, chosen as `origin` describes, has neither an
+    /// ASN nor a City database
+    NoDatabases { dir: Utf8PathBuf, origin: String },
+    /// A database file exists but is not a readable MaxMind database
+    DatabaseOpen {
+        path: Utf8PathBuf,
+        source: maxminddb::MaxMindDBError,
+    },
+    /// A prefix to ASN table could not be loaded
+    PrefixTable {
+        path: Utf8PathBuf,
+        source: Box<dyn StdError + Send + Sync>,
+    },
+    /// The template references a field that no database provides, maybe
+    /// a typo of the `suggestion`
+    InvalidTemplate {
+        field: String,
+        suggestion: Option<String>,
+    },
+    /// The template is malformed, e.g. has an unclosed {
+    TemplateSyntax(TemplateError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DatabaseNotFound(path) => write!(f, "{path}: database not found"),
+            Error::NoDatabases { dir, origin } => write!(
+                f,
+                "{dir}: database not found, no GeoLite2-ASN.mmdb, GeoLite2-City.mmdb, or other \
+                 ASN or City .mmdb file in this directory ({origin}). Name the directory with -I \
+                 or $GEOIP_MMDB_DIR"
+            ),
+            Error::DatabaseOpen { path, .. } => write!(f, "{path}: cannot read database"),
+            Error::PrefixTable { path, .. } => write!(f, "{path}: cannot load prefix table"),
+            Error::InvalidTemplate {
+                field,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "unknown template field {{{field}}}; did you mean {{{suggestion}}}?"
+            ),
+            Error::InvalidTemplate {
+                field,
+                suggestion: None,
+            } => write!(
+                f,
+                "unknown template field {{{field}}}, see --list-templates"
+            ),
+            Error::TemplateSyntax(_) => write!(f, "invalid template"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::DatabaseOpen { source, .. } => Some(source),
+            Error::PrefixTable { source, .. } => Some(source.as_ref()),
+            Error::TemplateSyntax(source) => Some(source),
+            Error::DatabaseNotFound(_)
+            | Error::NoDatabases { .. }
+            | Error::InvalidTemplate { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::export::utc_timestamp;
+use anyhow::{bail, Result};
+use camino::Utf8Path;
+use serde_json::json;
+use std::fs::File;
+use std::io::{self, Read};
+
+const FILE_MAGIC: &[u8] = b"ElfFile\0";
+const CHUNK_MAGIC: &[u8] = b"ElfChnk\0";
+const RECORD_MAGIC: &[u8] = b"\x2a\x2a\0\0";
+const FILE_HEADER_SIZE: usize = 4096;
+const CHUNK_SIZE: usize = 65536;
+/// offset of the first record in a chunk
+const CHUNK_HEADER_SIZE: usize = 512;
+/// magic, size, record id, and timestamp ahead of a record's event
+const RECORD_HEADER_SIZE: usize = 24;
+/// seconds between 1601-01-01, the FILETIME epoch, and 1970-01-01
+const FILETIME_EPOCH_OFFSET: u64 = 11_644_473_600;
+/// shortest run of characters taken for a string
+const MIN_STRING_CHARS: usize = 4;
+
+/// Invoke `f` with one NDJSON line per event record of a Windows EVTX event
+/// log:
+///
+/// `{"record_id":N,"timestamp":"...","strings":"..."}\n`
+///
+/// where `strings` holds the printable UTF-16 strings of the record's
+/// binary XML, element and attribute names as well as values, separated by
+/// spaces. The event XML is not rebuilt, which is enough to find and
+/// enrich the addresses of e.g. logon or firewall events
+pub fn for_each_record_line<F>(path: &Utf8Path, mut f: F) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut reader: Box<dyn Read> = match path.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        _ => Box::new(File::open(path)?),
+    };
+    let mut header = vec![0; FILE_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    if !header.starts_with(FILE_MAGIC) {
+        bail!("{path}: not an EVTX file");
+    }
+
+    let mut chunk = vec![0; CHUNK_SIZE];
+    let mut line = Vec::with_capacity(1024);
+    loop {
+        match reader.read_exact(&mut chunk) {
+            Ok(()) => {}
+            // files may be cut short, e.g. when copied off a live system
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        // unused chunks are left zeroed
+        if !chunk.starts_with(CHUNK_MAGIC) {
+            continue;
+        }
+        let free_space = u32_at(&chunk, 0x30).map_or(CHUNK_SIZE, |n| n as usize);
+        let mut pos = CHUNK_HEADER_SIZE;
+        while pos + RECORD_HEADER_SIZE <= free_space.min(CHUNK_SIZE) {
+            let record = &chunk[pos..];
+            if !record.starts_with(RECORD_MAGIC) {
+                break;
+            }
+            let size = u32_at(record, 4).unwrap_or_default() as usize;
+            if size < RECORD_HEADER_SIZE || size > record.len() {
+                break;
+            }
+            let record_id = u64_at(record, 8).unwrap_or_default();
+            let filetime = u64_at(record, 16).unwrap_or_default();
+            let secs = (filetime / 10_000_000).saturating_sub(FILETIME_EPOCH_OFFSET);
+            let event = json!({
+                "record_id": record_id,
+                "timestamp": utc_timestamp(secs),
+                "strings": utf16_strings(&record[RECORD_HEADER_SIZE..size]).join(" "),
+            });
+            line.clear();
+            serde_json::to_writer(&mut line, &event)?;
+            line.push(b'\n');
+            f(&line)?;
+            pos += size;
+        }
+    }
+    Ok(())
+}
+
+/// The runs of at least [`MIN_STRING_CHARS`] printable ASCII characters
+/// encoded as UTF-16LE in `data`, at either byte alignment
+fn utf16_strings(data: &[u8]) -> Vec<String> {
+    let printable = |pair: &[u8]| pair[1] == 0 && (0x20..0x7f).contains(&pair[0]);
+    let mut strings = Vec::new();
+    let mut i = 0;
+    while i + 1 < data.len() {
+        let run = data[i..]
+            .chunks_exact(2)
+            .take_while(|pair| printable(pair))
+            .count();
+        if run >= MIN_STRING_CHARS {
+            let text = data[i..i + 2 * run].iter().step_by(2).map(|&b| b as char);
+            strings.push(text.collect());
+            i += 2 * run;
+        } else {
+            i += 1;
+        }
+    }
+    strings
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
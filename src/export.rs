@@ -0,0 +1,294 @@
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use clap::{ArgGroup, Args};
+use serde_json::{json, Map, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// STIX 2.1 namespace for deterministic cyber-observable identifiers
+const STIX_NAMESPACE: Uuid = uuid::uuid!("00abedb4-aa42-466c-9c01-fed23315a9b7");
+
+/// Convert the unique public IPs of the input into threat-intel and map
+/// formats
+#[derive(Args, Debug)]
+#[clap(group(ArgGroup::new("export_format").required(true).multiple(true)))]
+pub struct ExportArgs {
+    /// Write a STIX 2.1 bundle of ipv4-addr/ipv6-addr objects to FILE ("-"
+    /// for stdout). Geoip fields are in the x_geoipsed_geo custom property
+    #[clap(long, value_name = "FILE", group = "export_format")]
+    pub stix: Option<Utf8PathBuf>,
+
+    /// Write a MISP event with one ip-dst attribute per IP to FILE ("-" for
+    /// stdout). Geoip fields are summarized in the attribute comment
+    #[clap(long, value_name = "FILE", group = "export_format")]
+    pub misp: Option<Utf8PathBuf>,
+
+    /// Write a GeoJSON FeatureCollection with one point per IP to FILE ("-"
+    /// for stdout), e.g. to drop onto a map. Properties are the geoip fields
+    /// and the number of times the IP occurs
+    #[clap(long, value_name = "FILE", group = "export_format")]
+    pub geojson: Option<Utf8PathBuf>,
+
+    /// Write a KML document with one placemark per located IP to FILE ("-"
+    /// for stdout)
+    #[clap(long, value_name = "FILE", group = "export_format")]
+    pub kml: Option<Utf8PathBuf>,
+
+    /// Title of the exported MISP event
+    #[clap(long, default_value = "geoipsed export")]
+    pub info: String,
+
+    /// Input file(s) to process. Leave empty or use "-" to read from stdin
+    #[clap(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    pub input: Vec<Utf8PathBuf>,
+}
+
+/// One unique IP to export with its geoip fields
+pub struct Indicator {
+    pub ip: IpAddr,
+    pub geo: Value,
+    /// occurrences of the IP in the input
+    pub count: u64,
+}
+
+/// Open FILE for writing, or stdout for "-"
+fn create(path: &Utf8PathBuf) -> Result<Box<dyn Write>> {
+    Ok(if path.as_str() == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    })
+}
+
+/// Write every requested export format
+pub fn write_all(args: &ExportArgs, indicators: &[Indicator]) -> Result<()> {
+    if let Some(path) = &args.stix {
+        let mut out = create(path)?;
+        serde_json::to_writer_pretty(&mut out, &stix_bundle(indicators))?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    if let Some(path) = &args.misp {
+        let mut out = create(path)?;
+        serde_json::to_writer_pretty(&mut out, &misp_event(&args.info, indicators))?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    if let Some(path) = &args.geojson {
+        let mut out = create(path)?;
+        serde_json::to_writer_pretty(&mut out, &geojson(indicators))?;
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    if let Some(path) = &args.kml {
+        let mut out = create(path)?;
+        write_kml(&mut out, indicators)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Geoip fields without the redundant ip and unknown values
+fn geo_properties(geo: &Value) -> Map<String, Value> {
+    geo.as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(k, v)| *k != "ip" && !v.is_null())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+/// A geoip field as `name=value` text, strings without their quotes
+fn property_text(name: &str, value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{name}={s}"),
+        value => format!("{name}={value}"),
+    }
+}
+
+/// A STIX 2.1 bundle with one address object per indicator. Address ids are
+/// deterministic UUIDv5s of the value, per the STIX 2.1 spec, so repeated
+/// exports of the same IP deduplicate in the receiving platform
+pub fn stix_bundle(indicators: &[Indicator]) -> Value {
+    let objects: Vec<Value> = indicators
+        .iter()
+        .map(|ind| {
+            let kind = match ind.ip {
+                IpAddr::V4(_) => "ipv4-addr",
+                IpAddr::V6(_) => "ipv6-addr",
+            };
+            let value = ind.ip.to_string();
+            let contributing = json!({ "value": value }).to_string();
+            let id = Uuid::new_v5(&STIX_NAMESPACE, contributing.as_bytes());
+            json!({
+                "type": kind,
+                "spec_version": "2.1",
+                "id": format!("{kind}--{id}"),
+                "value": value,
+                "x_geoipsed_geo": geo_properties(&ind.geo),
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "bundle",
+        "id": format!("bundle--{}", Uuid::new_v4()),
+        "objects": objects,
+    })
+}
+
+/// A MISP event with one ip-dst attribute per indicator
+pub fn misp_event(info: &str, indicators: &[Indicator]) -> Value {
+    let attributes: Vec<Value> = indicators
+        .iter()
+        .map(|ind| {
+            let comment: Vec<String> = geo_properties(&ind.geo)
+                .into_iter()
+                .map(|(k, v)| property_text(&k, &v))
+                .collect();
+            json!({
+                "uuid": Uuid::new_v4().to_string(),
+                "type": "ip-dst",
+                "category": "Network activity",
+                "to_ids": false,
+                "value": ind.ip.to_string(),
+                "comment": comment.join(" "),
+            })
+        })
+        .collect();
+
+    json!({
+        "Event": {
+            "uuid": Uuid::new_v4().to_string(),
+            "info": info,
+            "date": today(),
+            "threat_level_id": "4",
+            "analysis": "0",
+            "distribution": "0",
+            "Attribute": attributes,
+        }
+    })
+}
+
+/// Longitude and latitude of the geoip fields, or None when the databases
+/// do not locate the IP
+fn location(geo: &Value) -> Option<(f64, f64)> {
+    let coord = |name: &str| geo[name].as_f64();
+    match (coord("longitude")?, coord("latitude")?) {
+        (lon, lat) if lon == 0.0 && lat == 0.0 => None,
+        located => Some(located),
+    }
+}
+
+/// A GeoJSON FeatureCollection with one point feature per indicator.
+/// Indicators without a location have a null geometry
+pub fn geojson(indicators: &[Indicator]) -> Value {
+    let features: Vec<Value> = indicators
+        .iter()
+        .map(|ind| {
+            let mut properties = geo_properties(&ind.geo);
+            properties.retain(|k, _| k != "latitude" && k != "longitude");
+            properties.insert("ip".to_string(), ind.ip.to_string().into());
+            properties.insert("count".to_string(), ind.count.into());
+            let geometry = match location(&ind.geo) {
+                Some((lon, lat)) => json!({ "type": "Point", "coordinates": [lon, lat] }),
+                None => Value::Null,
+            };
+            json!({
+                "type": "Feature",
+                "geometry": geometry,
+                "properties": properties,
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+}
+
+/// `s` with the XML special characters escaped
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write a KML document with one placemark per located indicator, named by
+/// its IP and described by its geoip fields and count
+pub fn write_kml(out: &mut impl Write, indicators: &[Indicator]) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(out, "<Document>")?;
+    for ind in indicators {
+        let Some((lon, lat)) = location(&ind.geo) else {
+            continue;
+        };
+        let mut description: Vec<String> = geo_properties(&ind.geo)
+            .into_iter()
+            .filter(|(k, _)| k != "latitude" && k != "longitude")
+            .map(|(k, v)| property_text(&k, &v))
+            .collect();
+        description.push(format!("count={}", ind.count));
+        writeln!(out, "<Placemark>")?;
+        writeln!(out, "<name>{}</name>", ind.ip)?;
+        writeln!(
+            out,
+            "<description>{}</description>",
+            xml_escape(&description.join(" "))
+        )?;
+        writeln!(out, "<Point><coordinates>{lon},{lat}</coordinates></Point>")?;
+        writeln!(out, "</Placemark>")?;
+    }
+    writeln!(out, "</Document>")?;
+    writeln!(out, "</kml>")
+}
+
+/// Today's UTC date as YYYY-MM-DD
+fn today() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    format!("{y:04}-{m:02}-{d:02}")
+}
+
+/// `secs` since the Unix epoch as a UTC timestamp, e.g.
+/// 2023-11-14T22:13:20Z
+pub(crate) fn utc_timestamp(secs: u64) -> String {
+    let (y, m, d) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{y:04}-{m:02}-{d:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) date.
+/// via http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
@@ -0,0 +1,70 @@
+use clap::ValueEnum;
+use std::io::{self, BufRead, Read, Write};
+
+/// How events are delimited on the stdin and stdout of --framed mode, as
+/// spoken by the exec and pipe plugins of log shippers like Vector and
+/// Fluent Bit
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Framing {
+    /// One JSON event per line
+    Ndjson,
+    /// Each event is preceded by its length as a 4-byte big-endian integer
+    Length,
+}
+
+impl Framing {
+    /// Read the next event from `reader`, or None at the end of the input.
+    /// Input ending inside a length-prefixed event is an error
+    pub fn read(self, reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+        let mut frame = Vec::new();
+        match self {
+            Framing::Ndjson => {
+                if reader.read_until(b'\n', &mut frame)? == 0 {
+                    return Ok(None);
+                }
+                if frame.ends_with(b"\n") {
+                    frame.pop();
+                }
+                if frame.ends_with(b"\r") {
+                    frame.pop();
+                }
+            }
+            Framing::Length => {
+                let mut prefix = [0; 4];
+                match reader.read_exact(&mut prefix) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+                let len = u32::from_be_bytes(prefix) as u64;
+                // grown as the event arrives rather than trusting the prefix
+                reader.take(len).read_to_end(&mut frame)?;
+                if frame.len() as u64 != len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("input ended {} bytes into a {len} byte event", frame.len()),
+                    ));
+                }
+            }
+        }
+        Ok(Some(frame))
+    }
+
+    /// Write `frame` as one event and flush it, so the reading plugin gets
+    /// every event as soon as it is enriched
+    pub fn write(self, out: &mut impl Write, frame: &[u8]) -> io::Result<()> {
+        match self {
+            Framing::Ndjson => {
+                out.write_all(frame)?;
+                out.write_all(b"\n")?;
+            }
+            Framing::Length => {
+                let len = u32::try_from(frame.len())
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "event too long"))?;
+                out.write_all(&len.to_be_bytes())?;
+                out.write_all(frame)?;
+            }
+        }
+        out.flush()
+    }
+}
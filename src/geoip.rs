@@ -1,10 +1,23 @@
-use camino::Utf8PathBuf;
-use field_names::FieldNames;
+use crate::countrydb::CountryTable;
+use crate::enrich::EnrichDb;
+use crate::error::Error;
+use crate::geomath::Point;
+use crate::intel::IntelDb;
+use crate::ipparse::parse_ip_bytes;
+use crate::prefixdb::PrefixTable;
+use crate::template::{closest_field, Fields, Template};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
 use maxminddb::geoip2;
 use maxminddb::Mmap;
-use microtemplate::{render, Substitutions};
-use std::net::IpAddr;
-use termcolor::ColorChoice;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "embedded-country")]
+use std::sync::OnceLock;
+use termcolor::{Color, ColorChoice};
+use tracing::{debug, info, trace};
 
 // ipv4 - copied from cyberchef.org minus the cidr mask
 // ipv6 - https://gist.github.com/dfee/6ed3a4b05cfe7a6faf40a2102408d5d8
@@ -15,148 +28,1373 @@ pub const REGEX_PATTERN: &str = r"(?x)
     )
     |
     (
-        (?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}:[^\s:](?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9]).){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:::(?:ffff(?::0{1,4}){0,1}:){0,1}[^\s:](?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9]).){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:fe80:(?::(?:(?:[0-9a-fA-F]){1,4})){0,4}%[0-9a-zA-Z]{1,})|(?::(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,7}|:))|(?:(?:(?:[0-9a-fA-F]){1,4}):(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,6}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,2}(?::(?:(?:[0-9a-fA-F]){1,4})){1,5})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,3}(?::(?:(?:[0-9a-fA-F]){1,4})){1,4})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}(?::(?:(?:[0-9a-fA-F]){1,4})){1,3})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,5}(?::(?:(?:[0-9a-fA-F]){1,4})){1,2})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,6}:(?:(?:[0-9a-fA-F]){1,4}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,7}:)|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){7,7}(?:(?:[0-9a-fA-F]){1,4}))
+        (?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}:(?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:::(?:ffff(?::0{1,4}){0,1}:){0,1}(?:(?:(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])\.){3,3}(?:25[0-5]|(?:2[0-4]|1{0,1}[0-9]){0,1}[0-9])))|(?:fe80:(?::(?:(?:[0-9a-fA-F]){1,4})){0,4}%[0-9a-zA-Z]{1,})|(?::(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,7}|:))|(?:(?:(?:[0-9a-fA-F]){1,4}):(?:(?::(?:(?:[0-9a-fA-F]){1,4})){1,6}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,2}(?::(?:(?:[0-9a-fA-F]){1,4})){1,5})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,3}(?::(?:(?:[0-9a-fA-F]){1,4})){1,4})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,4}(?::(?:(?:[0-9a-fA-F]){1,4})){1,3})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,5}(?::(?:(?:[0-9a-fA-F]){1,4})){1,2})|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,6}:(?:(?:[0-9a-fA-F]){1,4}))|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){1,7}:)|(?:(?:(?:(?:[0-9a-fA-F]){1,4}):){7,7}(?:(?:[0-9a-fA-F]){1,4}))
     )";
 
-/// A simple struct to hold IP information purely to enable
-/// templated output customizations. All fields must be str
-#[derive(Substitutions, FieldNames)]
-struct IPInfo<'a> {
-    ip: &'a str,
-    asnnum: &'a str,
-    asnorg: &'a str,
-    city: &'a str,
-    continent: &'a str,
-    country_iso: &'a str,
-    country_full: &'a str,
-    latitude: &'a str,
-    longitude: &'a str,
-    timezone: &'a str,
-}
-
-pub fn print_ip_field_names() {
+/// Alternative IPv4 notations (see [`crate::ipparse::parse_obfuscated_ipv4`]):
+/// decimal, hex, or octal 32-bit numbers and dotted quads with hex or octal
+/// parts, matched with [`crate::entity::PatternSet::obfuscated_ipv4`]. Word
+/// boundaries keep them from matching inside longer numbers
+pub const OBFUSCATED_IPV4_PATTERN: &str = r"(?x)
+    \b(?:
+        (?:0[xX][0-9a-fA-F]{1,2}|0[0-7]{1,3}|\d{1,3})
+        (?:\.(?:0[xX][0-9a-fA-F]{1,2}|0[0-7]{1,3}|\d{1,3})){3}
+        |
+        0[xX][0-9a-fA-F]{7,8}
+        |
+        0[0-7]{10,11}
+        |
+        [1-9]\d{7,9}
+    )\b";
+
+/// Names of the fields of a [`GeoRecord`], in output order
+const FIELDS: &[&str] = &[
+    "ip",
+    "asnnum",
+    "asnorg",
+    "city",
+    "continent",
+    "country_iso",
+    "country_full",
+    "latitude",
+    "longitude",
+    "timezone",
+    "subdivision_iso",
+    "subdivision_name",
+    "postal_code",
+    "accuracy_radius",
+    "metro_code",
+    "distance_km",
+    "is_anycast",
+    "is_satellite_provider",
+    "is_in_european_union",
+    "isp",
+    "organization",
+    "connection_type",
+    "intel_hit",
+    "intel_source",
+];
+
+/// How true/false fields, e.g. {is_anycast}, are rendered
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum BoolFormat {
+    /// true or false
+    #[default]
+    #[value(name = "true-false")]
+    TrueFalse,
+    /// 1 or 0
+    #[value(name = "1-0")]
+    OneZero,
+}
+
+impl BoolFormat {
+    pub fn render(self, value: bool) -> &'static str {
+        match (self, value) {
+            (BoolFormat::TrueFalse, true) => "true",
+            (BoolFormat::TrueFalse, false) => "false",
+            (BoolFormat::OneZero, true) => "1",
+            (BoolFormat::OneZero, false) => "0",
+        }
+    }
+}
+
+/// The geoip fields of one IP address. String fields borrow from the
+/// databases and are empty when unknown
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoRecord<'a> {
+    /// the address as written in the input
+    pub ip: &'a str,
+    pub asnnum: u32,
+    pub asnorg: &'a str,
+    pub city: &'a str,
+    pub continent: &'a str,
+    pub country_iso: &'a str,
+    pub country_full: &'a str,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub timezone: &'a str,
+    /// the first, largest subdivision, e.g. the state "CA" of "US"
+    pub subdivision_iso: &'a str,
+    pub subdivision_name: &'a str,
+    pub postal_code: &'a str,
+    /// radius in km around the location within which the address likely is
+    pub accuracy_radius: Option<u16>,
+    /// US Nielsen DMA code
+    pub metro_code: Option<u16>,
+    /// great-circle distance from the --ref-point, if one is given
+    pub distance_km: Option<f64>,
+    /// traits of the network, None without a City record
+    pub is_anycast: Option<bool>,
+    pub is_satellite_provider: Option<bool>,
+    pub is_in_european_union: Option<bool>,
+    /// from a GeoIP2-ISP database
+    pub isp: &'a str,
+    pub organization: &'a str,
+    /// from a GeoIP2-Connection-Type database, e.g. "Cable/DSL"
+    pub connection_type: &'a str,
+    /// how the true/false fields are rendered
+    pub bool_format: BoolFormat,
+    /// names of the intel sources listing the address, or None if no intel
+    /// is loaded
+    pub intel_sources: Option<Vec<&'a str>>,
+    /// field name and label of the --enrich-csv table, if one is loaded
+    pub enrichment: Option<(&'a str, &'a str)>,
+}
+
+impl<'a> GeoRecord<'a> {
+    /// Whether a database had a record of the address, i.e. any field
+    /// looked up in a database has a value
+    pub fn is_found(&self) -> bool {
+        self.asnnum != 0
+            || !self.asnorg.is_empty()
+            || !self.country_iso.is_empty()
+            || !self.continent.is_empty()
+            || !self.isp.is_empty()
+            || !self.connection_type.is_empty()
+            || self.latitude != 0.0
+            || self.longitude != 0.0
+    }
+
+    /// Whether a database field in `sections` is still unknown
+    fn has_gaps(&self, sections: Sections) -> bool {
+        (sections.asn && (self.asnnum == 0 || self.asnorg.is_empty()))
+            || (sections.city && (self.country_iso.is_empty() || self.city.is_empty()))
+            || (sections.isp && self.isp.is_empty())
+            || (sections.connection && self.connection_type.is_empty())
+    }
+
+    /// Take the fields that are unknown here from `other`, a record of the
+    /// same IP from another database. The location is taken as a whole so
+    /// that coordinates and their accuracy are never mixed
+    fn fill_from(&mut self, other: GeoRecord<'a>) {
+        if self.asnnum == 0 {
+            self.asnnum = other.asnnum;
+        }
+        for (field, value) in [
+            (&mut self.asnorg, other.asnorg),
+            (&mut self.city, other.city),
+            (&mut self.continent, other.continent),
+            (&mut self.country_iso, other.country_iso),
+            (&mut self.country_full, other.country_full),
+            (&mut self.timezone, other.timezone),
+            (&mut self.subdivision_iso, other.subdivision_iso),
+            (&mut self.subdivision_name, other.subdivision_name),
+            (&mut self.postal_code, other.postal_code),
+            (&mut self.isp, other.isp),
+            (&mut self.organization, other.organization),
+            (&mut self.connection_type, other.connection_type),
+        ] {
+            if field.is_empty() {
+                *field = value;
+            }
+        }
+        if self.latitude == 0.0 && self.longitude == 0.0 {
+            self.latitude = other.latitude;
+            self.longitude = other.longitude;
+            self.accuracy_radius = other.accuracy_radius;
+            self.distance_km = other.distance_km;
+        }
+        self.metro_code = self.metro_code.or(other.metro_code);
+        self.is_anycast = self.is_anycast.or(other.is_anycast);
+        self.is_satellite_provider = self.is_satellite_provider.or(other.is_satellite_provider);
+        self.is_in_european_union = self.is_in_european_union.or(other.is_in_european_union);
+    }
+}
+
+impl Fields for GeoRecord<'_> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let value: Cow<str> = match name {
+            "ip" => self.ip.into(),
+            "asnnum" => self.asnnum.to_string().into(),
+            "asnorg" => self.asnorg.into(),
+            "city" => self.city.into(),
+            "continent" => self.continent.into(),
+            "country_iso" => self.country_iso.into(),
+            "country_full" => self.country_full.into(),
+            "latitude" => self.latitude.to_string().into(),
+            "longitude" => self.longitude.to_string().into(),
+            "timezone" => self.timezone.into(),
+            "subdivision_iso" => self.subdivision_iso.into(),
+            "subdivision_name" => self.subdivision_name.into(),
+            "postal_code" => self.postal_code.into(),
+            "accuracy_radius" => self
+                .accuracy_radius
+                .map(|r| r.to_string())
+                .unwrap_or_default()
+                .into(),
+            "metro_code" => self
+                .metro_code
+                .map(|c| c.to_string())
+                .unwrap_or_default()
+                .into(),
+            "distance_km" => self
+                .distance_km
+                .map(|d| format!("{d:.1}"))
+                .unwrap_or_default()
+                .into(),
+            "is_anycast" => self.render_bool(self.is_anycast).into(),
+            "is_satellite_provider" => self.render_bool(self.is_satellite_provider).into(),
+            "is_in_european_union" => self.render_bool(self.is_in_european_union).into(),
+            "isp" => self.isp.into(),
+            "organization" => self.organization.into(),
+            "connection_type" => self.connection_type.into(),
+            // intel fields are empty unless intel lists are loaded
+            "intel_hit" => self
+                .render_bool(self.intel_sources.as_ref().map(|s| !s.is_empty()))
+                .into(),
+            "intel_source" => match &self.intel_sources {
+                Some(sources) => sources.join(",").into(),
+                None => "".into(),
+            },
+            _ => match self.enrichment {
+                Some((field, label)) if field == name => label.into(),
+                _ => return None,
+            },
+        };
+        Some(value)
+    }
+
+    fn write_field_to(&self, name: &str, out: &mut dyn io::Write) -> io::Result<()> {
+        match name {
+            "asnnum" => write!(out, "{}", self.asnnum),
+            "latitude" => write!(out, "{}", self.latitude),
+            "longitude" => write!(out, "{}", self.longitude),
+            _ => match self.field(name) {
+                Some(value) => out.write_all(value.as_bytes()),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+impl GeoRecord<'_> {
+    /// `value` in the [`BoolFormat`] of the record, empty if unknown
+    fn render_bool(&self, value: Option<bool>) -> &'static str {
+        value.map_or("", |v| self.bool_format.render(v))
+    }
+
+    /// The record as a JSON object keyed by field name. Numbers and
+    /// true/false fields are typed, and unknown values, including an ASN of
+    /// 0 and a location of 0,0, are null. Intel and enrichment fields are
+    /// left out when empty
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+        let located = self.latitude != 0.0 || self.longitude != 0.0;
+        let mut obj = serde_json::Map::new();
+        for &name in FIELDS {
+            let value: Value = match name {
+                "asnnum" => (self.asnnum != 0).then_some(self.asnnum).into(),
+                "latitude" => located.then_some(self.latitude).into(),
+                "longitude" => located.then_some(self.longitude).into(),
+                "accuracy_radius" => self.accuracy_radius.into(),
+                "metro_code" => self.metro_code.into(),
+                // rounded as {distance_km} renders it
+                "distance_km" => self.distance_km.map(|d| (d * 10.0).round() / 10.0).into(),
+                "is_anycast" => self.is_anycast.into(),
+                "is_satellite_provider" => self.is_satellite_provider.into(),
+                "is_in_european_union" => self.is_in_european_union.into(),
+                "intel_hit" => match &self.intel_sources {
+                    Some(sources) => (!sources.is_empty()).into(),
+                    None => continue,
+                },
+                "intel_source" => match &self.intel_sources {
+                    Some(sources) if !sources.is_empty() => sources.join(",").into(),
+                    _ => continue,
+                },
+                name => match self.field(name).unwrap_or_default() {
+                    value if value.is_empty() => Value::Null,
+                    value => value.into_owned().into(),
+                },
+            };
+            obj.insert(name.to_string(), value);
+        }
+        if let Some((field, label)) = self.enrichment.filter(|(_, label)| !label.is_empty()) {
+            obj.insert(field.to_string(), label.into());
+        }
+        Value::Object(obj)
+    }
+}
+
+/// Whether an IPv4 address is globally routable, i.e. not private, shared,
+/// loopback, link-local, documentation, benchmarking, multicast, or reserved
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || a == 0
+        || a >= 240
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b)))
+}
+
+/// Whether an IPv6 address is globally routable, i.e. not loopback,
+/// unique-local, link-local, documentation, multicast, or an IPv4-mapped
+/// address of a non-public IPv4 address
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(&v4);
+    }
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
+
+/// Whether an IP address is globally routable
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// Names of the geoip fields available to templates and structured outputs
+pub fn ip_field_names() -> &'static [&'static str] {
+    FIELDS
+}
+
+/// A field available to templates, with what it holds and an example value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldInfo<'a> {
+    pub name: &'a str,
+    pub description: &'a str,
+    pub example: &'a str,
+}
+
+impl FieldInfo<'_> {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            "description": self.description,
+            "example": self.example,
+        })
+    }
+}
+
+/// The geoip fields, followed by the `enrich_field` of an --enrich-csv
+/// table if one is given
+pub fn available_fields(enrich_field: Option<&str>) -> Vec<FieldInfo<'_>> {
+    let geoip = FIELDS.iter().map(|&name| {
+        let (description, example) = match name {
+            "ip" => ("the address as written in the input", "81.2.69.205"),
+            "asnnum" => ("autonomous system number, 0 if unknown", "15169"),
+            "asnorg" => ("autonomous system organization", "GOOGLE"),
+            "city" => ("city name", "London"),
+            "continent" => ("continent code", "EU"),
+            "country_iso" => ("ISO 3166 country code", "GB"),
+            "country_full" => ("country name", "United Kingdom"),
+            "latitude" => ("approximate latitude", "51.5142"),
+            "longitude" => ("approximate longitude", "-0.0931"),
+            "timezone" => ("IANA time zone", "Europe/London"),
+            "subdivision_iso" => ("ISO 3166-2 code of the region, e.g. state", "ENG"),
+            "subdivision_name" => ("name of the region, e.g. state", "England"),
+            "postal_code" => ("postal code", "EC2V"),
+            "accuracy_radius" => ("km around the location the address is likely in", "100"),
+            "metro_code" => ("US metro code (Nielsen DMA)", "807"),
+            "distance_km" => ("great-circle km from the --ref-point", "342.9"),
+            "is_anycast" => ("whether the network is anycast", "false"),
+            "is_satellite_provider" => ("whether the network is a satellite provider", "false"),
+            "is_in_european_union" => ("whether the country is in the EU", "false"),
+            "isp" => ("internet service provider (GeoIP2-ISP)", "Comcast Cable"),
+            "organization" => (
+                "organization of the network (GeoIP2-ISP)",
+                "Comcast Business",
+            ),
+            "connection_type" => ("connection type (GeoIP2-Connection-Type)", "Cable/DSL"),
+            "intel_hit" => ("whether an --intel list has the address", "true"),
+            "intel_source" => ("--intel lists that have the address", "blocklist.txt"),
+            _ => unreachable!("every field is described"),
+        };
+        FieldInfo {
+            name,
+            description,
+            example,
+        }
+    });
+    let enrich = enrich_field.map(|name| FieldInfo {
+        name,
+        description: "label of the --enrich-csv network containing the address",
+        example: "corp-vpn",
+    });
+    geoip.chain(enrich).collect()
+}
+
+/// Print the fields of [`available_fields`] as a table, or as a JSON array
+/// of objects with `json`
+pub fn print_ip_field_names(enrich_field: Option<&str>, json: bool) {
+    let fields = available_fields(enrich_field);
+    if json {
+        let fields: Vec<_> = fields.iter().map(FieldInfo::to_json).collect();
+        println!("{}", serde_json::Value::Array(fields));
+        return;
+    }
     println!("Available template geoip field names are:");
-    for f in IPInfo::FIELDS {
-        println!("{{{f}}}");
+    for f in fields {
+        let name = format!("{{{}}}", f.name);
+        println!("{name:<16} {} (e.g. {})", f.description, f.example);
     }
 }
 
-pub struct GeoIPSed {
-    asnreader: maxminddb::Reader<Mmap>,
-    cityreader: maxminddb::Reader<Mmap>,
-    pub color: ColorChoice,
-    pub template: String,
+/// Environment variables naming the database directory, in order of
+/// precedence
+pub const DB_DIR_ENV: &[&str] = &["GEOIP_MMDB_DIR", "MAXMIND_MMDB_DIR"];
+
+/// Directories searched for databases when none is named, in order. The
+/// second is where geoipupdate built from source puts them
+pub const DEFAULT_DB_DIRS: &[&str] = &["/usr/share/GeoIP", "/usr/local/share/GeoIP"];
+
+/// The database directory: `dir` if given, else the first of [`DB_DIR_ENV`]
+/// that `env` has set, else the first of [`DEFAULT_DB_DIRS`] that exists.
+/// `env` looks up a variable, e.g. `|name| std::env::var(name).ok()`
+///
+/// ```
+/// use geoipsed::geoip::resolve_db_dir;
+///
+/// let env = |name: &str| (name == "MAXMIND_MMDB_DIR").then(|| "/srv/mmdb".to_string());
+/// assert_eq!(resolve_db_dir(None, env), "/srv/mmdb");
+/// assert_eq!(resolve_db_dir(Some("/tmp".into()), env), "/tmp");
+/// ```
+pub fn resolve_db_dir(dir: Option<&Utf8Path>, env: impl Fn(&str) -> Option<String>) -> Utf8PathBuf {
+    resolve_db_dir_origin(dir, env).0
 }
 
-impl Default for GeoIPSed {
+/// [`resolve_db_dir`], with how the directory was chosen for error messages
+fn resolve_db_dir_origin(
+    dir: Option<&Utf8Path>,
+    env: impl Fn(&str) -> Option<String>,
+) -> (Utf8PathBuf, String) {
+    if let Some(dir) = dir {
+        return (dir.to_owned(), "the directory given".to_string());
+    }
+    if let Some((name, dir)) = DB_DIR_ENV.iter().find_map(|name| {
+        env(name)
+            .filter(|dir| !dir.is_empty())
+            .map(|dir| (name, dir))
+    }) {
+        debug!(env = name, %dir, "database directory from environment");
+        return (dir.into(), format!("${name}"));
+    }
+    let origin = format!(
+        "the first existing of {}, as neither ${} is set",
+        DEFAULT_DB_DIRS.join(" and "),
+        DB_DIR_ENV.join(" nor $")
+    );
+    let dir = DEFAULT_DB_DIRS
+        .iter()
+        .find(|dir| Utf8Path::new(dir).is_dir())
+        .unwrap_or(&DEFAULT_DB_DIRS[0]);
+    (Utf8PathBuf::from(dir), origin)
+}
+
+/// Open the MaxMind database at `path`
+fn open_mmdb(path: Utf8PathBuf) -> Result<maxminddb::Reader<Mmap>, Error> {
+    if !path.exists() {
+        return Err(Error::DatabaseNotFound(path));
+    }
+    maxminddb::Reader::open_mmap(&path).map_err(|source| Error::DatabaseOpen { path, source })
+}
+
+/// A database, possibly split into a file of IPv4 networks and one of IPv6
+/// networks. IPv6 databases map the IPv4 space too, so without an IPv4 file
+/// the IPv6 one serves both
+struct Mmdb {
+    v4: Option<maxminddb::Reader<Mmap>>,
+    v6: Option<maxminddb::Reader<Mmap>>,
+}
+
+impl Mmdb {
+    /// Open the files of a database, or None if it has neither
+    fn open(v6: Option<Utf8PathBuf>, v4: Option<Utf8PathBuf>) -> Result<Option<Self>, Error> {
+        let (v6, v4) = (
+            v6.map(open_mmdb).transpose()?,
+            v4.map(open_mmdb).transpose()?,
+        );
+        Ok((v6.is_some() || v4.is_some()).then_some(Self { v4, v6 }))
+    }
+
+    /// The file to look `ip` up in
+    fn reader(&self, ip: IpAddr) -> Option<&maxminddb::Reader<Mmap>> {
+        match ip {
+            IpAddr::V4(_) => self.v4.as_ref().or(self.v6.as_ref()),
+            IpAddr::V6(_) => self.v6.as_ref(),
+        }
+    }
+
+    fn readers(&self) -> impl Iterator<Item = &maxminddb::Reader<Mmap>> {
+        self.v4.iter().chain(&self.v6)
+    }
+}
+
+/// Where autonomous system numbers and organizations come from
+enum AsnSource {
+    /// GeoLite2-ASN.mmdb
+    Mmdb(Box<Mmdb>),
+    /// prefix to ASN table, which has no organization names
+    Pfx2as(PrefixTable),
+}
+
+/// ASN and City databases found in a directory. A database may be split
+/// into a file of IPv4 networks, used for IPv4 addresses, and one of IPv6
+/// networks
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Databases {
+    pub asn: Option<Utf8PathBuf>,
+    pub city: Option<Utf8PathBuf>,
+    pub asn_v4: Option<Utf8PathBuf>,
+    pub city_v4: Option<Utf8PathBuf>,
+    /// the paid GeoIP2-ISP and GeoIP2-Connection-Type databases, if any
+    pub isp: Option<Utf8PathBuf>,
+    pub connection_type: Option<Utf8PathBuf>,
+}
+
+/// Find the ASN and City databases in `dir`. GeoLite2-ASN.mmdb and
+/// GeoLite2-City.mmdb are used when present, otherwise each .mmdb file is
+/// classified by the database_type of its metadata, so that GeoIP2 and
+/// GeoIP2-compatible databases (e.g. DBIP-City-Lite, DBIP-ASN-Lite) work
+/// under any file name. A Country database stands in for a missing City one.
+/// Files with an IPv4 metadata ip_version or "ipv4" in their name are the
+/// IPv4 halves of split databases. GeoIP2-ISP and GeoIP2-Connection-Type
+/// databases are picked up alongside them
+pub fn autodetect(dir: &Utf8Path) -> Databases {
+    let named = |name: &str| Some(dir.join(name)).filter(|p| p.is_file());
+    let mut found = Databases {
+        asn: named("GeoLite2-ASN.mmdb"),
+        city: named("GeoLite2-City.mmdb"),
+        isp: named("GeoIP2-ISP.mmdb"),
+        connection_type: named("GeoIP2-Connection-Type.mmdb"),
+        ..Databases::default()
+    };
+    if found.asn.is_some() && found.city.is_some() {
+        debug!(%dir, ?found, "found GeoLite2 databases by name");
+        return found;
+    }
+
+    let mut paths: Vec<Utf8PathBuf> = dir
+        .read_dir_utf8()
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.into_path())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("mmdb"))
+        })
+        .collect();
+    paths.sort();
+
+    let (mut country, mut country_v4) = (None, None);
+    for path in paths {
+        let reader = match maxminddb::Reader::open_mmap(&path) {
+            Ok(reader) => reader,
+            Err(e) => {
+                debug!(%path, error = %e, "skipping unreadable .mmdb file");
+                continue;
+            }
+        };
+        let kind = &reader.metadata.database_type;
+        let v4 = reader.metadata.ip_version == 4
+            || path
+                .file_name()
+                .is_some_and(|name| name.to_ascii_lowercase().contains("ipv4"));
+        debug!(%path, database_type = kind, v4, "classifying database");
+        let slot = match (kind, v4) {
+            // checked first, as "GeoIP2-ISP" databases also carry ASNs
+            (kind, _) if kind.contains("Connection-Type") => &mut found.connection_type,
+            (kind, _) if kind.contains("ISP") => &mut found.isp,
+            (kind, false) if kind.contains("ASN") => &mut found.asn,
+            (kind, true) if kind.contains("ASN") => &mut found.asn_v4,
+            (kind, false) if kind.contains("City") => &mut found.city,
+            (kind, true) if kind.contains("City") => &mut found.city_v4,
+            (kind, false) if kind.contains("Country") => &mut country,
+            (kind, true) if kind.contains("Country") => &mut country_v4,
+            _ => continue,
+        };
+        slot.get_or_insert(path);
+    }
+    if found.city.is_none() && found.city_v4.is_none() {
+        (found.city, found.city_v4) = (country, country_v4);
+    }
+    debug!(%dir, ?found, "autodetected databases");
+    found
+}
+
+/// Which lookups a rendering needs, so that unused databases can be skipped
+#[derive(Copy, Clone, Debug)]
+struct Sections {
+    asn: bool,
+    city: bool,
+    isp: bool,
+    connection: bool,
+    intel: bool,
+    enrich: bool,
+}
+
+impl Sections {
+    /// Every lookup, for records that are output in full
+    const ALL: Sections = Sections {
+        asn: true,
+        city: true,
+        isp: true,
+        connection: true,
+        intel: true,
+        enrich: true,
+    };
+
+    /// The lookups needed by the fields `template` references. Fields that
+    /// are not geoip fields may be the --enrich-csv field
+    fn of(template: &Template) -> Self {
+        let mut sections = Sections {
+            asn: false,
+            city: false,
+            isp: false,
+            connection: false,
+            intel: false,
+            enrich: false,
+        };
+        for name in template.field_names() {
+            match name {
+                "ip" => {}
+                "asnnum" | "asnorg" => sections.asn = true,
+                "isp" | "organization" => sections.isp = true,
+                "connection_type" => sections.connection = true,
+                "intel_hit" | "intel_source" => sections.intel = true,
+                name if FIELDS.contains(&name) => sections.city = true,
+                _ => sections.enrich = true,
+            }
+        }
+        sections
+    }
+}
+
+/// `template` bookended with ansi red escapes if we are printing color
+pub fn highlight(template: &str, color: ColorChoice) -> String {
+    highlight_with(template, color, Color::Red)
+}
+
+/// `template` bookended with ansi escapes of bold `fg` if we are printing
+/// color
+pub fn highlight_with(template: &str, color: ColorChoice, fg: Color) -> String {
+    match color {
+        ColorChoice::Always => format!("{}{template}{ESCAPE_END}", escape_start(fg)),
+        _ => template.to_string(),
+    }
+}
+
+/// The ansi escape starting bold `fg` text
+fn escape_start(fg: Color) -> String {
+    let code = match fg {
+        Color::Black => "30".to_string(),
+        Color::Green => "32".to_string(),
+        Color::Yellow => "33".to_string(),
+        Color::Blue => "34".to_string(),
+        Color::Magenta => "35".to_string(),
+        Color::Cyan => "36".to_string(),
+        Color::White => "37".to_string(),
+        Color::Ansi256(n) => format!("38;5;{n}"),
+        Color::Rgb(r, g, b) => format!("38;2;{r};{g};{b}"),
+        _ => "31".to_string(),
+    };
+    format!("\x1b[1;{code}m")
+}
+
+/// The ansi escape ending highlighted text
+const ESCAPE_END: &str = "\x1b[0;0m";
+
+/// Which part of the IP decorations is colored
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum ColorScope {
+    /// The whole decoration
+    #[default]
+    Decoration,
+    /// Only the {ip} field, leaving the geoip fields plain
+    Ip,
+}
+
+/// Colors of the decorations of IPv4 and IPv6 addresses, which differ so
+/// that the two stand apart in the logs of dual-stack hosts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IpColors {
+    pub v4: Color,
+    pub v6: Color,
+    pub scope: ColorScope,
+}
+
+impl IpColors {
+    /// `template` highlighted in `fg` per the scope, if we are printing
+    /// color
+    fn paint(&self, template: &str, color: ColorChoice, fg: Color) -> Template {
+        match (self.scope, color) {
+            (ColorScope::Ip, ColorChoice::Always) => {
+                Template::new(template).with_style("ip", &escape_start(fg), ESCAPE_END)
+            }
+            _ => Template::new(&highlight_with(template, color, fg)),
+        }
+    }
+}
+
+impl Default for IpColors {
     fn default() -> Self {
         Self {
-            asnreader: maxminddb::Reader::open_mmap("/usr/share/GeoIP/GeoLite2-ASN.mmdb")
-                .expect("Could not read GeoLite2-ASN.mmdb"),
-            cityreader: maxminddb::Reader::open_mmap("/usr/share/GeoIP/GeoLite2-City.mmdb")
-                .expect("Could not read GeoLite2-City.mmdb"),
-            color: ColorChoice::Auto,
-            template: "<{ip}|AS{asnnum}_{asnorg}|{country_iso}|{city}>".to_string(),
+            v4: Color::Red,
+            v6: Color::Cyan,
+            scope: ColorScope::Decoration,
+        }
+    }
+}
+
+/// The classes of data a [`GeoIPSed`] can serve, by the databases it has
+/// open
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// AS numbers, from an ASN database or a prefix table
+    pub asn: bool,
+    /// AS organization names, which prefix tables do not have
+    pub asn_org: bool,
+    /// locations, from a City database
+    pub city: bool,
+    /// country codes alone, from the table of the embedded-country feature
+    pub country: bool,
+    /// ISP and organization names, from a GeoIP2-ISP database
+    pub isp: bool,
+    /// connection types, from a GeoIP2-Connection-Type database
+    pub connection_type: bool,
+}
+
+impl Capabilities {
+    /// Whether the template field `name` can have a value. Fields that are
+    /// not looked up in a database, e.g. {ip}, always can
+    pub fn provides(&self, name: &str) -> bool {
+        match name {
+            "asnnum" => self.asn,
+            "asnorg" => self.asn_org,
+            "isp" | "organization" => self.isp,
+            "connection_type" => self.connection_type,
+            "country_iso" => self.city || self.country,
+            "ip" | "intel_hit" | "intel_source" => true,
+            name => !FIELDS.contains(&name) || self.city,
+        }
+    }
+}
+
+/// The country table embedded by the embedded-country feature, or None if
+/// it was built without one
+#[cfg(feature = "embedded-country")]
+fn embedded_countries() -> Option<&'static CountryTable> {
+    static TABLE: OnceLock<Option<CountryTable>> = OnceLock::new();
+    TABLE
+        .get_or_init(|| {
+            CountryTable::from_bytes(include_bytes!(concat!(env!("OUT_DIR"), "/country.bin")))
+                .filter(|table| !table.is_empty())
+        })
+        .as_ref()
+}
+
+#[cfg(not(feature = "embedded-country"))]
+fn embedded_countries() -> Option<&'static CountryTable> {
+    None
+}
+
+/// Decoration used when no --template is given
+pub const DEFAULT_TEMPLATE: &str = "<{ip}|AS{asnnum}_{asnorg}|{country_iso}|{city}>";
+
+/// How spaces in decorations, e.g. of "Google LLC", are written so that
+/// the decoration stays one whitespace-delimited token or not
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum SpacePolicy {
+    /// Replace every space of the decoration with an underscore
+    #[default]
+    Underscore,
+    /// Leave spaces as they are
+    Keep,
+    /// Put double quotes around field values that contain spaces
+    Quote,
+}
+
+impl SpacePolicy {
+    /// Write `template` rendered with `fields` to `out`, its spaces written
+    /// as this policy says
+    pub fn write(
+        self,
+        template: &Template,
+        fields: &impl Fields,
+        out: &mut impl io::Write,
+    ) -> io::Result<()> {
+        match self {
+            SpacePolicy::Quote => template.write(&Quoted(fields), out),
+            SpacePolicy::Keep => template.write(fields, out),
+            SpacePolicy::Underscore => template.write(fields, &mut Underscored(out)),
         }
     }
+
+    /// `template` rendered with `fields`, its spaces written as this policy
+    /// says
+    pub fn render(self, template: &Template, fields: &impl Fields) -> String {
+        let mut out = Vec::new();
+        self.write(template, fields, &mut out)
+            .expect("writing to a Vec cannot fail");
+        String::from_utf8(out).expect("templates render valid utf-8")
+    }
+}
+
+/// Fields whose values containing spaces are double quoted
+struct Quoted<'a, F>(&'a F);
+
+/// Writer that replaces the spaces of what is written with underscores
+struct Underscored<W>(W);
+
+impl<W: io::Write> io::Write for Underscored<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut words = buf.split(|&b| b == b' ');
+        if let Some(first) = words.next() {
+            self.0.write_all(first)?;
+        }
+        for word in words {
+            self.0.write_all(b"_")?;
+            self.0.write_all(word)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<F: Fields> Fields for Quoted<'_, F> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        let value = self.0.field(name)?;
+        if !value.contains(' ') {
+            return Some(value);
+        }
+        Some(format!("\"{}\"", value.replace('"', "\\\"")).into())
+    }
+}
+
+/// Curated templates for common needs. Spaces in decorations become
+/// underscores by default, so presets separate fields with punctuation
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum TemplatePreset {
+    /// One field per column, e.g. for grep '|US|' or cut -d'|'
+    Grepable,
+    /// Quoted key-value pairs
+    JsonIsh,
+    /// Space-separated key=value pairs, as in logfmt or Splunk
+    Kv,
+    /// The country only
+    Minimal,
+    /// Every geoip field
+    Full,
+}
+
+impl TemplatePreset {
+    pub const ALL: [TemplatePreset; 5] = [
+        TemplatePreset::Grepable,
+        TemplatePreset::JsonIsh,
+        TemplatePreset::Kv,
+        TemplatePreset::Minimal,
+        TemplatePreset::Full,
+    ];
+
+    /// Name of the preset, as given to --template-preset
+    pub fn name(self) -> &'static str {
+        match self {
+            TemplatePreset::Grepable => "grepable",
+            TemplatePreset::JsonIsh => "json-ish",
+            TemplatePreset::Kv => "kv",
+            TemplatePreset::Minimal => "minimal",
+            TemplatePreset::Full => "full",
+        }
+    }
+
+    pub fn template(self) -> &'static str {
+        match self {
+            TemplatePreset::Grepable => "<{ip}|AS{asnnum}|{asnorg}|{country_iso}|{city}>",
+            TemplatePreset::JsonIsh => {
+                r#"{ip}["asn":{asnnum},"org":"{asnorg}","cc":"{country_iso}","city":"{city}"]"#
+            }
+            TemplatePreset::Kv => "ip={ip} asn={asnnum} cc={country_iso}",
+            TemplatePreset::Minimal => "<{ip}|{country_iso}>",
+            TemplatePreset::Full => {
+                "<{ip}|AS{asnnum}_{asnorg}|{continent}|{country_iso}|{country_full}|{city}\
+                 |{latitude},{longitude}|{timezone}>"
+            }
+        }
+    }
+}
+
+/// The geoip databases and lookup settings. Set the public fields before
+/// sharing it: lookups only read it, and it is `Send + Sync`, so one
+/// instance can serve many threads behind an `Arc`
+pub struct GeoIPSed {
+    // either database may be missing, but not both
+    asnsource: Option<AsnSource>,
+    cityreader: Option<Mmdb>,
+    // optional paid databases
+    ispreader: Option<Mmdb>,
+    connreader: Option<Mmdb>,
+    // country codes of the embedded-country feature, the last resort
+    countries: Option<&'static CountryTable>,
+    pub color: ColorChoice,
+    template: Template,
+    /// the template highlighted in the IPv6 color
+    template_v6: Template,
+    // lookups needed to render the template
+    sections: Sections,
+    pub intel: Option<IntelDb>,
+    pub enrich: Option<EnrichDb>,
+    /// databases, in order, that fill in the fields these databases leave
+    /// empty for an IP, e.g. a commercial City database backed by GeoLite2
+    pub fallbacks: Vec<GeoIPSed>,
+    /// language of city and country names, e.g. "de", falling back to "en"
+    pub lang: String,
+    /// how spaces in decorations are written
+    pub space_policy: SpacePolicy,
+    /// how true/false fields are rendered
+    pub bool_format: BoolFormat,
+    /// point that {distance_km} is measured from
+    pub ref_point: Option<Point>,
+    /// give JSON output records their Elastic Common Schema fields
+    pub ecs: bool,
 }
 
 impl GeoIPSed {
+    /// Open the databases in `mmdbpath`, or the directory found by
+    /// [`resolve_db_dir`], found with [`autodetect`]. If a `pfx2as` prefix
+    /// table is given it supplies the ASN fields instead of an ASN
+    /// database. Either database may be missing, leaving its fields empty,
+    /// but not both
     pub fn new(
         mmdbpath: Option<Utf8PathBuf>,
+        pfx2as: Option<Utf8PathBuf>,
         user_template: Option<String>,
         color: ColorChoice,
-    ) -> Self {
-        let dbpath = mmdbpath.unwrap_or_else(|| Utf8PathBuf::from("/usr/share/GeoIP"));
-        let mut template = user_template
-            .unwrap_or_else(|| "<{ip}|AS{asnnum}_{asnorg}|{country_iso}|{city}>".to_string());
+        ip_colors: IpColors,
+    ) -> Result<Self, Error> {
+        let (dbpath, origin) =
+            resolve_db_dir_origin(mmdbpath.as_deref(), |name| std::env::var(name).ok());
+        let template = user_template.unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+        Template::compile(&template).map_err(Error::TemplateSyntax)?;
+        let template_v6 = ip_colors.paint(&template, color, ip_colors.v6);
+        let template = ip_colors.paint(&template, color, ip_colors.v4);
 
-        if color == ColorChoice::Always {
-            // if we are printing color, bookend the template with ansi red escapes
-            template = format!("\x1b[1;31m{}\x1b[0;0m", template);
+        let databases = autodetect(&dbpath);
+        let asnsource = match pfx2as {
+            Some(path) => Some(AsnSource::Pfx2as(PrefixTable::open(&path).map_err(
+                |e| Error::PrefixTable {
+                    path,
+                    source: e.into(),
+                },
+            )?)),
+            None => Mmdb::open(databases.asn, databases.asn_v4)?
+                .map(|asndb| AsnSource::Mmdb(Box::new(asndb))),
+        };
+        let cityreader = Mmdb::open(databases.city, databases.city_v4)?;
+        let ispreader = Mmdb::open(databases.isp, None)?;
+        let connreader = Mmdb::open(databases.connection_type, None)?;
+        let countries = embedded_countries();
+        if asnsource.is_none() && cityreader.is_none() && countries.is_none() {
+            return Err(Error::NoDatabases {
+                dir: dbpath,
+                origin,
+            });
         }
 
-        Self {
-            asnreader: maxminddb::Reader::open_mmap(dbpath.join("GeoLite2-ASN.mmdb"))
-                .expect("Could not read GeoLite2-ASN.mmdb"),
-            cityreader: maxminddb::Reader::open_mmap(dbpath.join("GeoLite2-City.mmdb"))
-                .expect("Could not read GeoLite2-City.mmdb"),
+        let geoipdb = Self {
+            asnsource,
+            cityreader,
+            ispreader,
+            connreader,
+            countries,
             color,
+            sections: Sections::of(&template),
             template,
+            template_v6,
+            intel: None,
+            enrich: None,
+            fallbacks: Vec::new(),
+            lang: "en".to_string(),
+            space_policy: SpacePolicy::Underscore,
+            bool_format: BoolFormat::TrueFalse,
+            ref_point: None,
+            ecs: false,
+        };
+        for (database_type, build_epoch) in geoipdb.database_builds() {
+            info!(database_type, build_epoch, "opened database");
+        }
+        Ok(geoipdb)
+    }
+
+    /// What the opened databases, and those of the fallbacks, can serve
+    pub fn capabilities(&self) -> Capabilities {
+        let own = Capabilities {
+            asn: self.asnsource.is_some(),
+            asn_org: matches!(self.asnsource, Some(AsnSource::Mmdb(_))),
+            city: self.cityreader.is_some(),
+            country: self.countries.is_some(),
+            isp: self.ispreader.is_some(),
+            connection_type: self.connreader.is_some(),
+        };
+        self.fallbacks.iter().fold(own, |acc, fallback| {
+            let other = fallback.capabilities();
+            Capabilities {
+                asn: acc.asn || other.asn,
+                asn_org: acc.asn_org || other.asn_org,
+                city: acc.city || other.city,
+                country: acc.country || other.country,
+                isp: acc.isp || other.isp,
+                connection_type: acc.connection_type || other.connection_type,
+            }
+        })
+    }
+
+    /// Fields of the template that are always empty because the database
+    /// providing them is missing
+    pub fn unavailable_fields(&self) -> Vec<&str> {
+        let capabilities = self.capabilities();
+        let mut fields: Vec<&str> = Vec::new();
+        for name in self.template.field_names() {
+            if !capabilities.provides(name) && !fields.contains(&name) {
+                fields.push(name);
+            }
+        }
+        fields
+    }
+
+    /// Check that every field of the template is a geoip field or the
+    /// field of the enrichment table, which must be loaded first
+    pub fn check_template(&self) -> Result<(), Error> {
+        let enrich_field = self.enrich.as_ref().map(EnrichDb::field);
+        match self
+            .template
+            .field_names()
+            .find(|name| !FIELDS.contains(name) && Some(*name) != enrich_field)
+        {
+            Some(field) => Err(Error::InvalidTemplate {
+                field: field.to_string(),
+                suggestion: closest_field(field, FIELDS.iter().copied().chain(enrich_field))
+                    .map(str::to_string),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// The name in [`GeoIPSed::lang`], else in English
+    fn localized<'a>(&self, names: &BTreeMap<&'a str, &'a str>) -> &'a str {
+        names
+            .get(self.lang.as_str())
+            .or_else(|| names.get("en"))
+            .copied()
+            .unwrap_or("")
+    }
+
+    /// Database type and build time (seconds since the unix epoch) of each
+    /// open database
+    pub fn database_builds(&self) -> Vec<(&str, u64)> {
+        let asnreader = match &self.asnsource {
+            Some(AsnSource::Mmdb(asnreader)) => Some(asnreader.as_ref()),
+            _ => None,
+        };
+        asnreader
+            .into_iter()
+            .chain(&self.cityreader)
+            .chain(&self.ispreader)
+            .chain(&self.connreader)
+            .flat_map(Mmdb::readers)
+            .map(|r| (r.metadata.database_type.as_str(), r.metadata.build_epoch))
+            .collect()
+    }
+
+    /// The compiled decoration template
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+
+    /// Parse `s` as an IP address. Obfuscated IPv4 notations are looked up
+    /// by the dotted quads of [`crate::entity::Entity::canonical_text`]
+    pub fn parse_ip(&self, s: &str) -> Option<IpAddr> {
+        parse_ip_bytes(s.as_bytes())
+    }
+
+    /// Whether `s` is an IP listed by a loaded intel source. Always true
+    /// when no intel is loaded so that filtering on hits is a no-op
+    pub fn is_intel_hit(&self, s: &str) -> bool {
+        match (&self.intel, self.parse_ip(s)) {
+            (Some(intel), Some(ip)) => intel.is_hit(ip),
+            (Some(_), None) => false,
+            (None, _) => true,
         }
     }
 
     #[inline]
     pub fn lookup(&self, s: &str) -> String {
-        let ip: IpAddr = match s.parse() {
-            Ok(ip) => ip,
-            // if not an ip, just return and be done
-            Err(_) => return s.to_string(),
-        };
-
-        // if match ip {
-        //     IpAddr::V4(ip) => {
-        //         ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_broadcast()
-        //     }
-        //     IpAddr::V6(ip) => ip.is_loopback(),
-        // } {
-        //     return format!("{}|||", s);
-        // }
-
-        let mut asnnum: u32 = 0;
-        let mut asnorg: &str = "";
-        let mut city: &str = "";
-        let mut continent: &str = "";
-        let mut country_iso: &str = "";
-        let mut country_full: &str = "";
-        let mut latitude: f64 = 0.0;
-        let mut longitude: f64 = 0.0;
-        let mut timezone: &str = "";
-
-        if let Ok(asnrecord) = self.asnreader.lookup::<geoip2::Asn>(ip) {
-            asnnum = asnrecord.autonomous_system_number.unwrap_or(0);
-            asnorg = asnrecord.autonomous_system_organization.unwrap_or("");
-        };
-
-        if let Ok(cityrecord) = self.cityreader.lookup::<geoip2::City>(ip) {
+        self.lookup_found(s).0
+    }
+
+    /// Like [`lookup`](Self::lookup), with whether a database has a record
+    /// of the IP. Addresses without one, e.g. unallocated or non-routable
+    /// ones, are decorated with empty fields
+    pub fn lookup_found(&self, s: &str) -> (String, bool) {
+        let mut decoration = Vec::with_capacity(s.len() + 64);
+        let found = self
+            .lookup_write(s, &mut decoration)
+            .expect("writing to a Vec cannot fail");
+        let decoration = String::from_utf8(decoration).expect("templates render valid utf-8");
+        (decoration, found)
+    }
+
+    /// Write the decoration of [`lookup`](Self::lookup) straight to `out`,
+    /// without allocating for it, and return whether a database has a
+    /// record of the IP
+    pub fn lookup_write(&self, s: &str, out: &mut impl io::Write) -> io::Result<bool> {
+        // apply template to render enrichment per user-specification. if not
+        // an ip, just return and be done
+        let Some(record) = self.lookup_sections(s, self.sections) else {
+            out.write_all(s.as_bytes())?;
+            return Ok(false);
+        };
+        self.write_record(&record, out)?;
+        Ok(record.is_found())
+    }
+
+    /// Like [`lookup_write`](Self::lookup_write), also returning the country
+    /// of the IP from the same lookup, or None if `s` is not a valid IP
+    /// address
+    pub fn lookup_write_country(
+        &self,
+        s: &str,
+        out: &mut impl io::Write,
+    ) -> io::Result<(bool, Option<String>)> {
+        let sections = Sections {
+            city: true,
+            ..self.sections
+        };
+        let Some(record) = self.lookup_sections(s, sections) else {
+            out.write_all(s.as_bytes())?;
+            return Ok((false, None));
+        };
+        self.write_record(&record, out)?;
+        Ok((record.is_found(), Some(record.country_iso.to_string())))
+    }
+
+    /// Both the decoration of [`lookup`](Self::lookup) and the JSON record of
+    /// [`lookup_json`](Self::lookup_json), from a single lookup of the IP
+    pub fn lookup_with_json(&self, s: &str) -> (String, Option<serde_json::Value>) {
+        let Some(record) = self.lookup_record(s) else {
+            return (s.to_string(), None);
+        };
+        let mut decoration = Vec::with_capacity(s.len() + 64);
+        self.write_record(&record, &mut decoration)
+            .expect("writing to a Vec cannot fail");
+        let decoration = String::from_utf8(decoration).expect("templates render valid utf-8");
+        (decoration, Some(record.to_json()))
+    }
+
+    /// Render the template for the address family of `record` to `out`
+    fn write_record(&self, record: &GeoRecord, out: &mut impl io::Write) -> io::Result<()> {
+        // of the valid addresses, only IPv6 ones have colons
+        let template = match record.ip.contains(':') {
+            true => &self.template_v6,
+            false => &self.template,
+        };
+        self.space_policy.write(template, record, out)
+    }
+
+    /// Look up the geoip fields of an IP as a JSON object, or None if `s`
+    /// is not a valid IP address
+    pub fn lookup_json(&self, s: &str) -> Option<serde_json::Value> {
+        self.lookup_record(s).map(|record| record.to_json())
+    }
+
+    /// Look up the geoip fields of an IP as the JSON record written to
+    /// output: that of [`lookup_json`](Self::lookup_json), or its ECS fields
+    /// if `ecs` is set
+    pub fn lookup_output_json(&self, s: &str) -> Option<serde_json::Value> {
+        let record = self.lookup_json(s)?;
+        Some(match self.ecs {
+            true => crate::ecs::endpoint(&record),
+            false => record,
+        })
+    }
+
+    /// Gather all geoip fields of `s`, or None if `s` is not a valid IP
+    /// address
+    pub fn lookup_record<'a>(&'a self, s: &'a str) -> Option<GeoRecord<'a>> {
+        self.lookup_sections(s, Sections::ALL)
+    }
+
+    /// Gather the geoip fields of `s` from the databases in `sections`,
+    /// leaving the other fields empty
+    fn lookup_sections<'a>(&'a self, s: &'a str, sections: Sections) -> Option<GeoRecord<'a>> {
+        let ip = parse_ip_bytes(s.as_bytes())?;
+
+        let mut record = GeoRecord {
+            ip: s,
+            asnnum: 0,
+            asnorg: "",
+            city: "",
+            continent: "",
+            country_iso: "",
+            country_full: "",
+            latitude: 0.0,
+            longitude: 0.0,
+            timezone: "",
+            subdivision_iso: "",
+            subdivision_name: "",
+            postal_code: "",
+            accuracy_radius: None,
+            metro_code: None,
+            distance_km: None,
+            is_anycast: None,
+            is_satellite_provider: None,
+            is_in_european_union: None,
+            isp: "",
+            organization: "",
+            connection_type: "",
+            bool_format: self.bool_format,
+            intel_sources: None,
+            enrichment: None,
+        };
+
+        // databases the caller has no use for are not queried at all
+        match &self.asnsource {
+            _ if !sections.asn => {}
+            None => {}
+            Some(AsnSource::Mmdb(asndb)) => {
+                let asnrecord = asndb
+                    .reader(ip)
+                    .and_then(|reader| reader.lookup::<geoip2::Asn>(ip).ok());
+                if let Some(asnrecord) = asnrecord {
+                    record.asnnum = asnrecord.autonomous_system_number.unwrap_or(0);
+                    record.asnorg = asnrecord.autonomous_system_organization.unwrap_or("");
+                };
+            }
+            Some(AsnSource::Pfx2as(table)) => record.asnnum = table.lookup(ip).unwrap_or(0),
+        }
+
+        let cityrecord = sections
+            .city
+            .then_some(self.cityreader.as_ref())
+            .flatten()
+            .and_then(|citydb| citydb.reader(ip))
+            .and_then(|reader| reader.lookup::<geoip2::City>(ip).ok());
+        if let Some(cityrecord) = cityrecord {
             // from https://github.com/oschwald/maxminddb-rust/blob/main/examples/within.rs
-            continent = cityrecord.continent.and_then(|c| c.code).unwrap_or("");
+            record.continent = cityrecord.continent.and_then(|c| c.code).unwrap_or("");
+            // traits that do not apply are left out of the databases
+            let traits = cityrecord.traits.as_ref();
+            record.is_anycast = Some(traits.and_then(|t| t.is_anycast).unwrap_or(false));
+            record.is_satellite_provider = Some(
+                traits
+                    .and_then(|t| t.is_satellite_provider)
+                    .unwrap_or(false),
+            );
+            record.is_in_european_union = Some(false);
             if let Some(c) = cityrecord.country {
-                country_iso = c.iso_code.unwrap_or("");
-                if let Some(n) = c.names {
-                    country_full = n.get("en").unwrap_or(&"");
+                record.is_in_european_union = Some(c.is_in_european_union.unwrap_or(false));
+                record.country_iso = c.iso_code.unwrap_or("");
+                if let Some(names) = c.names {
+                    record.country_full = self.localized(&names);
                 }
             }
 
-            // get city name, hard coded for en language currently
-            city = match cityrecord.city.and_then(|c| c.names) {
-                Some(names) => names.get("en").unwrap_or(&""),
+            record.city = match cityrecord.city.and_then(|c| c.names) {
+                Some(names) => self.localized(&names),
                 None => "",
             };
 
+            if let Some(subdivision) = cityrecord.subdivisions.and_then(|s| s.into_iter().next()) {
+                record.subdivision_iso = subdivision.iso_code.unwrap_or("");
+                if let Some(names) = subdivision.names {
+                    record.subdivision_name = self.localized(&names);
+                }
+            }
+            record.postal_code = cityrecord.postal.and_then(|p| p.code).unwrap_or("");
+
             // pull out location specific fields
             if let Some(locrecord) = cityrecord.location {
-                timezone = locrecord.time_zone.unwrap_or("");
-                latitude = locrecord.latitude.unwrap_or(0.0);
-                longitude = locrecord.longitude.unwrap_or(0.0);
+                record.timezone = locrecord.time_zone.unwrap_or("");
+                record.latitude = locrecord.latitude.unwrap_or(0.0);
+                record.longitude = locrecord.longitude.unwrap_or(0.0);
+                record.accuracy_radius = locrecord.accuracy_radius;
+                record.metro_code = locrecord.metro_code;
+                if let (Some(lat), Some(lon), Some(from)) =
+                    (locrecord.latitude, locrecord.longitude, self.ref_point)
+                {
+                    record.distance_km = Some(from.distance_km(Point { lat, lon }));
+                }
             };
         };
 
-        // create ipinfo struct just for purposes of applying template
-        let ipinfo = IPInfo {
-            ip: s,
-            asnnum: &asnnum.to_string(),
-            asnorg,
-            city,
-            continent,
-            country_iso,
-            country_full,
-            latitude: &latitude.to_string(),
-            longitude: &longitude.to_string(),
-            timezone,
-        };
-
-        // apply template to render enrichment per user-specification
-        render(&self.template, ipinfo).replace(' ', "_")
+        let isprecord = sections
+            .isp
+            .then_some(self.ispreader.as_ref())
+            .flatten()
+            .and_then(|ispdb| ispdb.reader(ip))
+            .and_then(|reader| reader.lookup::<geoip2::Isp>(ip).ok());
+        if let Some(isprecord) = isprecord {
+            record.isp = isprecord.isp.unwrap_or("");
+            record.organization = isprecord.organization.unwrap_or("");
+        }
+        let connrecord = sections
+            .connection
+            .then_some(self.connreader.as_ref())
+            .flatten()
+            .and_then(|conndb| conndb.reader(ip))
+            .and_then(|reader| reader.lookup::<geoip2::ConnectionType>(ip).ok());
+        if let Some(connrecord) = connrecord {
+            record.connection_type = connrecord.connection_type.unwrap_or("");
+        }
+
+        for fallback in &self.fallbacks {
+            if !record.has_gaps(sections) {
+                break;
+            }
+            let databases = Sections {
+                intel: false,
+                enrich: false,
+                ..sections
+            };
+            if let Some(other) = fallback.lookup_sections(s, databases) {
+                record.fill_from(other);
+            }
+        }
+
+        if sections.city && record.country_iso.is_empty() {
+            if let Some(country) = self.countries.and_then(|table| table.lookup(ip)) {
+                record.country_iso = country;
+            }
+        }
+
+        if sections.intel {
+            record.intel_sources = self.intel.as_ref().map(|intel| intel.matches(ip).collect());
+        }
+        if sections.enrich {
+            record.enrichment = self
+                .enrich
+                .as_ref()
+                .map(|db| (db.field(), db.label(ip).unwrap_or("")));
+        }
+
+        trace!(
+            ip = s,
+            asnnum = record.asnnum,
+            country_iso = record.country_iso,
+            "looked up"
+        );
+        Some(record)
     }
 }
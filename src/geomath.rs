@@ -0,0 +1,63 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Mean radius of the earth in km, as used by the haversine formula
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Error returned when a point is not written as "LAT,LON" in degrees
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParsePointError(pub(crate) String);
+
+impl fmt::Display for ParsePointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid point {:?}, expected LAT,LON in degrees, e.g. 51.5142,-0.0931",
+            self.0
+        )
+    }
+}
+
+impl Error for ParsePointError {}
+
+/// A location on the earth, parsed from "LAT,LON" in decimal degrees
+///
+/// ```
+/// use geoipsed::geomath::Point;
+///
+/// let london: Point = "51.5142,-0.0931".parse().unwrap();
+/// let paris: Point = "48.8566,2.3522".parse().unwrap();
+/// assert_eq!(london.distance_km(paris).round(), 343.0);
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+impl Point {
+    /// Great-circle distance to `other` in km by the haversine formula
+    pub fn distance_km(self, other: Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+impl FromStr for Point {
+    type Err = ParsePointError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePointError(s.to_string());
+        let (lat, lon) = s.split_once(',').ok_or_else(err)?;
+        let lat: f64 = lat.trim().parse().map_err(|_| err())?;
+        let lon: f64 = lon.trim().parse().map_err(|_| err())?;
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(err());
+        }
+        Ok(Point { lat, lon })
+    }
+}
@@ -0,0 +1,190 @@
+use crate::geoip::GeoIPSed;
+use anyhow::Result;
+use clap::Args;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tokio_stream::StreamExt;
+use tonic::{Request, Response, Status, Streaming};
+
+include!(concat!(env!("OUT_DIR"), "/geoipsed.v1.Enrichment.rs"));
+
+pub use enrichment_client::EnrichmentClient;
+pub use enrichment_server::{Enrichment, EnrichmentServer};
+
+// The messages mirror proto/geoipsed.proto, which is the published
+// interface. Keep the tags in sync with it
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecorateRequest {
+    #[prost(bytes = "vec", tag = "1")]
+    pub text: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DecorateResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub text: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LookupRequest {
+    #[prost(string, tag = "1")]
+    pub ip: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct LookupResponse {
+    #[prost(string, tag = "1")]
+    pub ip: String,
+    #[prost(bool, tag = "2")]
+    pub found: bool,
+    #[prost(uint32, tag = "3")]
+    pub asnnum: u32,
+    #[prost(string, tag = "4")]
+    pub asnorg: String,
+    #[prost(string, tag = "5")]
+    pub city: String,
+    #[prost(string, tag = "6")]
+    pub continent: String,
+    #[prost(string, tag = "7")]
+    pub country_iso: String,
+    #[prost(string, tag = "8")]
+    pub country_full: String,
+    #[prost(double, tag = "9")]
+    pub latitude: f64,
+    #[prost(double, tag = "10")]
+    pub longitude: f64,
+    #[prost(string, tag = "11")]
+    pub timezone: String,
+    #[prost(string, tag = "12")]
+    pub subdivision_iso: String,
+    #[prost(string, tag = "13")]
+    pub subdivision_name: String,
+    #[prost(string, tag = "14")]
+    pub postal_code: String,
+    #[prost(uint32, tag = "15")]
+    pub accuracy_radius: u32,
+    #[prost(uint32, tag = "16")]
+    pub metro_code: u32,
+}
+
+/// Serve decorations and lookups over gRPC
+#[derive(Args, Debug)]
+pub struct GrpcArgs {
+    /// Address to listen on. Use port 0 to pick a free port, which is
+    /// printed on stderr
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:50051")]
+    pub listen: SocketAddr,
+}
+
+/// Decorates text the way the command line does
+pub type Decorate = dyn Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync;
+
+/// The Enrichment service of proto/geoipsed.proto
+pub struct EnrichmentService {
+    geoipdb: Arc<GeoIPSed>,
+    decorate: Arc<Decorate>,
+}
+
+impl EnrichmentService {
+    pub fn new(geoipdb: Arc<GeoIPSed>, decorate: Arc<Decorate>) -> Self {
+        Self { geoipdb, decorate }
+    }
+
+    fn lookup(&self, ip: String) -> LookupResponse {
+        let record = self
+            .geoipdb
+            .parse_ip(&ip)
+            .map(|parsed| parsed.to_string())
+            .and_then(|parsed| {
+                let record = self.geoipdb.lookup_record(&parsed)?;
+                Some(LookupResponse {
+                    ip: ip.clone(),
+                    found: true,
+                    asnnum: record.asnnum,
+                    asnorg: record.asnorg.to_string(),
+                    city: record.city.to_string(),
+                    continent: record.continent.to_string(),
+                    country_iso: record.country_iso.to_string(),
+                    country_full: record.country_full.to_string(),
+                    latitude: record.latitude,
+                    longitude: record.longitude,
+                    timezone: record.timezone.to_string(),
+                    subdivision_iso: record.subdivision_iso.to_string(),
+                    subdivision_name: record.subdivision_name.to_string(),
+                    postal_code: record.postal_code.to_string(),
+                    accuracy_radius: record.accuracy_radius.map_or(0, u32::from),
+                    metro_code: record.metro_code.map_or(0, u32::from),
+                })
+            });
+        record.unwrap_or(LookupResponse {
+            ip,
+            ..Default::default()
+        })
+    }
+}
+
+/// Answer each message of `requests` with `f`, in order, until the client
+/// closes its side of the stream
+fn respond<I, O, F>(mut requests: Streaming<I>, f: F) -> ReceiverStream<Result<O, Status>>
+where
+    I: Send + 'static,
+    O: Send + 'static,
+    F: Fn(I) -> Result<O, Status> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(64);
+    tokio::spawn(async move {
+        while let Some(request) = requests.next().await {
+            let response = request.and_then(&f);
+            if tx.send(response).await.is_err() {
+                break;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+#[tonic::async_trait]
+impl Enrichment for EnrichmentService {
+    type DecorateStream = ReceiverStream<Result<DecorateResponse, Status>>;
+    type LookupStream = ReceiverStream<Result<LookupResponse, Status>>;
+
+    async fn decorate(
+        &self,
+        request: Request<Streaming<DecorateRequest>>,
+    ) -> Result<Response<Self::DecorateStream>, Status> {
+        let decorate = self.decorate.clone();
+        Ok(Response::new(respond(request.into_inner(), move |req| {
+            let text = decorate(&req.text).map_err(|e| Status::internal(e.to_string()))?;
+            Ok(DecorateResponse { text })
+        })))
+    }
+
+    async fn lookup(
+        &self,
+        request: Request<Streaming<LookupRequest>>,
+    ) -> Result<Response<Self::LookupStream>, Status> {
+        let service = Self::new(self.geoipdb.clone(), self.decorate.clone());
+        Ok(Response::new(respond(request.into_inner(), move |req| {
+            Ok(service.lookup(req.ip))
+        })))
+    }
+}
+
+/// Answer requests until the process is stopped, keeping the databases and
+/// caches warm across requests
+pub fn serve(args: &GrpcArgs, geoipdb: Arc<GeoIPSed>, decorate: Arc<Decorate>) -> Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(args.listen).await?;
+        eprintln!("geoipsed listening on grpc://{}", listener.local_addr()?);
+        let service = EnrichmentService::new(geoipdb, decorate);
+        tonic::transport::Server::builder()
+            .add_service(EnrichmentServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await?;
+        Ok(())
+    })
+}
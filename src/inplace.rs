@@ -0,0 +1,124 @@
+use anyhow::{bail, Context, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+
+/// Replacement contents of a file being edited in place, or of a rewritten
+/// copy of it. They are written to a temporary file next to the target,
+/// which is renamed over it on [`InPlaceFile::commit`] so that readers never
+/// see a partial file. The temporary file is removed if the edit is dropped
+/// before that
+pub struct InPlaceFile {
+    /// file whose permissions and modification time are kept
+    source: Utf8PathBuf,
+    path: Utf8PathBuf,
+    temp: Utf8PathBuf,
+    out: Option<BufWriter<File>>,
+    committed: bool,
+}
+
+impl InPlaceFile {
+    /// Start replacing the contents of `path`
+    pub fn create(path: &Utf8Path) -> Result<Self> {
+        Self::create_copy(path, path)
+    }
+
+    /// Start writing a rewritten copy of `source` to `path`, creating the
+    /// directories leading to it
+    pub fn create_copy(source: &Utf8Path, path: &Utf8Path) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_str().is_empty()) {
+            fs::create_dir_all(dir).with_context(|| format!("{dir}: cannot create directory"))?;
+        }
+        let name = path
+            .file_name()
+            .with_context(|| format!("{path}: not a file"))?;
+        let temp = path.with_file_name(format!(".{name}.geoipsed-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)
+            .with_context(|| format!("{temp}: cannot create temporary file"))?;
+        Ok(Self {
+            source: source.to_owned(),
+            path: path.to_owned(),
+            temp,
+            out: Some(BufWriter::new(file)),
+            committed: false,
+        })
+    }
+
+    /// Replace the target file with the contents written, keeping the
+    /// permissions and, if `keep_mtime` is set, the modification time of the
+    /// source. With a `backup_suffix` the original is kept with the suffix
+    /// appended to its name
+    pub fn commit(mut self, backup_suffix: Option<&str>, keep_mtime: bool) -> Result<()> {
+        let file = self
+            .out
+            .take()
+            .expect("only taken on commit")
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        let metadata = fs::metadata(&self.source)?;
+        file.set_permissions(metadata.permissions())?;
+        if keep_mtime {
+            file.set_modified(metadata.modified()?)?;
+        }
+        file.sync_all()?;
+        drop(file);
+        if let Some(suffix) = backup_suffix.filter(|s| !s.is_empty()) {
+            let backup = format!("{}{suffix}", self.path);
+            // a stale backup would keep hard_link from replacing it
+            let _ = fs::remove_file(&backup);
+            if fs::hard_link(&self.path, &backup).is_err() {
+                fs::copy(&self.path, &backup)
+                    .with_context(|| format!("{backup}: cannot write backup"))?;
+            }
+        }
+        fs::rename(&self.temp, &self.path)
+            .with_context(|| format!("{}: cannot replace", self.path))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for InPlaceFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.as_mut().expect("open until commit").write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.out.as_mut().expect("open until commit").write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.as_mut().expect("open until commit").flush()
+    }
+}
+
+impl Drop for InPlaceFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// Where the copy of `path` goes in `dir`, at the same relative path. Root
+/// and drive prefixes of absolute paths are dropped, so /var/log/syslog is
+/// mirrored as DIR/var/log/syslog
+pub fn mirror_path(dir: &Utf8Path, path: &Utf8Path) -> Result<Utf8PathBuf> {
+    let mut mirrored = dir.to_owned();
+    for component in path.components() {
+        match component {
+            Utf8Component::Normal(name) => mirrored.push(name),
+            Utf8Component::ParentDir => {
+                bail!("{path}: cannot mirror a path outside of the current directory")
+            }
+            Utf8Component::RootDir | Utf8Component::Prefix(_) | Utf8Component::CurDir => {}
+        }
+    }
+    if mirrored == dir {
+        bail!("{path}: not a file");
+    }
+    Ok(mirrored)
+}
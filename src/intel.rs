@@ -0,0 +1,95 @@
+use crate::ipset::{IpRange, IpSet};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use serde_json::Value;
+use std::net::IpAddr;
+
+/// Addresses of one intel source
+struct Source {
+    name: String,
+    set: IpSet,
+}
+
+/// Threat-intel lists of IPs and CIDRs to match extracted IPs against
+pub struct IntelDb {
+    sources: Vec<Source>,
+}
+
+impl IntelDb {
+    /// Load each file as one intel source, named after its file name. Files
+    /// may be plain lists or CSVs (the first IP/CIDR of each line is used,
+    /// lines starting with # are comments) or MISP event JSON
+    pub fn load(paths: &[Utf8PathBuf]) -> Result<Self> {
+        let sources = paths
+            .iter()
+            .map(|path| {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Could not read intel file {path}"))?;
+                let name = path.file_name().unwrap_or(path.as_str()).to_string();
+                let set = if text.trim_start().starts_with('{') {
+                    misp_ranges(&text)
+                        .with_context(|| format!("Could not parse MISP intel file {path}"))?
+                } else {
+                    list_ranges(&text)
+                };
+                Ok(Source { name, set })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { sources })
+    }
+
+    /// Names of the sources that list `ip`
+    pub fn matches(&self, ip: IpAddr) -> impl Iterator<Item = &str> {
+        self.sources
+            .iter()
+            .filter(move |s| s.set.contains(ip))
+            .map(|s| s.name.as_str())
+    }
+
+    /// Whether any source lists `ip`
+    pub fn is_hit(&self, ip: IpAddr) -> bool {
+        self.matches(ip).next().is_some()
+    }
+}
+
+/// First IP/CIDR token of each non-comment line
+fn list_ranges(text: &str) -> IpSet {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            line.split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+                .map(|t| t.trim_matches('"'))
+                .find_map(|t| t.parse::<IpRange>().ok())
+        })
+        .collect()
+}
+
+/// Values of the ip-src/ip-dst attributes of a MISP event, including those
+/// of its objects. Both a bare event and a REST API response are accepted
+fn misp_ranges(text: &str) -> Result<IpSet> {
+    let json: Value = serde_json::from_str(text)?;
+    let events: Vec<&Value> = match json.get("response") {
+        Some(Value::Array(items)) => items.iter().filter_map(|i| i.get("Event")).collect(),
+        _ => json.get("Event").into_iter().collect(),
+    };
+
+    let mut ranges: Vec<IpRange> = Vec::new();
+    for event in events {
+        let objects = event.get("Object").and_then(Value::as_array);
+        let attributes = std::iter::once(event)
+            .chain(objects.into_iter().flatten())
+            .filter_map(|o| o.get("Attribute").and_then(Value::as_array))
+            .flatten();
+        for attribute in attributes {
+            let kind = attribute["type"].as_str().unwrap_or_default();
+            if !matches!(kind, "ip-src" | "ip-dst" | "ip-src|port" | "ip-dst|port") {
+                continue;
+            }
+            let value = attribute["value"].as_str().unwrap_or_default();
+            // composite ip|port attributes
+            let value = value.split('|').next().unwrap_or_default();
+            ranges.extend(value.parse::<IpRange>().ok());
+        }
+    }
+    Ok(ranges.into_iter().collect())
+}
@@ -0,0 +1,137 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Parse a dotted-quad IPv4 address straight from bytes, e.g. a regex match
+/// over a line of input, without building a str first. Like std, octets
+/// with leading zeros ("01.2.3.4") are rejected as they may be octal
+pub fn parse_ipv4_bytes(s: &[u8]) -> Option<Ipv4Addr> {
+    let mut octets = [0u8; 4];
+    let mut parts = s.split(|&b| b == b'.');
+    for octet in &mut octets {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 3 || (part.len() > 1 && part[0] == b'0') {
+            return None;
+        }
+        let mut value: u16 = 0;
+        for &b in part {
+            if !b.is_ascii_digit() {
+                return None;
+            }
+            value = value * 10 + u16::from(b - b'0');
+        }
+        *octet = u8::try_from(value).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Addr::from(octets)),
+    }
+}
+
+/// Parse an IPv6 address straight from bytes. Compressed forms ("2001:db8::1"),
+/// an IPv4 tail ("::ffff:192.0.2.1"), and a lone "::" are accepted. More than
+/// one "::", groups of more than four hex digits, and zone ids are rejected
+pub fn parse_ipv6_bytes(s: &[u8]) -> Option<Ipv6Addr> {
+    let mut head = [0u16; 8];
+    let mut tail = [0u16; 8];
+    let (head_len, tail_len) = match s.windows(2).position(|w| w == b"::") {
+        Some(at) => {
+            let (before, after) = (&s[..at], &s[at + 2..]);
+            if after.windows(2).any(|w| w == b"::") {
+                return None;
+            }
+            let head_len = parse_groups(before, false, &mut head)?;
+            let tail_len = parse_groups(after, true, &mut tail)?;
+            // "::" stands in for at least one group
+            if head_len + tail_len > 7 {
+                return None;
+            }
+            (head_len, tail_len)
+        }
+        None => match parse_groups(s, true, &mut head)? {
+            8 => (8, 0),
+            _ => return None,
+        },
+    };
+
+    let mut segments = [0u16; 8];
+    segments[..head_len].copy_from_slice(&head[..head_len]);
+    segments[8 - tail_len..].copy_from_slice(&tail[..tail_len]);
+    Some(Ipv6Addr::from(segments))
+}
+
+/// Parse either IPv4 or IPv6 address from bytes
+pub fn parse_ip_bytes(s: &[u8]) -> Option<IpAddr> {
+    if s.contains(&b':') {
+        parse_ipv6_bytes(s).map(IpAddr::V6)
+    } else {
+        parse_ipv4_bytes(s).map(IpAddr::V4)
+    }
+}
+
+/// Parse colon-separated hex groups into `out`, returning how many were
+/// written. If `v4_tail` is set the last group may be a dotted quad, which
+/// counts as two groups. An empty input has no groups
+fn parse_groups(s: &[u8], v4_tail: bool, out: &mut [u16; 8]) -> Option<usize> {
+    if s.is_empty() {
+        return Some(0);
+    }
+    let mut n = 0;
+    let mut groups = s.split(|&b| b == b':').peekable();
+    while let Some(group) = groups.next() {
+        let last = groups.peek().is_none();
+        if last && v4_tail && group.contains(&b'.') {
+            let [a, b, c, d] = parse_ipv4_bytes(group)?.octets();
+            if n + 2 > 8 {
+                return None;
+            }
+            out[n] = u16::from_be_bytes([a, b]);
+            out[n + 1] = u16::from_be_bytes([c, d]);
+            n += 2;
+            continue;
+        }
+        if group.is_empty() || group.len() > 4 || n == 8 {
+            return None;
+        }
+        let mut value: u16 = 0;
+        for &b in group {
+            value = value << 4 | char::from(b).to_digit(16)? as u16;
+        }
+        out[n] = value;
+        n += 1;
+    }
+    Some(n)
+}
+
+/// Parse one component of an obfuscated IPv4 address the way inet_aton
+/// does: 0x-prefixed hex, 0-prefixed octal, or decimal
+fn parse_radix_u32(s: &[u8]) -> Option<u32> {
+    let (digits, radix) = match s {
+        [b'0', b'x' | b'X', hex @ ..] => (hex, 16),
+        [b'0', octal @ ..] if !octal.is_empty() => (octal, 8),
+        _ => (s, 10),
+    };
+    let digits = std::str::from_utf8(digits).ok()?;
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
+    }
+    u32::from_str_radix(digits, radix).ok()
+}
+
+/// Parse the alternative IPv4 notations accepted by inet_aton and browsers
+/// and abused to disguise addresses: a single 32-bit number in decimal
+/// ("3232235777"), hex ("0xC0A80101"), or octal ("030052000401"), or a
+/// dotted quad with hex or octal parts ("0300.0250.0001.0001",
+/// "0xc0.0xa8.1.1"). Plain dotted-quads are accepted too
+pub fn parse_obfuscated_ipv4(s: &[u8]) -> Option<Ipv4Addr> {
+    if !s.contains(&b'.') {
+        return parse_radix_u32(s).map(Ipv4Addr::from);
+    }
+    let mut octets = [0u8; 4];
+    let mut parts = s.split(|&b| b == b'.');
+    for octet in &mut octets {
+        *octet = u8::try_from(parse_radix_u32(parts.next()?)?).ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Addr::from(octets)),
+    }
+}
@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Map an address into one 128-bit key space. IPv4 addresses use their
+/// IPv4-mapped IPv6 form so ::ffff:a.b.c.d and a.b.c.d are the same member
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Inverse of `key`, giving back IPv4 addresses for the IPv4-mapped range
+fn addr(k: u128) -> IpAddr {
+    let ip = Ipv6Addr::from(k);
+    match ip.to_ipv4_mapped() {
+        Some(v4) => IpAddr::V4(v4),
+        None => IpAddr::V6(ip),
+    }
+}
+
+/// Error returned when an IP address or CIDR network cannot be parsed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseCidrError(pub(crate) String);
+
+impl fmt::Display for ParseCidrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IP address or CIDR network: {:?}", self.0)
+    }
+}
+
+impl Error for ParseCidrError {}
+
+/// An inclusive range of IP addresses, parsed from an address ("1.2.3.4")
+/// or a CIDR network ("10.0.0.0/8", "2001:db8::/32")
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IpRange {
+    start: u128,
+    end: u128,
+}
+
+impl IpRange {
+    /// Range from `start` to `end` inclusive, or None if `end` < `start`
+    pub fn new(start: IpAddr, end: IpAddr) -> Option<Self> {
+        let (start, end) = (key(start), key(end));
+        (start <= end).then_some(Self { start, end })
+    }
+
+    /// First address of the range
+    pub fn start(&self) -> IpAddr {
+        addr(self.start)
+    }
+
+    /// Last address of the range
+    pub fn end(&self) -> IpAddr {
+        addr(self.end)
+    }
+
+    /// Whether `ip` is within the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        (self.start..=self.end).contains(&key(ip))
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(ip: IpAddr) -> Self {
+        Self {
+            start: key(ip),
+            end: key(ip),
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = ParseCidrError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseCidrError(s.to_string());
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix.parse::<u32>().map_err(|_| err())?)),
+            None => (s, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|_| err())?;
+        let prefix = match (ip, prefix) {
+            (IpAddr::V4(_), Some(p)) if p <= 32 => p + 96,
+            (IpAddr::V6(_), Some(p)) if p <= 128 => p,
+            (_, Some(_)) => return Err(err()),
+            (_, None) => 128,
+        };
+        let hostmask = u128::MAX.checked_shr(prefix).unwrap_or(0);
+        let start = key(ip) & !hostmask;
+        Ok(Self {
+            start,
+            end: start | hostmask,
+        })
+    }
+}
+
+/// A set of IP addresses stored as sorted, coalesced ranges, so that
+/// membership tests are a binary search regardless of how many addresses
+/// the networks in the set span. IPv4 and IPv6 addresses share one set.
+///
+/// ```
+/// use geoipsed::ipset::IpSet;
+///
+/// let set: IpSet = ["10.0.0.0/8", "192.168.1.1"]
+///     .iter()
+///     .map(|s| s.parse().unwrap())
+///     .collect();
+/// assert!(set.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!set.contains("192.168.1.2".parse().unwrap()));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IpSet {
+    ranges: Vec<IpRange>,
+}
+
+impl IpSet {
+    /// An empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sort and coalesce overlapping or adjacent ranges
+    fn normalize(&mut self) {
+        self.ranges.sort_unstable();
+        let mut merged: Vec<IpRange> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end.saturating_add(1) => {
+                    last.end = last.end.max(range.end)
+                }
+                _ => merged.push(range),
+            }
+        }
+        self.ranges = merged;
+    }
+
+    /// Add a range to the set. Prefer `extend` or `collect` for bulk
+    /// insertion, which normalizes the set only once
+    pub fn insert(&mut self, range: impl Into<IpRange>) {
+        self.extend(std::iter::once(range.into()));
+    }
+
+    /// Whether `ip` is in the set, in O(log n) of the number of ranges
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let k = key(ip);
+        let i = self.ranges.partition_point(|r| r.start <= k);
+        i > 0 && self.ranges[i - 1].end >= k
+    }
+
+    /// Whether the set has no addresses
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// The disjoint, sorted ranges of the set
+    pub fn ranges(&self) -> &[IpRange] {
+        &self.ranges
+    }
+
+    /// Addresses in either set
+    pub fn union(&self, other: &IpSet) -> IpSet {
+        let mut set = self.clone();
+        set.extend(other.ranges.iter().copied());
+        set
+    }
+
+    /// Addresses in both sets
+    pub fn intersection(&self, other: &IpSet) -> IpSet {
+        let (mut a, mut b) = (
+            self.ranges.iter().peekable(),
+            other.ranges.iter().peekable(),
+        );
+        let mut ranges = Vec::new();
+        // both lists are sorted and disjoint, so walk them together
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            let start = x.start.max(y.start);
+            let end = x.end.min(y.end);
+            if start <= end {
+                ranges.push(IpRange { start, end });
+            }
+            if x.end < y.end {
+                a.next();
+            } else {
+                b.next();
+            }
+        }
+        IpSet { ranges }
+    }
+}
+
+impl Extend<IpRange> for IpSet {
+    fn extend<I: IntoIterator<Item = IpRange>>(&mut self, iter: I) {
+        self.ranges.extend(iter);
+        self.normalize();
+    }
+}
+
+impl FromIterator<IpRange> for IpSet {
+    fn from_iter<I: IntoIterator<Item = IpRange>>(iter: I) -> Self {
+        let mut set = IpSet::new();
+        set.extend(iter);
+        set
+    }
+}
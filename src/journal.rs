@@ -0,0 +1,79 @@
+use crate::export::utc_timestamp;
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::process::{Child, Command, Stdio};
+
+/// Start `journalctl` writing the entries matching `matches`, e.g.
+/// `_SYSTEMD_UNIT=sshd.service`, as JSON lines on its stdout, and keep
+/// waiting for new entries if `follow` is set
+pub fn spawn(matches: &[String], follow: bool) -> Result<Child> {
+    let mut cmd = Command::new("journalctl");
+    cmd.args(["--output=json", "--no-pager"]);
+    if follow {
+        cmd.arg("--follow");
+    }
+    cmd.args(matches)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("cannot run journalctl")
+}
+
+/// One journal entry, as exported by `journalctl --output=json`
+pub struct Entry(serde_json::Map<String, Value>);
+
+impl Entry {
+    /// Parse a line of `journalctl --output=json`, or None if it is not a
+    /// JSON object
+    pub fn parse(line: &[u8]) -> Option<Self> {
+        match serde_json::from_slice(line) {
+            Ok(Value::Object(fields)) => Some(Self(fields)),
+            _ => None,
+        }
+    }
+
+    /// The entry's fields, e.g. for JSON output
+    pub fn into_fields(self) -> serde_json::Map<String, Value> {
+        self.0
+    }
+
+    /// The MESSAGE field. journalctl writes messages that are not valid
+    /// UTF-8 as arrays of bytes
+    pub fn message(&self) -> Option<Cow<'_, [u8]>> {
+        match self.0.get("MESSAGE")? {
+            Value::String(s) => Some(Cow::Borrowed(s.as_bytes())),
+            Value::Array(bytes) => bytes
+                .iter()
+                .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .map(Cow::Owned),
+            _ => None,
+        }
+    }
+
+    /// The syslog style "TIMESTAMP HOST IDENTIFIER[PID]: " header of the
+    /// entry, with the time in UTC
+    pub fn header(&self) -> String {
+        let field = |name: &str| self.0.get(name).and_then(Value::as_str);
+        let mut header = String::new();
+        let micros = field("__REALTIME_TIMESTAMP").and_then(|t| t.parse::<u64>().ok());
+        if let Some(micros) = micros {
+            header.push_str(&utc_timestamp(micros / 1_000_000));
+            header.push(' ');
+        }
+        if let Some(host) = field("_HOSTNAME") {
+            header.push_str(host);
+            header.push(' ');
+        }
+        let identifier = field("SYSLOG_IDENTIFIER")
+            .or_else(|| field("_COMM"))
+            .unwrap_or("-");
+        header.push_str(identifier);
+        if let Some(pid) = field("_PID") {
+            header.push_str(&format!("[{pid}]"));
+        }
+        header.push_str(": ");
+        header
+    }
+}
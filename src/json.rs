@@ -0,0 +1,195 @@
+use crate::entity::{EntityKind, Extractor, PatternSet, SearchProfile};
+use crate::geoip::GeoIPSed;
+use clap::ValueEnum;
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::{Map, Value};
+use smallvec::SmallVec;
+
+/// Suffix of the key added next to each decorated field, e.g. the geo
+/// record(s) for "src_ip" are added as "src_ip_geo"
+pub const GEO_SUFFIX: &str = "_geo";
+
+/// Well-known JSON log schemas whose IP-bearing fields are known ahead of time
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Preset {
+    /// Zeek conn.log (and other logs keyed by the connection 4-tuple)
+    ZeekConn,
+    /// Suricata EVE JSON
+    SuricataEve,
+}
+
+impl Preset {
+    /// The top-level fields of this schema that hold IP addresses
+    pub fn fields(self) -> &'static [&'static str] {
+        match self {
+            Preset::ZeekConn => &["id.orig_h", "id.resp_h"],
+            Preset::SuricataEve => &["src_ip", "dest_ip"],
+        }
+    }
+}
+
+/// Extractor of the IP addresses in string values
+fn ip_extractor(obfuscated_ipv4: bool) -> Extractor {
+    PatternSet::new()
+        .search_profile(SearchProfile::Throughput)
+        .obfuscated_ipv4(obfuscated_ipv4)
+        .kind(EntityKind::Ip)
+        .build()
+        .expect("the IP pattern compiles")
+}
+
+/// Enriches JSON objects by adding geoip records next to the string fields
+/// that contain IP addresses. Keys, non-string values, and the original
+/// field values are left untouched so the output stays valid for any schema
+/// consumer of the input.
+pub struct JsonDecorator<'a> {
+    geoipdb: &'a GeoIPSed,
+    re: Extractor,
+    fields: Vec<String>,
+    cache: HashMap<String, Option<Value>>,
+}
+
+impl<'a> JsonDecorator<'a> {
+    /// Create a decorator that only looks within the named top-level
+    /// `fields`. If `fields` is empty, every string value of the object,
+    /// including those of nested objects, is examined
+    pub fn new(geoipdb: &'a GeoIPSed, fields: Vec<String>) -> Self {
+        Self {
+            geoipdb,
+            re: ip_extractor(false),
+            fields,
+            cache: HashMap::default(),
+        }
+    }
+
+    /// Whether obfuscated IPv4 addresses are decorated too, see
+    /// [`PatternSet::obfuscated_ipv4`]
+    pub fn obfuscated_ipv4(mut self, obfuscated_ipv4: bool) -> Self {
+        self.re = ip_extractor(obfuscated_ipv4);
+        self
+    }
+
+    /// Decorate one line of NDJSON. Lines that are not JSON objects are
+    /// returned as-is (minus surrounding whitespace) so that mixed or
+    /// malformed input does not abort the stream
+    pub fn decorate_line(&mut self, line: &[u8]) -> Vec<u8> {
+        self.decorate_tagged_line(line, None)
+    }
+
+    /// [`decorate_line`](Self::decorate_line), adding a "stream" field of
+    /// `stream` to objects, e.g. the name of the pipe the line was read from
+    pub fn decorate_tagged_line(&mut self, line: &[u8], stream: Option<&str>) -> Vec<u8> {
+        self.decorate_object(line, stream)
+            .unwrap_or_else(|| line.trim_ascii().to_vec())
+    }
+
+    /// Like [`decorate_tagged_line`](Self::decorate_tagged_line), or None
+    /// if `line` is not a JSON object, so that the caller can pass the line
+    /// through exactly as it was read
+    pub fn decorate_object(&mut self, line: &[u8], stream: Option<&str>) -> Option<Vec<u8>> {
+        let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(line.trim_ascii()) else {
+            return None;
+        };
+        if self.fields.is_empty() {
+            self.decorate_all(&mut obj);
+        } else {
+            // taken for the loop rather than cloned for every line
+            let fields = std::mem::take(&mut self.fields);
+            for field in &fields {
+                self.decorate_field(&mut obj, field);
+            }
+            self.fields = fields;
+        }
+        if let Some(stream) = stream {
+            obj.insert("stream".to_string(), stream.into());
+        }
+        Some(serde_json::to_vec(&Value::Object(obj)).expect("JSON always serializes"))
+    }
+
+    /// Recursively decorate every string value of an object, including
+    /// those inside arrays
+    fn decorate_all(&mut self, obj: &mut Map<String, Value>) {
+        let keys: Vec<String> = obj.keys().cloned().collect();
+        for key in keys.iter().filter(|k| !k.ends_with(GEO_SUFFIX)) {
+            match obj.get_mut(key) {
+                Some(Value::Object(nested)) => self.decorate_all(nested),
+                Some(Value::Array(items)) => {
+                    let records = self.decorate_array(items);
+                    if !records.is_empty() {
+                        insert_geo(obj, key, Value::Array(records));
+                    }
+                }
+                _ => self.decorate_field(obj, key),
+            }
+        }
+    }
+
+    /// Decorate the objects of an array in place and return one record per
+    /// IP found in its strings, which become the array's `<field>_geo`
+    fn decorate_array(&mut self, items: &mut [Value]) -> Vec<Value> {
+        let mut records = Vec::new();
+        for item in items {
+            match item {
+                Value::Object(nested) => self.decorate_all(nested),
+                Value::Array(inner) => records.extend(self.decorate_array(inner)),
+                Value::String(text) => records.extend(self.records(text).0),
+                _ => {}
+            }
+        }
+        records
+    }
+
+    /// Add the `<field>_geo` sibling of a string field if it contains IPs.
+    /// When the whole value is a single IP the sibling is that IP's record,
+    /// otherwise it is an array with one record per IP found in the text
+    fn decorate_field(&mut self, obj: &mut Map<String, Value>, field: &str) {
+        let Some(Value::String(text)) = obj.get(field) else {
+            return;
+        };
+
+        let (mut records, exact) = self.records(text);
+        let geo = match records.len() {
+            0 => return,
+            1 if exact => records.pop().unwrap(),
+            _ => Value::Array(records.into_vec()),
+        };
+        insert_geo(obj, field, geo);
+    }
+
+    /// The records of the IPs in `text`, and whether the last of them
+    /// spans all of it
+    fn records(&mut self, text: &str) -> (SmallVec<[Value; 4]>, bool) {
+        // fields rarely hold more than a few IPs, which then need no
+        // allocation of their own
+        let mut records: SmallVec<[Value; 4]> = SmallVec::new();
+        let mut exact = false;
+        for m in self.re.find_iter(text.as_bytes()) {
+            // IPs are ASCII, so their range is on character boundaries
+            let ip = &text[m.range()];
+            // the key is only copied on a miss
+            let record = if let Some(record) = self.cache.get(ip) {
+                record
+            } else {
+                let canonical = m.canonical_text().unwrap_or_default();
+                let record = self.geoipdb.lookup_output_json(&canonical);
+                self.cache.entry(ip.to_string()).or_insert(record)
+            };
+            if let Some(record) = record {
+                exact = m.start() == 0 && m.end() == text.len();
+                records.push(record.clone());
+            }
+        }
+        (records, exact)
+    }
+}
+
+/// Place `geo` as the `<field>_geo` key right after the field it describes
+fn insert_geo(obj: &mut Map<String, Value>, field: &str, geo: Value) {
+    let key = format!("{field}{GEO_SUFFIX}");
+    obj.shift_remove(&key);
+    let index = obj
+        .keys()
+        .position(|k| k == field)
+        .map_or(obj.len(), |i| i + 1);
+    obj.shift_insert(index, key, geo);
+}
@@ -0,0 +1,64 @@
+//! Inline decoration of IPv4 and IPv6 address geolocations
+//!
+//! The geoipsed command line tool is built from these modules, which are
+//! also usable on their own, e.g. [`ipset::IpSet`] for fast membership tests
+//! over large lists of IP ranges or [`prefixtrie::PrefixTrie`] to map
+//! networks to custom metadata by longest prefix match, and
+//! [`pipeline::Pipeline`] runs the whole decoration of an input in a few
+//! lines.
+
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod bench;
+pub mod countrydb;
+pub mod dedup;
+pub mod domain;
+pub mod ecs;
+pub mod encoding;
+pub mod enrich;
+pub mod entity;
+pub mod error;
+pub mod evtx;
+pub mod export;
+pub mod framed;
+pub mod geoip;
+pub mod geomath;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod inplace;
+pub mod intel;
+pub mod ipparse;
+pub mod ipset;
+pub mod journal;
+pub mod json;
+pub mod logformat;
+pub mod mac;
+pub mod metrics;
+pub mod mmdb;
+pub mod ordered;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod pipeline;
+mod prefixdb;
+pub mod prefixtrie;
+pub mod sample;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod sink;
+pub mod stats;
+pub mod template;
+
+pub use error::Error;
+
+// extraction and lookups are shared by threads behind Arc, e.g. with
+// --threads and in the enrichment services, so these must stay Send + Sync
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<entity::Extractor>();
+    assert_send_sync::<geoip::GeoIPSed>();
+    assert_send_sync::<mac::MacDecorator>();
+    assert_send_sync::<domain::DomainDecorator>();
+    assert_send_sync::<intel::IntelDb>();
+    assert_send_sync::<enrich::EnrichDb>();
+    assert_send_sync::<template::Template>();
+};
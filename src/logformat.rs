@@ -0,0 +1,177 @@
+use clap::ValueEnum;
+use regex::bytes::Regex;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+/// CEF extension keys (short and full names) whose values are addresses.
+/// See the ArcSight "Implementing ArcSight CEF" guide
+const CEF_IP_KEYS: &[&[u8]] = &[
+    b"src",
+    b"sourceAddress",
+    b"dst",
+    b"destinationAddress",
+    b"dvc",
+    b"deviceAddress",
+    b"agt",
+    b"agentAddress",
+    b"sourceTranslatedAddress",
+    b"destinationTranslatedAddress",
+    b"deviceTranslatedAddress",
+    b"agentTranslatedAddress",
+    b"c6a1",
+    b"c6a2",
+    b"c6a3",
+    b"c6a4",
+    b"deviceCustomIPv6Address1",
+    b"deviceCustomIPv6Address2",
+    b"deviceCustomIPv6Address3",
+    b"deviceCustomIPv6Address4",
+];
+
+/// LEEF attribute keys whose values are addresses. See the QRadar
+/// "Log Event Extended Format" predefined attributes
+const LEEF_IP_KEYS: &[&[u8]] = &[
+    b"src",
+    b"dst",
+    b"srcPreNAT",
+    b"dstPreNAT",
+    b"srcPostNAT",
+    b"dstPostNAT",
+    b"identSrc",
+];
+
+/// Structured log formats whose address fields are known, so that only
+/// those fields are decorated and free-text payloads are left untouched
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum LogFormat {
+    /// ArcSight Common Event Format
+    Cef,
+    /// QRadar Log Event Extended Format
+    Leef,
+}
+
+impl LogFormat {
+    /// Byte ranges of the values of the address fields in `line`. Lines
+    /// that are not in this format have no address fields
+    pub fn ip_value_spans(self, line: &[u8]) -> Vec<Range<usize>> {
+        match self {
+            LogFormat::Cef => cef_spans(line),
+            LogFormat::Leef => leef_spans(line),
+        }
+    }
+}
+
+/// Find `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Offset just past the `n`th unescaped pipe at or after `start`
+fn skip_pipes(line: &[u8], start: usize, n: usize) -> Option<usize> {
+    let mut seen = 0;
+    let mut escaped = false;
+    for (i, &c) in line.iter().enumerate().skip(start) {
+        match c {
+            b'\\' if !escaped => escaped = true,
+            b'|' if !escaped => {
+                seen += 1;
+                if seen == n {
+                    return Some(i + 1);
+                }
+            }
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Strip the line terminator and trailing whitespace from a value range
+fn trim_end(line: &[u8], mut range: Range<usize>) -> Range<usize> {
+    while range.end > range.start && line[range.end - 1].is_ascii_whitespace() {
+        range.end -= 1;
+    }
+    range
+}
+
+fn cef_spans(line: &[u8]) -> Vec<Range<usize>> {
+    static KEY: OnceLock<Regex> = OnceLock::new();
+    // extension keys are alphanumeric and preceded by a space (or start the
+    // extension). "=" within values is escaped, so it can't be confused for a key
+    let key = KEY.get_or_init(|| Regex::new(r"(?:^|[ ])([A-Za-z0-9_.\[\]-]+)=").unwrap());
+
+    // header is CEF:Version|Vendor|Product|Version|SignatureID|Name|Severity|
+    let Some(header) = find(line, b"CEF:") else {
+        return Vec::new();
+    };
+    let Some(ext) = skip_pipes(line, header, 7) else {
+        return Vec::new();
+    };
+    let extension = &line[ext..];
+
+    let keys: Vec<_> = key
+        .captures_iter(extension)
+        .map(|c| (c.get(1).unwrap(), c.get(0).unwrap().end()))
+        .collect();
+
+    let mut spans = Vec::new();
+    for (i, (name, value_start)) in keys.iter().enumerate() {
+        if !CEF_IP_KEYS.contains(&name.as_bytes()) {
+            continue;
+        }
+        // a value runs until the space in front of the next key
+        let value_end = keys
+            .get(i + 1)
+            .map_or(extension.len(), |(next, _)| next.start());
+        spans.push(trim_end(line, ext + value_start..ext + value_end));
+    }
+    spans
+}
+
+fn leef_spans(line: &[u8]) -> Vec<Range<usize>> {
+    // LEEF:1.0|Vendor|Product|Version|EventID|attributes
+    // LEEF:2.0|Vendor|Product|Version|EventID|Delimiter|attributes
+    let Some(header) = find(line, b"LEEF:") else {
+        return Vec::new();
+    };
+    let version = &line[header + 5..];
+    let (attrs, delimiter) = if version.starts_with(b"1.") {
+        match skip_pipes(line, header, 5) {
+            Some(attrs) => (attrs, b'\t'),
+            None => return Vec::new(),
+        }
+    } else {
+        let (Some(delim_start), Some(attrs)) =
+            (skip_pipes(line, header, 5), skip_pipes(line, header, 6))
+        else {
+            return Vec::new();
+        };
+        (attrs, leef_delimiter(&line[delim_start..attrs - 1]))
+    };
+
+    let mut spans = Vec::new();
+    let mut start = attrs;
+    for attr in line[attrs..].split(|&c| c == delimiter) {
+        if let Some(eq) = attr.iter().position(|&c| c == b'=') {
+            if LEEF_IP_KEYS.contains(&&attr[..eq]) {
+                spans.push(trim_end(line, start + eq + 1..start + attr.len()));
+            }
+        }
+        start += attr.len() + 1;
+    }
+    spans
+}
+
+/// LEEF 2.0 delimiters are a single character or its hex value written as
+/// xHH or 0xHH. Tab is the default when unspecified
+fn leef_delimiter(spec: &[u8]) -> u8 {
+    let hex = spec
+        .strip_prefix(b"0x")
+        .or_else(|| spec.strip_prefix(b"x"))
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .and_then(|h| u8::from_str_radix(h, 16).ok());
+    match (hex, spec) {
+        (Some(c), _) => c,
+        (None, [c]) => *c,
+        _ => b'\t',
+    }
+}
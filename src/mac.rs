@@ -0,0 +1,147 @@
+use crate::geoip::SpacePolicy;
+use crate::template::{Fields, Template};
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use rustc_hash::FxHashMap as HashMap;
+use std::borrow::Cow;
+
+/// Parse a MAC address in colon, dash, or Cisco dot notation
+pub fn parse_mac(s: &[u8]) -> Option<[u8; 6]> {
+    let hex: Vec<u8> = s
+        .iter()
+        .copied()
+        .filter(|b| !matches!(b, b':' | b'-' | b'.'))
+        .collect();
+    if hex.len() != 12 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    for (byte, pair) in mac.iter_mut().zip(hex.chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(mac)
+}
+
+/// Organizationally unique identifier (first 24 bits) of a MAC address
+fn oui(mac: [u8; 6]) -> u32 {
+    u32::from_be_bytes([0, mac[0], mac[1], mac[2]])
+}
+
+/// Vendors of MAC address blocks (MA-L assignments)
+pub struct OuiDb {
+    vendors: HashMap<u32, String>,
+}
+
+impl OuiDb {
+    /// Load the IEEE registry CSV (oui.csv: `MA-L,001A2B,"Vendor",...`) or a
+    /// Wireshark manuf file (`00:1A:2B<TAB>Short<TAB>Vendor`). Only 24-bit
+    /// assignments are used
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read OUI file {path}"))?;
+        let mut vendors = HashMap::default();
+        for line in text.lines() {
+            if let Some((prefix, vendor)) = parse_csv_line(line).or_else(|| parse_manuf_line(line))
+            {
+                vendors.insert(prefix, vendor);
+            }
+        }
+        Ok(Self { vendors })
+    }
+
+    /// Vendor of the block `mac` belongs to
+    pub fn vendor(&self, mac: [u8; 6]) -> Option<&str> {
+        self.vendors.get(&oui(mac)).map(String::as_str)
+    }
+}
+
+/// `MA-L,001A2B,"Vendor, Inc.",address`
+fn parse_csv_line(line: &str) -> Option<(u32, String)> {
+    let rest = line.strip_prefix("MA-L,")?;
+    let (hex, rest) = rest.split_once(',')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let prefix = u32::from_str_radix(hex, 16).ok()?;
+    let vendor = match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"')?.0,
+        None => rest.split(',').next()?,
+    };
+    Some((prefix, vendor.trim().to_string()))
+}
+
+/// `00:1A:2B<TAB>Short<TAB>Vendor`, where the long name is optional
+fn parse_manuf_line(line: &str) -> Option<(u32, String)> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split('\t');
+    let prefix = fields.next()?;
+    // longer prefixes (e.g. 00:1A:2B:3C:40/28) are not 24-bit assignments
+    if prefix.len() != 8 {
+        return None;
+    }
+    let mac = parse_mac(format!("{prefix}:00:00:00").as_bytes())?;
+    let short = fields.next()?;
+    let vendor = fields.next().unwrap_or(short);
+    Some((oui(mac), vendor.trim().to_string()))
+}
+
+/// Template fields of a MAC address
+struct MacRecord<'a> {
+    mac: &'a str,
+    vendor: &'a str,
+}
+
+impl Fields for MacRecord<'_> {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "mac" => Some(self.mac.into()),
+            "vendor" => Some(self.vendor.into()),
+            _ => None,
+        }
+    }
+}
+
+/// Decorates MAC addresses with their vendor
+pub struct MacDecorator {
+    template: Template,
+    oui: Option<OuiDb>,
+    space_policy: SpacePolicy,
+}
+
+impl MacDecorator {
+    /// Default template of MAC address decorations
+    pub const DEFAULT_TEMPLATE: &'static str = "<{mac}|{vendor}>";
+
+    /// Decorate with `template`, whose fields are {mac} and {vendor}. The
+    /// vendor is empty unless an OUI database is given
+    pub fn new(template: &str, oui: Option<OuiDb>) -> Self {
+        Self {
+            template: Template::new(template),
+            oui,
+            space_policy: SpacePolicy::default(),
+        }
+    }
+
+    /// Write the spaces of decorations as `policy` says, instead of as
+    /// underscores
+    pub fn space_policy(mut self, policy: SpacePolicy) -> Self {
+        self.space_policy = policy;
+        self
+    }
+
+    /// Render the decoration of `s`, or return it unchanged if it is not a
+    /// MAC address
+    pub fn lookup(&self, s: &str) -> String {
+        let Some(mac) = parse_mac(s.as_bytes()) else {
+            return s.to_string();
+        };
+        let vendor = self.oui.as_ref().and_then(|db| db.vendor(mac));
+        let record = MacRecord {
+            mac: s,
+            vendor: vendor.unwrap_or(""),
+        };
+        self.space_policy.render(&self.template, &record)
+    }
+}
@@ -1,20 +1,49 @@
-use anyhow::{Error, Result};
-use camino::Utf8PathBuf;
-use clap::{Parser, ValueEnum};
+use anyhow::{anyhow, bail, Context, Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use grep_cli::{self, stdout};
-use regex::bytes::Regex;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use memmap2::Mmap;
 use ripline::{
     line_buffer::{LineBufferBuilder, LineBufferReader},
-    lines::LineIter,
+    lines::{without_terminator, LineIter},
     LineTerminator,
 };
-use rustc_hash::FxHashMap as HashMap;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet, FxHasher};
+use std::fmt;
 use std::fs::File;
-use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::IpAddr;
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use termcolor::ColorChoice;
+use tracing::level_filters::LevelFilter;
 
-pub mod geoip;
+#[cfg(feature = "archive")]
+use geoipsed::archive;
+use geoipsed::entity::{Entity, EntityKind, Extractor, PatternSet, Rejection, SearchProfile};
+#[cfg(feature = "grpc")]
+use geoipsed::grpc;
+use geoipsed::inplace::{self, InPlaceFile};
+use geoipsed::ipparse::parse_obfuscated_ipv4;
+use geoipsed::metrics::Metrics;
+use geoipsed::ordered::{OrderedWriter, ReorderWindow};
+#[cfg(feature = "pcap")]
+use geoipsed::pcap;
+#[cfg(feature = "serve")]
+use geoipsed::serve;
+use geoipsed::sink::http::PostFormat;
+use geoipsed::sink::text::TextSink;
+use geoipsed::sink::{LineWriter, Sink};
+use geoipsed::template::Template;
+use geoipsed::{
+    bench, dedup, domain, encoding, enrich, evtx, export, framed, geoip, geomath, intel, journal,
+    json, logformat, mac, mmdb, sample, sink, stats,
+};
 
 const BUFFERSIZE: usize = 64 * 1024;
 
@@ -46,35 +75,1218 @@ fn get_input(path: Option<Utf8PathBuf>) -> Result<Box<dyn Read + Send + 'static>
     Ok(reader)
 }
 
+/// Invoke `f` on each line of the input, or the --sample of them,
+/// terminator included, logging the throughput of each input and showing it
+/// with --progress. Returns the number of lines read
+fn for_each_line<F>(path: &Utf8Path, args: &Args, mut f: F) -> Result<u64>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    for_each_source_line(path, args, |_, line| f(line))
+}
+
+/// [`for_each_line`], also passing `f` the source of each line: `path`, or
+/// "ARCHIVE!MEMBER" for the members of archives
+fn for_each_source_line<F>(path: &Utf8Path, args: &Args, mut f: F) -> Result<u64>
+where
+    F: FnMut(&str, &[u8]) -> Result<()>,
+{
+    let _span = tracing::info_span!("input", %path).entered();
+    let start = Instant::now();
+    let (mut lines, mut bytes) = (0u64, 0u64);
+    let bar = progress_bar(path, args);
+    // bytes not yet added to the bar, which is updated in steps
+    let mut unreported = 0u64;
+    let mut sampler = args.sample.map(|rate| {
+        // inputs of one run are sampled independently of each other
+        let mut hasher = FxHasher::default();
+        path.hash(&mut hasher);
+        let seed = args.seed.expect("seed is set with sample");
+        sample::Sampler::new(rate, seed ^ hasher.finish())
+    });
+    tracing::debug!(mmap = args.mmap, "reading input");
+    let read = read_lines(path, args, |source, line| {
+        lines += 1;
+        bytes += line.len() as u64;
+        if let Some(bar) = &bar {
+            unreported += line.len() as u64;
+            if unreported >= PROGRESS_STEP {
+                bar.inc(std::mem::take(&mut unreported));
+            }
+        }
+        match sampler.as_mut().is_none_or(sample::Sampler::keep) {
+            true => f(source, line),
+            false => Ok(()),
+        }
+    });
+    match read {
+        Err(e) if e.is::<StopReading>() => tracing::debug!("stopped reading early"),
+        other => other?,
+    }
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    let elapsed = start.elapsed();
+    tracing::debug!(
+        lines,
+        bytes,
+        elapsed_ms = elapsed.as_millis() as u64,
+        mb_per_sec = bytes as f64 / 1e6 / elapsed.as_secs_f64().max(1e-9),
+        "finished input"
+    );
+    Ok(lines)
+}
+
+/// Returned by the `f` of [`for_each_line`] to stop reading the input
+/// early, e.g. once --max-count is reached. Not an error of the run
+#[derive(Debug)]
+struct StopReading;
+
+impl fmt::Display for StopReading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("stopped reading the input")
+    }
+}
+
+impl std::error::Error for StopReading {}
+
+/// Bytes of input handed to a worker at a time with --threads
+const BATCH_SIZE: usize = 256 * 1024;
+
+/// Batches each worker may be ahead of the output with --threads
+const BATCHES_AHEAD: u64 = 4;
+
+/// Consecutive lines of the input, with the end offset of each
+#[derive(Default)]
+struct Batch {
+    seq: u64,
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl Batch {
+    fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts
+            .zip(&self.ends)
+            .map(|(start, &end)| &self.data[start..end])
+    }
+}
+
+/// [`for_each_line`] on one thread per element of `states`. Batches of lines
+/// are decorated by `f` with the state of the worker that took them, into a
+/// buffer that is passed to `out` in input order by an [`OrderedWriter`].
+/// The states carry caches and counts across inputs
+fn for_each_line_parallel<S, F>(
+    path: &Utf8Path,
+    args: &Args,
+    out: &mut (dyn Sink + Send),
+    states: &mut [S],
+    f: &F,
+) -> Result<u64>
+where
+    S: Send,
+    F: Fn(&mut S, &[u8], &mut dyn Write) -> Result<()> + Sync,
+{
+    let window = ReorderWindow::new(BATCHES_AHEAD * states.len() as u64);
+    let (batch_tx, batch_rx) = mpsc::sync_channel::<Batch>(states.len());
+    // dropped with the last worker, so that sending fails once all are gone
+    let batch_rx = Arc::new(Mutex::new(batch_rx));
+    let (done_tx, done_rx) = mpsc::sync_channel::<(u64, Result<Vec<u8>>)>(states.len());
+
+    thread::scope(|scope| {
+        for state in states.iter_mut() {
+            let batch_rx = Arc::clone(&batch_rx);
+            let done_tx = done_tx.clone();
+            scope.spawn(move || loop {
+                let batch = batch_rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                let Ok(batch) = batch else { break };
+                let mut buf = Vec::with_capacity(batch.data.len() * 2);
+                let result = batch
+                    .lines()
+                    .try_for_each(|line| f(state, line, &mut buf))
+                    .map(|()| buf);
+                if done_tx.send((batch.seq, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop((batch_rx, done_tx));
+
+        let window = &window;
+        let writer = scope.spawn(move || -> Result<()> {
+            let terminator = args.line_terminator().as_byte();
+            let mut ordered = OrderedWriter::new(LineWriter::new(out, terminator));
+            for (seq, result) in done_rx {
+                match result.and_then(|chunk| Ok(ordered.push(seq, chunk)?)) {
+                    Ok(written) => window.advance(written),
+                    Err(err) => {
+                        window.close();
+                        return Err(err);
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        let mut batch = Batch::default();
+        let send = |batch: Batch| -> Result<()> {
+            window.enter(batch.seq);
+            batch_tx
+                .send(batch)
+                .map_err(|_| anyhow::anyhow!("workers stopped"))
+        };
+        let read = for_each_line(path, args, |line| {
+            batch.data.extend_from_slice(line);
+            batch.ends.push(batch.data.len());
+            if batch.data.len() >= BATCH_SIZE {
+                let seq = batch.seq + 1;
+                send(std::mem::replace(
+                    &mut batch,
+                    Batch {
+                        seq,
+                        ..Batch::default()
+                    },
+                ))?;
+            }
+            Ok(())
+        })
+        .and_then(|lines| {
+            if !batch.ends.is_empty() {
+                send(batch)?;
+            }
+            Ok(lines)
+        });
+        drop(batch_tx);
+        // an error of the writer, e.g. a broken pipe, is why sending failed
+        writer.join().expect("writer does not panic")?;
+        read
+    })
+}
+
+/// Bytes read between updates of the progress bar
+const PROGRESS_STEP: u64 = 1 << 16;
+
+/// Progress bar of a file input with --progress. There is none for stdin
+/// and other pipes, whose size is unknown, or when stderr is not a terminal
+fn progress_bar(path: &Utf8Path, args: &Args) -> Option<ProgressBar> {
+    if !args.progress || path.as_os_str() == "-" || !io::stderr().is_terminal() {
+        return None;
+    }
+    let metadata = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let bar = ProgressBar::with_draw_target(Some(metadata.len()), ProgressDrawTarget::stderr());
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{prefix} {wide_bar} {bytes}/{total_bytes} {binary_bytes_per_sec} ETA {eta}",
+        )
+        .expect("valid progress template"),
+    );
+    bar.set_prefix(path.to_string());
+    Some(bar)
+}
+
+/// Totals of a run, reported on stderr with --progress
+struct Summary {
+    start: Instant,
+    lines: u64,
+    matches: u64,
+    /// matches served from cached lookups of IPs without a record
+    negative_hits: u64,
+}
+
+impl Summary {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            lines: 0,
+            matches: 0,
+            negative_hits: 0,
+        }
+    }
+
+    /// Report the totals, with the number of `unique` matches
+    fn report(&self, args: &Args, unique: usize) {
+        if args.progress {
+            let negative = match self.negative_hits {
+                0 => String::new(),
+                hits => format!(", {hits} cache hits without a record"),
+            };
+            eprintln!(
+                "{} lines, {} matches, {unique} unique{negative} in {:.2}s",
+                self.lines,
+                self.matches,
+                self.start.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
+
+/// The --max-count and --max-matches limits of a run
+struct MatchLimits {
+    max_count: Option<u64>,
+    max_matches: Option<u64>,
+    /// lines with matches output from the current input
+    lines: u64,
+    /// matches output from all inputs
+    matches: u64,
+}
+
+impl MatchLimits {
+    fn new(args: &Args) -> Self {
+        Self {
+            max_count: args.max_count,
+            max_matches: args.max_matches,
+            lines: 0,
+            matches: 0,
+        }
+    }
+
+    /// Start counting the lines of the next input
+    fn next_input(&mut self) {
+        self.lines = 0;
+    }
+
+    /// How many of `found` matches may still be output
+    fn allowed(&self, found: u64) -> u64 {
+        self.max_matches
+            .map_or(found, |max| found.min(max.saturating_sub(self.matches)))
+    }
+
+    /// Count a line output with `found` matches, and stop reading the input
+    /// with [`StopReading`] once a limit is reached
+    fn count(&mut self, found: u64) -> Result<()> {
+        if found == 0 {
+            return Ok(());
+        }
+        self.lines += 1;
+        self.matches += found;
+        if self.max_count.is_some_and(|max| self.lines >= max) || self.is_exhausted() {
+            return Err(StopReading.into());
+        }
+        Ok(())
+    }
+
+    /// Whether --max-matches is reached, so that no more inputs are read
+    fn is_exhausted(&self) -> bool {
+        self.max_matches.is_some_and(|max| self.matches >= max)
+    }
+}
+
+/// Lines of the input for [`for_each_line`]. When `--mmap` is set and the
+/// input is a regular file, the file is memory-mapped and lines are sliced
+/// directly out of the mapping instead of copied through a buffer. Lines
+/// pass through a [`LineGuard`] for --binary and --max-line-length
+fn read_lines<F>(path: &Utf8Path, args: &Args, mut f: F) -> Result<()>
+where
+    F: FnMut(&str, &[u8]) -> Result<()>,
+{
+    let terminator = args.line_terminator();
+
+    #[cfg(feature = "pcap")]
+    if args.pcap {
+        return pcap::for_each_packet_line(path, args.obfuscated_ipv4, |line| {
+            f(path.as_str(), line)
+        });
+    }
+    if args.evtx {
+        return evtx::for_each_record_line(path, |line| f(path.as_str(), line));
+    }
+    #[cfg(feature = "archive")]
+    if let Some(kind) = archive::ArchiveKind::of(path) {
+        let glob = member_glob(args)?;
+        return archive::for_each_member(path, kind, glob.as_ref(), |member, reader| {
+            let source = format!("{path}!{member}");
+            read_stream(path, args, reader, |line| f(&source, line))
+        });
+    }
+
+    if args.mmap && path.as_os_str() != "-" {
+        let file = File::open(path)?;
+        // pipes and devices, e.g. /dev/stdin or <(cmd), cannot be mapped
+        if !file.metadata()?.is_file() {
+            let reader = BufReader::with_capacity(BUFFERSIZE, file);
+            return read_stream(path, args, reader, |line| f(path.as_str(), line));
+        }
+        let mut guard = LineGuard::new(path, args, |line: &[u8]| f(path.as_str(), line));
+        // safety: the mapping is only read for the duration of this call. as
+        // with grep/ripgrep, truncating the file underneath us is the caller's
+        // problem and may result in a SIGBUS
+        let mmap = unsafe { Mmap::map(&file)? };
+        for line in LineIter::new(terminator.as_byte(), &mmap) {
+            if !guard.feed(line, true)? {
+                break;
+            }
+        }
+        return guard.finish();
+    }
+
+    let reader = get_input(Some(path.to_owned()))?;
+    read_stream(path, args, reader, |line| f(path.as_str(), line))
+}
+
+/// Whether `path` is read as an archive of inputs rather than as lines
+#[cfg(feature = "archive")]
+fn is_archive(path: &Utf8Path) -> bool {
+    archive::ArchiveKind::of(path).is_some()
+}
+
+#[cfg(not(feature = "archive"))]
+fn is_archive(_path: &Utf8Path) -> bool {
+    false
+}
+
+/// Write the `output` lines of a line read from an archive member, each
+/// prefixed with the "ARCHIVE!MEMBER:" `source`
+fn write_with_source(
+    out: &mut (impl Sink + ?Sized),
+    source: &str,
+    output: &[u8],
+    terminator: u8,
+) -> Result<()> {
+    let mut prefixed = Vec::new();
+    for line in output.split_inclusive(|&b| b == terminator) {
+        prefixed.clear();
+        write!(prefixed, "{source}:")?;
+        prefixed.extend_from_slice(line);
+        out.write_line(&prefixed)?;
+    }
+    Ok(())
+}
+
+/// The --glob patterns of the archive members to read, if any
+#[cfg(feature = "archive")]
+fn member_glob(args: &Args) -> Result<Option<globset::GlobSet>> {
+    if args.glob.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = globset::GlobSetBuilder::new();
+    for pattern in &args.glob {
+        builder.add(globset::Glob::new(pattern)?);
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// The lines of `reader`, the contents of `path`, for [`read_lines`]
+fn read_stream<'a, F>(path: &Utf8Path, args: &Args, reader: impl Read + 'a, f: F) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let terminator = args.line_terminator();
+    let mut guard = LineGuard::new(path, args, f);
+    let reader: Box<dyn Read + 'a> = match args.encoding {
+        Some(encoding) => encoding.decode(reader)?,
+        None => Box::new(reader),
+    };
+    if let Some(max) = args.max_line_length {
+        // the line buffer grows to fit any line, so read pieces of at most
+        // max bytes instead
+        read_pieces(reader, terminator.as_byte(), max, |piece, complete| {
+            guard.feed(piece, complete)
+        })?;
+        return guard.finish();
+    }
+
+    let mut line_buffer = LineBufferBuilder::new()
+        .line_terminator(terminator.as_byte())
+        .build();
+    let mut lb_reader = LineBufferReader::new(reader, &mut line_buffer);
+
+    // line reader
+    'fill: while lb_reader.fill()? {
+        for line in LineIter::new(terminator.as_byte(), lb_reader.buffer()) {
+            if !guard.feed(line, true)? {
+                break 'fill;
+            }
+        }
+        lb_reader.consume_all();
+    }
+    guard.finish()
+}
+
+/// Read lines of at most `max` bytes, terminator excluded, from `reader`.
+/// Longer lines are passed to `f` in pieces of `max` bytes, and `f` is told
+/// whether each piece ends its line. Stops when `f` returns false
+fn read_pieces<F>(reader: impl Read, terminator: u8, max: usize, mut f: F) -> Result<()>
+where
+    F: FnMut(&[u8], bool) -> Result<bool>,
+{
+    let mut reader = BufReader::with_capacity(BUFFERSIZE, reader);
+    let mut line = Vec::with_capacity(max.min(BUFFERSIZE) + 1);
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break;
+        }
+        if line.len() == max {
+            // a full piece ends its line only if the terminator comes next
+            let complete = buf[0] == terminator;
+            if complete {
+                line.push(terminator);
+                reader.consume(1);
+            }
+            let more = f(&line, complete)?;
+            line.clear();
+            if !more {
+                return Ok(());
+            }
+            continue;
+        }
+        let window = &buf[..buf.len().min(max - line.len())];
+        match memchr::memchr(terminator, window) {
+            Some(i) => {
+                line.extend_from_slice(&window[..=i]);
+                reader.consume(i + 1);
+                let more = f(&line, true)?;
+                line.clear();
+                if !more {
+                    return Ok(());
+                }
+            }
+            None => {
+                let n = window.len();
+                line.extend_from_slice(window);
+                reader.consume(n);
+            }
+        }
+    }
+    if !line.is_empty() {
+        f(&line, true)?;
+    }
+    Ok(())
+}
+
+/// Applies --binary and --max-line-length to the lines of one input before
+/// passing them on
+struct LineGuard<'a, F> {
+    path: &'a Utf8Path,
+    terminator: LineTerminator,
+    binary: BinaryMode,
+    max_line_length: Option<usize>,
+    long_lines: LongLines,
+    f: F,
+    // offset of the next piece in the input
+    offset: u64,
+    // dropping the rest of a line that was skipped
+    dropping: bool,
+    // end of a chunked line not passed on yet
+    carry: Vec<u8>,
+    skipped_long: u64,
+    skipped_binary: u64,
+}
+
+impl<'a, F> LineGuard<'a, F>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    fn new(path: &'a Utf8Path, args: &Args, f: F) -> Self {
+        Self {
+            path,
+            terminator: args.line_terminator(),
+            binary: args.binary,
+            max_line_length: args.max_line_length,
+            long_lines: args.long_lines,
+            f,
+            offset: 0,
+            dropping: false,
+            carry: Vec::new(),
+            skipped_long: 0,
+            skipped_binary: 0,
+        }
+    }
+
+    /// Pass on `piece` of a line, which is the end of the line if
+    /// `complete`. Returns false once the rest of the input is to be skipped
+    fn feed(&mut self, piece: &[u8], complete: bool) -> Result<bool> {
+        let offset = self.offset;
+        self.offset += piece.len() as u64;
+        if self.dropping {
+            self.dropping = !complete;
+            return Ok(true);
+        }
+        // with --null-data NUL bytes end lines rather than mark binary data
+        if self.binary != BinaryMode::Text && self.terminator.as_byte() != 0 {
+            if let Some(nul) = memchr::memchr(0, piece) {
+                if self.binary == BinaryMode::Skip {
+                    eprintln!(
+                        "warning: {}: binary data at byte {}, skipping the rest of the input. \
+                         Use --encoding for UTF-16 input or --binary=text to process it",
+                        self.path,
+                        offset + nul as u64
+                    );
+                    return Ok(false);
+                }
+                self.skipped_binary += 1;
+                self.dropping = !complete;
+                return Ok(true);
+            }
+        }
+        let Some(max) = self.max_line_length else {
+            (self.f)(piece)?;
+            return Ok(true);
+        };
+        let content = match complete {
+            true => without_terminator(piece, self.terminator).len(),
+            false => piece.len(),
+        };
+        let long = content > max || !complete;
+        match (long, self.long_lines) {
+            (true, LongLines::Skip) => {
+                self.skipped_long += 1;
+                self.dropping = !complete;
+            }
+            (true, LongLines::Chunk) => self.chunk(piece, complete, max)?,
+            (false, _) if self.carry.is_empty() => (self.f)(piece)?,
+            (false, _) => self.chunk(piece, complete, max)?,
+        }
+        Ok(true)
+    }
+
+    /// Pass on `piece` in chunks of at most `max` bytes, each cut after the
+    /// last byte that cannot be part of an entity so that addresses are only
+    /// split when they are in a run of more than `max` such bytes. Unless
+    /// the line is `complete` the rest is kept for the next piece
+    fn chunk(&mut self, piece: &[u8], complete: bool, max: usize) -> Result<()> {
+        let mut data = std::mem::take(&mut self.carry);
+        data.extend_from_slice(piece);
+        let mut rest = &data[..];
+        while rest.len() > max {
+            let cut = rest[..max]
+                .iter()
+                .rposition(|&b| !(b.is_ascii_alphanumeric() || b == b'.' || b == b':'))
+                .map_or(max, |i| i + 1);
+            (self.f)(&rest[..cut])?;
+            rest = &rest[cut..];
+        }
+        if complete {
+            (self.f)(rest)?;
+        } else {
+            self.carry = rest.to_vec();
+        }
+        Ok(())
+    }
+
+    /// Warn about the lines that were skipped
+    fn finish(self) -> Result<()> {
+        if self.skipped_long > 0 {
+            eprintln!(
+                "warning: {}: skipped {} lines longer than --max-line-length {}",
+                self.path,
+                self.skipped_long,
+                self.max_line_length.unwrap_or_default()
+            );
+        }
+        if self.skipped_binary > 0 {
+            eprintln!(
+                "warning: {}: skipped {} lines of binary data",
+                self.path, self.skipped_binary
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Iterate the entities in `line`, restricted to the address fields of
+/// `format` if one is given
+#[inline]
+fn find_iter<'e, 'h>(
+    extractor: &'e Extractor,
+    format: Option<logformat::LogFormat>,
+    line: &'h [u8],
+) -> impl Iterator<Item = Entity<'e, 'h>> + 'e
+where
+    'h: 'e,
+{
+    let spans = format.map(|f| f.ip_value_spans(line));
+    extractor.find_iter(line).filter(move |m| match &spans {
+        Some(spans) => spans
+            .iter()
+            .any(|s| s.start <= m.start() && m.end() <= s.end),
+        None => true,
+    })
+}
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+#[clap(group(ArgGroup::new("json_mode").args(["json_input", "preset"]).multiple(true)))]
+#[clap(group(ArgGroup::new("post").args(["post_url", "splunk_hec"])))]
+#[clap(group(ArgGroup::new("rewrite").args(["in_place", "output_dir"])))]
+#[clap(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     /// Show only nonempty parts of lines that match
     #[clap(short, long)]
     only_matching: bool,
 
-    /// Use markers to highlight the matching strings
-    #[clap(short = 'C', long, value_enum, default_value_t = ArgsColorChoice::Auto)]
+    /// Use markers to highlight the matching strings, or the bars of
+    /// stats --chart
+    #[clap(
+        short = 'C',
+        long,
+        value_enum,
+        default_value_t = ArgsColorChoice::Auto,
+        global = true
+    )]
     color: ArgsColorChoice,
 
+    /// Color of the decorations of IPv4 addresses: a name like red or
+    /// green, an ANSI 256-color number, or R,G,B
+    #[clap(long, value_name = "COLOR", default_value = "red", global = true)]
+    color_v4: termcolor::Color,
+
+    /// Color of the decorations of IPv6 addresses, see --color-v4
+    #[clap(long, value_name = "COLOR", default_value = "cyan", global = true)]
+    color_v6: termcolor::Color,
+
+    /// Which part of the IP decorations to color: the whole decoration, or
+    /// only the {ip} field of it
+    #[clap(
+        long,
+        value_enum,
+        value_name = "SCOPE",
+        default_value = "decoration",
+        global = true
+    )]
+    color_scope: geoip::ColorScope,
+
     /// Specify the format of the IP address decoration. Use the --list-templates option
     /// to see which fields are available. Field names are enclosed in {}, for example
     /// "{field1} any fixed string {field2} & {field3}"
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     template: Option<String>,
 
-    /// Specify directory containing GeoLite2-ASN.mmdb and GeoLite2-City.mmdb
-    #[clap(short = 'I', value_name = "DIR", value_hint = clap::ValueHint::DirPath, env = "MAXMIND_MMDB_DIR")]
+    /// Specify directory containing GeoLite2-ASN.mmdb and GeoLite2-City.mmdb.
+    /// Other .mmdb files are recognized as ASN or City databases by their
+    /// metadata, e.g. DBIP-ASN-Lite and DBIP-City-Lite. Defaults to
+    /// $GEOIP_MMDB_DIR, then $MAXMIND_MMDB_DIR, then /usr/share/GeoIP or
+    /// /usr/local/share/GeoIP
+    #[clap(short = 'I', value_name = "DIR", value_hint = clap::ValueHint::DirPath, global = true)]
     include: Option<Utf8PathBuf>,
 
+    /// Fill in the fields the databases of -I have no value for from the
+    /// databases in DIR, field by field. Repeat to fall back further, in
+    /// order
+    #[clap(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath, global = true)]
+    fallback_dir: Vec<Utf8PathBuf>,
+
+    /// Take {asnnum} from a CAIDA prefix2as style table ("prefix length asn"
+    /// or "prefix/length asn" per line) instead of GeoLite2-ASN.mmdb
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, global = true)]
+    pfx2as: Option<Utf8PathBuf>,
+
     /// Display a list of available template substitution parameters to
     /// use in --template format string
     #[clap(short = 'L', long)]
     list_templates: bool,
 
+    /// Use a curated template instead of writing one, see --list-presets
+    #[clap(long, value_enum, value_name = "PRESET", conflicts_with = "template")]
+    template_preset: Option<geoip::TemplatePreset>,
+
+    /// Display the --template-preset names and their templates
+    #[clap(long)]
+    list_presets: bool,
+
+    /// Print --list-templates as JSON, e.g. for tools that build templates
+    #[clap(long, requires = "list_templates")]
+    json: bool,
+
+    /// Show a progress bar with throughput and ETA on stderr while reading
+    /// files, and totals of lines, matches, and unique matches at the end,
+    /// with the repeats of IPs no database has a record of
+    #[clap(long, global = true)]
+    progress: bool,
+
+    /// Log diagnostics, such as how databases were found and the throughput
+    /// of each input, on stderr at LEVEL or more severe
+    #[clap(
+        long,
+        value_enum,
+        value_name = "LEVEL",
+        default_value = "warn",
+        global = true
+    )]
+    log_level: LogLevel,
+
+    /// Format of the diagnostics logged on stderr
+    #[clap(long, value_enum, default_value = "text", global = true)]
+    log_format: LogOutput,
+
+    /// Only decorate the address fields of the given log format (e.g. src=,
+    /// dst=, dvc= of CEF) and leave free-text payloads and lines in other
+    /// formats untouched
+    #[clap(long, value_enum, global = true)]
+    format: Option<logformat::LogFormat>,
+
+    /// Parse each input line as a JSON object and add geoip records next to
+    /// the string fields that contain IPs instead of decorating the raw text.
+    /// Lines that are not JSON objects are passed through unchanged
+    #[clap(long, conflicts_with_all = ["only_matching", "format"])]
+    #[cfg_attr(feature = "pcap", clap(conflicts_with = "pcap"))]
+    json_input: bool,
+
+    /// Comma-separated list of top-level fields to enrich in --json-input
+    /// mode. If empty, all string values are examined. The record(s) for a
+    /// field named "src_ip" are added as "src_ip_geo"
+    #[clap(
+        long,
+        value_name = "FIELDS",
+        value_delimiter = ',',
+        requires = "json_mode"
+    )]
+    json_fields: Vec<String>,
+
+    /// Enrich the IP fields of a well-known JSON log schema, e.g. id.orig_h
+    /// and id.resp_h of Zeek or src_ip and dest_ip of Suricata EVE. Implies
+    /// --json-input
+    #[clap(long, value_enum, conflicts_with_all = ["only_matching", "format"])]
+    #[cfg_attr(feature = "pcap", clap(conflicts_with = "pcap"))]
+    preset: Option<json::Preset>,
+
+    /// Treat input files as pcap/pcapng packet captures. Each IP packet
+    /// becomes a line of "<timestamp> <src> <dst>" followed by any IPs found
+    /// in its payload, which is then decorated like any other input
+    #[cfg(feature = "pcap")]
+    #[clap(long, conflicts_with_all = ["format", "mmap"], global = true)]
+    pcap: bool,
+
+    /// Only read the members of .tar, .tar.gz/.tgz, and .zip inputs whose
+    /// path in the archive matches GLOB, e.g. "*.log" or "var/log/**". Can be
+    /// repeated
+    #[cfg(feature = "archive")]
+    #[clap(long, value_name = "GLOB", global = true)]
+    glob: Vec<String>,
+
+    /// Treat input files as Windows EVTX event logs. Each event record
+    /// becomes an NDJSON line of its record_id, timestamp, and the strings
+    /// of its event, which is enriched as with --json-input unless -o or
+    /// --output is given
+    #[clap(long, conflicts_with_all = ["format", "mmap"], global = true)]
+    #[cfg_attr(feature = "pcap", clap(conflicts_with = "pcap"))]
+    evtx: bool,
+
+    /// Comma-separated kinds of entities to extract and decorate: ip, mac,
+    /// domain. MAC addresses are decorated with --mac-template and domains
+    /// with --domain-template
+    #[clap(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "ip",
+        conflicts_with_all = ["json_mode", "output"]
+    )]
+    entities: Vec<EntityArg>,
+
+    /// Specify the format of domain decorations. Fields are {domain},
+    /// {registered}, {suffix}, and with --resolve the ip fields of the
+    /// address the domain resolves to
+    #[clap(long, value_name = "TEMPLATE")]
+    domain_template: Option<String>,
+
+    /// Resolve extracted domains and decorate them with the ip fields of
+    /// their first A/AAAA record
+    #[clap(long)]
+    resolve: bool,
+
+    /// Give up on resolving the domains of a line after MILLISECONDS
+    #[clap(
+        long,
+        value_name = "MILLISECONDS",
+        default_value_t = 2000,
+        requires = "resolve"
+    )]
+    resolve_timeout: u64,
+
+    /// Resolve at most N domains at once
+    #[clap(long, value_name = "N", default_value_t = 16, requires = "resolve")]
+    resolve_concurrency: usize,
+
+    /// Specify the format of MAC address decorations. Fields are {mac} and
+    /// {vendor}
+    #[clap(long, value_name = "TEMPLATE")]
+    mac_template: Option<String>,
+
+    /// Load MAC address vendors from the IEEE oui.csv registry or a
+    /// Wireshark manuf file for the {vendor} field
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    oui: Option<Utf8PathBuf>,
+
+    /// Send the extracted IPs and their geoip fields to a structured output
+    /// instead of printing decorated text. Supported: sqlite:PATH,
+    /// parquet:PATH, jsonl[:PATH], csv[:PATH], es-bulk[:PATH]
+    /// (Elasticsearch _bulk requests of ECS documents), to stdout if no PATH
+    #[clap(long, value_name = "KIND:PATH", conflicts_with_all = ["only_matching", "json_mode", "ecs"])]
+    output: Option<sink::OutputSpec>,
+
+    /// Elasticsearch index of --output es-bulk documents
+    #[clap(long, value_name = "NAME", default_value = sink::DEFAULT_INDEX)]
+    index: String,
+
+    /// POST the extracted IPs and their geoip fields as NDJSON records to an
+    /// http:// endpoint, e.g. a SOAR webhook, retrying failures with backoff.
+    /// With --output es-bulk, post to an Elasticsearch _bulk API instead.
+    /// The Authorization header is taken from $GEOIPSED_POST_AUTH
+    #[clap(
+        long,
+        value_name = "URL",
+        conflicts_with_all = ["only_matching", "json_mode", "ecs"]
+    )]
+    post_url: Option<String>,
+
+    /// Send the extracted IPs and their geoip fields as events to the Splunk
+    /// HTTP Event Collector at URL, e.g.
+    /// http://splunk:8088/services/collector/event, retrying failures with
+    /// backoff
+    #[clap(
+        long,
+        value_name = "URL",
+        requires = "token",
+        conflicts_with_all = ["only_matching", "json_mode", "ecs", "output"]
+    )]
+    splunk_hec: Option<String>,
+
+    /// Name of the environment variable holding the --splunk-hec token
+    #[clap(long, value_name = "ENVVAR", requires = "splunk_hec")]
+    token: Option<String>,
+
+    /// Records per --post-url or --splunk-hec request
+    #[clap(long, value_name = "N", default_value_t = 500, requires = "post", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    batch: usize,
+
+    /// Load a threat-intel list of IPs/CIDRs (plain text, CSV, or MISP event
+    /// JSON) to match against. Can be repeated. Exposes the {intel_hit} and
+    /// {intel_source} template fields
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, global = true)]
+    intel: Vec<Utf8PathBuf>,
+
+    /// Also extract IPv4 addresses written as decimal, hex, or octal numbers
+    /// or dotted quads with hex or octal parts (e.g. 3232235777, 0xC0A80101,
+    /// 0300.0250.0001.0001) and decorate them as their dotted quad. Off by
+    /// default as plain numbers are easily mistaken for addresses
+    #[clap(long, global = true)]
+    obfuscated_ipv4: bool,
+
+    /// Language of the {city} and {country_full} names, e.g. de, fr, ja,
+    /// pt-BR, or zh-CN. Names missing in it are given in English
+    #[clap(long, value_name = "LANG", default_value = "en", global = true)]
+    lang: String,
+
+    /// How spaces in decorations, e.g. in organization names, are written.
+    /// Underscore by default, or quote with --template-preset kv, whose
+    /// spaces separate its pairs
+    #[clap(long, value_enum, value_name = "POLICY", global = true)]
+    space_policy: Option<geoip::SpacePolicy>,
+
+    /// How true/false fields, e.g. {is_anycast} and {intel_hit}, are
+    /// rendered
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = geoip::BoolFormat::TrueFalse,
+        global = true
+    )]
+    bool_format: geoip::BoolFormat,
+
+    /// Measure the {distance_km} template field from this point, given as
+    /// LAT,LON in degrees, e.g. an office location for impossible travel
+    /// triage
+    #[clap(
+        long,
+        value_name = "LAT,LON",
+        allow_hyphen_values = true,
+        global = true
+    )]
+    ref_point: Option<geomath::Point>,
+
+    /// Write the geoip records of --json-input and serve under their Elastic
+    /// Common Schema names, e.g. geo.country_iso_code, as.number, and
+    /// as.organization.name, leaving out fields without one
+    #[clap(long, global = true)]
+    ecs: bool,
+
+    /// Keep spaces in decorations, the same as --space-policy keep
+    #[clap(long, conflicts_with = "space_policy", global = true)]
+    no_space_replace: bool,
+
+    /// Warn on stderr when a database was built more than DAYS ago. Use 0 to
+    /// disable the warning
+    #[clap(long, value_name = "DAYS", default_value_t = 90, global = true)]
+    max_db_age: u64,
+
+    /// Fail instead of running when a database was built more than DAYS ago
+    #[clap(long, value_name = "DAYS", global = true)]
+    strict_db_age: Option<u64>,
+
+    /// Load a CSV of "cidr,label" pairs, e.g. an internal asset inventory,
+    /// and expose the label of the most specific network containing each IP
+    /// as the --enrich-field template field
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, global = true)]
+    enrich_csv: Option<Utf8PathBuf>,
+
+    /// Template field name of the --enrich-csv labels
+    #[clap(
+        long,
+        value_name = "NAME",
+        default_value = "label",
+        requires = "enrich_csv",
+        global = true
+    )]
+    enrich_field: String,
+
+    /// Only process a random sample of RATE of the lines, given as a
+    /// percentage (e.g. 1%) or fraction (e.g. 0.01), for quick estimates of
+    /// e.g. the country or ASN distribution of very large inputs
+    #[clap(long, value_name = "RATE", global = true)]
+    sample: Option<sample::SampleRate>,
+
+    /// Seed of the --sample choice of lines, so that runs with the same seed
+    /// sample the same lines. Random by default
+    #[clap(long, value_name = "N", requires = "sample", global = true)]
+    seed: Option<u64>,
+
+    /// When decorating, skip lines whose set of extracted entities was
+    /// already seen within the last N lines, or N seconds if written as e.g.
+    /// 30s, to quiet a scanner flooding a log being tailed
+    #[clap(long, value_name = "N", conflicts_with = "threads")]
+    dedup_window: Option<dedup::DedupWindow>,
+
+    /// Only output IPs listed by an --intel source. When decorating, only
+    /// lines containing at least one such IP are printed
+    #[clap(long, requires = "intel", global = true)]
+    only_intel_hits: bool,
+
+    /// Memory-map input files instead of reading them through a buffer. Has
+    /// no effect when reading from stdin
+    #[clap(long, global = true)]
+    mmap: bool,
+
+    /// Skip or split lines longer than N bytes, see --long-lines. Without
+    /// it, a line is read into memory whole however long it is
+    #[clap(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), global = true)]
+    max_line_length: Option<usize>,
+
+    /// What to do with lines longer than --max-line-length: skip them, or
+    /// process them in pieces of at most that length. Addresses spanning
+    /// two pieces are not found
+    #[clap(
+        long,
+        value_enum,
+        default_value = "chunk",
+        requires = "max_line_length",
+        global = true
+    )]
+    long_lines: LongLines,
+
+    /// How to treat binary input, recognized by NUL bytes: skip the rest of
+    /// an input from its first NUL byte on, process only its lines without
+    /// NUL bytes, or process it all as text. Not applied with --null-data
+    #[clap(long, value_enum, default_value = "skip", global = true)]
+    binary: BinaryMode,
+
+    /// Edit the input files in place instead of printing to stdout. With a
+    /// SUFFIX, e.g. --in-place=.bak, the original of each file is kept with
+    /// the suffix appended to its name
+    #[clap(
+        short = 'i',
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "",
+        conflicts_with_all = ["output", "evtx"]
+    )]
+    #[cfg_attr(feature = "pcap", clap(conflicts_with = "pcap"))]
+    in_place: Option<String>,
+
+    /// Rewrite IP decorations made with --template, or the default one,
+    /// back to the bare IPs, e.g. to feed decorated logs to tools that need
+    /// the original format. No databases are needed
+    #[clap(long, conflicts_with_all = ["only_matching", "json_mode", "output"])]
+    strip: bool,
+
+    /// Keep the modification times of files edited with --in-place or
+    /// written to --output-dir
+    #[clap(long, requires = "rewrite")]
+    keep_mtime: bool,
+
+    /// Write the output of each input file to its own file in DIR at the
+    /// same relative path, e.g. logs/a.log to DIR/logs/a.log, instead of
+    /// printing everything to stdout
+    #[clap(
+        long,
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        conflicts_with_all = ["output", "post", "input_fifo", "framed", "journal"]
+    )]
+    output_dir: Option<Utf8PathBuf>,
+
+    /// Stop reading each input after N lines with matches, like grep -m
+    #[clap(
+        short = 'm',
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["json_mode", "output", "post", "in_place", "threads", "input_fifo", "framed", "journal", "strip", "evtx"]
+    )]
+    max_count: Option<u64>,
+
+    /// Stop reading all inputs once N matches were output in total. Without
+    /// -o, the line of the Nth match is output whole
+    #[clap(
+        long,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["json_mode", "output", "post", "in_place", "threads", "input_fifo", "framed", "journal", "strip", "evtx"]
+    )]
+    max_matches: Option<u64>,
+
+    /// Decorate lines on N threads. Output is in input order whatever the
+    /// number of threads
+    #[clap(long, value_name = "N", default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    threads: usize,
+
+    /// Look up the IPs listed one per line in FILE, in parallel, before
+    /// reading any input, so that decorating e.g. a --journal, the pipes of
+    /// --input-fifo, or requests to the services starts with a warm cache
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, global = true)]
+    prewarm: Option<Utf8PathBuf>,
+
+    /// Transcode the input from ENCODING to UTF-8 before scanning, e.g.
+    /// UTF-16 Windows event log exports. Output is UTF-8
+    #[clap(
+        long,
+        value_enum,
+        value_name = "ENCODING",
+        conflicts_with = "mmap",
+        global = true
+    )]
+    encoding: Option<encoding::InputEncoding>,
+
+    /// Lines end with CRLF, e.g. in logs from Windows. Output lines of -o
+    /// and --json-input end with CRLF too
+    #[clap(long, global = true)]
+    crlf: bool,
+
+    /// Lines end with NUL bytes instead of newlines, e.g. the output of
+    /// find -print0. Output lines of -o and --json-input end with NUL too
+    #[clap(short = '0', long, conflicts_with = "crlf", global = true)]
+    null_data: bool,
+
     /// Input file(s) to process. Leave empty or use "-" to read from stdin
     #[clap(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
     input: Vec<Utf8PathBuf>,
+
+    /// Read lines from the named pipe (or file) at PATH as they arrive and
+    /// prefix each output line with "NAME:", or add a "stream" field of NAME
+    /// with --json-input. Can be repeated to enrich several pipes in one
+    /// process
+    #[clap(
+        long,
+        value_name = "NAME=PATH",
+        conflicts_with_all = ["input", "in_place", "threads", "dedup_window"]
+    )]
+    input_fifo: Vec<NamedInput>,
+
+    /// Read JSON events framed as FRAMING from stdin and write each one,
+    /// enriched as with --json-input, as a frame to stdout as soon as it is
+    /// done, e.g. for the exec plugins of Vector and Fluent Bit. Events come
+    /// out in input order, one for every event in
+    #[clap(
+        long,
+        value_enum,
+        value_name = "FRAMING",
+        conflicts_with_all = ["input", "in_place", "threads", "dedup_window", "input_fifo", "output", "post", "only_matching"]
+    )]
+    framed: Option<framed::Framing>,
+
+    /// Read the systemd journal entries matching MATCHES (e.g.
+    /// _SYSTEMD_UNIT=sshd.service, all entries if none) through journalctl
+    /// and decorate their MESSAGE field. With --json-input, the entries are
+    /// written as JSON with MESSAGE_geo added
+    #[clap(
+        long,
+        value_name = "MATCHES",
+        num_args = 0..,
+        conflicts_with_all = ["input", "in_place", "threads", "dedup_window", "input_fifo", "framed", "output", "post", "only_matching"]
+    )]
+    journal: Option<Vec<String>>,
+
+    /// Keep reading --journal entries as they are added
+    #[clap(long, requires = "journal")]
+    follow: bool,
+}
+
+/// An input with a name, given as `NAME=PATH`
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct NamedInput {
+    name: String,
+    path: Utf8PathBuf,
+}
+
+impl FromStr for NamedInput {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once('=') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok(NamedInput {
+                name: name.to_string(),
+                path: Utf8PathBuf::from(path),
+            }),
+            _ => bail!("expected NAME=PATH, for example syslog=/var/run/syslog.fifo"),
+        }
+    }
+}
+
+impl Args {
+    /// Terminator of input lines, and of the lines printed by -o and
+    /// --json-input
+    fn line_terminator(&self) -> LineTerminator {
+        match (self.crlf, self.null_data) {
+            (true, _) => LineTerminator::crlf(),
+            (_, true) => LineTerminator::byte(0),
+            _ => LineTerminator::byte(b'\n'),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Export the unique public IPs of the input as STIX 2.1 or MISP
+    /// indicators, or as GeoJSON or KML points, with geoip enrichment
+    Export(export::ExportArgs),
+    /// Count the IPs of the input per country or ASN, as a table or a bar
+    /// chart
+    Stats(stats::StatsArgs),
+    /// Explain how a line is matched: print each candidate span, whether
+    /// it was extracted or which check excluded it, and its decoration
+    Test(TestArgs),
+    /// Measure extraction, lookup, and rendering throughput in MB/s on
+    /// generated dense, sparse, and IP-free corpora with the selected
+    /// databases
+    Bench(bench::BenchArgs),
+    /// Build MaxMind DB files, e.g. of internal networks, to use with -I or
+    /// --fallback-dir
+    Mmdb(mmdb::MmdbArgs),
+    /// Serve decorations and lookups over HTTP, keeping databases and
+    /// caches warm between requests
+    #[cfg(feature = "serve")]
+    Serve(serve::ServeArgs),
+    /// Serve decorations and lookups over gRPC (see proto/geoipsed.proto),
+    /// keeping databases and caches warm between requests
+    #[cfg(feature = "grpc")]
+    Grpc(grpc::GrpcArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct TestArgs {
+    /// Line to explain. Read from stdin if not given
+    text: Option<String>,
+
+    /// Comma-separated kinds of entities to extract: ip, mac, domain
+    #[clap(long, value_enum, value_delimiter = ',', default_value = "ip")]
+    entities: Vec<EntityArg>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
@@ -84,24 +1296,145 @@ enum ArgsColorChoice {
     Auto,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum LongLines {
+    Skip,
+    Chunk,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum BinaryMode {
+    Skip,
+    Process,
+    Text,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum LogOutput {
+    Text,
+    Json,
+}
+
+/// Kinds of entities that can be extracted
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+enum EntityArg {
+    Ip,
+    Mac,
+    Domain,
+}
+
 fn main() -> Result<()> {
     let mut args = Args::parse();
 
+    let logger = tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(args.log_level.filter())
+        .with_ansi(io::stderr().is_terminal());
+    match args.log_format {
+        LogOutput::Text => logger.init(),
+        LogOutput::Json => logger.json().init(),
+    }
+
+    if args.sample.is_some() && args.seed.is_none() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        args.seed = Some(now.as_nanos() as u64 ^ u64::from(std::process::id()));
+    }
+
+    if args.list_presets {
+        for preset in geoip::TemplatePreset::ALL {
+            println!("{:<10} {}", preset.name(), preset.template());
+        }
+        return Ok(());
+    }
+    if args.evtx && !args.only_matching && args.output.is_none() && args.post_url.is_none() {
+        args.json_input = true;
+    }
+    if let Some(preset) = args.template_preset {
+        args.template = Some(preset.template().to_string());
+    }
+
     // if user asks to see available template names
     if args.list_templates {
-        geoip::print_ip_field_names();
+        let enrich_field = args.enrich_csv.as_ref().map(|_| args.enrich_field.as_str());
+        geoip::print_ip_field_names(enrich_field, args.json);
         return Ok(());
     }
 
+    if let Some(sink::OutputSpec::EsBulk { index, .. }) = &mut args.output {
+        index.clone_from(&args.index);
+    }
+    if let Some(url) = args.post_url.take() {
+        let format = match args.output.take() {
+            None => PostFormat::Ndjson,
+            Some(sink::OutputSpec::EsBulk { path: None, index }) => PostFormat::EsBulk { index },
+            Some(_) => bail!("--post-url only combines with --output es-bulk"),
+        };
+        args.output = Some(sink::OutputSpec::Http {
+            url,
+            batch: args.batch,
+            format,
+        });
+    }
+    if let Some(url) = args.splunk_hec.take() {
+        args.output = Some(sink::OutputSpec::Http {
+            url,
+            batch: args.batch,
+            format: PostFormat::SplunkHec {
+                token_env: args.token.take().expect("--splunk-hec requires --token"),
+            },
+        });
+    }
+
+    // subcommands bring their own input files
+    if let Some(Command::Export(export)) = &mut args.command {
+        args.input = std::mem::take(&mut export.input);
+    }
+    if let Some(Command::Stats(stats)) = &mut args.command {
+        args.input = std::mem::take(&mut stats.input);
+    }
+    if let Some(Command::Test(test)) = &mut args.command {
+        args.entities = std::mem::take(&mut test.entities);
+    }
+
     // if no files specified, add stdin
     if args.input.is_empty() {
         args.input.push(Utf8PathBuf::from("-"));
     }
+    if args.in_place.is_some() && args.input.iter().any(|p| p.as_os_str() == "-") {
+        bail!("--in-place edits files and cannot be used with stdin");
+    }
 
     // determine appropriate colormode. auto simply
     // tests if stdout is a tty (if so, then yes color)
     // or otherwise don't color if it's to a file or another pipe
     let colormode = match args.color {
+        // files edited in place are not a terminal
+        ArgsColorChoice::Auto if args.in_place.is_some() => ColorChoice::Never,
         ArgsColorChoice::Auto => {
             if std::io::stdout().is_terminal() {
                 ColorChoice::Always
@@ -114,10 +1447,24 @@ fn main() -> Result<()> {
     };
 
     // invoke the command!
-    let invoke = if args.only_matching {
-        run_onlymatching(args, colormode)
-    } else {
-        run(args, colormode)
+    let invoke = match &args.command {
+        Some(Command::Export(_)) => run_export(args),
+        Some(Command::Stats(_)) => run_stats(args, colormode),
+        Some(Command::Test(_)) => run_test(args, colormode),
+        Some(Command::Bench(_)) => run_bench(args),
+        Some(Command::Mmdb(_)) => run_mmdb(args),
+        #[cfg(feature = "serve")]
+        Some(Command::Serve(_)) => run_serve(args),
+        #[cfg(feature = "grpc")]
+        Some(Command::Grpc(_)) => run_grpc(args),
+        None if !args.input_fifo.is_empty() => run_fifos(args, colormode),
+        None if args.framed.is_some() => run_framed(args),
+        None if args.journal.is_some() => run_journal(args, colormode),
+        None if args.strip => run_strip(args),
+        None if args.output.is_some() => run_sink(args),
+        None if args.json_input || args.preset.is_some() => run_json(args),
+        None if args.only_matching => run_onlymatching(args, colormode),
+        None => run(args, colormode),
     };
 
     match invoke {
@@ -126,83 +1473,1055 @@ fn main() -> Result<()> {
     }
 }
 
+/// Space policy of --space-policy and --no-space-replace
+fn space_policy(args: &Args) -> geoip::SpacePolicy {
+    match (args.no_space_replace, args.space_policy) {
+        (true, _) => geoip::SpacePolicy::Keep,
+        (false, Some(policy)) => policy,
+        (false, None) if args.template_preset == Some(geoip::TemplatePreset::Kv) => {
+            geoip::SpacePolicy::Quote
+        }
+        (false, None) => geoip::SpacePolicy::Underscore,
+    }
+}
+
+/// Extractor of the --entities kinds. Their patterns only match ASCII, so
+/// the ASCII-only profile keeps lines with non-ASCII bytes on the fast path
+fn extractor(args: &Args) -> Result<Extractor> {
+    let set = PatternSet::new()
+        .search_profile(SearchProfile::Throughput)
+        .obfuscated_ipv4(args.obfuscated_ipv4);
+    Ok(args
+        .entities
+        .iter()
+        .fold(set, |set, kind| match kind {
+            EntityArg::Ip => set.kind(EntityKind::Ip),
+            EntityArg::Mac => set.kind(EntityKind::Mac),
+            EntityArg::Domain => set.kind(EntityKind::Domain),
+        })
+        .build()?)
+}
+
+/// Decorator of MAC addresses, if they are extracted
+fn mac_decorator(args: &Args, colormode: ColorChoice) -> Result<Option<mac::MacDecorator>> {
+    if !args.entities.contains(&EntityArg::Mac) {
+        return Ok(None);
+    }
+    let oui = args.oui.as_deref().map(mac::OuiDb::load).transpose()?;
+    let template = args
+        .mac_template
+        .as_deref()
+        .unwrap_or(mac::MacDecorator::DEFAULT_TEMPLATE);
+    Ok(Some(
+        mac::MacDecorator::new(&geoip::highlight(template, colormode), oui)
+            .space_policy(space_policy(args)),
+    ))
+}
+
+/// Decorator of domains, if they are extracted
+fn domain_decorator(args: &Args, colormode: ColorChoice) -> Option<domain::DomainDecorator> {
+    if !args.entities.contains(&EntityArg::Domain) {
+        return None;
+    }
+    let resolver = args.resolve.then(|| {
+        domain::Resolver::new(
+            Duration::from_millis(args.resolve_timeout),
+            args.resolve_concurrency,
+        )
+    });
+    let template = match (&args.domain_template, args.resolve) {
+        (Some(template), _) => template,
+        (None, false) => domain::DomainDecorator::DEFAULT_TEMPLATE,
+        (None, true) => domain::DomainDecorator::RESOLVE_TEMPLATE,
+    };
+    Some(
+        domain::DomainDecorator::new(&geoip::highlight(template, colormode), resolver)
+            .space_policy(space_policy(args)),
+    )
+}
+
+/// Decorators of the entities other than ips
+struct Decorators {
+    macs: Option<mac::MacDecorator>,
+    domains: Option<domain::DomainDecorator>,
+}
+
+impl Decorators {
+    fn new(args: &Args, colormode: ColorChoice) -> Result<Self> {
+        Ok(Self {
+            macs: mac_decorator(args, colormode)?,
+            domains: domain_decorator(args, colormode),
+        })
+    }
+
+    /// Start resolving the domains among `entities` before they are
+    /// decorated one by one
+    fn prefetch<'e>(&self, entities: impl Iterator<Item = Entity<'e, 'e>>) {
+        if let Some(domains) = &self.domains {
+            domains.prefetch(
+                entities
+                    .filter(|m| *m.kind() == EntityKind::Domain)
+                    .filter_map(|m| std::str::from_utf8(m.as_bytes()).ok()),
+            );
+        }
+    }
+
+    /// Decoration of an extracted entity
+    fn decorate(&self, geoipdb: &geoip::GeoIPSed, m: &Entity, s: &str) -> String {
+        let mut out = Vec::with_capacity(s.len() + 64);
+        self.decorate_write(geoipdb, m, s, &mut out, false);
+        String::from_utf8(out).expect("decorations are valid utf-8")
+    }
+
+    /// Write the decoration of an extracted entity to `out`, returning
+    /// whether a database has a record of it and, if `country` is set, the
+    /// country of an IP from the same lookup. Entities other than IPs count
+    /// as found
+    fn decorate_write(
+        &self,
+        geoipdb: &geoip::GeoIPSed,
+        m: &Entity,
+        s: &str,
+        out: &mut Vec<u8>,
+        country: bool,
+    ) -> (bool, Option<String>) {
+        // obfuscated IPv4 addresses are looked up as their dotted quads
+        let ip = || m.canonical_text().unwrap_or(s.into());
+        let text = match (m.kind(), &self.macs, &self.domains) {
+            (EntityKind::Ip, _, _) if country => {
+                return geoipdb
+                    .lookup_write_country(&ip(), out)
+                    .expect("writing to a Vec cannot fail")
+            }
+            (EntityKind::Ip, _, _) => {
+                let found = geoipdb
+                    .lookup_write(&ip(), out)
+                    .expect("writing to a Vec cannot fail");
+                return (found, None);
+            }
+            (EntityKind::Mac, Some(macs), _) => macs.lookup(s),
+            (EntityKind::Domain, _, Some(domains)) => domains.lookup(s, geoipdb),
+            _ => s.to_string(),
+        };
+        out.extend_from_slice(text.as_bytes());
+        (true, None)
+    }
+}
+
+/// Whether a match is an IP listed by a loaded intel source
 #[inline]
-fn run(args: Args, colormode: ColorChoice) -> Result<()> {
-    let geoipdb = geoip::GeoIPSed::new(args.include, args.template, colormode);
-    let re = Regex::new(geoip::REGEX_PATTERN).unwrap();
-    let mut out = stdout(colormode);
-    let mut cache: HashMap<String, String> = HashMap::default();
-
-    for path in args.input {
-        let reader = get_input(Some(path))?;
-        let terminator = LineTerminator::byte(b'\n');
-        let mut line_buffer = LineBufferBuilder::new().build();
-        let mut lb_reader = LineBufferReader::new(reader, &mut line_buffer);
-        let mut _lastpos: usize = 0;
-
-        // line reader
-        while lb_reader.fill()? {
-            let lines = LineIter::new(terminator.as_byte(), lb_reader.buffer());
-            for line in lines {
-                _lastpos = 0;
-                for m in re.find_iter(line) {
-                    let ipstr = String::from_utf8(m.as_bytes().to_vec())
-                        .unwrap_or_else(|_| "decode error".into());
-                    // lookup ip in cache or decorate if new
-                    let decorated: &str = cache
-                        .entry(ipstr)
-                        .or_insert_with_key(|key| geoipdb.lookup(key));
-
-                    // print gap from last match to current match
-                    out.write_all(&line[_lastpos..m.start()])?;
-                    // print decorated ip
-                    out.write_all(decorated.as_bytes())?;
-                    _lastpos = m.end();
-                }
-                // add trailing...(or entire line in case of no matches)
-                out.write_all(&line[_lastpos..])?;
-            }
-            lb_reader.consume_all();
+fn is_intel_hit(geoipdb: &geoip::GeoIPSed, m: &Entity) -> bool {
+    *m.kind() == EntityKind::Ip && m.canonical_text().is_some_and(|s| geoipdb.is_intel_hit(&s))
+}
+
+/// Warn about, or with --strict-db-age refuse, databases built longer ago
+/// than allowed. Stale data silently yields wrong locations and owners
+fn check_db_age(geoipdb: &geoip::GeoIPSed, args: &Args) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    for (kind, built) in geoipdb.database_builds() {
+        let age = now.saturating_sub(built) / 86400;
+        if let Some(days) = args.strict_db_age.filter(|&days| age > days) {
+            bail!("{kind} database is {age} days old, more than --strict-db-age {days}");
+        }
+        if args.max_db_age > 0 && age > args.max_db_age {
+            eprintln!(
+                "warning: {kind} database is {age} days old, more than --max-db-age {}. \
+                 Update it, e.g. with geoipupdate",
+                args.max_db_age
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Open the geoip databases and any intel lists or enrichment table
+/// requested by `args`
+fn open_geoipdb(args: &Args, colormode: ColorChoice) -> Result<geoip::GeoIPSed> {
+    let mut geoipdb = geoip::GeoIPSed::new(
+        args.include.clone(),
+        args.pfx2as.clone(),
+        args.template.clone(),
+        colormode,
+        geoip::IpColors {
+            v4: args.color_v4,
+            v6: args.color_v6,
+            scope: args.color_scope,
+        },
+    )?;
+    check_db_age(&geoipdb, args)?;
+    geoipdb.lang = args.lang.clone();
+    geoipdb.bool_format = args.bool_format;
+    geoipdb.ref_point = args.ref_point;
+    geoipdb.ecs = args.ecs;
+    for dir in &args.fallback_dir {
+        let mut fallback = geoip::GeoIPSed::new(
+            Some(dir.clone()),
+            None,
+            None,
+            ColorChoice::Never,
+            geoip::IpColors::default(),
+        )?;
+        check_db_age(&fallback, args)?;
+        fallback.lang = args.lang.clone();
+        fallback.ref_point = args.ref_point;
+        geoipdb.fallbacks.push(fallback);
+    }
+    let unavailable = geoipdb.unavailable_fields();
+    if !unavailable.is_empty() {
+        tracing::warn!(
+            fields = unavailable.join(","),
+            "database missing, these template fields will be empty"
+        );
+    }
+    geoipdb.space_policy = space_policy(args);
+    if !args.intel.is_empty() {
+        geoipdb.intel = Some(intel::IntelDb::load(&args.intel)?);
+        tracing::info!(lists = args.intel.len(), "loaded intel");
+    }
+    if let Some(path) = &args.enrich_csv {
+        geoipdb.enrich = Some(enrich::EnrichDb::load(path, &args.enrich_field)?);
+        tracing::info!(%path, field = args.enrich_field, "loaded enrichment table");
+    }
+    geoipdb.check_template()?;
+    Ok(geoipdb)
+}
+
+/// A cached decoration
+#[derive(Clone)]
+struct Decorated {
+    text: Vec<u8>,
+    // country of an ip, only looked up when counting metrics
+    country: Option<String>,
+    // whether a database has a record of the ip. Those without one are
+    // cached all the same, and counted apart in the stats
+    found: bool,
+    // times the decoration was served from the cache
+    hits: u64,
+    // looked up ahead of the input, with --prewarm
+    prewarmed: bool,
+}
+
+/// Decorations of the IPs listed in the --prewarm file, looked up in
+/// parallel, to seed the caches with before reading any input
+fn prewarm(args: &Args, geoipdb: &geoip::GeoIPSed) -> Result<HashMap<String, Decorated>> {
+    let Some(path) = &args.prewarm else {
+        return Ok(HashMap::default());
+    };
+    let text = std::fs::read_to_string(path).with_context(|| format!("Could not read {path}"))?;
+    let mut ips = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // obfuscated IPv4 addresses are cached as written, looked up as
+        // their dotted quads
+        let canonical = match geoipdb.parse_ip(line) {
+            Some(_) => line.to_string(),
+            None => match args.obfuscated_ipv4 {
+                true => parse_obfuscated_ipv4(line.as_bytes()).map(|ip| ip.to_string()),
+                false => None,
+            }
+            .ok_or_else(|| anyhow!("{path}:{}: not an IP address", i + 1))?,
+        };
+        ips.push((line, canonical));
+    }
+
+    let start = Instant::now();
+    let decorate = |(ip, canonical): &(&str, String)| {
+        let mut text = Vec::with_capacity(ip.len() + 64);
+        let (found, country) = geoipdb
+            .lookup_write_country(canonical, &mut text)
+            .expect("writing to a Vec cannot fail");
+        let decorated = Decorated {
+            text,
+            country,
+            found,
+            hits: 0,
+            prewarmed: true,
+        };
+        (ip.to_string(), decorated)
+    };
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = ips.len().div_ceil(threads).max(1);
+    let cache: HashMap<String, Decorated> = thread::scope(|scope| {
+        let decorate = &decorate;
+        let workers: Vec<_> = ips
+            .chunks(chunk)
+            .map(|ips| scope.spawn(move || ips.iter().map(decorate).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("prewarm worker panicked"))
+            .collect()
+    });
+    tracing::info!(%path, ips = cache.len(), elapsed = ?start.elapsed(), "prewarmed cache");
+    Ok(cache)
+}
+
+/// Extraction and decoration of the entities of lines
+struct LineDecorator {
+    re: Extractor,
+    format: Option<logformat::LogFormat>,
+    decorators: Decorators,
+}
+
+impl LineDecorator {
+    fn new(args: &Args, colormode: ColorChoice) -> Result<Self> {
+        Ok(Self {
+            re: extractor(args)?,
+            format: args.format,
+            decorators: Decorators::new(args, colormode)?,
+        })
+    }
+
+    /// Write `line` with its entities decorated, caching decorations.
+    /// Returns the number of entities
+    fn decorate_line(
+        &self,
+        out: &mut impl Write,
+        line: &[u8],
+        geoipdb: &geoip::GeoIPSed,
+        cache: &mut HashMap<String, Decorated>,
+        metrics: Option<&Metrics>,
+    ) -> Result<u64> {
+        self.decorators
+            .prefetch(find_iter(&self.re, self.format, line));
+        let mut matches = 0;
+        let mut lastpos: usize = 0;
+        for m in find_iter(&self.re, self.format, line) {
+            let ipstr = std::str::from_utf8(m.as_bytes()).unwrap_or("decode error");
+            // lookup ip in cache or decorate if new. the key is only copied
+            // on a miss, keeping cache hits free of allocations
+            let decorated = if let Some(decorated) = cache.get_mut(ipstr) {
+                decorated.hits += 1;
+                if let Some(metrics) = metrics {
+                    metrics.cache_hit(decorated.found);
+                }
+                decorated
+            } else {
+                let start = Instant::now();
+                let mut text = Vec::with_capacity(ipstr.len() + 64);
+                let (found, country) = self.decorators.decorate_write(
+                    geoipdb,
+                    &m,
+                    ipstr,
+                    &mut text,
+                    metrics.is_some(),
+                );
+                if let Some(metrics) = metrics {
+                    metrics.cache_miss(start.elapsed());
+                }
+                cache.entry(ipstr.to_string()).or_insert(Decorated {
+                    text,
+                    country,
+                    found,
+                    hits: 0,
+                    prewarmed: false,
+                })
+            };
+            if let Some(metrics) = metrics {
+                metrics.entity(m.kind().name(), decorated.country.as_deref());
+            }
+
+            // print gap from last match to current match
+            out.write_all(&line[lastpos..m.start()])?;
+            // print decorated ip
+            out.write_all(&decorated.text)?;
+            lastpos = m.end();
+            matches += 1;
         }
+        // add trailing...(or entire line in case of no matches)
+        out.write_all(&line[lastpos..])?;
+        if let Some(metrics) = metrics {
+            metrics.line();
+        }
+        Ok(matches)
+    }
+
+    /// Decorate the lines of `text`, e.g. the body of a service request
+    #[cfg(any(feature = "serve", feature = "grpc"))]
+    fn decorate_text(
+        &self,
+        text: &[u8],
+        geoipdb: &geoip::GeoIPSed,
+        cache: &mut HashMap<String, Decorated>,
+        metrics: Option<&Metrics>,
+    ) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(text.len() * 2);
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            self.decorate_line(&mut out, line, geoipdb, cache, metrics)?;
+        }
+        Ok(out)
+    }
+}
+
+/// Rewrite the decorations of the template back to the bare IPs
+fn run_strip(args: Args) -> Result<()> {
+    let template = args.template.as_deref().unwrap_or(geoip::DEFAULT_TEMPLATE);
+    // decorations may be highlighted whole or only their {ip} field, see
+    // geoip::ColorScope
+    let highlight = regex::bytes::Regex::new(r"\x1b\[1;[0-9;]+m|\x1b\[0;0m")?;
+    let ip_pattern = r"(?:\x1b\[1;[0-9;]+m)?[0-9A-Fa-f:.]+(?:\x1b\[0;0m)?";
+    let Some(mut pattern) = Template::new(template).inverse_pattern("ip", ip_pattern) else {
+        bail!("--strip needs a template with an {{ip}} field");
+    };
+    // the spaces of the template were written as underscores, and only
+    // its literals can have any as the field patterns have none
+    if space_policy(&args) == geoip::SpacePolicy::Underscore {
+        pattern = pattern.replace(' ', "[ _]");
+    }
+    let re = regex::bytes::Regex::new(&format!(r"(?:\x1b\[1;[0-9;]+m)?{pattern}(?:\x1b\[0;0m)?"))?;
+    let mut console = stdout(ColorChoice::Never);
+    for path in &args.input {
+        let mut edit = edit_in_place(path, &args)?;
+        let out: &mut dyn Write = match &mut edit {
+            Some(edit) => edit,
+            None => &mut console,
+        };
+        for_each_line(path, &args, |line| {
+            let stripped = re.replace_all(line, |caps: &regex::bytes::Captures| {
+                highlight.replace_all(&caps["ip"], &b""[..]).into_owned()
+            });
+            out.write_all(&stripped)?;
+            Ok(())
+        })?;
         out.flush()?;
+        commit_in_place(edit, &args)?;
+    }
+    Ok(())
+}
+
+/// Replacement of the file at `path` with --in-place, or its copy in
+/// --output-dir, where output goes instead of stdout
+fn edit_in_place(path: &Utf8Path, args: &Args) -> Result<Option<InPlaceFile>> {
+    if args.in_place.is_none() && args.output_dir.is_none() {
+        return Ok(None);
+    }
+    if path.as_str() == "-" {
+        bail!("--in-place and --output-dir need input files, not stdin");
+    }
+    if is_archive(path) {
+        bail!("{path}: archives cannot be rewritten");
+    }
+    match &args.output_dir {
+        Some(dir) => InPlaceFile::create_copy(path, &inplace::mirror_path(dir, path)?).map(Some),
+        None => InPlaceFile::create(path).map(Some),
+    }
+}
+
+/// Replace the file of an --in-place edit once it has been written in full
+fn commit_in_place(edit: Option<InPlaceFile>, args: &Args) -> Result<()> {
+    match edit {
+        Some(edit) => edit.commit(args.in_place.as_deref(), args.keep_mtime),
+        None => Ok(()),
+    }
+}
+
+#[inline]
+fn run(args: Args, colormode: ColorChoice) -> Result<()> {
+    let geoipdb = open_geoipdb(&args, colormode)?;
+    let decorator = LineDecorator::new(&args, colormode)?;
+    let mut console = stdout(colormode);
+    // a cache and match count per thread
+    let warm = prewarm(&args, &geoipdb)?;
+    let mut states: Vec<(HashMap<String, Decorated>, u64)> =
+        (0..args.threads).map(|_| (warm.clone(), 0)).collect();
+    let mut summary = Summary::new();
+    let terminator = args.line_terminator();
+    let mut dedup = args.dedup_window.map(dedup::Dedup::new);
+    let mut limits = MatchLimits::new(&args);
+    let decorate = |(cache, matches): &mut (HashMap<String, Decorated>, u64),
+                    line: &[u8],
+                    mut out: &mut dyn Write|
+     -> Result<()> {
+        if args.only_intel_hits
+            && !find_iter(&decorator.re, args.format, line).any(|m| is_intel_hit(&geoipdb, &m))
+        {
+            return Ok(());
+        }
+        *matches += decorator.decorate_line(&mut out, line, &geoipdb, cache, None)?;
+        Ok(())
+    };
+
+    let mut decorated = Vec::new();
+    for path in &args.input {
+        let mut edit = edit_in_place(path, &args)?;
+        let out: &mut (dyn Write + Send) = match &mut edit {
+            Some(edit) => edit,
+            None => &mut console,
+        };
+        let sink: &mut (dyn Sink + Send) = &mut TextSink::new(out);
+        limits.next_input();
+        summary.lines += if args.threads > 1 && !is_archive(path) {
+            for_each_line_parallel(path, &args, sink, &mut states, &decorate)?
+        } else {
+            for_each_source_line(path, &args, |source, line| {
+                let repeat = dedup.as_mut().is_some_and(|dedup| {
+                    dedup.is_repeat(
+                        find_iter(&decorator.re, args.format, line).map(|m| m.as_bytes()),
+                    )
+                });
+                if repeat {
+                    return Ok(());
+                }
+                let before = states[0].1;
+                decorated.clear();
+                decorate(&mut states[0], line, &mut decorated)?;
+                match source == path {
+                    _ if decorated.is_empty() => {}
+                    true => sink.write_line(&decorated)?,
+                    false => write_with_source(sink, source, &decorated, terminator.as_byte())?,
+                }
+                limits.count(states[0].1 - before)
+            })?
+        };
+        sink.flush()?;
+        commit_in_place(edit, &args)?;
+        if limits.is_exhausted() {
+            break;
+        }
     }
+    summary.matches = states.iter().map(|(_, matches)| matches).sum();
+    summary.negative_hits = states
+        .iter()
+        .flat_map(|(cache, _)| cache.values())
+        .filter(|decorated| !decorated.found)
+        .map(|decorated| decorated.hits)
+        .sum();
+    let unique: HashSet<&String> = states
+        .iter()
+        .flat_map(|(cache, _)| cache.iter())
+        .filter(|(_, decorated)| !decorated.prewarmed || decorated.hits > 0)
+        .map(|(ip, _)| ip)
+        .collect();
+    summary.report(&args, unique.len());
     Ok(())
 }
 
 #[inline]
 fn run_onlymatching(args: Args, colormode: ColorChoice) -> Result<()> {
-    let geoipdb = geoip::GeoIPSed::new(args.include, args.template, colormode);
-    let re = Regex::new(geoip::REGEX_PATTERN).unwrap();
-    let mut out = stdout(colormode);
-    let mut cache: HashMap<String, String> = HashMap::default();
-
-    for path in args.input {
-        let reader = get_input(Some(path))?;
-        let terminator = LineTerminator::byte(b'\n');
-        let mut line_buffer = LineBufferBuilder::new().build();
-        let mut lb_reader = LineBufferReader::new(reader, &mut line_buffer);
-
-        // line reader
-        while lb_reader.fill()? {
-            let lines = LineIter::new(terminator.as_byte(), lb_reader.buffer());
-            for line in lines {
-                for m in re.find_iter(line) {
-                    let ipstr = String::from_utf8(m.as_bytes().to_vec())
-                        .unwrap_or_else(|_| "decode error".into());
-                    // lookup ip in cache or decorate if new
-                    let decorated: &str = cache
-                        .entry(ipstr)
-                        .or_insert_with_key(|key| geoipdb.lookup(key));
-
-                    // *only* print decorated ip
-                    out.write_all(decorated.as_bytes())?;
-                    // and a newline
-                    out.write_all(&[b'\n'])?;
-                }
-            }
-            lb_reader.consume_all();
+    let geoipdb = open_geoipdb(&args, colormode)?;
+    let decorators = Decorators::new(&args, colormode)?;
+    let re = extractor(&args)?;
+    let mut console = stdout(colormode);
+    // a cache and match count per thread
+    let mut states: Vec<(HashMap<String, String>, u64)> =
+        (0..args.threads).map(|_| Default::default()).collect();
+    let mut summary = Summary::new();
+    let terminator = args.line_terminator();
+    let mut limits = MatchLimits::new(&args);
+    let decorate = |(cache, matches): &mut (HashMap<String, String>, u64),
+                    line: &[u8],
+                    out: &mut dyn Write|
+     -> Result<()> {
+        decorators.prefetch(find_iter(&re, args.format, line));
+        for m in find_iter(&re, args.format, line) {
+            if args.only_intel_hits && !is_intel_hit(&geoipdb, &m) {
+                continue;
+            }
+            let ipstr = std::str::from_utf8(m.as_bytes()).unwrap_or("decode error");
+            // lookup ip in cache or decorate if new, copying the key only on
+            // a miss
+            let decorated: &str = if let Some(decorated) = cache.get(ipstr) {
+                decorated
+            } else {
+                let decorated = decorators.decorate(&geoipdb, &m, ipstr);
+                cache.entry(ipstr.to_string()).or_insert(decorated)
+            };
+
+            // *only* print decorated ip
+            out.write_all(decorated.as_bytes())?;
+            *matches += 1;
+            // and a line terminator
+            out.write_all(terminator.as_bytes())?;
         }
+        Ok(())
+    };
+
+    let mut decorated = Vec::new();
+    for path in &args.input {
+        let mut edit = edit_in_place(path, &args)?;
+        let out: &mut (dyn Write + Send) = match &mut edit {
+            Some(edit) => edit,
+            None => &mut console,
+        };
+        let sink: &mut (dyn Sink + Send) = &mut TextSink::new(out);
+        limits.next_input();
+        summary.lines += if args.threads > 1 && !is_archive(path) {
+            for_each_line_parallel(path, &args, sink, &mut states, &decorate)?
+        } else {
+            for_each_source_line(path, &args, |source, line| {
+                let before = states[0].1;
+                decorated.clear();
+                decorate(&mut states[0], line, &mut decorated)?;
+                // one match per output line, of which --max-matches may
+                // leave room for only some
+                let found = limits.allowed(states[0].1 - before);
+                states[0].1 = before + found;
+                let end = decorated
+                    .split_inclusive(|&b| b == terminator.as_byte())
+                    .take(found as usize)
+                    .map(<[u8]>::len)
+                    .sum();
+                decorated.truncate(end);
+                match source == path {
+                    true => sink::write_lines(sink, &decorated, terminator.as_byte())?,
+                    false => write_with_source(sink, source, &decorated, terminator.as_byte())?,
+                }
+                limits.count(found)
+            })?
+        };
+        sink.flush()?;
+        commit_in_place(edit, &args)?;
+        if limits.is_exhausted() {
+            break;
+        }
+    }
+    summary.matches = states.iter().map(|(_, matches)| matches).sum();
+    let unique: HashSet<&String> = states.iter().flat_map(|(cache, _)| cache.keys()).collect();
+    summary.report(&args, unique.len());
+    Ok(())
+}
+
+#[inline]
+fn run_json(args: Args) -> Result<()> {
+    // json output is never colored so it stays parseable
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let mut fields = args.json_fields.clone();
+    if let Some(preset) = args.preset {
+        fields.extend(preset.fields().iter().map(|f| f.to_string()));
+    }
+    let mut decorator =
+        json::JsonDecorator::new(&geoipdb, fields).obfuscated_ipv4(args.obfuscated_ipv4);
+    let mut console = stdout(ColorChoice::Never);
+    let terminator = args.line_terminator();
+
+    for path in &args.input {
+        let mut edit = edit_in_place(path, &args)?;
+        let out: &mut dyn Write = match &mut edit {
+            Some(edit) => edit,
+            None => &mut console,
+        };
+        for_each_source_line(path, &args, |source, line| {
+            // archive members are named in the "stream" field
+            let stream = (source != path).then_some(source);
+            match decorator.decorate_object(without_terminator(line, terminator), stream) {
+                Some(object) => {
+                    out.write_all(&object)?;
+                    out.write_all(terminator.as_bytes())?;
+                }
+                None => out.write_all(line)?,
+            }
+            Ok(())
+        })?;
         out.flush()?;
+        commit_in_place(edit, &args)?;
     }
     Ok(())
 }
+
+/// Enrich the --framed events of stdin one at a time
+fn run_framed(args: Args) -> Result<()> {
+    let framing = args.framed.expect("framed is set");
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let mut fields = args.json_fields.clone();
+    if let Some(preset) = args.preset {
+        fields.extend(preset.fields().iter().map(|f| f.to_string()));
+    }
+    let mut decorator =
+        json::JsonDecorator::new(&geoipdb, fields).obfuscated_ipv4(args.obfuscated_ipv4);
+    let mut reader = io::stdin().lock();
+    let mut out = io::stdout().lock();
+    while let Some(frame) = framing.read(&mut reader)? {
+        framing.write(&mut out, &decorator.decorate_line(&frame))?;
+    }
+    Ok(())
+}
+
+/// Decorate the MESSAGE of the --journal entries written by journalctl
+fn run_journal(args: Args, colormode: ColorChoice) -> Result<()> {
+    let json = args.json_input || args.preset.is_some();
+    // json output is never colored so it stays parseable
+    let colormode = if json { ColorChoice::Never } else { colormode };
+    let geoipdb = open_geoipdb(&args, colormode)?;
+    let decorator = LineDecorator::new(&args, colormode)?;
+    let mut fields = args.json_fields.clone();
+    if fields.is_empty() {
+        fields.push("MESSAGE".to_string());
+    }
+    let mut json_decorator =
+        json::JsonDecorator::new(&geoipdb, fields).obfuscated_ipv4(args.obfuscated_ipv4);
+    let mut cache = prewarm(&args, &geoipdb)?;
+    let mut console = stdout(colormode);
+    let terminator = args.line_terminator();
+
+    let matches = args.journal.as_deref().unwrap_or_default();
+    let mut child = journal::spawn(matches, args.follow)?;
+    let reader = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if json {
+            console.write_all(&json_decorator.decorate_line(&line))?;
+            console.write_all(terminator.as_bytes())?;
+        } else if let Some(entry) = journal::Entry::parse(&line) {
+            let Some(message) = entry.message() else {
+                continue;
+            };
+            console.write_all(entry.header().as_bytes())?;
+            let mut message = message.into_owned();
+            message.extend_from_slice(terminator.as_bytes());
+            decorator.decorate_line(&mut console, &message, &geoipdb, &mut cache, None)?;
+        }
+        if args.follow {
+            console.flush()?;
+        }
+    }
+    console.flush()?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("journalctl failed: {status}");
+    }
+    Ok(())
+}
+
+/// Decorate the lines of the --input-fifo pipes in the order they arrive,
+/// reading each pipe on its own thread, and tag them with the pipe's name
+fn run_fifos(args: Args, colormode: ColorChoice) -> Result<()> {
+    let json = args.json_input || args.preset.is_some();
+    // json output is never colored so it stays parseable
+    let colormode = if json { ColorChoice::Never } else { colormode };
+    let geoipdb = open_geoipdb(&args, colormode)?;
+    let decorator = LineDecorator::new(&args, colormode)?;
+    let mut fields = args.json_fields.clone();
+    if let Some(preset) = args.preset {
+        fields.extend(preset.fields().iter().map(|f| f.to_string()));
+    }
+    let mut json_decorator =
+        json::JsonDecorator::new(&geoipdb, fields).obfuscated_ipv4(args.obfuscated_ipv4);
+    let mut cache = prewarm(&args, &geoipdb)?;
+    let mut console = stdout(colormode);
+    let terminator = args.line_terminator();
+    let (line_tx, line_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(1024);
+
+    thread::scope(|scope| {
+        let readers: Vec<_> = args
+            .input_fifo
+            .iter()
+            .enumerate()
+            .map(|(i, fifo)| {
+                let line_tx = line_tx.clone();
+                let args = &args;
+                scope.spawn(move || {
+                    for_each_line(&fifo.path, args, |line| {
+                        line_tx
+                            .send((i, line.to_vec()))
+                            .map_err(|_| anyhow::anyhow!("output stopped"))
+                    })
+                })
+            })
+            .collect();
+        // the lines end once every reader is done with its pipe
+        drop(line_tx);
+
+        for (i, line) in line_rx {
+            let name = &args.input_fifo[i].name;
+            if json {
+                let line = without_terminator(&line, terminator);
+                if line.trim_ascii().is_empty() {
+                    continue;
+                }
+                console.write_all(&json_decorator.decorate_tagged_line(line, Some(name)))?;
+                console.write_all(terminator.as_bytes())?;
+            } else {
+                write!(console, "{name}:")?;
+                decorator.decorate_line(&mut console, &line, &geoipdb, &mut cache, None)?;
+            }
+            console.flush()?;
+        }
+        for reader in readers {
+            reader.join().expect("reader thread panicked")?;
+        }
+        Ok(())
+    })
+}
+
+#[inline]
+fn run_sink(args: Args) -> Result<()> {
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let re = extractor(&args)?;
+    let mut sink = args.output.as_ref().expect("output is set").open()?;
+    let mut cache: HashMap<String, Option<serde_json::Value>> = HashMap::default();
+    let mut summary = Summary::new();
+
+    for path in &args.input {
+        let mut current = path.to_string();
+        let mut line_no: u64 = 0;
+        let mut offset: u64 = 0;
+        summary.lines += for_each_source_line(path, &args, |source, line| {
+            // line numbers and offsets start over with each archive member
+            if source != current {
+                current = source.to_string();
+                (line_no, offset) = (0, 0);
+            }
+            line_no += 1;
+            for m in find_iter(&re, args.format, line) {
+                let Some(ipstr) = m.canonical_text() else {
+                    continue;
+                };
+                if args.only_intel_hits && !geoipdb.is_intel_hit(&ipstr) {
+                    continue;
+                }
+                let geo = cache
+                    .entry(ipstr.to_string())
+                    .or_insert_with_key(|key| geoipdb.lookup_json(key));
+                // skip strings that look like but are not valid ips
+                if let Some(geo) = geo {
+                    sink.write_record(&sink::Occurrence {
+                        source,
+                        line_no,
+                        offset: offset + m.start() as u64,
+                        ip: &ipstr,
+                        geo,
+                    })?;
+                    summary.matches += 1;
+                }
+            }
+            offset += line.len() as u64;
+            Ok(())
+        })?;
+    }
+    sink.finish()?;
+    summary.report(&args, cache.values().filter(|geo| geo.is_some()).count());
+    Ok(())
+}
+
+#[cfg(feature = "serve")]
+fn run_serve(args: Args) -> Result<()> {
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let decorator = LineDecorator::new(&args, ColorChoice::Never)?;
+    let mut cache = prewarm(&args, &geoipdb)?;
+    let Some(Command::Serve(serve)) = &args.command else {
+        unreachable!("run_serve is only invoked for the serve subcommand");
+    };
+    serve::serve(serve, &geoipdb, |body, metrics| {
+        decorator.decorate_text(body, &geoipdb, &mut cache, Some(metrics))
+    })
+}
+
+#[cfg(feature = "grpc")]
+fn run_grpc(args: Args) -> Result<()> {
+    let geoipdb = std::sync::Arc::new(open_geoipdb(&args, ColorChoice::Never)?);
+    let decorator = LineDecorator::new(&args, ColorChoice::Never)?;
+    let cache = std::sync::Mutex::new(prewarm(&args, &geoipdb)?);
+    let Some(Command::Grpc(grpc)) = &args.command else {
+        unreachable!("run_grpc is only invoked for the grpc subcommand");
+    };
+    let db = geoipdb.clone();
+    let decorate = move |text: &[u8]| {
+        let mut cache = cache.lock().map_err(|e| anyhow::anyhow!("{e}"))?;
+        decorator.decorate_text(text, &db, &mut cache, None)
+    };
+    grpc::serve(grpc, geoipdb, std::sync::Arc::new(decorate))
+}
+
+/// Benchmark the selected databases on generated corpora
+fn run_bench(args: Args) -> Result<()> {
+    let Some(Command::Bench(bench)) = &args.command else {
+        unreachable!("run_bench is only invoked for the bench subcommand");
+    };
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let results = bench::measure(bench, &geoipdb, args.obfuscated_ipv4)?;
+    bench::print(&mut stdout(ColorChoice::Never), &results)
+}
+
+/// Build a MaxMind DB file
+fn run_mmdb(args: Args) -> Result<()> {
+    let Some(Command::Mmdb(mmdb)) = &args.command else {
+        unreachable!("run_mmdb is only invoked for the mmdb subcommand");
+    };
+    match &mmdb.command {
+        mmdb::MmdbCommand::Build(build) => {
+            let networks = mmdb::build(build)?;
+            tracing::info!(networks, path = %build.database, "built database");
+        }
+    }
+    Ok(())
+}
+
+/// Count the IPs of the input per country or ASN, and per time bucket if
+/// a timestamp format is given
+fn run_stats(args: Args, colormode: ColorChoice) -> Result<()> {
+    let Some(Command::Stats(stats)) = &args.command else {
+        unreachable!("run_stats is only invoked for the stats subcommand");
+    };
+    let pattern = match (&stats.timestamp_regex, stats.timestamp_preset) {
+        (Some(pattern), _) => Some(pattern.as_str()),
+        (None, Some(preset)) => Some(preset.pattern()),
+        (None, None) => None,
+    };
+    let timestamps = pattern
+        .map(|pattern| stats::TimestampReader::new(pattern, stats.bucket))
+        .transpose()?;
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let re = extractor(&args)?;
+    let mut counts = stats::Counts::default();
+    let mut series = stats::Series::default();
+    let mut summary = Summary::new();
+
+    for path in &args.input {
+        summary.lines += for_each_line(path, &args, |line| {
+            let bucket = timestamps.as_ref().and_then(|t| t.bucket(line));
+            for m in find_iter(&re, args.format, line) {
+                if args.only_intel_hits && !is_intel_hit(&geoipdb, &m) {
+                    continue;
+                }
+                let Some(ip) = m.ip() else {
+                    continue;
+                };
+                let canonical = ip.to_string();
+                let Some(record) = geoipdb.lookup_record(&canonical) else {
+                    continue;
+                };
+                summary.matches += 1;
+                match timestamps {
+                    Some(_) => series.add(bucket.as_deref(), ip, stats.by.of(&record)),
+                    None => counts.add(stats.by.of(&record)),
+                }
+            }
+            Ok(())
+        })?;
+    }
+
+    let mut out = stdout(colormode);
+    let rows = match timestamps {
+        Some(_) => series.hits(),
+        None => counts.sorted(stats.top),
+    };
+    match (timestamps.is_some(), stats.chart, stats.json) {
+        (_, true, _) => stats::print_chart(&mut out, &rows)?,
+        (true, _, true) => series.print_json(&mut out, stats.top)?,
+        (true, _, false) => series.print_table(&mut out, stats.top)?,
+        (false, _, true) => stats::print_json(&mut out, &rows)?,
+        (false, _, false) => stats::print_table(&mut out, &rows)?,
+    }
+    out.flush()?;
+    summary.report(&args, rows.len());
+    Ok(())
+}
+
+/// Explain the candidate spans of one line
+fn run_test(args: Args, colormode: ColorChoice) -> Result<()> {
+    let Some(Command::Test(test)) = &args.command else {
+        unreachable!("run_test is only invoked for the test subcommand");
+    };
+    let line = match &test.text {
+        Some(text) => text.clone(),
+        None => {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    let line = line.as_bytes();
+    let geoipdb = open_geoipdb(&args, colormode)?;
+    let decorators = Decorators::new(&args, colormode)?;
+    let re = extractor(&args)?;
+    let spans = args.format.map(|f| f.ip_value_spans(line));
+    let mut out = stdout(colormode);
+
+    let mut candidates = 0;
+    for (m, rejection) in re.candidates(line) {
+        candidates += 1;
+        let text = String::from_utf8_lossy(m.as_bytes());
+        let in_fields = spans.as_ref().is_none_or(|spans| {
+            spans
+                .iter()
+                .any(|s| s.start <= m.start() && m.end() <= s.end)
+        });
+        let ip = (*m.kind() == EntityKind::Ip).then(|| m.ip());
+        let verdict = if let Some(rejection) = rejection {
+            match rejection {
+                Rejection::Validator => {
+                    format!("excluded: rejected by the {} validator", m.kind().name())
+                }
+                rejection => format!("excluded: {rejection}"),
+            }
+        } else if !in_fields {
+            format!(
+                "excluded: outside the address fields of --format {}",
+                args.format
+                    .and_then(|f| f.to_possible_value())
+                    .map_or_else(String::new, |v| v.get_name().to_string())
+            )
+        } else if ip == Some(None) {
+            "excluded: not a valid IP address".to_string()
+        } else if args.only_intel_hits && !is_intel_hit(&geoipdb, &m) {
+            "excluded: not listed by --intel".to_string()
+        } else {
+            format!("-> {}", decorators.decorate(&geoipdb, &m, &text))
+        };
+        writeln!(
+            out,
+            "{}..{} {} {:?} {verdict}",
+            m.start(),
+            m.end(),
+            m.kind().name(),
+            text
+        )?;
+    }
+    if candidates == 0 {
+        writeln!(out, "no candidates")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+#[inline]
+fn run_export(args: Args) -> Result<()> {
+    let geoipdb = open_geoipdb(&args, ColorChoice::Never)?;
+    let re = extractor(&args)?;
+    // index of each IP's indicator, None for IPs that are not exported
+    let mut seen: HashMap<IpAddr, Option<usize>> = HashMap::default();
+    let mut indicators: Vec<export::Indicator> = Vec::new();
+    let mut summary = Summary::new();
+
+    for path in &args.input {
+        summary.lines += for_each_line(path, &args, |line| {
+            for m in find_iter(&re, args.format, line) {
+                let Some(ip) = m.ip() else {
+                    continue;
+                };
+                summary.matches += 1;
+                if !geoip::is_public(&ip) {
+                    continue;
+                }
+                if let Some(index) = seen.get(&ip) {
+                    if let Some(i) = *index {
+                        indicators[i].count += 1;
+                    }
+                    continue;
+                }
+                let geo = match args.only_intel_hits && !is_intel_hit(&geoipdb, &m) {
+                    true => None,
+                    false => geoipdb.lookup_json(&ip.to_string()),
+                };
+                let index = geo.map(|geo| {
+                    indicators.push(export::Indicator { ip, geo, count: 1 });
+                    indicators.len() - 1
+                });
+                seen.insert(ip, index);
+            }
+            Ok(())
+        })?;
+    }
+
+    let Some(Command::Export(export)) = &args.command else {
+        unreachable!("run_export is only invoked for the export subcommand");
+    };
+    export::write_all(export, &indicators)?;
+    summary.report(&args, indicators.len());
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use rustc_hash::FxHashMap as HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the lookup latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.01, 0.1, 1.0,
+];
+
+/// Counters of a long-running enrichment service, rendered in the
+/// Prometheus text exposition format
+#[derive(Default)]
+pub struct Metrics {
+    lines: AtomicU64,
+    cache_hits: AtomicU64,
+    // hits of IPs no database has a record of, counted in cache_hits too
+    cache_negative_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    // per bucket of LATENCY_BUCKETS, not cumulative
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    latency_count: AtomicU64,
+    latency_sum_nanos: AtomicU64,
+    entities: Mutex<HashMap<String, u64>>,
+    countries: Mutex<HashMap<String, u64>>,
+    dropped: Mutex<HashMap<String, u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a processed line
+    pub fn line(&self) {
+        self.lines.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an extracted entity of `kind`, and its country if known
+    pub fn entity(&self, kind: &str, country: Option<&str>) {
+        increment(&self.entities, kind);
+        if let Some(country) = country.filter(|c| !c.is_empty()) {
+            increment(&self.countries, country);
+        }
+    }
+
+    /// Count a request turned away for `reason`, e.g. a full queue
+    pub fn dropped(&self, reason: &str) {
+        increment(&self.dropped, reason);
+    }
+
+    /// Count a decoration served from the cache, of an IP that a database
+    /// has a record of if `found`
+    pub fn cache_hit(&self, found: bool) {
+        self.cache_hits.fetch_add(1, Ordering::Relaxed);
+        if !found {
+            self.cache_negative_hits.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a decoration missing from the cache, which took `elapsed` to
+    /// look up
+    pub fn cache_miss(&self, elapsed: Duration) {
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.latency_count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.latency_sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counter = |out: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            let value = value.load(Ordering::Relaxed);
+            let _ = write!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n"
+            );
+        };
+        counter(
+            &mut out,
+            "geoipsed_lines_total",
+            "Lines processed.",
+            &self.lines,
+        );
+        labeled(
+            &mut out,
+            "geoipsed_entities_total",
+            "Entities extracted, by kind.",
+            "kind",
+            &self.entities,
+        );
+        labeled(
+            &mut out,
+            "geoipsed_country_extractions_total",
+            "IPs extracted, by country.",
+            "country",
+            &self.countries,
+        );
+        counter(
+            &mut out,
+            "geoipsed_cache_hits_total",
+            "Decorations served from the cache.",
+            &self.cache_hits,
+        );
+        counter(
+            &mut out,
+            "geoipsed_cache_negative_hits_total",
+            "Decorations served from the cache of IPs without a database record.",
+            &self.cache_negative_hits,
+        );
+        counter(
+            &mut out,
+            "geoipsed_cache_misses_total",
+            "Decorations looked up in the databases.",
+            &self.cache_misses,
+        );
+
+        labeled(
+            &mut out,
+            "geoipsed_requests_dropped_total",
+            "Requests turned away, by reason.",
+            "reason",
+            &self.dropped,
+        );
+
+        let name = "geoipsed_lookup_duration_seconds";
+        let _ = write!(
+            out,
+            "# HELP {name} Time to decorate an entity missing from the cache.\n\
+             # TYPE {name} histogram\n"
+        );
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&self.latency_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let count = self.latency_count.load(Ordering::Relaxed);
+        let sum = self.latency_sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = write!(
+            out,
+            "{name}_bucket{{le=\"+Inf\"}} {count}\n{name}_sum {sum}\n{name}_count {count}\n"
+        );
+        out
+    }
+}
+
+fn increment(counts: &Mutex<HashMap<String, u64>>, key: &str) {
+    if let Ok(mut counts) = counts.lock() {
+        match counts.get_mut(key) {
+            Some(count) => *count += 1,
+            None => {
+                counts.insert(key.to_string(), 1);
+            }
+        }
+    }
+}
+
+/// Render a counter with one series per label value, in label order
+fn labeled(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    counts: &Mutex<HashMap<String, u64>>,
+) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} counter\n");
+    let Ok(counts) = counts.lock() else {
+        return;
+    };
+    let mut series: Vec<_> = counts.iter().collect();
+    series.sort();
+    for (value, count) in series {
+        let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+        let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+    }
+}
@@ -0,0 +1,477 @@
+use crate::ipset::IpRange;
+use anyhow::{bail, Context, Result};
+use camino::Utf8PathBuf;
+use clap::{Args, Subcommand};
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Marker between the data section and the metadata of a MaxMind database
+const METADATA_START: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+/// Work with MaxMind DB files
+#[derive(Args, Debug)]
+pub struct MmdbArgs {
+    #[clap(subcommand)]
+    pub command: MmdbCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MmdbCommand {
+    /// Build a MaxMind DB file from a CSV of networks and their fields
+    Build(BuildArgs),
+}
+
+/// Build a MaxMind DB from a CSV
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// CSV whose header starts with "network" (CIDR) or "start_ip,end_ip",
+    /// followed by the names of the fields of each record. Dotted names,
+    /// e.g. "country.iso_code", build nested maps. "-" reads stdin
+    #[clap(value_name = "CSV", value_hint = clap::ValueHint::FilePath)]
+    pub csv: Utf8PathBuf,
+
+    /// MaxMind DB file to write
+    #[clap(value_name = "DB", value_hint = clap::ValueHint::FilePath)]
+    pub database: Utf8PathBuf,
+
+    /// Database type of the metadata. -I and --fallback-dir recognize
+    /// types containing "ASN", "City", or "ISP"
+    #[clap(long, value_name = "TYPE", default_value = "geoipsed-Custom")]
+    pub database_type: String,
+
+    /// English description of the database
+    #[clap(long, value_name = "TEXT")]
+    pub description: Option<String>,
+}
+
+/// Writer of MaxMind DB files: networks are mapped to records of JSON
+/// fields, written as an IPv6 database in which IPv4 networks are also
+/// found under ::/96, the layout of MaxMind's own databases. Identical
+/// records are stored once.
+///
+/// ```
+/// use geoipsed::mmdb::MmdbWriter;
+/// use serde_json::json;
+///
+/// let mut writer = MmdbWriter::new("Internal-ASN");
+/// let record = json!({"autonomous_system_number": 64512, "autonomous_system_organization": "Corp"});
+/// writer.insert("10.0.0.0/8".parse().unwrap(), record.as_object().unwrap());
+/// let mut db = Vec::new();
+/// writer.write(&mut db)?;
+///
+/// let reader = maxminddb::Reader::from_source(db)?;
+/// let asn: maxminddb::geoip2::Asn = reader.lookup("10.1.2.3".parse()?)?;
+/// assert_eq!(asn.autonomous_system_organization, Some("Corp"));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct MmdbWriter {
+    database_type: String,
+    description: Option<String>,
+    /// networks as a key in the IPv6 tree, prefix length, and data offset
+    networks: Vec<(u128, u8, u32)>,
+    data: Vec<u8>,
+    /// offsets of the records already in `data`
+    offsets: HashMap<Vec<u8>, u32>,
+}
+
+impl MmdbWriter {
+    pub fn new(database_type: &str) -> Self {
+        Self {
+            database_type: database_type.to_string(),
+            description: None,
+            networks: Vec::new(),
+            data: Vec::new(),
+            offsets: HashMap::default(),
+        }
+    }
+
+    /// Describe the database in its metadata
+    pub fn description(mut self, text: &str) -> Self {
+        self.description = Some(text.to_string());
+        self
+    }
+
+    /// Number of networks inserted, ranges counting as the networks they
+    /// are split into
+    pub fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    /// Map the addresses of `range` to `record`. Fields that are null are
+    /// left out. Where networks overlap, the most specific wins
+    pub fn insert(&mut self, range: IpRange, record: &Map<String, Value>) {
+        let mut bytes = Vec::new();
+        encode(&Value::Object(record.clone()), &mut bytes);
+        let offset = match self.offsets.get(&bytes) {
+            Some(&offset) => offset,
+            None => {
+                let offset = self.data.len() as u32;
+                self.data.extend_from_slice(&bytes);
+                self.offsets.insert(bytes, offset);
+                offset
+            }
+        };
+        let (start, end, bits) = match (range.start(), range.end()) {
+            (IpAddr::V4(start), IpAddr::V4(end)) => (key(start.into()), key(end.into()), 32),
+            (start, end) => (key(start), key(end), 128),
+        };
+        for (key, len) in networks(start, end, bits) {
+            self.networks.push((key, len + (128 - bits), offset));
+        }
+    }
+
+    /// Insert the networks of a CSV whose header starts with "network" or
+    /// "start_ip,end_ip", the other columns naming the fields of each
+    /// network's record. Empty values are left out, and values that are
+    /// integers, decimals, or true/false are written as such
+    pub fn insert_csv(&mut self, text: &str) -> Result<()> {
+        let mut lines = text.lines().enumerate();
+        let header = lines
+            .next()
+            .map(|(_, line)| split_csv_line(line))
+            .unwrap_or_default();
+        let ranges = match header.first().map(String::as_str) {
+            Some("network") => 1,
+            Some("start_ip") if header.get(1).is_some_and(|h| h == "end_ip") => 2,
+            _ => {
+                bail!("line 1: expected a header starting with \"network\" or \"start_ip,end_ip\"")
+            }
+        };
+        for (i, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let values = split_csv_line(line);
+            let range = match values.as_slice() {
+                [network, ..] if ranges == 1 => network.parse().ok(),
+                [start, end, ..] => match (start.parse(), end.parse()) {
+                    (Ok(start), Ok(end)) => IpRange::new(start, end),
+                    _ => None,
+                },
+                _ => None,
+            };
+            let Some(range) = range else {
+                bail!("line {}: invalid network {:?}", i + 1, values.first());
+            };
+            let mut record = Map::new();
+            for (name, value) in header.iter().zip(values.iter()).skip(ranges) {
+                if !value.is_empty() {
+                    set_path(&mut record, name, typed(value));
+                }
+            }
+            self.insert(range, &record);
+        }
+        Ok(())
+    }
+
+    /// Write the database to `out`
+    pub fn write(&self, mut out: impl Write) -> Result<()> {
+        let mut networks = self.networks.clone();
+        // less specific networks first, so that more specific ones split them
+        networks.sort_by_key(|&(_, len, _)| len);
+        let mut tree = vec![[Record::Empty; 2]];
+        for (key, len, offset) in networks {
+            insert(&mut tree, key, len, offset);
+        }
+
+        let node_count = tree.len() as u64;
+        let record_size = match node_count + 16 + self.data.len() as u64 {
+            max if max < 1 << 24 => 24,
+            max if max < 1 << 28 => 28,
+            max if max < 1 << 32 => 32,
+            _ => bail!("database too large"),
+        };
+        let value = |record: Record| -> u32 {
+            match record {
+                Record::Empty => node_count as u32,
+                Record::Node(node) => node,
+                Record::Data(offset) => node_count as u32 + 16 + offset,
+            }
+        };
+        let mut section = Vec::with_capacity(tree.len() * record_size / 4);
+        for [left, right] in tree {
+            let (l, r) = (value(left), value(right));
+            match record_size {
+                24 => {
+                    section.extend_from_slice(&l.to_be_bytes()[1..]);
+                    section.extend_from_slice(&r.to_be_bytes()[1..]);
+                }
+                28 => {
+                    section.extend_from_slice(&l.to_be_bytes()[1..]);
+                    section.push((((l >> 24) & 0xf) << 4 | (r >> 24) & 0xf) as u8);
+                    section.extend_from_slice(&r.to_be_bytes()[1..]);
+                }
+                _ => {
+                    section.extend_from_slice(&l.to_be_bytes());
+                    section.extend_from_slice(&r.to_be_bytes());
+                }
+            }
+        }
+        out.write_all(&section)?;
+        out.write_all(&[0; 16])?;
+        out.write_all(&self.data)?;
+        out.write_all(METADATA_START)?;
+        out.write_all(&self.metadata(node_count as u32, record_size as u16))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// The metadata map, with the integer types the format requires
+    fn metadata(&self, node_count: u32, record_size: u16) -> Vec<u8> {
+        let build_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut out = Vec::new();
+        control(&mut out, MAP, 9);
+        encode_str("binary_format_major_version", &mut out);
+        encode_uint(UINT16, 2, &mut out);
+        encode_str("binary_format_minor_version", &mut out);
+        encode_uint(UINT16, 0, &mut out);
+        encode_str("build_epoch", &mut out);
+        encode_uint(UINT64, build_epoch, &mut out);
+        encode_str("database_type", &mut out);
+        encode_str(&self.database_type, &mut out);
+        encode_str("description", &mut out);
+        match &self.description {
+            Some(description) => {
+                control(&mut out, MAP, 1);
+                encode_str("en", &mut out);
+                encode_str(description, &mut out);
+            }
+            None => control(&mut out, MAP, 0),
+        }
+        encode_str("ip_version", &mut out);
+        encode_uint(UINT16, 6, &mut out);
+        encode_str("languages", &mut out);
+        control(&mut out, ARRAY, 1);
+        encode_str("en", &mut out);
+        encode_str("node_count", &mut out);
+        encode_uint(UINT32, node_count.into(), &mut out);
+        encode_str("record_size", &mut out);
+        encode_uint(UINT16, record_size.into(), &mut out);
+        out
+    }
+}
+
+/// Record of a search tree node: another node, no data, or an offset into
+/// the data section
+#[derive(Copy, Clone)]
+enum Record {
+    Empty,
+    Node(u32),
+    Data(u32),
+}
+
+/// Point the records of the network `key`/`len` at `offset`, splitting the
+/// less specific networks already in `tree` on the way
+fn insert(tree: &mut Vec<[Record; 2]>, key: u128, len: u8, offset: u32) {
+    if len == 0 {
+        tree[0] = [Record::Data(offset); 2];
+        return;
+    }
+    let mut node = 0;
+    for i in 0..len {
+        let bit = ((key >> (127 - u32::from(i))) & 1) as usize;
+        if i == len - 1 {
+            tree[node][bit] = Record::Data(offset);
+            return;
+        }
+        node = match tree[node][bit] {
+            Record::Node(next) => next as usize,
+            record => {
+                tree.push([record; 2]);
+                let next = tree.len() - 1;
+                tree[node][bit] = Record::Node(next as u32);
+                next
+            }
+        };
+    }
+}
+
+/// Key of an address in the tree, IPv4 addresses under ::/96
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip).into(),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// The fewest networks, as start and prefix length in a space of `bits`
+/// bits, covering `start` to `end` inclusive
+fn networks(start: u128, end: u128, bits: u8) -> Vec<(u128, u8)> {
+    let hostmask = |size: u32| u128::MAX.checked_shr(128 - size).unwrap_or(0);
+    let mut networks = Vec::new();
+    let mut start = start;
+    loop {
+        // the largest aligned block at start that does not pass end
+        let mut size = start.trailing_zeros().min(u32::from(bits));
+        while size > 0 && start | hostmask(size) > end {
+            size -= 1;
+        }
+        networks.push((start, bits - size as u8));
+        let last = start | hostmask(size);
+        if last >= end {
+            return networks;
+        }
+        start = last + 1;
+    }
+}
+
+/// Set the field at the dotted `path` of `record`, creating nested maps
+fn set_path(record: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        Some((name, rest)) => {
+            let nested = record
+                .entry(name)
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !nested.is_object() {
+                *nested = Value::Object(Map::new());
+            }
+            if let Value::Object(nested) = nested {
+                set_path(nested, rest, value);
+            }
+        }
+        None => {
+            record.insert(path.to_string(), value);
+        }
+    }
+}
+
+/// A CSV value as an integer, decimal, or boolean if it is one. Integers
+/// with leading zeros, e.g. postal codes, stay strings
+fn typed(value: &str) -> Value {
+    let leading_zero = value.len() > 1 && value.starts_with('0') && !value.starts_with("0.");
+    match value {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ if leading_zero => Value::String(value.to_string()),
+        _ => match (value.parse::<u64>(), value.parse::<f64>()) {
+            (Ok(n), _) => Value::from(n),
+            (_, Ok(n)) if n.is_finite() && value.contains('.') => Value::from(n),
+            _ => Value::String(value.to_string()),
+        },
+    }
+}
+
+/// The fields of a CSV line, unquoting quoted fields
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field).trim().to_string()),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
+// data section types
+const STRING: u8 = 2;
+const DOUBLE: u8 = 3;
+const UINT16: u8 = 5;
+const UINT32: u8 = 6;
+const MAP: u8 = 7;
+const INT32: u8 = 8;
+const UINT64: u8 = 9;
+const ARRAY: u8 = 11;
+const BOOLEAN: u8 = 14;
+
+/// Append the control byte(s) of a value of `kind` and `size`
+fn control(out: &mut Vec<u8>, kind: u8, size: usize) {
+    let (bits, extra): (u8, &[u8]) = match size {
+        0..=28 => (size as u8, &[]),
+        29..=284 => (29, &(size as u32 - 29).to_be_bytes()[3..]),
+        285..=65820 => (30, &(size as u32 - 285).to_be_bytes()[2..]),
+        _ => (31, &(size as u32 - 65821).to_be_bytes()[1..]),
+    };
+    match kind {
+        0..=7 => out.push(kind << 5 | bits),
+        _ => out.extend_from_slice(&[bits, kind - 7]),
+    }
+    out.extend_from_slice(extra);
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    control(out, STRING, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Append an unsigned integer in as few bytes as it needs
+fn encode_uint(kind: u8, n: u64, out: &mut Vec<u8>) {
+    let bytes = n.to_be_bytes();
+    let skip = (n.leading_zeros() / 8) as usize;
+    control(out, kind, 8 - skip);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+/// Append `value` in the data section format. Null fields are left out
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => {}
+        Value::Bool(b) => control(out, BOOLEAN, usize::from(*b)),
+        Value::String(s) => encode_str(s, out),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) if n <= u64::from(u32::MAX) => encode_uint(UINT32, n, out),
+            (Some(n), _) => encode_uint(UINT64, n, out),
+            (_, Some(n)) if i32::try_from(n).is_ok() => {
+                control(out, INT32, 4);
+                out.extend_from_slice(&(n as i32).to_be_bytes());
+            }
+            _ => {
+                control(out, DOUBLE, 8);
+                out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_be_bytes());
+            }
+        },
+        Value::Array(values) => {
+            // null encodes to nothing, so it must not be counted either
+            let values: Vec<_> = values.iter().filter(|v| !v.is_null()).collect();
+            control(out, ARRAY, values.len());
+            for value in values {
+                encode(value, out);
+            }
+        }
+        Value::Object(fields) => {
+            let fields: Vec<_> = fields.iter().filter(|(_, v)| !v.is_null()).collect();
+            control(out, MAP, fields.len());
+            for (name, value) in fields {
+                encode_str(name, out);
+                encode(value, out);
+            }
+        }
+    }
+}
+
+/// Build the database of `args`
+pub fn build(args: &BuildArgs) -> Result<usize> {
+    let text = match args.csv.as_str() {
+        "-" => std::io::read_to_string(std::io::stdin().lock())?,
+        path => std::fs::read_to_string(path).with_context(|| format!("Could not read {path}"))?,
+    };
+    let mut writer = MmdbWriter::new(&args.database_type);
+    if let Some(description) = &args.description {
+        writer = writer.description(description);
+    }
+    writer
+        .insert_csv(&text)
+        .with_context(|| format!("{}: cannot build a database", args.csv))?;
+    let file = std::fs::File::create(&args.database)
+        .with_context(|| format!("Could not create {}", args.database))?;
+    writer.write(std::io::BufWriter::new(file))?;
+    Ok(writer.len())
+}
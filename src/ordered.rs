@@ -0,0 +1,95 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::{Condvar, Mutex};
+
+/// Writes chunks that are produced out of order, e.g. by worker threads, in
+/// the order of their sequence numbers, which start at 0. Chunks that
+/// arrive early wait in a reorder buffer; bound its size with a
+/// [`ReorderWindow`]
+///
+/// ```
+/// use geoipsed::ordered::OrderedWriter;
+///
+/// let mut out = OrderedWriter::new(Vec::new());
+/// out.push(1, b"world\n".to_vec()).unwrap();
+/// out.push(0, b"hello ".to_vec()).unwrap();
+/// assert_eq!(out.into_inner(), b"hello world\n");
+/// ```
+pub struct OrderedWriter<W> {
+    out: W,
+    next: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+}
+
+impl<W: Write> OrderedWriter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Write chunk `seq` once all chunks before it are written. Returns the
+    /// number of chunks written so far
+    pub fn push(&mut self, seq: u64, chunk: Vec<u8>) -> io::Result<u64> {
+        self.pending.insert(seq, chunk);
+        while let Some(chunk) = self.pending.remove(&self.next) {
+            self.out.write_all(&chunk)?;
+            self.next += 1;
+        }
+        Ok(self.next)
+    }
+
+    /// Number of chunks waiting for an earlier one
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Bounds how far ahead of the writer chunks may be produced, and so the
+/// memory held by an [`OrderedWriter`], however slow any one chunk is
+pub struct ReorderWindow {
+    size: u64,
+    written: Mutex<u64>,
+    advanced: Condvar,
+}
+
+impl ReorderWindow {
+    /// Allow at most `size` chunks between the next one to write and the
+    /// last one produced
+    pub fn new(size: u64) -> Self {
+        Self {
+            size: size.max(1),
+            written: Mutex::new(0),
+            advanced: Condvar::new(),
+        }
+    }
+
+    /// Wait until chunk `seq` is within the window
+    pub fn enter(&self, seq: u64) {
+        let mut written = self.written.lock().unwrap_or_else(|e| e.into_inner());
+        while seq >= *written + self.size {
+            written = self
+                .advanced
+                .wait(written)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Record that the first `written` chunks are written
+    pub fn advance(&self, written: u64) {
+        *self.written.lock().unwrap_or_else(|e| e.into_inner()) = written;
+        self.advanced.notify_all();
+    }
+
+    /// Stop waiting, e.g. after the writer failed, so that producers wind
+    /// down instead of blocking forever
+    pub fn close(&self) {
+        self.advance(u64::MAX - self.size);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::entity::{EntityKind, PatternSet, SearchProfile};
+use anyhow::Result;
+use camino::Utf8Path;
+use pcap_file::pcap::PcapReader;
+use pcap_file::pcapng::{Block, PcapNgReader};
+use pcap_file::DataLink;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+/// Invoke `f` with one text line per IP packet of a pcap or pcapng file:
+///
+/// `<epoch seconds> <src ip> <dst ip>[ <ip found in payload>...]\n`
+///
+/// so that packet captures flow through the same decoration pipeline as
+/// log lines. Non-IP frames and unsupported link types are skipped
+pub fn for_each_packet_line<F>(path: &Utf8Path, obfuscated_ipv4: bool, mut f: F) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let re = PatternSet::new()
+        .search_profile(SearchProfile::Throughput)
+        .obfuscated_ipv4(obfuscated_ipv4)
+        .kind(EntityKind::Ip)
+        .build()?;
+    let mut reader = BufReader::new(File::open(path)?);
+    let pcapng = reader.fill_buf()?.starts_with(&[0x0a, 0x0d, 0x0d, 0x0a]);
+    let mut line: Vec<u8> = Vec::with_capacity(256);
+
+    let mut emit = |ts: Duration, datalink: DataLink, data: &[u8]| -> Result<()> {
+        if let Some((src, dst, payload)) = parse_frame(datalink, data) {
+            line.clear();
+            write!(
+                line,
+                "{}.{:06} {src} {dst}",
+                ts.as_secs(),
+                ts.subsec_micros()
+            )?;
+            for m in re.find_iter(payload) {
+                line.push(b' ');
+                line.extend_from_slice(m.as_bytes());
+            }
+            line.push(b'\n');
+            f(&line)?;
+        }
+        Ok(())
+    };
+
+    if pcapng {
+        let mut pcapng = PcapNgReader::new(reader)?;
+        // link types of the interfaces of the current section, by interface id
+        let mut interfaces: Vec<DataLink> = Vec::new();
+        while let Some(block) = pcapng.next_block() {
+            match block? {
+                Block::SectionHeader(_) => interfaces.clear(),
+                Block::InterfaceDescription(iface) => interfaces.push(iface.linktype),
+                Block::EnhancedPacket(pkt) => {
+                    if let Some(&datalink) = interfaces.get(pkt.interface_id as usize) {
+                        emit(pkt.timestamp, datalink, &pkt.data)?;
+                    }
+                }
+                Block::SimplePacket(pkt) => {
+                    // simple packets have no timestamp and belong to the first interface
+                    if let Some(&datalink) = interfaces.first() {
+                        emit(Duration::ZERO, datalink, &pkt.data)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+    } else {
+        let mut pcap = PcapReader::new(reader)?;
+        let datalink = pcap.header().datalink;
+        while let Some(pkt) = pcap.next_packet() {
+            let pkt = pkt?;
+            emit(pkt.timestamp, datalink, &pkt.data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Get the source, destination, and payload of the IP packet in a frame
+fn parse_frame(datalink: DataLink, data: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    match datalink {
+        DataLink::ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+            let mut offset = 14;
+            // skip (possibly stacked) 802.1Q tags
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype = u16::from_be_bytes([*data.get(offset + 2)?, *data.get(offset + 3)?]);
+                offset += 4;
+            }
+            parse_ip(ethertype, data.get(offset..)?)
+        }
+        DataLink::LINUX_SLL => {
+            let ethertype = u16::from_be_bytes([*data.get(14)?, *data.get(15)?]);
+            parse_ip(ethertype, data.get(16..)?)
+        }
+        DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => {
+            let ethertype = match data.first()? >> 4 {
+                4 => ETHERTYPE_IPV4,
+                6 => ETHERTYPE_IPV6,
+                _ => return None,
+            };
+            parse_ip(ethertype, data)
+        }
+        // BSD loopback has a 4 byte address family header in host byte order
+        DataLink::NULL | DataLink::LOOP => {
+            let ethertype = match data.get(4)? >> 4 {
+                4 => ETHERTYPE_IPV4,
+                6 => ETHERTYPE_IPV6,
+                _ => return None,
+            };
+            parse_ip(ethertype, data.get(4..)?)
+        }
+        _ => None,
+    }
+}
+
+/// Get the source, destination, and payload of an IPv4 or IPv6 packet
+fn parse_ip(ethertype: u16, packet: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            let version_ihl = *packet.first()?;
+            let header_len = usize::from(version_ihl & 0x0f) * 4;
+            if version_ihl >> 4 != 4 || header_len < 20 {
+                return None;
+            }
+            let total_len = u16::from_be_bytes(packet.get(2..4)?.try_into().ok()?);
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            // ethernet pads short frames, so the payload ends at the total
+            // length rather than at the end of the frame
+            let end = usize::from(total_len).min(packet.len());
+            Some((
+                Ipv4Addr::from(src).into(),
+                Ipv4Addr::from(dst).into(),
+                packet.get(header_len..end).unwrap_or_default(),
+            ))
+        }
+        ETHERTYPE_IPV6 => {
+            let payload_len = u16::from_be_bytes(packet.get(4..6)?.try_into().ok()?);
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let end = (40 + usize::from(payload_len)).min(packet.len());
+            Some((
+                Ipv6Addr::from(src).into(),
+                Ipv6Addr::from(dst).into(),
+                &packet[40..end],
+            ))
+        }
+        _ => None,
+    }
+}
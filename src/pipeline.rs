@@ -0,0 +1,305 @@
+use crate::entity::{EntityKind, Extractor, PatternSet, SearchProfile};
+use crate::geoip::GeoIPSed;
+use crate::sink::text::TextSink;
+use crate::sink::{Occurrence, Sink};
+use anyhow::{anyhow, Result};
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// What a [`Pipeline`] found for one IP: its decoration and, if it is a
+/// valid address, its geoip record
+struct Lookup {
+    decoration: String,
+    record: Option<Value>,
+}
+
+/// Lookups of the IPs a [`Pipeline`] has seen, so that each is looked up
+/// once however often it occurs. Pass one made with
+/// [`Cache::with_capacity`] to [`Pipeline::cache`] to bound its size
+#[derive(Default)]
+pub struct Cache {
+    lookups: HashMap<String, Lookup>,
+    /// IPs of a bounded cache in the order they were cached, oldest first
+    order: VecDeque<String>,
+    /// lookups kept before the oldest is evicted, unbounded if None
+    capacity: Option<usize>,
+}
+
+impl Cache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache of at most `capacity` lookups, evicting the oldest when full to
+    /// bound its memory on inputs with very many distinct IPs
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            lookups: HashMap::default(),
+            order: VecDeque::default(),
+            capacity: Some(capacity),
+        }
+    }
+
+    /// Number of IPs cached
+    pub fn len(&self) -> usize {
+        self.lookups.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lookups.is_empty()
+    }
+
+    fn lookup(&mut self, geoipdb: &GeoIPSed, ip: &str) -> &Lookup {
+        // the key is only copied for IPs not seen before
+        if !self.lookups.contains_key(ip) {
+            if let Some(capacity) = self.capacity {
+                while self.lookups.len() >= capacity.max(1) {
+                    let Some(oldest) = self.order.pop_front() else {
+                        break;
+                    };
+                    self.lookups.remove(&oldest);
+                }
+                self.order.push_back(ip.to_string());
+            }
+            let (decoration, record) = geoipdb.lookup_with_json(ip);
+            self.lookups
+                .insert(ip.to_string(), Lookup { decoration, record });
+        }
+        &self.lookups[ip]
+    }
+}
+
+/// Line number and byte offset reached in an input
+#[derive(Default)]
+struct Position {
+    line_no: u64,
+    offset: u64,
+}
+
+/// Totals of the inputs run through a [`Pipeline`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Totals {
+    pub lines: u64,
+    pub matches: u64,
+}
+
+/// The read-scan-lookup-render-write loop of geoipsed: reads lines, finds
+/// the IPs in each with an [`Extractor`], looks them up with a
+/// [`GeoIPSed`], and writes the decorated lines and the occurrences of the
+/// IPs to a [`Sink`], stdout by default.
+///
+/// ```no_run
+/// use geoipsed::geoip::{GeoIPSed, IpColors};
+/// use geoipsed::pipeline::Pipeline;
+/// use termcolor::ColorChoice;
+///
+/// let geoipdb = GeoIPSed::new(None, None, None, ColorChoice::Never, IpColors::default())?;
+/// let totals = Pipeline::new().enricher(&geoipdb).run(std::io::stdin())?;
+/// eprintln!("{} IPs in {} lines", totals.matches, totals.lines);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// Entities other than IPs, e.g. of an extractor of MAC addresses too, are
+/// written as they are
+pub struct Pipeline<'a> {
+    extractor: Option<Extractor>,
+    enricher: Option<&'a GeoIPSed>,
+    cache: Cache,
+    sink: Option<Box<dyn Sink + 'a>>,
+    terminator: u8,
+    obfuscated_ipv4: bool,
+    totals: Totals,
+}
+
+impl Default for Pipeline<'_> {
+    fn default() -> Self {
+        Self {
+            extractor: None,
+            enricher: None,
+            cache: Cache::default(),
+            sink: None,
+            terminator: b'\n',
+            obfuscated_ipv4: false,
+            totals: Totals::default(),
+        }
+    }
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Find entities with `extractor` instead of the IP pattern of the
+    /// enricher. The default finds every address, as the command line
+    /// does, with [`SearchProfile::Throughput`]
+    pub fn extractor(mut self, extractor: Extractor) -> Self {
+        self.extractor = Some(extractor);
+        self
+    }
+
+    /// Whether the default extractor also finds obfuscated IPv4 addresses,
+    /// see [`PatternSet::obfuscated_ipv4`]. Ignored if an extractor is
+    /// given
+    pub fn obfuscated_ipv4(mut self, obfuscated_ipv4: bool) -> Self {
+        self.obfuscated_ipv4 = obfuscated_ipv4;
+        self
+    }
+
+    /// Look up and decorate IPs with `geoipdb`. Required
+    pub fn enricher(mut self, geoipdb: &'a GeoIPSed) -> Self {
+        self.enricher = Some(geoipdb);
+        self
+    }
+
+    /// Cache lookups in `cache` instead of an unbounded new one
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Write to `sink` instead of printing the decorated lines to stdout
+    pub fn sink(mut self, sink: impl Sink + 'a) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Lines end with `terminator` instead of a newline
+    pub fn terminator(mut self, terminator: u8) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    /// Run the lines of `reader` through the pipeline and finish the sink
+    pub fn run(mut self, reader: impl Read) -> Result<Totals> {
+        self.process("-", reader)?;
+        self.finish()
+    }
+
+    /// Run the lines of `reader`, the input named `source` in occurrences,
+    /// through the pipeline. Call [`Pipeline::finish`] after the last input
+    pub fn process(&mut self, source: &str, reader: impl Read) -> Result<()> {
+        let geoipdb = self.prepare()?;
+        if self.sink.is_none() {
+            self.sink = Some(Box::new(TextSink::new(io::stdout().lock())));
+        }
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        let mut decorated = Vec::new();
+        let mut position = Position::default();
+        loop {
+            line.clear();
+            if reader.read_until(self.terminator, &mut line)? == 0 {
+                break;
+            }
+            decorated.clear();
+            self.decorate(geoipdb, source, &mut position, &line, &mut decorated)?;
+            if let Some(sink) = self.sink.as_mut() {
+                sink.write_line(&decorated)?;
+            }
+        }
+        match self.sink.as_mut() {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`Pipeline::run`], but read lines from and write the decorated
+    /// lines to tokio streams, e.g. of network connections, without
+    /// blocking the runtime on them. Occurrences go to the sink if one is
+    /// set, which should not block for long
+    #[cfg(feature = "async")]
+    pub async fn run_async<R, W>(mut self, reader: R, mut writer: W) -> Result<Totals>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+        let geoipdb = self.prepare()?;
+        let mut reader = tokio::io::BufReader::new(reader);
+        let mut line = Vec::new();
+        let mut decorated = Vec::new();
+        let mut position = Position::default();
+        loop {
+            line.clear();
+            if reader.read_until(self.terminator, &mut line).await? == 0 {
+                break;
+            }
+            decorated.clear();
+            self.decorate(geoipdb, "-", &mut position, &line, &mut decorated)?;
+            writer.write_all(&decorated).await?;
+        }
+        writer.flush().await?;
+        self.finish()
+    }
+
+    /// The enricher, after building the default extractor if needed
+    fn prepare(&mut self) -> Result<&'a GeoIPSed> {
+        let geoipdb = self
+            .enricher
+            .ok_or_else(|| anyhow!("a pipeline needs an enricher"))?;
+        if self.extractor.is_none() {
+            self.extractor = Some(
+                PatternSet::permissive()
+                    .search_profile(SearchProfile::Throughput)
+                    .obfuscated_ipv4(self.obfuscated_ipv4)
+                    .kind(EntityKind::Ip)
+                    .build()?,
+            );
+        }
+        Ok(geoipdb)
+    }
+
+    /// Append `line`, at `position` of `source`, to `out` with its IPs
+    /// decorated, and write their occurrences to the sink
+    fn decorate(
+        &mut self,
+        geoipdb: &GeoIPSed,
+        source: &str,
+        position: &mut Position,
+        line: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        let extractor = self.extractor.as_ref().expect("extractor is set");
+        position.line_no += 1;
+        let mut lastpos = 0;
+        for m in extractor.find_iter(line) {
+            let ip = match (m.kind(), m.canonical_text()) {
+                (EntityKind::Ip, Some(ip)) => ip,
+                _ => continue,
+            };
+            let lookup = self.cache.lookup(geoipdb, &ip);
+            out.extend_from_slice(&line[lastpos..m.start()]);
+            out.extend_from_slice(lookup.decoration.as_bytes());
+            lastpos = m.end();
+            // strings that look like but are not valid ips have no record
+            if let Some(geo) = &lookup.record {
+                if let Some(sink) = self.sink.as_mut() {
+                    sink.write_record(&Occurrence {
+                        source,
+                        line_no: position.line_no,
+                        offset: position.offset + m.start() as u64,
+                        ip: &ip,
+                        geo,
+                    })?;
+                }
+                self.totals.matches += 1;
+            }
+        }
+        out.extend_from_slice(&line[lastpos..]);
+        position.offset += line.len() as u64;
+        self.totals.lines += 1;
+        Ok(())
+    }
+
+    /// Finish the sink, returning the totals of all inputs
+    pub fn finish(self) -> Result<Totals> {
+        if let Some(sink) = self.sink {
+            sink.finish()?;
+        }
+        Ok(self.totals)
+    }
+}
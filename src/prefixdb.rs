@@ -0,0 +1,55 @@
+use crate::prefixtrie::PrefixTrie;
+use anyhow::{bail, Context, Result};
+use camino::Utf8Path;
+use std::net::IpAddr;
+
+/// Prefix to origin ASN table loaded from a CAIDA Routeviews prefix2as
+/// file (`prefix<TAB>length<TAB>asn`) or a `prefix/length asn` listing such
+/// as those derived from MRT dumps
+pub struct PrefixTable {
+    trie: PrefixTrie<u32>,
+}
+
+impl PrefixTable {
+    /// Load a pfx2as table. Multi-origin (`123_456`) and AS-set (`123,456`)
+    /// entries map to their first ASN. Lines starting with # are comments
+    pub fn open(path: &Utf8Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read prefix table {path}"))?;
+        let mut trie = PrefixTrie::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((ip, len, asn)) = parse_line(line) else {
+                bail!(
+                    "{path}:{}: expected \"prefix length asn\" or \"prefix/length asn\"",
+                    i + 1
+                );
+            };
+            trie.insert(ip, len, asn);
+        }
+        Ok(Self { trie })
+    }
+
+    /// Origin ASN of the longest prefix containing `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        self.trie.lookup(ip).copied()
+    }
+}
+
+fn parse_line(line: &str) -> Option<(IpAddr, u8, u32)> {
+    let mut fields = line.split_whitespace();
+    let prefix = fields.next()?;
+    let (ip, len) = match prefix.split_once('/') {
+        Some((ip, len)) => (ip, len),
+        None => (prefix, fields.next()?),
+    };
+    let asn = fields
+        .next()?
+        .split(['_', ','])
+        .next()?
+        .trim_start_matches("AS");
+    Some((ip.parse().ok()?, len.parse().ok()?, asn.parse().ok()?))
+}
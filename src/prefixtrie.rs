@@ -0,0 +1,213 @@
+use crate::ipset::ParseCidrError;
+use std::net::IpAddr;
+
+/// Map an address into one 128-bit key space. IPv4 addresses use their
+/// IPv4-mapped IPv6 form, so an IPv4 /n prefix is a /(96+n) prefix
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+/// Bit `i` of `key`, counting from the most significant bit
+#[inline]
+fn bit(key: u128, i: u8) -> usize {
+    ((key >> (127 - u32::from(i))) & 1) as usize
+}
+
+/// `key` with all but the first `len` bits cleared
+#[inline]
+fn mask(key: u128, len: u8) -> u128 {
+    match len {
+        0 => 0,
+        len => key & (u128::MAX << (128 - u32::from(len))),
+    }
+}
+
+/// Number of leading bits `a` and `b` share, up to `max`
+#[inline]
+fn common_len(a: u128, b: u128, max: u8) -> u8 {
+    ((a ^ b).leading_zeros() as u8).min(max)
+}
+
+const NONE: u32 = u32::MAX;
+
+#[derive(Clone, Debug)]
+struct Node<V> {
+    key: u128,
+    len: u8,
+    value: Option<V>,
+    children: [u32; 2],
+}
+
+/// Path-compressed binary (radix) trie mapping IPv4 and IPv6 prefixes to
+/// arbitrary values with longest-prefix-match lookups. Nodes live in one Vec
+/// and refer to each other by index, and chains of single-child nodes are
+/// collapsed, so even a full routing table stays compact.
+///
+/// ```
+/// use geoipsed::prefixtrie::PrefixTrie;
+///
+/// let mut sites = PrefixTrie::new();
+/// sites.insert_cidr("10.0.0.0/8", "corp").unwrap();
+/// sites.insert_cidr("10.20.0.0/16", "datacenter").unwrap();
+/// assert_eq!(sites.lookup("10.20.1.1".parse().unwrap()), Some(&"datacenter"));
+/// assert_eq!(sites.lookup("10.1.1.1".parse().unwrap()), Some(&"corp"));
+/// assert_eq!(sites.lookup("192.0.2.1".parse().unwrap()), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct PrefixTrie<V> {
+    nodes: Vec<Node<V>>,
+    len: usize,
+}
+
+impl<V> Default for PrefixTrie<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> PrefixTrie<V> {
+    /// Empty trie
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                key: 0,
+                len: 0,
+                value: None,
+                children: [NONE; 2],
+            }],
+            len: 0,
+        }
+    }
+
+    /// Number of prefixes in the trie
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the trie has no prefixes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, key: u128, len: u8, value: Option<V>) -> u32 {
+        self.nodes.push(Node {
+            key,
+            len,
+            value,
+            children: [NONE; 2],
+        });
+        (self.nodes.len() - 1) as u32
+    }
+
+    /// Map the prefix `ip`/`len` (in bits of the address family, clamped to
+    /// 32 or 128) to `value`. Returns the previous value of the same prefix
+    pub fn insert(&mut self, ip: IpAddr, len: u8, value: V) -> Option<V> {
+        let len = match ip {
+            IpAddr::V4(_) => len.min(32) + 96,
+            IpAddr::V6(_) => len.min(128),
+        };
+        let key = mask(key(ip), len);
+
+        let mut node = 0;
+        loop {
+            // invariant: the prefix of `node` is a prefix of key/len
+            if self.nodes[node].len == len {
+                let old = self.nodes[node].value.replace(value);
+                if old.is_none() {
+                    self.len += 1;
+                }
+                return old;
+            }
+            let b = bit(key, self.nodes[node].len);
+            let child = self.nodes[node].children[b];
+            if child == NONE {
+                let leaf = self.push(key, len, Some(value));
+                self.nodes[node].children[b] = leaf;
+                self.len += 1;
+                return None;
+            }
+
+            let child = child as usize;
+            let (child_key, child_len) = (self.nodes[child].key, self.nodes[child].len);
+            let common = common_len(child_key, key, child_len.min(len));
+            if common == child_len {
+                node = child;
+                continue;
+            }
+
+            // the new prefix diverges from (or is a prefix of) the child, so
+            // split the edge with a node for their common prefix
+            let mid = self.push(mask(key, common), common, None);
+            self.nodes[node].children[b] = mid;
+            self.nodes[mid as usize].children[bit(child_key, common)] = child as u32;
+            if common == len {
+                self.nodes[mid as usize].value = Some(value);
+            } else {
+                let leaf = self.push(key, len, Some(value));
+                self.nodes[mid as usize].children[bit(key, common)] = leaf;
+            }
+            self.len += 1;
+            return None;
+        }
+    }
+
+    /// Map a network written as an address ("192.0.2.1") or in CIDR notation
+    /// ("10.0.0.0/8", "2001:db8::/32") to `value`. Returns the previous value
+    /// of the same prefix
+    pub fn insert_cidr(&mut self, cidr: &str, value: V) -> Result<Option<V>, ParseCidrError> {
+        let err = || ParseCidrError(cidr.to_string());
+        let (ip, len) = match cidr.split_once('/') {
+            Some((ip, len)) => (ip, Some(len.parse::<u8>().map_err(|_| err())?)),
+            None => (cidr, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|_| err())?;
+        let len = match (ip, len) {
+            (IpAddr::V4(_), Some(len)) if len <= 32 => len,
+            (IpAddr::V6(_), Some(len)) if len <= 128 => len,
+            (_, Some(_)) => return Err(err()),
+            (IpAddr::V4(_), None) => 32,
+            (IpAddr::V6(_), None) => 128,
+        };
+        Ok(self.insert(ip, len, value))
+    }
+
+    /// Value of the longest prefix containing `ip`
+    pub fn lookup(&self, ip: IpAddr) -> Option<&V> {
+        let key = key(ip);
+        let mut node = &self.nodes[0];
+        let mut best = node.value.as_ref();
+        while node.len < 128 {
+            let child = node.children[bit(key, node.len)];
+            if child == NONE {
+                break;
+            }
+            node = &self.nodes[child as usize];
+            if mask(key, node.len) != node.key {
+                break;
+            }
+            if let Some(value) = &node.value {
+                best = Some(value);
+            }
+        }
+        best
+    }
+}
+
+impl<V> Extend<(IpAddr, u8, V)> for PrefixTrie<V> {
+    fn extend<I: IntoIterator<Item = (IpAddr, u8, V)>>(&mut self, iter: I) {
+        for (ip, len, value) in iter {
+            self.insert(ip, len, value);
+        }
+    }
+}
+
+impl<V> FromIterator<(IpAddr, u8, V)> for PrefixTrie<V> {
+    fn from_iter<I: IntoIterator<Item = (IpAddr, u8, V)>>(iter: I) -> Self {
+        let mut trie = PrefixTrie::new();
+        trie.extend(iter);
+        trie
+    }
+}
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Error returned when a sample rate is not a fraction or percentage in
+/// (0, 1]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseRateError(pub(crate) String);
+
+impl fmt::Display for ParseRateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid sample rate {:?}, expected a percentage (e.g. 1%) or fraction (e.g. 0.01) of at most 100%",
+            self.0
+        )
+    }
+}
+
+impl Error for ParseRateError {}
+
+/// Fraction of lines to sample, parsed from e.g. "1%" or "0.01"
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampleRate(f64);
+
+impl SampleRate {
+    pub fn fraction(self) -> f64 {
+        self.0
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = ParseRateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseRateError(s.to_string());
+        let rate = match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f64>().map_err(|_| err())? / 100.0,
+            None => s.parse::<f64>().map_err(|_| err())?,
+        };
+        match rate > 0.0 && rate <= 1.0 {
+            true => Ok(SampleRate(rate)),
+            false => Err(err()),
+        }
+    }
+}
+
+/// Decides which lines to keep, each independently with the probability of
+/// the sample rate. The same seed keeps the same lines
+pub struct Sampler {
+    /// keep values below this of the 64-bit random range
+    threshold: u64,
+    state: u64,
+}
+
+impl Sampler {
+    pub fn new(rate: SampleRate, seed: u64) -> Self {
+        let threshold = match rate.0 >= 1.0 {
+            true => u64::MAX,
+            false => (rate.0 * u64::MAX as f64) as u64,
+        };
+        Self {
+            threshold,
+            state: seed,
+        }
+    }
+
+    /// Whether to keep the next line
+    pub fn keep(&mut self) -> bool {
+        self.next_u64() <= self.threshold
+    }
+
+    /// SplitMix64, plenty for sampling and without a dependency
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
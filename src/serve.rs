@@ -0,0 +1,241 @@
+use crate::geoip::GeoIPSed;
+use crate::metrics::Metrics;
+use anyhow::{anyhow, Result};
+use clap::{Args, ValueEnum};
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::json;
+use std::io::Read;
+use std::net::IpAddr;
+use std::sync::mpsc::{self, TrySendError};
+use std::thread;
+use std::time::Instant;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body accepted by POST /decorate
+const MAX_BODY: u64 = 64 * 1024 * 1024;
+
+/// Serve decorations and lookups over HTTP
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// Address to listen on. Use port 0 to pick a free port, which is
+    /// printed on stderr
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    /// Requests per second allowed from each client address, with bursts
+    /// of as many. Requests over the limit are answered with a 429. 0 for
+    /// no limit
+    #[clap(long, value_name = "N", default_value_t = 0)]
+    pub rate_limit: u32,
+
+    /// Requests waiting to be answered before --shed applies
+    #[clap(long, value_name = "N", default_value_t = 1024, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub queue_size: usize,
+
+    /// What to do with requests arriving at a full queue: answer them with
+    /// a 503, or stop accepting requests until the queue has room
+    #[clap(long, value_enum, value_name = "POLICY", default_value_t = ShedPolicy::Drop)]
+    pub shed: ShedPolicy,
+}
+
+/// How requests arriving at a full queue are handled
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum ShedPolicy {
+    /// answer with 503 Service Unavailable
+    Drop,
+    /// wait for room in the queue
+    Block,
+}
+
+/// Per-client token buckets holding up to `rate` tokens, refilled at
+/// `rate` tokens per second
+struct RateLimiter {
+    rate: f64,
+    buckets: HashMap<IpAddr, (f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate),
+            buckets: HashMap::default(),
+        }
+    }
+
+    /// Whether `client` may make another request now
+    fn allow(&mut self, client: IpAddr) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let rate = self.rate;
+        // clients idle long enough to have a full bucket need no entry
+        if self.buckets.len() > 10_000 {
+            self.buckets.retain(|_, (tokens, last)| {
+                *tokens + now.duration_since(*last).as_secs_f64() * rate < rate
+            });
+        }
+        let (tokens, last) = self.buckets.entry(client).or_insert((rate, now));
+        *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * rate).min(rate);
+        *last = now;
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+/// Answer requests until the process is stopped, keeping the databases and
+/// caches warm across requests:
+///
+/// * `POST /decorate` decorates the request body with `decorate` and returns
+///   the text
+/// * `GET /lookup/{ip}` returns the geoip fields of one address as JSON
+/// * `GET /healthz` returns "ok"
+/// * `GET /metrics` returns the counters `decorate` keeps in its
+///   [`Metrics`] argument, in the Prometheus text format
+///
+/// Requests are accepted on a separate thread that applies the
+/// --rate-limit and queues them for answering, bounded by --queue-size, so
+/// that a burst of traffic cannot grow memory without bound. Health checks
+/// and metrics scrapes are not rate limited
+pub fn serve<F>(args: &ServeArgs, geoipdb: &GeoIPSed, mut decorate: F) -> Result<()>
+where
+    F: FnMut(&[u8], &Metrics) -> Result<Vec<u8>>,
+{
+    let metrics = Metrics::new();
+    let server = Server::http(&args.listen).map_err(|e| anyhow!("{}: {e}", args.listen))?;
+    if let Some(addr) = server.server_addr().to_ip() {
+        eprintln!("geoipsed listening on http://{addr}");
+    }
+    let (send, queue) = mpsc::sync_channel::<Request>(args.queue_size);
+    thread::scope(|scope| {
+        let (server, metrics) = (&server, &metrics);
+        let mut limiter = RateLimiter::new(args.rate_limit);
+        scope.spawn(move || {
+            for request in server.incoming_requests() {
+                let exempt = matches!(request.url(), "/healthz" | "/metrics");
+                let client = request.remote_addr().map(|addr| addr.ip());
+                if !exempt && client.is_some_and(|client| !limiter.allow(client)) {
+                    metrics.dropped("rate_limit");
+                    let _ = request.respond(text(429, b"too many requests\n".to_vec()));
+                    continue;
+                }
+                let queued = match args.shed {
+                    ShedPolicy::Block => send
+                        .send(request)
+                        .map_err(|e| TrySendError::Disconnected(e.0)),
+                    ShedPolicy::Drop => send.try_send(request),
+                };
+                match queued {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(request)) => {
+                        metrics.dropped("queue_full");
+                        let _ = request.respond(text(503, b"queue full\n".to_vec()));
+                    }
+                    Err(TrySendError::Disconnected(_)) => break,
+                }
+            }
+        });
+        answer(queue, geoipdb, metrics, &mut decorate);
+    });
+    Ok(())
+}
+
+/// Answer the queued requests until the accepting thread stops
+fn answer<F>(
+    queue: mpsc::Receiver<Request>,
+    geoipdb: &GeoIPSed,
+    metrics: &Metrics,
+    decorate: &mut F,
+) where
+    F: FnMut(&[u8], &Metrics) -> Result<Vec<u8>>,
+{
+    for mut request in queue {
+        let response = match (request.method(), request.url()) {
+            (Method::Get, "/healthz") => text(200, b"ok\n".to_vec()),
+            (Method::Get, "/metrics") => Response::from_data(metrics.render().into_bytes())
+                .with_header(header("text/plain; version=0.0.4; charset=utf-8")),
+            (Method::Get, url) if url.starts_with("/lookup/") => {
+                let ip = percent_decode(&url["/lookup/".len()..]);
+                match geoipdb.lookup_output_json(&ip) {
+                    Some(record) => json_response(200, &record),
+                    None => json_error(404, "not an IP address"),
+                }
+            }
+            (Method::Post, "/decorate") => match read_body(&mut request) {
+                Ok(body) => match decorate(&body, metrics) {
+                    Ok(decorated) => text(200, decorated),
+                    Err(e) => text(500, format!("{e}\n").into_bytes()),
+                },
+                Err(e) => text(400, format!("{e}\n").into_bytes()),
+            },
+            (_, "/healthz" | "/metrics" | "/decorate") => {
+                text(405, b"method not allowed\n".to_vec())
+            }
+            (_, url) if url.starts_with("/lookup/") => text(405, b"method not allowed\n".to_vec()),
+            _ => text(404, b"not found\n".to_vec()),
+        };
+        // a client that hung up is no reason to stop serving
+        let _ = request.respond(response);
+    }
+}
+
+type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+fn header(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("valid header")
+}
+
+fn text(status: u16, body: Vec<u8>) -> HttpResponse {
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("text/plain; charset=utf-8"))
+}
+
+fn json_response(status: u16, value: &serde_json::Value) -> HttpResponse {
+    let mut body = value.to_string().into_bytes();
+    body.push(b'\n');
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("application/json"))
+}
+
+fn json_error(status: u16, message: &str) -> HttpResponse {
+    json_response(status, &json!({ "error": message }))
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>> {
+    let mut body = Vec::with_capacity(request.body_length().unwrap_or(0).min(MAX_BODY as usize));
+    request
+        .as_reader()
+        .take(MAX_BODY + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BODY {
+        return Err(anyhow!("request body larger than {MAX_BODY} bytes"));
+    }
+    Ok(body)
+}
+
+/// Decode %XX escapes, e.g. the colons of an IPv6 address in a URL
+fn percent_decode(s: &str) -> String {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [bytes.next(), bytes.next()];
+            if let [Some(hi), Some(lo)] = hex {
+                if let Ok(byte) = u8::from_str_radix(&String::from_utf8_lossy(&[hi, lo]), 16) {
+                    out.push(byte);
+                    continue;
+                }
+            }
+            out.push(b);
+            out.extend(hex.into_iter().flatten());
+        } else {
+            out.push(b);
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, bail, Error, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+
+pub mod csv;
+pub mod esbulk;
+pub mod http;
+pub mod jsonl;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod text;
+
+/// Elasticsearch index of es-bulk output without --index
+pub const DEFAULT_INDEX: &str = "geoipsed";
+
+/// Destination for extracted IPs when the decorated text itself is not the
+/// desired output. Specified on the command line as `KIND:PATH`, or with
+/// --post-url or --splunk-hec for an HTTP endpoint
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputSpec {
+    /// SQLite database, `sqlite:PATH`
+    Sqlite(Utf8PathBuf),
+    /// Parquet file, `parquet:PATH`
+    Parquet(Utf8PathBuf),
+    /// JSON lines, `jsonl` for stdout or `jsonl:PATH`
+    JsonLines(Option<Utf8PathBuf>),
+    /// CSV with a header row, `csv` for stdout or `csv:PATH`
+    Csv(Option<Utf8PathBuf>),
+    /// Elasticsearch `_bulk` requests for `index`, `es-bulk` for stdout or
+    /// `es-bulk:PATH`
+    EsBulk {
+        path: Option<Utf8PathBuf>,
+        index: String,
+    },
+    /// HTTP endpoint posted to in batches of `batch` records
+    Http {
+        url: String,
+        batch: usize,
+        format: http::PostFormat,
+    },
+}
+
+impl FromStr for OutputSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "jsonl" => return Ok(OutputSpec::JsonLines(None)),
+            "csv" => return Ok(OutputSpec::Csv(None)),
+            "es-bulk" => {
+                return Ok(OutputSpec::EsBulk {
+                    path: None,
+                    index: DEFAULT_INDEX.to_string(),
+                })
+            }
+            _ => {}
+        }
+        let (kind, path) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected KIND:PATH, for example sqlite:ips.db"))?;
+        if path.is_empty() {
+            bail!("missing output path after {kind}:");
+        }
+        match kind {
+            "sqlite" => Ok(OutputSpec::Sqlite(Utf8PathBuf::from(path))),
+            "parquet" => Ok(OutputSpec::Parquet(Utf8PathBuf::from(path))),
+            "jsonl" => Ok(OutputSpec::JsonLines(Some(Utf8PathBuf::from(path)))),
+            "csv" => Ok(OutputSpec::Csv(Some(Utf8PathBuf::from(path)))),
+            "es-bulk" => Ok(OutputSpec::EsBulk {
+                path: Some(Utf8PathBuf::from(path)),
+                index: DEFAULT_INDEX.to_string(),
+            }),
+            _ => bail!("unknown output kind {kind:?}, expected one of: sqlite, parquet, jsonl, csv, es-bulk"),
+        }
+    }
+}
+
+impl OutputSpec {
+    /// Create the sink described by this spec
+    pub fn open(&self) -> Result<Box<dyn Sink>> {
+        match self {
+            #[cfg(feature = "sqlite")]
+            OutputSpec::Sqlite(path) => Ok(Box::new(sqlite::SqliteSink::create(path)?)),
+            #[cfg(not(feature = "sqlite"))]
+            OutputSpec::Sqlite(_) => {
+                bail!("sqlite output requires geoipsed to be built with the `sqlite` feature")
+            }
+            OutputSpec::JsonLines(path) => Ok(Box::new(jsonl::JsonLinesSink::new(create(
+                path.as_deref(),
+            )?))),
+            OutputSpec::Csv(path) => Ok(Box::new(csv::CsvSink::new(create(path.as_deref())?))),
+            OutputSpec::EsBulk { path, index } => Ok(Box::new(esbulk::EsBulkSink::create(
+                path.as_deref(),
+                index,
+            )?)),
+            OutputSpec::Http { url, batch, format } => {
+                Ok(Box::new(http::HttpSink::create(url, *batch, format)?))
+            }
+            #[cfg(feature = "parquet")]
+            OutputSpec::Parquet(path) => Ok(Box::new(parquet::ParquetSink::create(path)?)),
+            #[cfg(not(feature = "parquet"))]
+            OutputSpec::Parquet(_) => {
+                bail!("parquet output requires geoipsed to be built with the `parquet` feature")
+            }
+        }
+    }
+}
+
+/// Buffered writer of the file at `path`, or stdout if None
+pub(crate) fn create(path: Option<&Utf8Path>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+/// One IP address found in the input along with its geoip record
+pub struct Occurrence<'a> {
+    /// Input file the IP was found in, "-" for stdin
+    pub source: &'a str,
+    /// 1-based line number within the source
+    pub line_no: u64,
+    /// Byte offset of the IP from the start of the source
+    pub offset: u64,
+    /// The IP as it appeared in the input
+    pub ip: &'a str,
+    /// Geoip fields of the IP, keyed by template field name
+    pub geo: &'a Value,
+}
+
+impl Occurrence<'_> {
+    /// The flat JSON record of the occurrence: its source, line number,
+    /// and offset followed by the geoip fields
+    pub fn to_record(&self) -> Map<String, Value> {
+        let mut record = Map::new();
+        record.insert("source".to_string(), self.source.into());
+        record.insert("line_no".to_string(), self.line_no.into());
+        record.insert("offset".to_string(), self.offset.into());
+        if let Some(geo) = self.geo.as_object() {
+            record.extend(geo.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        record
+    }
+}
+
+/// Destination of the output of a run: lines of decorated text, IP
+/// occurrences with their geoip records, or both. Sinks ignore what they
+/// have no use for. Implement it to send output where geoipsed does not,
+/// see [`text::TextSink`], [`jsonl::JsonLinesSink`], and [`csv::CsvSink`]
+pub trait Sink {
+    /// Write one line of output, terminator included
+    fn write_line(&mut self, _line: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Record one occurrence
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()>;
+
+    /// Write out what is buffered so far
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Flush and close the sink once all input has been processed
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()
+    }
+}
+
+/// Pass `output`, whole lines ending in `terminator` except maybe the last,
+/// to `sink` a line at a time
+pub fn write_lines(sink: &mut (impl Sink + ?Sized), output: &[u8], terminator: u8) -> Result<()> {
+    output
+        .split_inclusive(|&b| b == terminator)
+        .try_for_each(|line| sink.write_line(line))
+}
+
+/// Writer passing what is written to a sink a line at a time, for writers
+/// of whole lines such as an [`crate::ordered::OrderedWriter`] of decorated
+/// batches. Each write must end at a line terminator or the end of output
+pub struct LineWriter<'a, S: ?Sized> {
+    sink: &'a mut S,
+    terminator: u8,
+}
+
+impl<'a, S: Sink + ?Sized> LineWriter<'a, S> {
+    pub fn new(sink: &'a mut S, terminator: u8) -> Self {
+        Self { sink, terminator }
+    }
+}
+
+impl<S: Sink + ?Sized> Write for LineWriter<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_lines(self.sink, buf, self.terminator).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush().map_err(io::Error::other)
+    }
+}
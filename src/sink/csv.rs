@@ -0,0 +1,63 @@
+use super::{Occurrence, Sink};
+use crate::geoip::ip_field_names;
+use anyhow::Result;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io::Write;
+
+/// Writes each occurrence as a CSV row of its source, line number, offset,
+/// and geoip fields, after a header row naming the columns
+pub struct CsvSink<W: Write> {
+    out: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            header_written: false,
+        }
+    }
+}
+
+/// `value` quoted if it contains a comma, quote, or line break
+fn csv_field(value: &str) -> Cow<'_, str> {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")).into(),
+        false => value.into(),
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        if !self.header_written {
+            let mut header = vec!["source", "line_no", "offset"];
+            header.extend(ip_field_names());
+            writeln!(self.out, "{}", header.join(","))?;
+            self.header_written = true;
+        }
+        write!(
+            self.out,
+            "{},{},{}",
+            csv_field(occurrence.source),
+            occurrence.line_no,
+            occurrence.offset
+        )?;
+        for name in ip_field_names() {
+            let value = match &occurrence.geo[name] {
+                Value::Null => Cow::Borrowed(""),
+                Value::String(s) => Cow::Borrowed(s.as_str()),
+                other => Cow::Owned(other.to_string()),
+            };
+            write!(self.out, ",{}", csv_field(&value))?;
+        }
+        writeln!(self.out)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
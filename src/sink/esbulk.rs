@@ -0,0 +1,105 @@
+use super::{Occurrence, Sink};
+use crate::ecs;
+use anyhow::Result;
+use camino::Utf8Path;
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// Groups the occurrences of each input line into one ECS document. The
+/// first IP of a line is its `source` and the second its `destination`, as
+/// in most flow and firewall logs, and all of them are in `related.ip`
+#[derive(Default)]
+pub struct LineDocuments {
+    /// source and line number of the pending line
+    line: Option<(String, u64)>,
+    offset: u64,
+    endpoints: Vec<Value>,
+}
+
+impl LineDocuments {
+    /// Add `occurrence`, returning the document of the previous line if
+    /// the occurrence starts a new line
+    pub fn push(&mut self, occurrence: &Occurrence) -> Option<Value> {
+        let same_line = self.line.as_ref().is_some_and(|(source, line_no)| {
+            source == occurrence.source && *line_no == occurrence.line_no
+        });
+        let done = if same_line { None } else { self.finish() };
+        if self.line.is_none() {
+            self.line = Some((occurrence.source.to_string(), occurrence.line_no));
+            self.offset = occurrence.offset;
+        }
+        self.endpoints.push(ecs::endpoint(occurrence.geo));
+        done
+    }
+
+    /// The document of the pending line, if any
+    pub fn finish(&mut self) -> Option<Value> {
+        let (source, line_no) = self.line.take()?;
+        let endpoints = std::mem::take(&mut self.endpoints);
+        let related: Vec<&Value> = endpoints.iter().map(|e| &e["ip"]).collect();
+        let mut document = json!({
+            "log": {
+                "file": { "path": source },
+                "offset": self.offset,
+                "line_no": line_no,
+            },
+            "related": { "ip": related },
+        });
+        for (role, endpoint) in ["source", "destination"].into_iter().zip(&endpoints) {
+            document[role] = endpoint.clone();
+        }
+        Some(document)
+    }
+}
+
+/// Writes Elasticsearch `_bulk` requests: an index action line followed by
+/// the ECS document of each input line with IPs
+pub struct EsBulkSink {
+    out: Box<dyn Write>,
+    action: Vec<u8>,
+    documents: LineDocuments,
+}
+
+impl EsBulkSink {
+    /// Sink writing the documents for `index` to `path`, or stdout if None
+    pub fn create(path: Option<&Utf8Path>, index: &str) -> Result<Self> {
+        Ok(Self {
+            out: super::create(path)?,
+            action: bulk_action(index),
+            documents: LineDocuments::default(),
+        })
+    }
+
+    fn write_document(&mut self, document: &Value) -> Result<()> {
+        self.out.write_all(&self.action)?;
+        serde_json::to_writer(&mut self.out, document)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// The `_bulk` action line indexing the next document into `index`
+pub fn bulk_action(index: &str) -> Vec<u8> {
+    let mut action = json!({ "index": { "_index": index } })
+        .to_string()
+        .into_bytes();
+    action.push(b'\n');
+    action
+}
+
+impl Sink for EsBulkSink {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        if let Some(document) = self.documents.push(occurrence) {
+            self.write_document(&document)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if let Some(document) = self.documents.finish() {
+            self.write_document(&document)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use super::esbulk::{bulk_action, LineDocuments};
+use super::{Occurrence, Sink};
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+/// Environment variable holding the value of the Authorization header of
+/// posted batches, e.g. "Bearer TOKEN" or "ApiKey KEY"
+pub const AUTH_ENV: &str = "GEOIPSED_POST_AUTH";
+
+/// Attempts to post a batch before giving up
+const ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry, doubled after each further failure
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Sourcetype of the events sent to a Splunk HTTP Event Collector
+const HEC_SOURCETYPE: &str = "geoipsed";
+
+/// What the records posted by [`HttpSink`] look like
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PostFormat {
+    /// One NDJSON record per occurrence
+    Ndjson,
+    /// Elasticsearch `_bulk` requests of ECS documents for `index`
+    EsBulk { index: String },
+    /// Splunk HTTP Event Collector events, authenticated with the token in
+    /// the environment variable `token_env`
+    SplunkHec { token_env: String },
+}
+
+/// Posts occurrences as NDJSON records in batches to an http:// endpoint,
+/// e.g. a SOAR webhook, as ECS documents to an Elasticsearch `_bulk` API,
+/// or as events to a Splunk HTTP Event Collector. Failed posts are retried
+/// with exponential backoff
+pub struct HttpSink {
+    host: String,
+    path: String,
+    auth: Option<String>,
+    /// action line and documents of the lines, for a `_bulk` API
+    bulk: Option<(Vec<u8>, LineDocuments)>,
+    /// wrap records in HEC events
+    hec: bool,
+    batch: usize,
+    pending: Vec<u8>,
+    records: usize,
+}
+
+impl HttpSink {
+    /// Sink posting every `batch` records to `url` in `format`. Only plain
+    /// http:// URLs are supported
+    pub fn create(url: &str, batch: usize, format: &PostFormat) -> Result<Self> {
+        let Some(rest) = url.strip_prefix("http://") else {
+            bail!("{url}: only http:// URLs are supported, e.g. of a local shipper or proxy");
+        };
+        let (host, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            bail!("{url}: missing host");
+        }
+        let host = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{host}:80"),
+        };
+        let auth = match format {
+            PostFormat::SplunkHec { token_env } => match std::env::var(token_env) {
+                Ok(token) => Some(format!("Splunk {token}")),
+                Err(_) => bail!("the HEC token variable ${token_env} is not set"),
+            },
+            _ => std::env::var(AUTH_ENV).ok(),
+        };
+        let bulk = match format {
+            PostFormat::EsBulk { index } => Some((bulk_action(index), LineDocuments::default())),
+            _ => None,
+        };
+        Ok(Self {
+            host,
+            path: path.to_string(),
+            auth,
+            bulk,
+            hec: matches!(format, PostFormat::SplunkHec { .. }),
+            batch,
+            pending: Vec::new(),
+            records: 0,
+        })
+    }
+
+    /// Add `record` to the batch, posting the batch once it is full
+    fn push(&mut self, record: &Value) -> Result<()> {
+        if let Some((action, _)) = &self.bulk {
+            self.pending.extend_from_slice(action);
+        }
+        serde_json::to_writer(&mut self.pending, record)?;
+        self.pending.push(b'\n');
+        self.records += 1;
+        if self.records >= self.batch {
+            self.post_pending()?;
+        }
+        Ok(())
+    }
+
+    /// Post the pending records, retrying failed attempts
+    fn post_pending(&mut self) -> Result<()> {
+        if self.records == 0 {
+            return Ok(());
+        }
+        let mut delay = BACKOFF;
+        for attempt in 1..=ATTEMPTS {
+            match self.post() {
+                Ok(()) => break,
+                Err(Failure::Fatal(e)) => return Err(e),
+                Err(Failure::Retry(e)) if attempt == ATTEMPTS => {
+                    return Err(e.context(format!("giving up after {ATTEMPTS} attempts")))
+                }
+                Err(Failure::Retry(e)) => {
+                    tracing::warn!(error = %e, attempt, "post failed, retrying");
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+            }
+        }
+        self.pending.clear();
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Make one POST of the pending records
+    fn post(&self) -> Result<(), Failure> {
+        let mut stream = TcpStream::connect(&self.host)
+            .with_context(|| format!("cannot connect to {}", self.host))
+            .map_err(Failure::Retry)?;
+        let timeout = Some(Duration::from_secs(30));
+        let _ = stream.set_read_timeout(timeout);
+        let _ = stream.set_write_timeout(timeout);
+
+        let mut head = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-ndjson\r\n\
+             Content-Length: {}\r\nConnection: close\r\n",
+            self.path,
+            self.host,
+            self.pending.len()
+        );
+        if let Some(auth) = &self.auth {
+            head.push_str(&format!("Authorization: {auth}\r\n"));
+        }
+        head.push_str("\r\n");
+        stream
+            .write_all(head.as_bytes())
+            .and_then(|()| stream.write_all(&self.pending))
+            .map_err(|e| Failure::Retry(e.into()))?;
+
+        let mut status_line = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut status_line)
+            .map_err(|e| Failure::Retry(e.into()))?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| Failure::Retry(anyhow!("invalid response {status_line:?}")))?;
+        match status {
+            200..=299 => Ok(()),
+            // throttled or unavailable endpoints may recover
+            408 | 429 | 500..=599 => Err(Failure::Retry(anyhow!("{}: HTTP {status}", self.host))),
+            _ => Err(Failure::Fatal(anyhow!("{}: HTTP {status}", self.host))),
+        }
+    }
+}
+
+/// Why a post failed
+enum Failure {
+    Retry(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+impl Sink for HttpSink {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        let record = match &mut self.bulk {
+            Some((_, documents)) => match documents.push(occurrence) {
+                Some(document) => document,
+                None => return Ok(()),
+            },
+            None => {
+                let record = occurrence.to_record();
+                match self.hec {
+                    true => json!({
+                        "source": occurrence.source,
+                        "sourcetype": HEC_SOURCETYPE,
+                        "event": record,
+                    }),
+                    false => Value::Object(record),
+                }
+            }
+        };
+        self.push(&record)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        if let Some(document) = self.bulk.as_mut().and_then(|(_, docs)| docs.finish()) {
+            self.push(&document)?;
+        }
+        self.post_pending()
+    }
+}
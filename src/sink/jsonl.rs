@@ -0,0 +1,29 @@
+use super::{Occurrence, Sink};
+use anyhow::Result;
+use serde_json::Value;
+use std::io::Write;
+
+/// Writes each occurrence as a JSON line of its source, line number,
+/// offset, and geoip fields, the records posted by --post-url
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Sink for JsonLinesSink<W> {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        serde_json::to_writer(&mut self.out, &Value::Object(occurrence.to_record()))?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use super::{Occurrence, Sink};
+use anyhow::Result;
+use arrow_array::builder::{
+    Float64Builder, StringBuilder, UInt32Builder, UInt64Builder, UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use camino::Utf8Path;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Number of occurrences buffered into each parquet row group
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Writes one row per occurrence into a snappy-compressed Parquet file with
+/// columns ip, version, asn, org, country, city, lat, lon, source, line_no.
+/// Unknown geoip values are written as nulls rather than empty strings/zeros
+pub struct ParquetSink {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    rows: usize,
+    ip: StringBuilder,
+    version: UInt8Builder,
+    asn: UInt32Builder,
+    org: StringBuilder,
+    country: StringBuilder,
+    city: StringBuilder,
+    lat: Float64Builder,
+    lon: Float64Builder,
+    source: StringBuilder,
+    line_no: UInt64Builder,
+}
+
+impl ParquetSink {
+    /// Create (or truncate) the parquet file at `path`
+    pub fn create(path: &Utf8Path) -> Result<Self> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("ip", DataType::Utf8, false),
+            Field::new("version", DataType::UInt8, false),
+            Field::new("asn", DataType::UInt32, true),
+            Field::new("org", DataType::Utf8, true),
+            Field::new("country", DataType::Utf8, true),
+            Field::new("city", DataType::Utf8, true),
+            Field::new("lat", DataType::Float64, true),
+            Field::new("lon", DataType::Float64, true),
+            Field::new("source", DataType::Utf8, false),
+            Field::new("line_no", DataType::UInt64, false),
+        ]));
+        let props = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), Some(props))?;
+
+        Ok(Self {
+            writer,
+            schema,
+            rows: 0,
+            ip: StringBuilder::new(),
+            version: UInt8Builder::new(),
+            asn: UInt32Builder::new(),
+            org: StringBuilder::new(),
+            country: StringBuilder::new(),
+            city: StringBuilder::new(),
+            lat: Float64Builder::new(),
+            lon: Float64Builder::new(),
+            source: StringBuilder::new(),
+            line_no: UInt64Builder::new(),
+        })
+    }
+
+    /// Write the buffered rows out as one record batch
+    fn flush(&mut self) -> Result<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.ip.finish()),
+            Arc::new(self.version.finish()),
+            Arc::new(self.asn.finish()),
+            Arc::new(self.org.finish()),
+            Arc::new(self.country.finish()),
+            Arc::new(self.city.finish()),
+            Arc::new(self.lat.finish()),
+            Arc::new(self.lon.finish()),
+            Arc::new(self.source.finish()),
+            Arc::new(self.line_no.finish()),
+        ];
+        self.writer
+            .write(&RecordBatch::try_new(self.schema.clone(), columns)?)?;
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        let geo = occurrence.geo;
+        let text = |field: &str| geo[field].as_str().filter(|v| !v.is_empty());
+        let number = |field: &str| geo[field].as_f64();
+
+        self.ip.append_value(occurrence.ip);
+        self.version
+            .append_value(match occurrence.ip.parse::<IpAddr>() {
+                Ok(IpAddr::V6(_)) => 6,
+                _ => 4,
+            });
+        self.asn.append_option(
+            geo["asnnum"]
+                .as_u64()
+                .and_then(|n| u32::try_from(n).ok())
+                .filter(|&n| n != 0),
+        );
+        self.org.append_option(text("asnorg"));
+        self.country.append_option(text("country_iso"));
+        self.city.append_option(text("city"));
+        // records without a location report 0,0
+        let (lat, lon) = match (number("latitude"), number("longitude")) {
+            (Some(lat), Some(lon)) if lat != 0.0 || lon != 0.0 => (Some(lat), Some(lon)),
+            _ => (None, None),
+        };
+        self.lat.append_option(lat);
+        self.lon.append_option(lon);
+        self.source.append_value(occurrence.source);
+        self.line_no.append_value(occurrence.line_no);
+
+        self.rows += 1;
+        if self.rows >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use super::{Occurrence, Sink};
+use crate::geoip::ip_field_names;
+use anyhow::Result;
+use camino::Utf8Path;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection};
+use rustc_hash::FxHashSet as HashSet;
+use serde_json::Value;
+
+/// Writes occurrences into a normalized SQLite schema:
+///
+/// * `unique_ips`: one row per distinct IP with all of its geoip fields
+/// * `occurrences`: one row per match with its file, line number, and offset
+pub struct SqliteSink {
+    conn: Connection,
+    seen: HashSet<String>,
+    insert_ip: String,
+}
+
+/// SQLite column type of a geoip field so numeric fields can be compared
+/// and aggregated without casts
+fn column_type(field: &str) -> &'static str {
+    match field {
+        "asnnum" | "accuracy_radius" | "metro_code" => "INTEGER",
+        "is_anycast" | "is_satellite_provider" | "is_in_european_union" | "intel_hit" => "INTEGER",
+        "latitude" | "longitude" | "distance_km" => "REAL",
+        _ => "TEXT",
+    }
+}
+
+/// SQLite value of a geoip field of a JSON record, NULL if unknown
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Bool(b) => SqlValue::Integer(i64::from(*b)),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        _ => SqlValue::Null,
+    }
+}
+
+impl SqliteSink {
+    /// Open (or create) the database at `path` and its tables. All writes
+    /// happen in one transaction that is committed by `finish`
+    pub fn create(path: &Utf8Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+
+        let fields = ip_field_names().iter().filter(|f| **f != "ip");
+        let columns: Vec<String> = fields
+            .clone()
+            .map(|f| format!("{f} {}", column_type(f)))
+            .collect();
+        conn.execute_batch(&format!(
+            r#"CREATE TABLE IF NOT EXISTS unique_ips (ip TEXT PRIMARY KEY, {});
+            CREATE TABLE IF NOT EXISTS occurrences (
+                file TEXT NOT NULL,
+                line_no INTEGER NOT NULL,
+                "offset" INTEGER NOT NULL,
+                ip TEXT NOT NULL REFERENCES unique_ips(ip)
+            );
+            CREATE INDEX IF NOT EXISTS occurrences_ip ON occurrences(ip);
+            BEGIN;"#,
+            columns.join(", ")
+        ))?;
+
+        let names: Vec<&str> = ip_field_names().to_vec();
+        let insert_ip = format!(
+            "INSERT OR IGNORE INTO unique_ips ({}) VALUES ({})",
+            names.join(", "),
+            vec!["?"; names.len()].join(", ")
+        );
+
+        Ok(Self {
+            conn,
+            seen: HashSet::default(),
+            insert_ip,
+        })
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_record(&mut self, occurrence: &Occurrence) -> Result<()> {
+        if !self.seen.contains(occurrence.ip) {
+            let values = ip_field_names()
+                .iter()
+                .map(|f| sql_value(&occurrence.geo[f]));
+            self.conn
+                .prepare_cached(&self.insert_ip)?
+                .execute(rusqlite::params_from_iter(values))?;
+            self.seen.insert(occurrence.ip.to_string());
+        }
+
+        self.conn
+            .prepare_cached(
+                r#"INSERT INTO occurrences (file, line_no, "offset", ip) VALUES (?, ?, ?, ?)"#,
+            )?
+            .execute(params![
+                occurrence.source,
+                occurrence.line_no as i64,
+                occurrence.offset as i64,
+                occurrence.ip
+            ])?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.conn.execute_batch("COMMIT;")?;
+        Ok(())
+    }
+}
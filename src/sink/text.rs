@@ -0,0 +1,36 @@
+use super::{Occurrence, Sink};
+use anyhow::Result;
+use std::io::Write;
+
+/// Writes the lines of decorated text as they are, e.g. to stdout. IP
+/// occurrences are not written, they are already decorated in the lines
+pub struct TextSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// The writer, e.g. to read back a buffer written to
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write> Sink for TextSink<W> {
+    fn write_line(&mut self, line: &[u8]) -> Result<()> {
+        self.out.write_all(line)?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, _occurrence: &Occurrence) -> Result<()> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+use crate::export::civil_from_days;
+use crate::geoip::GeoRecord;
+use anyhow::{bail, Result};
+use camino::Utf8PathBuf;
+use clap::{Args, ValueEnum};
+use regex::bytes::{Captures, Regex};
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::IpAddr;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/// Cells of the longest --chart bar
+const CHART_WIDTH: usize = 40;
+
+/// Count the IPs of the input per country or ASN
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// What to count hits by
+    #[clap(long, value_enum, default_value_t = StatsKey::Country)]
+    pub by: StatsKey,
+
+    /// Show only the N most frequent, 0 for all
+    #[clap(long, value_name = "N", default_value_t = 20)]
+    pub top: usize,
+
+    /// Draw the counts as a unicode bar chart, colored per --color. With
+    /// timestamps, the bars are the hits per time bucket
+    #[clap(long)]
+    pub chart: bool,
+
+    /// Print the counts as NDJSON, one object per key or time bucket
+    #[clap(long, conflicts_with = "chart")]
+    pub json: bool,
+
+    /// Count per time bucket, reading the time of each line with this
+    /// regex. Its named groups year (optional), month (number or name), day,
+    /// hour, and minute, or else epoch (unix seconds), give the time
+    #[clap(long, value_name = "REGEX", conflicts_with = "timestamp_preset")]
+    pub timestamp_regex: Option<String>,
+
+    /// Count per time bucket, reading the time of each line in a common
+    /// log format
+    #[clap(long, value_enum, value_name = "PRESET")]
+    pub timestamp_preset: Option<TimestampPreset>,
+
+    /// Width of the time buckets
+    #[clap(long, value_enum, default_value_t = BucketWidth::Hour)]
+    pub bucket: BucketWidth,
+
+    /// Input file(s) to process. Leave empty or use "-" to read from stdin
+    #[clap(value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    pub input: Vec<Utf8PathBuf>,
+}
+
+/// What stats counts hits by
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum StatsKey {
+    /// ISO country code
+    Country,
+    /// autonomous system number and organization
+    Asn,
+}
+
+impl StatsKey {
+    /// The key of `record`, "-" when the databases do not know it
+    pub fn of(self, record: &GeoRecord) -> String {
+        match self {
+            StatsKey::Country if record.country_iso.is_empty() => "-".to_string(),
+            StatsKey::Country => record.country_iso.to_string(),
+            StatsKey::Asn if record.asnnum == 0 => "-".to_string(),
+            StatsKey::Asn if record.asnorg.is_empty() => format!("AS{}", record.asnnum),
+            StatsKey::Asn => format!("AS{} {}", record.asnnum, record.asnorg),
+        }
+    }
+}
+
+/// Hits per key
+#[derive(Debug, Default)]
+pub struct Counts {
+    hits: HashMap<String, u64>,
+}
+
+impl Counts {
+    pub fn add(&mut self, key: String) {
+        *self.hits.entry(key).or_default() += 1;
+    }
+
+    /// The `top` most frequent keys and their counts, most frequent first
+    /// and ties by key. A `top` of 0 keeps every key
+    pub fn sorted(&self, top: usize) -> Vec<(&str, u64)> {
+        let mut rows: Vec<(&str, u64)> = self.hits.iter().map(|(k, &n)| (k.as_str(), n)).collect();
+        rows.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        if top > 0 {
+            rows.truncate(top);
+        }
+        rows
+    }
+}
+
+/// Common timestamp formats of log lines
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum TimestampPreset {
+    /// 2024-01-31T13:45:00 or 2024-01-31 13:45:00
+    Iso8601,
+    /// Jan 31 13:45:00, without a year
+    Syslog,
+    /// [31/Jan/2024:13:45:00 +0000] of Apache and nginx access logs
+    Clf,
+    /// unix seconds, e.g. 1706708700 or 1706708700.123 of Zeek logs
+    Epoch,
+}
+
+impl TimestampPreset {
+    pub fn pattern(self) -> &'static str {
+        match self {
+            TimestampPreset::Iso8601 => {
+                r"(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})[T ](?P<hour>\d{2}):(?P<minute>\d{2})"
+            }
+            TimestampPreset::Syslog => {
+                r"(?P<month>[A-Z][a-z]{2}) +(?P<day>\d{1,2}) (?P<hour>\d{2}):(?P<minute>\d{2})"
+            }
+            TimestampPreset::Clf => {
+                r"\[(?P<day>\d{2})/(?P<month>[A-Z][a-z]{2})/(?P<year>\d{4}):(?P<hour>\d{2}):(?P<minute>\d{2})"
+            }
+            TimestampPreset::Epoch => r"\b(?P<epoch>\d{9,10})(?:\.\d+)?\b",
+        }
+    }
+}
+
+/// Width of the time buckets of stats
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
+pub enum BucketWidth {
+    Minute,
+    Hour,
+}
+
+/// Reads the time bucket of log lines with a regex of named groups
+#[derive(Clone, Debug)]
+pub struct TimestampReader {
+    re: Regex,
+    width: BucketWidth,
+}
+
+impl TimestampReader {
+    /// Reader of the times matched by `pattern`, which needs either an
+    /// epoch group or month, day, hour, and minute groups
+    pub fn new(pattern: &str, width: BucketWidth) -> Result<Self> {
+        let re = Regex::new(pattern)?;
+        let has = |name: &str| re.capture_names().flatten().any(|n| n == name);
+        let fields = ["month", "day", "hour", "minute"];
+        if !has("epoch") && !fields.iter().all(|name| has(name)) {
+            bail!("--timestamp-regex needs named groups month, day, hour, and minute, or epoch");
+        }
+        Ok(Self { re, width })
+    }
+
+    /// The bucket of the first time in `line`, as "YYYY-MM-DD HH:MM" or
+    /// "MM-DD HH:MM" without a year, so that buckets sort by time
+    pub fn bucket(&self, line: &[u8]) -> Option<String> {
+        let caps = self.re.captures(line)?;
+        let (year, month, day, hour, minute) = match number(&caps, "epoch") {
+            Some(epoch) => {
+                let (year, month, day) = civil_from_days((epoch / 86_400) as i64);
+                let secs = epoch % 86_400;
+                (
+                    Some(year),
+                    month,
+                    day,
+                    (secs / 3600) as u32,
+                    (secs / 60 % 60) as u32,
+                )
+            }
+            None => (
+                number(&caps, "year").map(|y| y as i64),
+                month(caps.name("month")?.as_bytes())?,
+                number(&caps, "day")? as u32,
+                number(&caps, "hour")? as u32,
+                number(&caps, "minute")? as u32,
+            ),
+        };
+        let minute = match self.width {
+            BucketWidth::Minute => minute,
+            BucketWidth::Hour => 0,
+        };
+        Some(match year {
+            Some(year) => format!("{year:04}-{month:02}-{day:02} {hour:02}:{minute:02}"),
+            None => format!("{month:02}-{day:02} {hour:02}:{minute:02}"),
+        })
+    }
+}
+
+/// The named group `name` of `caps` as a number
+fn number(caps: &Captures, name: &str) -> Option<u64> {
+    std::str::from_utf8(caps.name(name)?.as_bytes())
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Month number of "1" to "12" or of an English month name such as "Jan"
+fn month(s: &[u8]) -> Option<u32> {
+    const NAMES: [&[u8]; 12] = [
+        b"jan", b"feb", b"mar", b"apr", b"may", b"jun", b"jul", b"aug", b"sep", b"oct", b"nov",
+        b"dec",
+    ];
+    if let Some(n) = std::str::from_utf8(s).ok().and_then(|s| s.parse().ok()) {
+        return (1..=12).contains(&n).then_some(n);
+    }
+    let prefix = s.get(..3)?.to_ascii_lowercase();
+    NAMES
+        .iter()
+        .position(|name| *name == prefix)
+        .map(|i| i as u32 + 1)
+}
+
+/// Hits, unique IPs, and per-key counts of one time bucket
+#[derive(Debug, Default)]
+struct Slot {
+    hits: u64,
+    ips: HashSet<IpAddr>,
+    counts: Counts,
+}
+
+/// Counts per time bucket. Lines without a time are in the "-" bucket
+#[derive(Debug, Default)]
+pub struct Series {
+    buckets: BTreeMap<String, Slot>,
+}
+
+impl Series {
+    pub fn add(&mut self, bucket: Option<&str>, ip: IpAddr, key: String) {
+        let bucket = bucket.unwrap_or("-");
+        if !self.buckets.contains_key(bucket) {
+            self.buckets.insert(bucket.to_string(), Slot::default());
+        }
+        let slot = self.buckets.get_mut(bucket).expect("bucket was just added");
+        slot.hits += 1;
+        slot.ips.insert(ip);
+        slot.counts.add(key);
+    }
+
+    /// Buckets in time order and their hits, e.g. for [`print_chart`]
+    pub fn hits(&self) -> Vec<(&str, u64)> {
+        self.buckets
+            .iter()
+            .map(|(bucket, slot)| (bucket.as_str(), slot.hits))
+            .collect()
+    }
+
+    /// Print a table of the hits, unique IPs, and `top` keys of each bucket
+    pub fn print_table(&self, out: &mut impl io::Write, top: usize) -> io::Result<()> {
+        let width = self.buckets.keys().map(String::len).max().unwrap_or(0);
+        writeln!(
+            out,
+            "{:<width$} {:>9} {:>9}  top",
+            "bucket", "hits", "unique"
+        )?;
+        for (bucket, slot) in &self.buckets {
+            let keys: Vec<String> = slot
+                .counts
+                .sorted(top)
+                .into_iter()
+                .map(|(key, n)| format!("{key}={n}"))
+                .collect();
+            writeln!(
+                out,
+                "{bucket:<width$} {:>9} {:>9}  {}",
+                slot.hits,
+                slot.ips.len(),
+                keys.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Print one JSON object per bucket
+    pub fn print_json(&self, out: &mut impl io::Write, top: usize) -> io::Result<()> {
+        for (bucket, slot) in &self.buckets {
+            let counts: serde_json::Map<String, serde_json::Value> = slot
+                .counts
+                .sorted(top)
+                .into_iter()
+                .map(|(key, n)| (key.to_string(), n.into()))
+                .collect();
+            let object = json!({
+                "bucket": bucket,
+                "hits": slot.hits,
+                "unique_ips": slot.ips.len(),
+                "counts": counts,
+            });
+            writeln!(out, "{object}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Print `rows` as a table of counts and keys
+pub fn print_table(out: &mut impl io::Write, rows: &[(&str, u64)]) -> io::Result<()> {
+    for (key, count) in rows {
+        writeln!(out, "{count:>9} {key}")?;
+    }
+    Ok(())
+}
+
+/// Print `rows` as one JSON object per key
+pub fn print_json(out: &mut impl io::Write, rows: &[(&str, u64)]) -> io::Result<()> {
+    for (key, count) in rows {
+        writeln!(out, "{}", json!({ "key": key, "count": count }))?;
+    }
+    Ok(())
+}
+
+/// Print `rows` as a bar chart scaled so the largest count fills
+/// [`CHART_WIDTH`] cells, drawn in eighths of a cell
+pub fn print_chart(out: &mut impl WriteColor, rows: &[(&str, u64)]) -> io::Result<()> {
+    let max = rows.iter().map(|&(_, n)| n).max().unwrap_or(0).max(1);
+    let key_width = rows
+        .iter()
+        .map(|(k, _)| k.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut color = ColorSpec::new();
+    color.set_fg(Some(Color::Red)).set_bold(true);
+    for &(key, count) in rows {
+        write!(out, "{key:<key_width$} ")?;
+        out.set_color(&color)?;
+        write!(out, "{}", bar(count, max))?;
+        out.reset()?;
+        writeln!(out, " {count}")?;
+    }
+    Ok(())
+}
+
+/// Bar of `count` relative to `max`, at least a sliver for nonzero counts
+fn bar(count: u64, max: u64) -> String {
+    const PARTIAL: [char; 8] = [' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'];
+    let eighths = ((count as u128 * CHART_WIDTH as u128 * 8 / max as u128) as usize).max(1);
+    let mut bar = "█".repeat(eighths / 8);
+    let partial = eighths % 8;
+    if partial > 0 {
+        bar.push(PARTIAL[partial]);
+    }
+    bar
+}
@@ -0,0 +1,258 @@
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Values that can be substituted into a [`Template`] by field name
+pub trait Fields {
+    /// Value of the field `name`, or None if there is no such field
+    fn field(&self, name: &str) -> Option<Cow<'_, str>>;
+
+    /// Write the value of the field `name` to `out`, if there is one.
+    /// Override to format values without an intermediate allocation
+    fn write_field_to(&self, name: &str, out: &mut dyn io::Write) -> io::Result<()> {
+        match self.field(name) {
+            Some(value) => out.write_all(value.as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Error returned by [`Template::compile`], with the byte offset of the
+/// offending {
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A { has no closing }
+    Unclosed(usize),
+    /// A {} names no field
+    EmptyField(usize),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unclosed(offset) => write!(f, "unclosed {{ at offset {offset}"),
+            TemplateError::EmptyField(offset) => write!(f, "empty {{}} at offset {offset}"),
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Field(String),
+}
+
+/// A decoration template such as "<{ip}|AS{asnnum}_{asnorg}>", parsed once
+/// into its literal text and `{field}` references so that rendering is a
+/// single pass over the parts.
+///
+/// ```
+/// use geoipsed::template::{Fields, Template};
+/// use std::borrow::Cow;
+///
+/// struct Host;
+/// impl Fields for Host {
+///     fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+///         (name == "name").then_some(Cow::Borrowed("gateway"))
+///     }
+/// }
+///
+/// let template = Template::new("<{name}|{missing}>");
+/// assert_eq!(template.render(&Host), "<gateway|>");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+    // total length of the literal text, to size render buffers
+    literal_len: usize,
+}
+
+impl Template {
+    /// Parse a template. Fields are enclosed in {}. An opening { without a
+    /// closing } is kept as literal text
+    pub fn new(template: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        let mut literal = String::new();
+        while let Some(open) = rest.find('{') {
+            let Some(close) = rest[open..].find('}') else {
+                break;
+            };
+            literal.push_str(&rest[..open]);
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Field(rest[open + 1..open + close].to_string()));
+            rest = &rest[open + close + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        let literal_len = literal_len(&parts);
+        Self { parts, literal_len }
+    }
+
+    /// Parse a template like [`Template::new`], but reject a { without a
+    /// closing } and an empty {}, which are likely typos in user input
+    pub fn compile(template: &str) -> Result<Self, TemplateError> {
+        let mut offset = 0;
+        while let Some(open) = template[offset..].find('{').map(|i| offset + i) {
+            match template[open..].find('}') {
+                None => return Err(TemplateError::Unclosed(open)),
+                Some(1) => return Err(TemplateError::EmptyField(open)),
+                Some(close) => offset = open + close + 1,
+            }
+        }
+        Ok(Self::new(template))
+    }
+
+    /// Write `start` before and `end` after every value of the field
+    /// `name`, e.g. ANSI escapes to color only that field of the renders
+    ///
+    /// ```
+    /// use geoipsed::template::{Fields, Template};
+    /// use std::borrow::Cow;
+    ///
+    /// struct Host;
+    /// impl Fields for Host {
+    ///     fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+    ///         Some(Cow::Borrowed(if name == "ip" { "10.0.0.1" } else { "GB" }))
+    ///     }
+    /// }
+    ///
+    /// let template = Template::new("<{ip}|{cc}>").with_style("ip", "[", "]");
+    /// assert_eq!(template.render(&Host), "<[10.0.0.1]|GB>");
+    /// ```
+    pub fn with_style(self, name: &str, start: &str, end: &str) -> Self {
+        let mut parts = Vec::with_capacity(self.parts.len() + 2);
+        for part in self.parts {
+            match part {
+                Part::Field(field) if field == name => {
+                    parts.push(Part::Literal(start.to_string()));
+                    parts.push(Part::Field(field));
+                    parts.push(Part::Literal(end.to_string()));
+                }
+                part => parts.push(part),
+            }
+        }
+        let literal_len = literal_len(&parts);
+        Self { parts, literal_len }
+    }
+
+    /// Names of the fields referenced by the template, in order
+    pub fn field_names(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Field(name) => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    /// A regex matching the renders of this template, the inverse of
+    /// [`Template::render`]. The first `{field}` becomes the capture group
+    /// `field` matching `field_pattern`, its other occurrences match it
+    /// too, and other fields match any text within a line. None if the
+    /// template does not reference `field`
+    ///
+    /// ```
+    /// use geoipsed::template::Template;
+    ///
+    /// let template = Template::new("<{ip}|{country_iso}>");
+    /// let pattern = template.inverse_pattern("ip", r"[0-9.]+").unwrap();
+    /// let re = regex::Regex::new(&pattern).unwrap();
+    /// assert_eq!(re.replace_all("from <10.0.0.1|GB>", "$ip"), "from 10.0.0.1");
+    /// ```
+    pub fn inverse_pattern(&self, field: &str, field_pattern: &str) -> Option<String> {
+        let mut pattern = String::new();
+        let mut captured = false;
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => pattern.push_str(&regex::escape(text)),
+                Part::Field(name) if name == field && !captured => {
+                    pattern.push_str(&format!("(?P<{field}>{field_pattern})"));
+                    captured = true;
+                }
+                Part::Field(name) if name == field => {
+                    pattern.push_str(&format!("(?:{field_pattern})"));
+                }
+                Part::Field(_) => pattern.push_str(r"[^\r\n]*?"),
+            }
+        }
+        captured.then_some(pattern)
+    }
+
+    /// Substitute the values of `fields`. Unknown fields render as empty
+    pub fn render(&self, fields: &impl Fields) -> String {
+        let mut out = String::with_capacity(self.literal_len + 32);
+        self.render_into(fields, &mut out);
+        out
+    }
+
+    /// Like [`Template::render`], but append to `out` so that a buffer can
+    /// be reused across renders
+    pub fn render_into(&self, fields: &impl Fields, out: &mut String) {
+        let mut buf = std::mem::take(out).into_bytes();
+        // writing to a Vec only fails if a field does
+        let _ = self.write(fields, &mut buf);
+        *out = match String::from_utf8(buf) {
+            Ok(rendered) => rendered,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        };
+    }
+
+    /// Like [`Template::render`], but write straight to `out`, e.g. the
+    /// output of a line, without building a string first
+    pub fn write(&self, fields: &impl Fields, out: &mut dyn io::Write) -> io::Result<()> {
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => out.write_all(text.as_bytes())?,
+                Part::Field(name) => fields.write_field_to(name, out)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Total length of the literal text of `parts`
+fn literal_len(parts: &[Part]) -> usize {
+    parts
+        .iter()
+        .map(|p| match p {
+            Part::Literal(text) => text.len(),
+            Part::Field(_) => 0,
+        })
+        .sum()
+}
+
+/// The one of `candidates` closest to the unknown field `name`, if any is
+/// within two edits of it, to suggest in place of a typo
+pub fn closest_field<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance between `a` and `b`, by characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
@@ -3,15 +3,20 @@ use std::io::Result;
 use std::path::PathBuf;
 use std::str;
 
+/// geoipsed reading local copies of Maxmind's test database files
+fn geoipsed_cmd() -> Command {
+    let mut cmd = Command::cargo_bin("geoipsed").unwrap();
+    cmd.env(
+        "MAXMIND_MMDB_DIR",
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind"),
+    );
+    cmd
+}
+
 /// Generic execution function that invokes geoipsed with path
 /// to local copies of Maxmind's test database files
 fn run_geoipsed(input: &str, args: &[&str]) -> Result<String> {
-    let mut maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    maxmind_dir.push("tests/maxmind");
-
-    let mut cmd = Command::cargo_bin("geoipsed").unwrap();
-    let output = cmd
-        .env("MAXMIND_MMDB_DIR", maxmind_dir.as_os_str())
+    let output = geoipsed_cmd()
         .args(args)
         .write_stdin(input)
         .output()
@@ -37,6 +42,29 @@ fn basic_ipv4() {
     assert_eq!(output_str, expected_output);
 }
 
+/// Test -o only extracts IPs that stand on their own token: addresses
+/// inside longer dotted or alphanumeric runs are dropped, while those next
+/// to punctuation such as a port colon or parentheses are kept
+#[test]
+fn ip_boundaries() {
+    let args = ["-o", "-t", "{ip}"];
+    for dropped in [
+        "x 1.2.3.4.5 y",
+        "x 310.0.0.0 y",
+        "host1.2.3.4 y",
+        "x 1.2.3.4a",
+    ] {
+        let output_str = run_geoipsed(&format!("{dropped}\n"), &args).unwrap();
+        assert_eq!(output_str, "", "{dropped}");
+    }
+    let kept = "1.2.3.4:80 (5.6.7.8) to 9.9.9.9. via a.b.10.0.0.1 and [2001:db8::1]:443\n";
+    let output_str = run_geoipsed(kept, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "1.2.3.4\n5.6.7.8\n9.9.9.9\n10.0.0.1\n2001:db8::1\n"
+    );
+}
+
 /// Basic test of single IPv6 enrichment
 #[test]
 fn basic_ipv6() {
@@ -180,3 +208,2017 @@ testing_175.16.199.52@Asia/Harbin
 
     assert_eq!(output_str, expected_output);
 }
+
+/// Test memory-mapped file input matches buffered file input
+#[test]
+fn mmap_file_input() {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("mmap_file_input.log");
+    std::fs::write(&path, "hello 67.43.156.1 world\nno ips here\n214.78.0.40\n")
+        .expect("Failed to write test input");
+    let path = path.to_str().unwrap();
+
+    let expected_output = r#"
+hello <67.43.156.1|AS35908_|BT|> world
+no ips here
+<214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>
+"#
+    .trim_start_matches('\n');
+
+    let buffered = run_geoipsed("", &[path]).expect("Failed to run geoipsed");
+    let mapped = run_geoipsed("", &["--mmap", path]).expect("Failed to run geoipsed");
+
+    assert_eq!(buffered, expected_output);
+    assert_eq!(mapped, expected_output);
+
+    // pipes are read as streams instead
+    let input = std::fs::read_to_string(path).unwrap();
+    let piped = run_geoipsed(&input, &["--mmap", "/dev/stdin"]).unwrap();
+    assert_eq!(piped, expected_output);
+}
+
+/// Test NDJSON input only enriches the requested fields
+#[test]
+fn json_input_fields() {
+    let args = ["--json-input", "--json-fields", "src_ip,message"];
+    let input = r#"
+{"src_ip":"81.2.69.205","dst_ip":"214.78.0.40","message":"from 89.160.20.135 and 175.16.199.37"}
+not json 81.2.69.205
+"#
+    .trim_start_matches('\n');
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let mut lines = output_str.lines();
+
+    let record: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+    let keys: Vec<&String> = record.as_object().unwrap().keys().collect();
+    assert_eq!(
+        keys,
+        ["src_ip", "src_ip_geo", "dst_ip", "message", "message_geo"]
+    );
+    // the original values are untouched
+    assert_eq!(record["src_ip"], "81.2.69.205");
+    // a field holding only an ip gets a single record
+    assert_eq!(record["src_ip_geo"]["city"], "London");
+    // unknown values are null
+    assert!(record["src_ip_geo"]["asnnum"].is_null());
+    // free text gets one record per ip
+    assert_eq!(record["message_geo"][0]["ip"], "89.160.20.135");
+    assert_eq!(record["message_geo"][0]["asnorg"], "Bredband2 AB");
+    assert_eq!(record["message_geo"][1]["country_iso"], "CN");
+
+    // non-json lines are passed through
+    assert_eq!(lines.next(), Some("not json 81.2.69.205"));
+
+    // byte for byte, blank lines and surrounding whitespace included
+    let input = "  not json  \n\n{\"a\":1}\r\n  [1]";
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "  not json  \n\n{\"a\":1}\n  [1]");
+}
+
+/// Test NDJSON input enriches all (nested) string values by default
+#[test]
+fn json_input_all_fields() {
+    let args = ["--json-input"];
+    let input = r#"{"a":{"b":"214.78.0.40"},"n":1,"v":"81.2.69.205.7"}"#;
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+
+    assert_eq!(record["a"]["b_geo"]["asnnum"], 721);
+    assert_eq!(record["n"], 1);
+    // the middle of a longer dotted token is not an address
+    assert!(record.get("v_geo").is_none());
+
+    // strings in arrays and objects inside arrays are enriched as well
+    let input = r#"{"a":["81.2.69.205","x"],"b":[{"c":"214.78.0.40"}]}"#;
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+    assert_eq!(record["a_geo"][0]["country_iso"], "GB");
+    assert_eq!(record["a_geo"].as_array().unwrap().len(), 1);
+    assert_eq!(record["b"][0]["c_geo"]["asnnum"], 721);
+}
+
+/// Test --ecs nests the geoip records under their ECS names
+#[test]
+fn json_input_ecs() {
+    let args = ["--json-input", "--ecs"];
+    let input = r#"{"src":"81.2.69.205","dst":"214.78.0.40"}"#;
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+
+    assert_eq!(record["src_geo"]["ip"], "81.2.69.205");
+    assert_eq!(record["src_geo"]["geo"]["country_iso_code"], "GB");
+    assert_eq!(record["src_geo"]["geo"]["city_name"], "London");
+    assert!(record["src_geo"].get("as").is_none());
+    assert_eq!(record["dst_geo"]["as"]["number"], 721);
+    assert_eq!(
+        record["dst_geo"]["as"]["organization"]["name"],
+        "DoD Network Information Center"
+    );
+    assert!(record["dst_geo"].get("asnnum").is_none());
+}
+
+/// Test CEF mode only decorates the address fields of the extension
+#[test]
+fn cef_format() {
+    let args = ["--format", "cef"];
+    let input = r#"
+CEF:0|Security|threatmanager|1.0|100|worm 81.2.69.205 stopped|10|src=81.2.69.205 dst=214.78.0.40 msg=seen 89.160.20.135
+plain 81.2.69.205
+"#
+    .trim_start_matches('\n');
+    let expected_output = r#"
+CEF:0|Security|threatmanager|1.0|100|worm 81.2.69.205 stopped|10|src=<81.2.69.205|AS0_|GB|London> dst=<214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego> msg=seen 89.160.20.135
+plain 81.2.69.205
+"#
+    .trim_start_matches('\n');
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test LEEF mode with both the 1.0 tab and 2.0 custom delimiters
+#[test]
+fn leef_format() {
+    let args = ["-o", "--format", "leef"];
+    let input = "LEEF:1.0|Microsoft|MSExchange|4.0|15345|src=81.2.69.205\tmsg=89.160.20.135\n\
+                 LEEF:2.0|Lancope|StealthWatch|1.0|41|x5E|msg=89.160.20.135^dst=214.78.0.40\n";
+    let expected_output = r#"
+<81.2.69.205|AS0_|GB|London>
+<214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>
+"#
+    .trim_start_matches('\n');
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test extracting addresses and payload IPs from a pcap capture
+#[cfg(feature = "pcap")]
+#[test]
+fn pcap_input() {
+    let payload = b"beacon 89.160.20.135";
+    let mut frame: Vec<u8> = Vec::new();
+    // ethernet: dst mac, src mac, ipv4 ethertype
+    frame.extend_from_slice(&[0; 12]);
+    frame.extend_from_slice(&[0x08, 0x00]);
+    // ipv4 header: 20 bytes, udp, 81.2.69.205 -> 214.78.0.40
+    let total_len = (20 + 8 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&total_len.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&[81, 2, 69, 205, 214, 78, 0, 40]);
+    // udp header
+    frame.extend_from_slice(&[0; 8]);
+    frame.extend_from_slice(payload);
+    // trailing bytes past the total length are not part of the payload
+    frame.extend_from_slice(b" 214.78.0.40");
+
+    let mut pcap: Vec<u8> = Vec::new();
+    // global header: little endian, v2.4, snaplen 65535, ethernet
+    pcap.extend_from_slice(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0]);
+    pcap.extend_from_slice(&[0; 8]);
+    pcap.extend_from_slice(&65535u32.to_le_bytes());
+    pcap.extend_from_slice(&1u32.to_le_bytes());
+    // packet header: ts 1700000000.000042
+    pcap.extend_from_slice(&1700000000u32.to_le_bytes());
+    pcap.extend_from_slice(&42u32.to_le_bytes());
+    pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    pcap.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+    pcap.extend_from_slice(&frame);
+
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("pcap_input.pcap");
+    std::fs::write(&path, pcap).expect("Failed to write test input");
+
+    let args = ["--pcap", path.to_str().unwrap()];
+    let expected_output = "1700000000.000042 <81.2.69.205|AS0_|GB|London> \
+        <214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego> \
+        <89.160.20.135|AS29518_Bredband2_AB|SE|Linköping>\n";
+
+    let output_str = run_geoipsed("", &args).expect("Failed to run geoipsed");
+
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test the Suricata EVE preset enriches src_ip and dest_ip only
+#[test]
+fn suricata_eve_preset() {
+    let args = ["--preset", "suricata-eve"];
+    let input = r#"{"event_type":"alert","src_ip":"81.2.69.205","dest_ip":"214.78.0.40","alert":{"signature":"seen 89.160.20.135"}}"#;
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+    let keys: Vec<&String> = record.as_object().unwrap().keys().collect();
+
+    assert_eq!(
+        keys,
+        [
+            "event_type",
+            "src_ip",
+            "src_ip_geo",
+            "dest_ip",
+            "dest_ip_geo",
+            "alert"
+        ]
+    );
+    assert_eq!(record["src_ip_geo"]["country_iso"], "GB");
+    assert_eq!(record["dest_ip_geo"]["asnnum"], 721);
+    assert!(record["alert"].get("signature_geo").is_none());
+}
+
+/// Test writing occurrences and unique ips to a sqlite database
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_output() {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("sqlite_output.db");
+    let _ = std::fs::remove_file(&path);
+    let output = format!("sqlite:{}", path.display());
+
+    let args = ["--output", &output];
+    let input = "a 81.2.69.205 b 214.78.0.40\n81.2.69.205\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, "");
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let occurrences: Vec<(String, i64, i64, String)> = conn
+        .prepare(r#"SELECT file, line_no, "offset", ip FROM occurrences ORDER BY rowid"#)
+        .unwrap()
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<_>>()
+        .unwrap();
+    assert_eq!(
+        occurrences,
+        [
+            ("-".to_string(), 1, 2, "81.2.69.205".to_string()),
+            ("-".to_string(), 1, 16, "214.78.0.40".to_string()),
+            ("-".to_string(), 2, 28, "81.2.69.205".to_string()),
+        ]
+    );
+
+    let (count, asnnum): (i64, i64) = conn
+        .query_row("SELECT COUNT(*), MAX(asnnum) FROM unique_ips", [], |r| {
+            Ok((r.get(0)?, r.get(1)?))
+        })
+        .unwrap();
+    assert_eq!((count, asnnum), (2, 721));
+}
+
+/// Test writing occurrences to a parquet file
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_output() {
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{Float64Type, UInt32Type, UInt8Type};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("parquet_output.parquet");
+    let output = format!("parquet:{}", path.display());
+
+    let args = ["--output", &output];
+    let input = "a 81.2.69.205 b 240b::beef:0:24\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, "");
+
+    let file = std::fs::File::open(&path).unwrap();
+    let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert_eq!(batch.num_rows(), 2);
+
+    let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+    let ip = column("ip");
+    let ip = ip.as_string::<i32>();
+    assert_eq!(ip.value(0), "81.2.69.205");
+    assert_eq!(ip.value(1), "240b::beef:0:24");
+    let version = column("version");
+    assert_eq!(version.as_primitive::<UInt8Type>().values(), &[4, 6]);
+    let asn = column("asn");
+    let asn = asn.as_primitive::<UInt32Type>();
+    assert!(asn.is_null(0));
+    assert_eq!(asn.value(1), 2516);
+    let lat = column("lat");
+    let lat = lat.as_primitive::<Float64Type>();
+    assert_eq!(lat.value(0), 51.5142);
+    assert!(lat.is_null(1));
+}
+
+/// Test exporting unique public ips as a STIX 2.1 bundle
+#[test]
+fn export_stix() {
+    let args = ["export", "--stix", "-"];
+    let input = "a 81.2.69.205 b 10.0.0.1 c 81.2.69.205 d 2001:db8::1 e 240b::beef:0:24\n";
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let bundle: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+
+    assert_eq!(bundle["type"], "bundle");
+    let objects = bundle["objects"].as_array().unwrap();
+    // private, documentation, and repeated ips are not exported
+    assert_eq!(objects.len(), 2);
+    assert_eq!(objects[0]["type"], "ipv4-addr");
+    assert_eq!(objects[0]["value"], "81.2.69.205");
+    // ids are deterministic per the STIX 2.1 spec
+    assert_eq!(
+        objects[0]["id"],
+        "ipv4-addr--ca52fec6-7f62-5077-a797-fba84ec9ddfd"
+    );
+    assert_eq!(objects[0]["x_geoipsed_geo"]["city"], "London");
+    assert_eq!(objects[1]["type"], "ipv6-addr");
+    assert_eq!(objects[1]["x_geoipsed_geo"]["asnnum"], 2516);
+}
+
+/// Test exporting unique public ips as a MISP event
+#[test]
+fn export_misp() {
+    let args = ["export", "--misp", "-", "--info", "incident 42"];
+    let input = "a 214.78.0.40 b 192.168.1.1\n";
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let event: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+
+    assert_eq!(event["Event"]["info"], "incident 42");
+    let attributes = event["Event"]["Attribute"].as_array().unwrap();
+    assert_eq!(attributes.len(), 1);
+    assert_eq!(attributes[0]["type"], "ip-dst");
+    assert_eq!(attributes[0]["value"], "214.78.0.40");
+    assert!(attributes[0]["comment"]
+        .as_str()
+        .unwrap()
+        .contains("asnorg=DoD Network Information Center"));
+}
+
+/// Test exporting unique public ips as GeoJSON points with their counts
+#[test]
+fn export_geojson() {
+    let args = ["export", "--geojson", "-"];
+    let input = "a 81.2.69.205 b 81.2.69.205 c 10.0.0.1\n214.78.0.40\n";
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    let collection: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+
+    assert_eq!(collection["type"], "FeatureCollection");
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    assert_eq!(features[0]["geometry"]["type"], "Point");
+    assert_eq!(
+        features[0]["geometry"]["coordinates"],
+        serde_json::json!([-0.0931, 51.5142])
+    );
+    assert_eq!(features[0]["properties"]["ip"], "81.2.69.205");
+    assert_eq!(features[0]["properties"]["count"], 2);
+    assert_eq!(features[1]["properties"]["asnnum"], 721);
+    assert_eq!(features[1]["properties"]["count"], 1);
+
+    let output_str = run_geoipsed(input, &["export", "--kml", "-"]).unwrap();
+    assert!(output_str.contains("<name>81.2.69.205</name>"));
+    assert!(output_str.contains("<coordinates>-0.0931,51.5142</coordinates>"));
+}
+
+/// Test counting ips per country and ASN, as a table and a bar chart
+#[test]
+fn stats_counts() {
+    let input = "81.2.69.205 x 214.78.0.40\n214.78.0.40 10.0.0.1 81.2.69.205\n214.78.0.40\n";
+    let output_str = run_geoipsed(input, &["stats"]).unwrap();
+    assert_eq!(output_str, "        3 US\n        2 GB\n        1 -\n");
+    let output_str = run_geoipsed(input, &["stats", "--by", "asn", "--top", "1"]).unwrap();
+    // ties are ordered by key, and addresses without an ASN count under -
+    assert_eq!(output_str, "        3 -\n");
+
+    let output_str = run_geoipsed(input, &["stats", "--chart", "-C", "never"]).unwrap();
+    let bars: Vec<&str> = output_str.lines().collect();
+    assert_eq!(bars[0], format!("US {} 3", "█".repeat(40)));
+    assert_eq!(bars[1], format!("GB {}▋ 2", "█".repeat(26)));
+}
+
+/// Test counting ips per time bucket of the line timestamps
+#[test]
+fn stats_time_buckets() {
+    let input = "2024-01-31T13:45:10 81.2.69.205 214.78.0.40\n\
+                 2024-01-31T13:59:00 214.78.0.40\n\
+                 2024-01-31T14:01:00 81.2.69.205\n\
+                 no time 214.78.0.40\n";
+    let output_str = run_geoipsed(input, &["stats", "--timestamp-preset", "iso8601"]).unwrap();
+    assert_eq!(
+        output_str,
+        "bucket                hits    unique  top\n\
+         -                        1         1  US=1\n\
+         2024-01-31 13:00         3         2  US=2 GB=1\n\
+         2024-01-31 14:00         1         1  GB=1\n"
+    );
+
+    let args = [
+        "stats",
+        "--timestamp-regex",
+        r"^(?P<epoch>\d+)",
+        "--bucket",
+        "minute",
+        "--json",
+    ];
+    let output_str = run_geoipsed("1706708710 81.2.69.205\n", &args).unwrap();
+    assert_eq!(
+        output_str,
+        r#"{"bucket":"2024-01-31 13:45","hits":1,"unique_ips":1,"counts":{"GB":1}}"#.to_string()
+            + "\n"
+    );
+
+    let output_str = run_geoipsed(input, &["stats", "--timestamp-regex", "(?P<hour>..)"]).unwrap();
+    assert_eq!(output_str, "");
+}
+
+/// Test matching extracted ips against plain text and MISP intel lists
+#[test]
+fn intel_matching() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let list = dir.join("intel_list.txt");
+    std::fs::write(&list, "# scanners\n81.2.0.0/16,scanner\n").unwrap();
+    let misp = dir.join("intel_misp.json");
+    std::fs::write(
+        &misp,
+        r#"{"Event":{"Attribute":[{"type":"ip-dst|port","value":"214.78.0.40|443"}]}}"#,
+    )
+    .unwrap();
+    let (list, misp) = (list.to_str().unwrap(), misp.to_str().unwrap());
+
+    let input = "a 81.2.69.205 b 214.78.0.40\nc 89.160.20.135\n";
+
+    let args = [
+        "--intel",
+        list,
+        "--intel",
+        misp,
+        "-t",
+        "{ip}:{intel_hit}:{intel_source}",
+    ];
+    let expected_output = r#"
+a 81.2.69.205:true:intel_list.txt b 214.78.0.40:true:intel_misp.json
+c 89.160.20.135:false:
+"#
+    .trim_start_matches('\n');
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    let args = ["-o", "--intel", misp, "--only-intel-hits"];
+    let expected_output = "<214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test taking ASNs from a prefix2as table with longest prefix matching
+#[test]
+fn pfx2as_asn() {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("pfx2as.txt");
+    std::fs::write(
+        &path,
+        "81.0.0.0\t8\t100\n81.2.69.0\t24\t200_201\n214.78.0.0/16 AS300\n2001:480::/32 400\n",
+    )
+    .unwrap();
+
+    let args = [
+        "-o",
+        "--pfx2as",
+        path.to_str().unwrap(),
+        "-t",
+        "{ip}|AS{asnnum}|{country_iso}",
+    ];
+    let input = "81.2.69.205\n81.3.1.1\n214.78.0.40\n2001:480::52\n175.16.199.37\n";
+    let expected_output = r#"
+81.2.69.205|AS200|GB
+81.3.1.1|AS100|
+214.78.0.40|AS300|US
+2001:480::52|AS400|US
+175.16.199.37|AS0|CN
+"#
+    .trim_start_matches('\n');
+
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test exposing labels of a user CSV as a template field
+#[test]
+fn enrich_csv_labels() {
+    let mut path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    path.push("assets.csv");
+    std::fs::write(
+        &path,
+        "cidr,site\n81.2.0.0/16,corp-vpn\n81.2.69.0/24,\"london office\"\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+
+    let args = [
+        "--enrich-csv",
+        path,
+        "--enrich-field",
+        "site",
+        "-t",
+        "{ip}|{site}|{country_iso}",
+    ];
+    let input = "a 81.2.69.205 b 81.2.1.1 c 214.78.0.40\n";
+    let expected_output = "a 81.2.69.205|london_office|GB b 81.2.1.1|corp-vpn| c 214.78.0.40||US\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    // the default field name is "label"
+    let args = ["-o", "--enrich-csv", path, "-t", "{ip}={label}"];
+    let output_str = run_geoipsed("81.2.1.1\n", &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, "81.2.1.1=corp-vpn\n");
+}
+
+/// Test databases are found by their metadata regardless of file name
+#[test]
+fn autodetect_databases() {
+    let mut dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    dir.push("autodetect");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("tests/maxmind/GeoLite2-ASN.mmdb", dir.join("asn-2024.mmdb")).unwrap();
+    std::fs::copy(
+        "tests/maxmind/GeoLite2-City.mmdb",
+        dir.join("city-2024.mmdb"),
+    )
+    .unwrap();
+    std::fs::write(dir.join("notes.mmdb"), "not a database").unwrap();
+
+    let mut cmd = Command::cargo_bin("geoipsed").unwrap();
+    let output = cmd
+        .env("MAXMIND_MMDB_DIR", &dir)
+        .write_stdin("a 81.2.69.205 b 214.78.0.40\n")
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "a <81.2.69.205|AS0_|GB|London> b <214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>\n"
+    );
+}
+
+/// Test fields missing from the -I databases are filled in from fallbacks
+#[test]
+fn fallback_dir() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("fallback_dir");
+    let (asn_dir, city_dir) = (dir.join("asn"), dir.join("city"));
+    std::fs::create_dir_all(&asn_dir).unwrap();
+    std::fs::create_dir_all(&city_dir).unwrap();
+    std::fs::copy(
+        "tests/maxmind/GeoLite2-ASN.mmdb",
+        asn_dir.join("GeoLite2-ASN.mmdb"),
+    )
+    .unwrap();
+    std::fs::copy(
+        "tests/maxmind/GeoLite2-City.mmdb",
+        city_dir.join("GeoLite2-City.mmdb"),
+    )
+    .unwrap();
+
+    let args = [
+        "-I",
+        asn_dir.to_str().unwrap(),
+        "--fallback-dir",
+        city_dir.to_str().unwrap(),
+    ];
+    let output_str = run_geoipsed("a 214.78.0.40 b\n", &args).expect("Failed to run geoipsed");
+    assert_eq!(
+        output_str,
+        "a <214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego> b\n"
+    );
+
+    let args = ["-I", asn_dir.to_str().unwrap()];
+    let output_str = run_geoipsed("a 214.78.0.40 b\n", &args).expect("Failed to run geoipsed");
+    assert_eq!(
+        output_str,
+        "a <214.78.0.40|AS721_DoD_Network_Information_Center||> b\n"
+    );
+}
+
+/// Test a database built by mmdb build fills in fields as a fallback
+#[test]
+fn mmdb_build() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mmdb_build");
+    std::fs::create_dir_all(&dir).unwrap();
+    let csv = dir.join("networks.csv");
+    std::fs::write(
+        &csv,
+        "network,autonomous_system_number,autonomous_system_organization\n\
+         81.2.69.0/24,64512,\"Corp, Inc\"\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("geoipsed").unwrap();
+    let status = cmd
+        .args(["mmdb", "build", "--database-type", "Internal-ASN"])
+        .arg(&csv)
+        .arg(dir.join("internal.mmdb"))
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success());
+
+    let args = ["--fallback-dir", dir.to_str().unwrap()];
+    let output_str =
+        run_geoipsed("a 81.2.69.205 b 214.78.0.40\n", &args).expect("Failed to run geoipsed");
+    assert_eq!(
+        output_str,
+        "a <81.2.69.205|AS64512_Corp,_Inc|GB|London> b <214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>\n"
+    );
+}
+
+/// Test warning about and refusing stale databases
+#[test]
+fn database_age() {
+    let run = |args: &[&str]| {
+        geoipsed_cmd()
+            .args(args)
+            .write_stdin("81.2.69.205\n")
+            .output()
+            .unwrap()
+    };
+
+    // the bundled test databases were built long ago
+    let output = run(&["-o", "--max-db-age", "30"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("warning: GeoLite2-City database is"));
+
+    let output = run(&["-o", "--max-db-age", "0"]);
+    assert!(output.stderr.is_empty());
+
+    let output = run(&["-o", "--strict-db-age", "30"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("more than --strict-db-age 30"));
+}
+
+/// Test a valid ip after a long run of ip characters is still extracted
+#[test]
+fn long_ip_character_run() {
+    let args = ["-o", "-t", "[{ip}]"];
+    let input = format!(
+        "{} 81.2.69.205\n{}81.2.69.205\n",
+        "1".repeat(64),
+        "9.".repeat(40)
+    );
+    let output_str = run_geoipsed(&input, &args).expect("Failed to run geoipsed");
+    assert!(output_str.lines().all(|l| l.starts_with('[')));
+    assert_eq!(output_str.matches("[81.2.69.205]").count(), 2);
+}
+
+/// Test ips inside urls, including bracketed ipv6 hosts, are extracted
+/// without the scheme, brackets, or port
+#[test]
+fn url_embedded_ips() {
+    let args = ["-t", "<{ip}|{country_iso}>"];
+    let input =
+        "GET http://81.2.69.205:8080/path https://[2001:480::52]/ ftp://user@214.78.0.40/x\n";
+    let expected_output = "GET http://<81.2.69.205|GB>:8080/path https://[<2001:480::52|US>]/ ftp://user@<214.78.0.40|US>/x\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test opt-in decoration of obfuscated ipv4 notations
+#[test]
+fn obfuscated_ipv4() {
+    let input = "http://3595436072/ 0xd64e0028 0326.0116.0.050 81.2.69.205\n";
+
+    let args = ["-t", "<{ip}|{country_iso}>"];
+    let expected_output = "http://3595436072/ 0xd64e0028 0326.0116.0.050 <81.2.69.205|GB>\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    let args = ["--obfuscated-ipv4", "-t", "<{ip}|{country_iso}>"];
+    let expected_output =
+        "http://<214.78.0.40|US>/ <214.78.0.40|US> <214.78.0.40|US> <81.2.69.205|GB>\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test decorating mac addresses with their vendor alongside ips
+#[test]
+fn mac_entities() {
+    let mut oui = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    oui.push("oui.csv");
+    std::fs::write(
+        &oui,
+        "Registry,Assignment,Organization Name,Organization Address\n\
+         MA-L,001A2B,\"Ayecom Technology Co., Ltd.\",Taiwan\n",
+    )
+    .unwrap();
+
+    let args = [
+        "--entities",
+        "ip,mac",
+        "--oui",
+        oui.to_str().unwrap(),
+        "--mac-template",
+        "[{mac} {vendor}]",
+        "-t",
+        "<{ip}|{country_iso}>",
+    ];
+    let input = "lease 81.2.69.205 00-1A-2B-3C-4D-5E 66:55:44:33:22:11\n";
+    let expected_output = "lease <81.2.69.205|GB> [00-1A-2B-3C-4D-5E_Ayecom_Technology_Co.,_Ltd.] [66:55:44:33:22:11_]\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    // mac decorations follow the space policy too
+    let output_str = run_geoipsed(input, &[&args[..], &["--no-space-replace"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "lease <81.2.69.205|GB> [00-1A-2B-3C-4D-5E Ayecom Technology Co., Ltd.] [66:55:44:33:22:11 ]\n"
+    );
+    let output_str =
+        run_geoipsed(input, &[&args[..], &["--space-policy", "quote"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "lease <81.2.69.205|GB> [00-1A-2B-3C-4D-5E \"Ayecom Technology Co., Ltd.\"] [66:55:44:33:22:11 ]\n"
+    );
+
+    // only ips are extracted by default
+    let args = ["-o", "-t", "{ip}"];
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, "81.2.69.205\n");
+}
+
+#[test]
+fn domain_entities() {
+    let args = [
+        "--entities",
+        "ip,domain",
+        "--domain-template",
+        "[{domain} {registered}]",
+        "-t",
+        "<{ip}|{country_iso}>",
+    ];
+    let input = "GET www.Example.co.uk from 81.2.69.205 via cdn.github.io, see notes.txt\n";
+    let expected_output = "GET [www.Example.co.uk_Example.co.uk] from <81.2.69.205|GB> via [cdn.github.io_cdn.github.io], see notes.txt\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    let args = [&args[..], &["--space-policy", "keep"]].concat();
+    let expected_output = "GET [www.Example.co.uk Example.co.uk] from <81.2.69.205|GB> via [cdn.github.io cdn.github.io], see notes.txt\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+}
+
+/// Test the HTTP endpoints of the serve subcommand
+#[cfg(feature = "serve")]
+#[test]
+fn serve_http() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let mut maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    maxmind_dir.push("tests/maxmind");
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_geoipsed"))
+        .env("MAXMIND_MMDB_DIR", maxmind_dir.as_os_str())
+        .args(["serve", "--max-db-age", "0", "--listen", "127.0.0.1:0"])
+        .args(["-t", "<{ip}|{country_iso}>"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let addr = banner.trim().rsplit("http://").next().unwrap().to_string();
+
+    let request = |request: String| {
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    };
+    let get = |path: &str| request(format!("GET {path} HTTP/1.0\r\n\r\n"));
+
+    assert_eq!(
+        get("/healthz"),
+        ("HTTP/1.0 200 OK".to_string(), "ok\n".to_string())
+    );
+
+    let (status, body) = get("/lookup/2001%3A480%3A%3A1");
+    assert_eq!(status, "HTTP/1.0 200 OK");
+    let record: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(record["country_iso"], "US");
+    assert_eq!(get("/lookup/nope").0, "HTTP/1.0 404 Not Found");
+
+    let text = "src 81.2.69.205\ndst 89.160.20.135\n";
+    let (status, body) = request(format!(
+        "POST /decorate HTTP/1.0\r\nContent-Length: {}\r\n\r\n{text}",
+        text.len()
+    ));
+    assert_eq!(status, "HTTP/1.0 200 OK");
+    assert_eq!(body, "src <81.2.69.205|GB>\ndst <89.160.20.135|SE>\n");
+
+    // the same ip again is a cache hit
+    let text = "src 81.2.69.205\n";
+    request(format!(
+        "POST /decorate HTTP/1.0\r\nContent-Length: {}\r\n\r\n{text}",
+        text.len()
+    ));
+    let (status, metrics) = get("/metrics");
+    assert_eq!(status, "HTTP/1.0 200 OK");
+    for sample in [
+        "geoipsed_lines_total 3",
+        "geoipsed_entities_total{kind=\"ip\"} 3",
+        "geoipsed_country_extractions_total{country=\"GB\"} 2",
+        "geoipsed_country_extractions_total{country=\"SE\"} 1",
+        "geoipsed_cache_hits_total 1",
+        "geoipsed_cache_negative_hits_total 0",
+        "geoipsed_cache_misses_total 2",
+        "geoipsed_lookup_duration_seconds_count 2",
+    ] {
+        assert!(
+            metrics.lines().any(|l| l == sample),
+            "{sample} in {metrics}"
+        );
+    }
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+/// Test requests over the --rate-limit of a client are turned away and
+/// counted
+#[cfg(feature = "serve")]
+#[test]
+fn serve_rate_limit() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind");
+    let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_geoipsed"))
+        .env("MAXMIND_MMDB_DIR", maxmind_dir.as_os_str())
+        .args(["serve", "--max-db-age", "0", "--listen", "127.0.0.1:0"])
+        .args(["--rate-limit", "1", "--queue-size", "8"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let addr = banner.trim().rsplit("http://").next().unwrap().to_string();
+
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    assert!(get("/lookup/81.2.69.205").starts_with("HTTP/1.0 200 OK"));
+    assert!(get("/lookup/81.2.69.205").starts_with("HTTP/1.0 429 Too Many Requests"));
+    // health checks and scrapes are not limited
+    assert!(get("/healthz").starts_with("HTTP/1.0 200 OK"));
+    let metrics = get("/metrics");
+    assert!(metrics
+        .lines()
+        .any(|l| l == "geoipsed_requests_dropped_total{reason=\"rate_limit\"} 1"));
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+/// Test diagnostics are logged as JSON on stderr, leaving stdout alone
+#[test]
+fn json_diagnostics() {
+    let output = geoipsed_cmd()
+        .args([
+            "--max-db-age",
+            "0",
+            "--log-level",
+            "debug",
+            "--log-format",
+            "json",
+        ])
+        .args(["-t", "{country_iso}"])
+        .write_stdin("hello 81.2.69.205\n")
+        .output()
+        .unwrap();
+    assert_eq!(str::from_utf8(&output.stdout).unwrap(), "hello GB\n");
+
+    let messages: Vec<serde_json::Value> = str::from_utf8(&output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let finished = messages
+        .iter()
+        .find(|m| m["fields"]["message"] == "finished input")
+        .expect("throughput of the input is logged");
+    assert_eq!(finished["fields"]["lines"], 1);
+    assert!(messages
+        .iter()
+        .any(|m| m["fields"]["message"] == "opened database"));
+    // lookups are only logged at trace level
+    assert!(!messages.iter().any(|m| m["level"] == "TRACE"));
+}
+
+/// Test --prewarm looks up the listed IPs ahead of the input, which only
+/// count once seen
+#[test]
+fn prewarm() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join("prewarm.txt");
+    std::fs::write(
+        &path,
+        "# top talkers\n81.2.69.205\n89.160.20.135\n\n8.8.8.8\n",
+    )
+    .unwrap();
+    let output = geoipsed_cmd()
+        .args(["--max-db-age", "0", "--progress", "--prewarm"])
+        .arg(&path)
+        .write_stdin("a 81.2.69.205 b 214.78.0.40\n")
+        .output()
+        .unwrap();
+    assert_eq!(
+        str::from_utf8(&output.stdout).unwrap(),
+        "a <81.2.69.205|AS0_|GB|London> b <214.78.0.40|AS721_DoD_Network_Information_Center|US|San_Diego>\n"
+    );
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("1 lines, 2 matches, 2 unique in "),
+        "{stderr}"
+    );
+
+    std::fs::write(&path, "81.2.69.205\nlocalhost\n").unwrap();
+    let output = geoipsed_cmd()
+        .arg("--prewarm")
+        .arg(&path)
+        .write_stdin("")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(str::from_utf8(&output.stderr)
+        .unwrap()
+        .contains("prewarm.txt:2: not an IP address"));
+}
+
+/// Test --progress reports totals on stderr without a bar when stderr is
+/// not a terminal
+#[test]
+fn progress_summary() {
+    let output = geoipsed_cmd()
+        .args(["--max-db-age", "0", "--progress", "-t", "{country_iso}"])
+        .write_stdin("a 81.2.69.205\nb 81.2.69.205 89.160.20.135\nc\n")
+        .output()
+        .unwrap();
+    assert_eq!(
+        str::from_utf8(&output.stdout).unwrap(),
+        "a GB\nb GB SE\nc\n"
+    );
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("3 lines, 3 matches, 2 unique in "),
+        "{stderr}"
+    );
+    assert_eq!(stderr.lines().count(), 1);
+
+    // repeats of IPs no database has a record of are counted apart
+    let output = geoipsed_cmd()
+        .args(["--max-db-age", "0", "--progress"])
+        .write_stdin("8.8.8.8 81.2.69.205\n8.8.8.8 81.2.69.205 8.8.8.8\n")
+        .output()
+        .unwrap();
+    let stderr = str::from_utf8(&output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("2 lines, 5 matches, 2 unique, 2 cache hits without a record in "),
+        "{stderr}"
+    );
+}
+
+/// Test the test subcommand explains each candidate span
+#[test]
+fn explain_candidates() {
+    let args = [
+        "test",
+        "--entities",
+        "ip,domain",
+        "-t",
+        "<{ip}|{country_iso}>",
+        "src=81.2.69.205 host=intranet.corp ref=www.example.com",
+    ];
+    let expected_output = "4..15 ip \"81.2.69.205\" -> <81.2.69.205|GB>\n\
+        21..34 domain \"intranet.corp\" excluded: rejected by the domain validator\n\
+        39..54 domain \"www.example.com\" -> <www.example.com|example.com>\n";
+    let output_str = run_geoipsed("", &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    // the line is read from stdin if not given
+    let args = ["test", "--format", "cef"];
+    let input = "CEF:0|V|P|1|100|n|5|src=81.2.69.205 msg=seen 89.160.20.135\n";
+    let expected_output = "24..35 ip \"81.2.69.205\" -> <81.2.69.205|AS0_|GB|London>\n\
+        45..58 ip \"89.160.20.135\" excluded: outside the address fields of --format cef\n";
+    let output_str = run_geoipsed(input, &args).expect("Failed to run geoipsed");
+    assert_eq!(output_str, expected_output);
+
+    // ips glued to a longer token are excluded by the boundary check
+    let output_str = run_geoipsed("", &["test", "v1.2.3.4 x"]).unwrap();
+    assert_eq!(
+        output_str,
+        "1..8 ip \"1.2.3.4\" excluded: not delimited from the surrounding text\n"
+    );
+
+    let output_str = run_geoipsed("", &["test", "nothing here"]).unwrap();
+    assert_eq!(output_str, "no candidates\n");
+}
+
+/// Bench reports a row per generated corpus
+#[test]
+fn bench_corpora() {
+    let output_str = run_geoipsed("", &["bench", "--lines", "100"]).unwrap();
+    let rows: Vec<Vec<&str>> = output_str
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows.len(), 5);
+    assert_eq!(rows[0][..3], ["corpus", "MB", "IPs"]);
+    let corpora: Vec<_> = rows[1..].iter().map(|row| (row[0], row[2])).collect();
+    assert_eq!(
+        corpora,
+        [
+            ("dense", "300"),
+            ("sparse", "5"),
+            ("no-ip", "0"),
+            ("utf8", "5")
+        ]
+    );
+    assert!(rows[1..].iter().all(|row| row.ends_with(&["MB/s"])));
+}
+
+/// Test binary input is cut off, filtered, or processed by --binary
+#[test]
+fn binary_input() {
+    let args = ["-t", "<{ip}>", "--max-db-age", "0"];
+    let input = "a 81.2.69.205\nb\0 81.2.69.205\nc 81.2.69.205\n";
+
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "a <81.2.69.205>\n");
+
+    let output_str = run_geoipsed(input, &[&args[..], &["--binary", "process"]].concat()).unwrap();
+    assert_eq!(output_str, "a <81.2.69.205>\nc <81.2.69.205>\n");
+
+    let output_str = run_geoipsed(input, &[&args[..], &["--binary", "text"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "a <81.2.69.205>\nb\0 <81.2.69.205>\nc <81.2.69.205>\n"
+    );
+}
+
+/// Test lines over --max-line-length are skipped or split between
+/// addresses
+#[test]
+fn long_lines() {
+    let args = ["-t", "<{ip}>", "--max-line-length", "16"];
+    let input = "a 81.2.69.205\nlong 81.2.69.205 81.2.69.205 81.2.69.205\nb 81.2.69.205";
+
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "a <81.2.69.205>\nlong <81.2.69.205> <81.2.69.205> <81.2.69.205>\nb <81.2.69.205>"
+    );
+
+    let output_str = run_geoipsed(input, &[&args[..], &["--long-lines", "skip"]].concat()).unwrap();
+    assert_eq!(output_str, "a <81.2.69.205>\nb <81.2.69.205>");
+}
+
+/// Test CRLF and NUL terminated lines round trip
+#[test]
+fn line_terminators() {
+    let args = ["-t", "<{ip}>", "--max-db-age", "0"];
+    let input = "a 81.2.69.205\r\nb 81.2.69.205\r\n";
+    let output_str = run_geoipsed(input, &[&args[..], &["--crlf"]].concat()).unwrap();
+    assert_eq!(output_str, "a <81.2.69.205>\r\nb <81.2.69.205>\r\n");
+    let output_str = run_geoipsed(input, &[&args[..], &["--crlf", "-o"]].concat()).unwrap();
+    assert_eq!(output_str, "<81.2.69.205>\r\n<81.2.69.205>\r\n");
+
+    let input = "a 81.2.69.205\0b\n81.2.69.205\0";
+    let output_str = run_geoipsed(input, &[&args[..], &["-0"]].concat()).unwrap();
+    assert_eq!(output_str, "a <81.2.69.205>\0b\n<81.2.69.205>\0");
+    let output_str = run_geoipsed(input, &[&args[..], &["-0", "-o"]].concat()).unwrap();
+    assert_eq!(output_str, "<81.2.69.205>\0<81.2.69.205>\0");
+
+    let input = "{\"ip\":\"81.2.69.205\"}\0";
+    let output_str = run_geoipsed(input, &["--json-input", "-0", "--json-fields", "ip"]).unwrap();
+    assert!(output_str.starts_with("{\"ip\":\"81.2.69.205\",\"ip_geo\":"));
+    assert!(output_str.ends_with("}\0"));
+}
+
+/// Test UTF-16 and Latin-1 input is transcoded to UTF-8
+#[test]
+fn encoded_input() {
+    let args = ["-t", "<{ip}>", "--max-db-age", "0"];
+    let text = "é 81.2.69.205\n";
+    let utf16le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let with_bom = [&[0xff, 0xfe][..], &utf16le].concat();
+    let expected = "é <81.2.69.205>\n";
+
+    let run = |input: &[u8], encoding: &str| {
+        let output = geoipsed_cmd()
+            .args(args)
+            .args(["--encoding", encoding])
+            .write_stdin(input)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(run(&utf16le, "utf-16le"), expected);
+    assert_eq!(run(&utf16le, "auto"), expected);
+    assert_eq!(run(&with_bom, "auto"), expected);
+    assert_eq!(run(b"\xe9 81.2.69.205\n", "latin1"), expected);
+    assert_eq!(run(text.as_bytes(), "auto"), expected);
+}
+
+/// Test files are rewritten in place, with a backup if asked
+#[test]
+fn in_place() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("in_place");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.log"), dir.join("b.log"));
+    std::fs::write(&a, "a 81.2.69.205\n").unwrap();
+    std::fs::write(&b, "b 81.2.69.205\n").unwrap();
+
+    let args = ["-t", "<{ip}>", "--in-place=.bak", "--keep-mtime"];
+    let output_str = run_geoipsed(
+        "",
+        &[&args[..], &[a.to_str().unwrap(), b.to_str().unwrap()]].concat(),
+    )
+    .unwrap();
+    assert_eq!(output_str, "");
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), "a <81.2.69.205>\n");
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), "b <81.2.69.205>\n");
+    assert_eq!(
+        std::fs::read_to_string(dir.join("a.log.bak")).unwrap(),
+        "a 81.2.69.205\n"
+    );
+    // only the edited files and their backups are left
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+
+    let output_str = run_geoipsed("", &["-o", "-i", a.to_str().unwrap()]).unwrap();
+    assert_eq!(output_str, "");
+    assert_eq!(
+        std::fs::read_to_string(&a).unwrap(),
+        "<81.2.69.205|AS0_|GB|London>\n"
+    );
+}
+
+/// Test --output-dir writes each input's output at its relative path
+#[test]
+fn output_dir() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("output_dir");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("logs/web")).unwrap();
+    std::fs::write(dir.join("logs/a.log"), "a 81.2.69.205\n").unwrap();
+    std::fs::write(dir.join("logs/web/b.log"), "b 214.78.0.40\n").unwrap();
+
+    let output = geoipsed_cmd()
+        .current_dir(&dir)
+        .args([
+            "-t",
+            "<{ip}>",
+            "--output-dir",
+            "out",
+            "logs/a.log",
+            "logs/web/b.log",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+    assert_eq!(read("out/logs/a.log"), "a <81.2.69.205>\n");
+    assert_eq!(read("out/logs/web/b.log"), "b <214.78.0.40>\n");
+    // the inputs are left alone
+    assert_eq!(read("logs/a.log"), "a 81.2.69.205\n");
+}
+
+/// Test --strip undoes the decorations of the default and a custom template
+#[test]
+fn strip_decorations() {
+    let input = "a 81.2.69.205 b 2001:480::1 c <not|a|decoration>\n";
+    let spaced = ["-t", "{ip} ({country_iso}, {city})"];
+    let cases: [&[&str]; 4] = [
+        &[],
+        &["-t", "{ip}[{country_iso}/{city}]"],
+        // literal spaces are written as underscores by default
+        &spaced,
+        &[&spaced[..], &["--space-policy", "keep"]].concat(),
+    ];
+    for template_args in cases {
+        let decorated = run_geoipsed(input, template_args).unwrap();
+        assert_ne!(decorated, input);
+        let stripped = run_geoipsed(&decorated, &[template_args, &["--strip"]].concat());
+        assert_eq!(stripped.unwrap(), input);
+    }
+}
+
+/// Test --threads keeps the output in input order across many batches
+#[test]
+fn threads_keep_order() {
+    let input: String = (0..15_000)
+        .map(|i| format!("{i} 81.2.69.{} x 2001:480::{i:x}\n", i % 256))
+        .collect();
+    for mode in [&[][..], &["-o"][..]] {
+        let sequential = run_geoipsed(&input, mode).unwrap();
+        let threaded = run_geoipsed(&input, &[mode, &["--threads", "4"]].concat()).unwrap();
+        assert_eq!(threaded.len(), sequential.len());
+        assert!(threaded == sequential);
+    }
+}
+
+/// Test setup errors are reported instead of panicking
+#[test]
+fn setup_errors() {
+    let maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind");
+    let run = |dir: &std::path::Path, args: &[&str]| {
+        let output = Command::cargo_bin("geoipsed")
+            .unwrap()
+            .env("MAXMIND_MMDB_DIR", dir)
+            .args(args)
+            .write_stdin("81.2.69.205\n")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    let stderr = run(&maxmind_dir.join("missing"), &[]);
+    assert!(stderr.contains("database not found"));
+    assert!(stderr.contains("($MAXMIND_MMDB_DIR)"), "{stderr}");
+    assert!(!stderr.contains("panicked"));
+
+    let stderr = run(&maxmind_dir, &["-t", "{ip} {contry}"]);
+    assert!(stderr.contains("unknown template field {contry}"));
+    let stderr = run(&maxmind_dir, &["-t", "{ip} {asn_org}"]);
+    assert!(stderr.contains("unknown template field {asn_org}; did you mean {asnorg}?"));
+    let stderr = run(&maxmind_dir, &["-t", "{ip} {asnorg"]);
+    assert!(stderr.contains("unclosed { at offset 5"));
+}
+
+/// Test decorating with only one of the databases
+#[test]
+fn single_database() {
+    let maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind");
+    for (name, expected, empty) in [
+        (
+            "GeoLite2-City.mmdb",
+            "<81.2.69.205|AS0_|GB|London>\n",
+            "asnnum,asnorg",
+        ),
+        (
+            "GeoLite2-ASN.mmdb",
+            "<81.2.69.205|AS0_||>\n",
+            "country_iso,city",
+        ),
+    ] {
+        let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("only-{name}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy(maxmind_dir.join(name), dir.join(name)).unwrap();
+        let output = Command::cargo_bin("geoipsed")
+            .unwrap()
+            .env("MAXMIND_MMDB_DIR", &dir)
+            .args(["-o", "--max-db-age", "0"])
+            .write_stdin("81.2.69.205\n")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains(empty));
+    }
+}
+
+/// Test databases split into IPv4 and IPv6 files serve the addresses of
+/// their version, in every combination
+#[test]
+fn split_databases() {
+    let maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind");
+    let (asn, city) = ("GeoLite2-ASN.mmdb", "GeoLite2-City.mmdb");
+    let v4 = "81.2.69.205|0|London\n214.78.0.40|721|San_Diego\n";
+    let v6 = "2600:6000::1|237|\n2001:480::1|0|San_Diego\n";
+    // a missing asnnum is 0
+    let v6_asn_only = "2600:6000::1|237|\n2001:480::1|0|\n";
+    let v6_city_only = "2600:6000::1|0|\n2001:480::1|0|San_Diego\n";
+    let cases = [
+        (
+            "split-all",
+            vec![
+                (asn, "asn-ipv4.mmdb"),
+                (asn, "asn-ipv6.mmdb"),
+                (city, "city-ipv4.mmdb"),
+                (city, "city-ipv6.mmdb"),
+            ],
+            format!("{v4}{v6}"),
+        ),
+        (
+            "split-city-v4",
+            vec![(asn, "asn.mmdb"), (city, "city-IPv4.mmdb")],
+            format!("{v4}{v6_asn_only}"),
+        ),
+        (
+            "split-asn-v4",
+            vec![(asn, "asn-IPv4.mmdb"), (city, "city.mmdb")],
+            format!("{v4}{v6_city_only}"),
+        ),
+        (
+            "split-v4-only",
+            vec![(asn, "asn-ipv4.mmdb"), (city, "city-ipv4.mmdb")],
+            format!("{v4}2600:6000::1|0|\n2001:480::1|0|\n"),
+        ),
+        (
+            "split-mixed",
+            vec![(asn, "asn-ipv6.mmdb"), (city, "city-ipv4.mmdb")],
+            format!("{v4}{v6_asn_only}"),
+        ),
+    ];
+    for (name, files, expected) in cases {
+        let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (from, to) in files {
+            std::fs::copy(maxmind_dir.join(from), dir.join(to)).unwrap();
+        }
+        let output = Command::cargo_bin("geoipsed")
+            .unwrap()
+            .env("MAXMIND_MMDB_DIR", &dir)
+            .args(["-o", "--max-db-age", "0", "-t", "{ip}|{asnnum}|{city}"])
+            .write_stdin("81.2.69.205 214.78.0.40 2600:6000::1 2001:480::1\n")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{name}"
+        );
+    }
+}
+
+/// Test -I takes precedence over GEOIP_MMDB_DIR, which takes precedence
+/// over MAXMIND_MMDB_DIR
+#[test]
+fn database_dir_precedence() {
+    let maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/maxmind");
+    let missing = maxmind_dir.join("missing");
+    let run = |geoip: &PathBuf, maxmind: &PathBuf, args: &[&str]| {
+        Command::cargo_bin("geoipsed")
+            .unwrap()
+            .env("GEOIP_MMDB_DIR", geoip)
+            .env("MAXMIND_MMDB_DIR", maxmind)
+            .args(["-o", "--max-db-age", "0"])
+            .args(args)
+            .write_stdin("81.2.69.205\n")
+            .output()
+            .unwrap()
+            .status
+            .success()
+    };
+    assert!(run(&maxmind_dir, &missing, &[]));
+    assert!(!run(&missing, &maxmind_dir, &[]));
+    assert!(run(
+        &missing,
+        &missing,
+        &["-I", maxmind_dir.to_str().unwrap()]
+    ));
+    assert!(run(&PathBuf::new(), &maxmind_dir, &[]));
+}
+
+/// Test --list-templates describes the fields, as text or JSON
+#[test]
+fn list_templates() {
+    let output_str = run_geoipsed("", &["-L"]).unwrap();
+    assert!(output_str.contains("{country_iso}    ISO 3166 country code (e.g. GB)\n"));
+
+    let output_str = run_geoipsed(
+        "",
+        &[
+            "-L",
+            "--json",
+            "--enrich-csv",
+            "ranges.csv",
+            "--enrich-field",
+            "site",
+        ],
+    )
+    .unwrap();
+    let fields: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+    let fields = fields.as_array().unwrap();
+    assert_eq!(fields[0]["name"], "ip");
+    assert_eq!(fields[0]["example"], "81.2.69.205");
+    assert_eq!(fields.last().unwrap()["name"], "site");
+}
+
+/// Test template presets, which --strip can undo too
+#[test]
+fn template_presets() {
+    let input = "a 214.78.0.40 b\n";
+    let output_str = run_geoipsed(input, &["--template-preset", "kv"]).unwrap();
+    assert_eq!(output_str, "a ip=214.78.0.40 asn=721 cc=US b\n");
+    let output_str = run_geoipsed(input, &["--template-preset", "grepable"]).unwrap();
+    assert_eq!(
+        output_str,
+        "a <214.78.0.40|AS721|DoD_Network_Information_Center|US|San_Diego> b\n"
+    );
+    let stripped = run_geoipsed(&output_str, &["--template-preset", "grepable", "--strip"]);
+    assert_eq!(stripped.unwrap(), input);
+
+    let output_str = run_geoipsed("", &["--list-presets"]).unwrap();
+    assert!(output_str.contains("minimal    <{ip}|{country_iso}>\n"));
+}
+
+/// Test --lang selects localized names, falling back to English
+#[test]
+fn localized_names() {
+    let args = ["-t", "{country_full}/{city}", "--max-db-age", "0"];
+    let input = "214.78.0.40 175.16.199.52\n";
+    let output_str = run_geoipsed(input, &[&args[..], &["--lang", "de"]].concat()).unwrap();
+    assert_eq!(output_str, "Vereinigte_Staaten/San_Diego China/Chángchūn\n");
+    let output_str = run_geoipsed(input, &[&args[..], &["--lang", "xx"]].concat()).unwrap();
+    assert_eq!(output_str, "United_States/San_Diego China/Changchun\n");
+}
+
+/// Test spaces in decorations are replaced, kept, or quoted
+#[test]
+fn space_policy() {
+    let input = "a 214.78.0.40 b\n";
+    let args = ["-t", "{asnorg}|{city}|{country_iso}"];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "a DoD_Network_Information_Center|San_Diego|US b\n"
+    );
+    let output_str = run_geoipsed(input, &[&args[..], &["--no-space-replace"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "a DoD Network Information Center|San Diego|US b\n"
+    );
+    let output_str =
+        run_geoipsed(input, &[&args[..], &["--space-policy", "quote"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "a \"DoD Network Information Center\"|\"San Diego\"|US b\n"
+    );
+}
+
+/// Test the region, postal code, and location accuracy fields
+#[test]
+fn region_fields() {
+    let args = [
+        "-o",
+        "-t",
+        "{subdivision_iso}|{subdivision_name}|{postal_code}|{accuracy_radius}|{metro_code}",
+    ];
+    let output_str = run_geoipsed("214.78.0.40 81.2.69.205", &args).unwrap();
+    assert_eq!(
+        output_str,
+        "CA|California|92136|10|825\nENG|England||100|\n"
+    );
+}
+
+/// Test network traits render as true/false or 1/0, empty when unknown
+#[test]
+fn trait_fields() {
+    let args = ["-o", "-t", "{ip}|{is_in_european_union}|{is_anycast}"];
+    let input = "89.160.20.188 81.2.69.205 10.0.0.1";
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "89.160.20.188|true|false\n81.2.69.205|false|false\n10.0.0.1||\n"
+    );
+    let output_str = run_geoipsed(input, &[&args[..], &["--bool-format", "1-0"]].concat()).unwrap();
+    assert_eq!(
+        output_str,
+        "89.160.20.188|1|0\n81.2.69.205|0|0\n10.0.0.1||\n"
+    );
+}
+
+/// Test the GeoIP2-ISP and Connection-Type fields are empty without the
+/// paid databases
+#[test]
+fn paid_fields() {
+    let args = [
+        "-o",
+        "-t",
+        "{ip}|{isp}|{organization}|{connection_type}|{country_iso}",
+    ];
+    let output_str = run_geoipsed("81.2.69.205", &args).unwrap();
+    assert_eq!(output_str, "81.2.69.205||||GB\n");
+    let output_str = run_geoipsed("", &["-L"]).unwrap();
+    assert!(output_str.contains("{connection_type}"));
+}
+
+/// Test {distance_km} is measured from --ref-point, empty without one
+#[test]
+fn distance_from_ref_point() {
+    let args = [
+        "-o",
+        "-t",
+        "{ip}|{distance_km}",
+        "--ref-point",
+        "48.8566,2.3522",
+    ];
+    let output_str = run_geoipsed("81.2.69.205 10.0.0.1", &args).unwrap();
+    assert_eq!(output_str, "81.2.69.205|342.9\n10.0.0.1|\n");
+    let output_str = run_geoipsed("81.2.69.205", &args[..3]).unwrap();
+    assert_eq!(output_str, "81.2.69.205|\n");
+    let output_str = run_geoipsed("81.2.69.205", &["--ref-point", "91,0"]).unwrap();
+    assert_eq!(output_str, "");
+}
+
+/// Test lines repeating the entities of a recent line are skipped
+#[test]
+fn dedup_window() {
+    let input = "a 81.2.69.205\nb 81.2.69.205\nno ip\nc 214.78.0.40 81.2.69.205\n\
+                 d 81.2.69.205 214.78.0.40\ne 81.2.69.205\n";
+    let args = ["-t", "{country_iso}", "--dedup-window", "2"];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "a GB\nno ip\nc US GB\ne GB\n");
+    let args = ["-t", "{country_iso}", "--dedup-window", "60s"];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "a GB\nno ip\nc US GB\n");
+}
+
+/// Test lines of several named inputs are tagged with their names
+#[test]
+fn input_fifos() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (fw, dns) = (dir.join("fifo_fw.log"), dir.join("fifo_dns.log"));
+    std::fs::write(&fw, "deny 81.2.69.205\n").unwrap();
+    std::fs::write(&dns, "query 214.78.0.40\n").unwrap();
+    let fw = format!("fw={}", fw.to_str().unwrap());
+    let dns = format!("dns={}", dns.to_str().unwrap());
+
+    let args = [
+        "-t",
+        "<{ip}|{country_iso}>",
+        "--input-fifo",
+        &fw,
+        "--input-fifo",
+        &dns,
+    ];
+    let output_str = run_geoipsed("", &args).unwrap();
+    let mut lines: Vec<&str> = output_str.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        ["dns:query <214.78.0.40|US>", "fw:deny <81.2.69.205|GB>"]
+    );
+
+    std::fs::write(dir.join("fifo_fw.log"), "{\"src\":\"81.2.69.205\"}\n").unwrap();
+    let output_str = run_geoipsed("", &["--json-input", "--input-fifo", &fw]).unwrap();
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+    assert_eq!(record["stream"], "fw");
+    assert_eq!(record["src_geo"]["country_iso"], "GB");
+}
+
+/// Test extracted IPs are posted in batches, retrying a failed post
+#[test]
+fn post_url() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/ingest", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut bodies = Vec::new();
+        // the first post is refused once and then retried
+        for status in ["503 Service Unavailable", "200 OK", "200 OK"] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(stream, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+            bodies.push(String::from_utf8(body).unwrap());
+        }
+        bodies
+    });
+
+    let input = "a 81.2.69.205 b 214.78.0.40\nc 89.160.20.135\n";
+    run_geoipsed(input, &["--post-url", &url, "--batch", "2"]).unwrap();
+    let bodies = server.join().unwrap();
+    assert_eq!(bodies[0], bodies[1]);
+    let records: Vec<serde_json::Value> = bodies[1..]
+        .iter()
+        .flat_map(|body| body.lines())
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["ip"], "81.2.69.205");
+    assert_eq!(records[0]["line_no"], 1);
+    assert_eq!(records[2]["country_iso"], "SE");
+    assert_eq!(records[2]["line_no"], 2);
+}
+
+/// Test es-bulk output pairs an index action with an ECS document per line
+#[test]
+fn es_bulk_output() {
+    let input = "a 81.2.69.205 b 214.78.0.40\nc 89.160.20.135\n";
+    let output_str = run_geoipsed(input, &["--output", "es-bulk", "--index", "logs-geo"]).unwrap();
+    let lines: Vec<serde_json::Value> = output_str
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(
+        lines[0],
+        serde_json::json!({"index": {"_index": "logs-geo"}})
+    );
+    assert_eq!(lines[2], lines[0]);
+
+    let document = &lines[1];
+    assert_eq!(document["log"]["line_no"], 1);
+    assert_eq!(document["source"]["ip"], "81.2.69.205");
+    assert_eq!(document["source"]["geo"]["country_iso_code"], "GB");
+    assert_eq!(document["source"]["geo"]["location"]["lat"], 51.5142);
+    assert_eq!(document["destination"]["as"]["number"], 721);
+    assert_eq!(
+        document["related"]["ip"],
+        serde_json::json!(["81.2.69.205", "214.78.0.40"])
+    );
+    assert_eq!(lines[3]["source"]["geo"]["country_iso_code"], "SE");
+    assert!(lines[3].get("destination").is_none());
+}
+
+/// Test --splunk-hec posts HEC events authenticated with the token
+#[test]
+fn splunk_hec() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "http://{}/services/collector/event",
+        listener.local_addr().unwrap()
+    );
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let (mut length, mut auth) = (0, String::new());
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header == "\r\n" {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            if let Some(value) = header.strip_prefix("Authorization: ") {
+                auth = value.trim().to_string();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        (auth, String::from_utf8(body).unwrap())
+    });
+
+    geoipsed_cmd()
+        .env("HEC_TOKEN", "0b4d-11")
+        .args(["--splunk-hec", &url, "--token", "HEC_TOKEN"])
+        .write_stdin("a 81.2.69.205 b 214.78.0.40\n")
+        .assert()
+        .success();
+    let (auth, body) = server.join().unwrap();
+    assert_eq!(auth, "Splunk 0b4d-11");
+    let events: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["sourcetype"], "geoipsed");
+    assert_eq!(events[0]["source"], "-");
+    assert_eq!(events[0]["event"]["ip"], "81.2.69.205");
+    assert_eq!(events[1]["event"]["asnnum"], 721);
+}
+
+/// Test --framed writes one enriched frame per input frame, in order
+#[test]
+fn framed_events() {
+    let input = "{\"src\":\"81.2.69.205\"}\nnot json\n\n{\"n\":1}\n";
+    let output_str = run_geoipsed(input, &["--framed", "ndjson"]).unwrap();
+    let lines: Vec<&str> = output_str.split('\n').collect();
+    assert_eq!(lines.len(), 5);
+    let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(event["src_geo"]["country_iso"], "GB");
+    assert_eq!(lines[1..], ["not json", "", "{\"n\":1}", ""]);
+
+    let mut input = Vec::new();
+    for event in [r#"{"a":"214.78.0.40"}"#, r#"{"b":"x"}"#] {
+        input.extend_from_slice(&(event.len() as u32).to_be_bytes());
+        input.extend_from_slice(event.as_bytes());
+    }
+    let output = geoipsed_cmd()
+        .args(["--framed", "length"])
+        .write_stdin(input)
+        .output()
+        .unwrap();
+    let mut frames = Vec::new();
+    let mut rest = &output.stdout[..];
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        frames.push(serde_json::from_slice::<serde_json::Value>(&rest[4..4 + len]).unwrap());
+        rest = &rest[4 + len..];
+    }
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["a_geo"]["asnnum"], 721);
+    assert_eq!(frames[1], serde_json::json!({"b": "x"}));
+}
+
+/// Test --journal decorates the MESSAGE of the entries journalctl writes
+#[cfg(unix)]
+#[test]
+fn journal_entries() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // a stand-in journalctl that prints its arguments and two entries
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("journal_bin");
+    std::fs::create_dir_all(&dir).unwrap();
+    let journalctl = dir.join("journalctl");
+    std::fs::write(
+        &journalctl,
+        r#"#!/bin/sh
+echo "{\"ARGS\":\"$*\",\"MESSAGE\":\"start\"}"
+echo '{"__REALTIME_TIMESTAMP":"1700000000000000","_HOSTNAME":"web1","SYSLOG_IDENTIFIER":"sshd","_PID":"42","MESSAGE":"Failed password from 81.2.69.205"}'
+"#,
+    )
+    .unwrap();
+    std::fs::set_permissions(&journalctl, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dir.display(), std::env::var("PATH").unwrap());
+
+    let run = |args: &[&str]| {
+        let output = geoipsed_cmd()
+            .env("PATH", &path)
+            .args(args)
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let output_str = run(&[
+        "-t",
+        "<{ip}|{country_iso}>",
+        "--journal",
+        "_SYSTEMD_UNIT=sshd.service",
+    ]);
+    assert_eq!(
+        output_str,
+        "-: start\n2023-11-14T22:13:20Z web1 sshd[42]: Failed password from <81.2.69.205|GB>\n"
+    );
+
+    let output_str = run(&["--json-input", "--journal", "--follow"]);
+    let mut lines = output_str.lines();
+    let entry: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!(entry["ARGS"], "--output=json --no-pager --follow");
+    let entry: serde_json::Value = serde_json::from_str(lines.next().unwrap()).unwrap();
+    assert_eq!(entry["MESSAGE_geo"][0]["country_iso"], "GB");
+}
+
+/// Test --evtx enriches the strings of each event record as NDJSON
+#[test]
+fn evtx_records() {
+    let utf16 = |s: &str| -> Vec<u8> { s.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+    let mut event = vec![0x0f, 0x01, 0x01, 0x00];
+    event.extend(utf16("IpAddress"));
+    event.extend([0x05, 0x01, 11, 0]);
+    event.extend(utf16("81.2.69.205"));
+    event.push(0x00);
+
+    // 2023-11-14T22:13:20Z as a FILETIME
+    let filetime: u64 = (1_700_000_000 + 11_644_473_600) * 10_000_000;
+    let size = (24 + event.len() + 4) as u32;
+    let mut record = b"\x2a\x2a\0\0".to_vec();
+    record.extend(size.to_le_bytes());
+    record.extend(7u64.to_le_bytes());
+    record.extend(filetime.to_le_bytes());
+    record.extend(&event);
+    record.extend(size.to_le_bytes());
+
+    let mut chunk = vec![0u8; 65536];
+    chunk[..8].copy_from_slice(b"ElfChnk\0");
+    chunk[0x30..0x34].copy_from_slice(&(512 + size).to_le_bytes());
+    chunk[512..512 + record.len()].copy_from_slice(&record);
+    let mut evtx = vec![0u8; 4096];
+    evtx[..8].copy_from_slice(b"ElfFile\0");
+    evtx.extend(chunk);
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("security.evtx");
+    std::fs::write(&path, evtx).unwrap();
+    let path = path.to_str().unwrap();
+
+    let output_str = run_geoipsed("", &["--evtx", path]).unwrap();
+    let record: serde_json::Value = serde_json::from_str(&output_str).unwrap();
+    assert_eq!(record["record_id"], 7);
+    assert_eq!(record["timestamp"], "2023-11-14T22:13:20Z");
+    assert_eq!(record["strings"], "IpAddress 81.2.69.205");
+    assert_eq!(record["strings_geo"][0]["country_iso"], "GB");
+
+    let args = ["--evtx", "-o", "-t", "<{ip}|{country_iso}>", path];
+    let output_str = run_geoipsed("", &args).unwrap();
+    assert_eq!(output_str, "<81.2.69.205|GB>\n");
+}
+
+/// Test the members of tar.gz and zip archives are decorated and labeled
+#[cfg(feature = "archive")]
+#[test]
+fn archive_members() {
+    use std::io::Write;
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let members = [
+        ("var/log/auth.log", "login from 81.2.69.205\n"),
+        ("var/log/notes.txt", "seen 214.78.0.40\n"),
+    ];
+
+    let tgz = dir.join("bundle.tar.gz");
+    let gz = flate2::write::GzEncoder::new(
+        std::fs::File::create(&tgz).unwrap(),
+        flate2::Compression::default(),
+    );
+    let mut tar = tar::Builder::new(gz);
+    for (name, contents) in members {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, name, contents.as_bytes())
+            .unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap();
+
+    let zip_path = dir.join("bundle.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+    for (name, contents) in members {
+        zip.start_file(name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let template = "<{ip}|{country_iso}>";
+    for path in [tgz.to_str().unwrap(), zip_path.to_str().unwrap()] {
+        let output_str = run_geoipsed("", &["-t", template, path]).unwrap();
+        assert_eq!(
+            output_str,
+            format!(
+                "{path}!var/log/auth.log:login from <81.2.69.205|GB>\n\
+                 {path}!var/log/notes.txt:seen <214.78.0.40|US>\n"
+            )
+        );
+        let output_str =
+            run_geoipsed("", &["-o", "-t", template, "--glob", "*.log", path]).unwrap();
+        assert_eq!(
+            output_str,
+            format!("{path}!var/log/auth.log:<81.2.69.205|GB>\n")
+        );
+    }
+}
+
+/// Test --sample keeps about RATE of the lines, the same ones for a seed
+#[test]
+fn sample_lines() {
+    let input: String = (0..1000).map(|i| format!("{i} 81.2.69.205\n")).collect();
+    let args = ["-t", "{country_iso}", "--sample", "10%", "--seed", "7"];
+    let output_str = run_geoipsed(&input, &args).unwrap();
+    let kept = output_str.lines().count();
+    assert!((50..150).contains(&kept), "kept {kept} of 1000 lines");
+    assert!(output_str.lines().all(|line| line.ends_with(" GB")));
+    assert_eq!(run_geoipsed(&input, &args).unwrap(), output_str);
+
+    let output_str = run_geoipsed(&input, &["--sample", "0.5", "--seed", "8"]).unwrap();
+    assert_ne!(output_str.lines().count(), kept);
+    let output_str = run_geoipsed(&input, &["--sample", "100%"]).unwrap();
+    assert_eq!(output_str.lines().count(), 1000);
+    assert_eq!(run_geoipsed(&input, &["--sample", "0%"]).unwrap(), "");
+}
+
+#[test]
+fn max_count() {
+    let input = "a\nb 81.2.69.205 214.78.0.40\nc\nd 89.160.20.135\ne\n";
+    let args = ["-t", "{country_iso}", "-m", "1"];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "a\nb GB US\n");
+
+    let output_str = run_geoipsed(input, &["-t", "{country_iso}", "-o", "-m", "1"]).unwrap();
+    assert_eq!(output_str, "GB\nUS\n");
+
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (first, second) = (dir.join("max_count_1.log"), dir.join("max_count_2.log"));
+    std::fs::write(&first, input).expect("Failed to write test input");
+    std::fs::write(&second, input).expect("Failed to write test input");
+    let paths = [first.to_str().unwrap(), second.to_str().unwrap()];
+
+    let mut args = vec!["-t", "{country_iso}", "-o", "-m", "1"];
+    args.extend(paths);
+    assert_eq!(run_geoipsed("", &args).unwrap(), "GB\nUS\nGB\nUS\n");
+
+    let mut args = vec!["-t", "{country_iso}", "-o", "--max-matches", "3"];
+    args.extend(paths);
+    assert_eq!(run_geoipsed("", &args).unwrap(), "GB\nUS\nSE\n");
+
+    let mut args = vec!["-t", "{country_iso}", "--max-matches", "1"];
+    args.extend(paths);
+    assert_eq!(run_geoipsed("", &args).unwrap(), "a\nb GB US\n");
+}
+
+#[test]
+fn ip_version_colors() {
+    let input = "81.2.69.205 2001:480::1\n";
+    let args = ["-t", "{country_iso}", "-C", "always"];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(output_str, "\x1b[1;31mGB\x1b[0;0m \x1b[1;36mUS\x1b[0;0m\n");
+
+    let args = [
+        "-t",
+        "{country_iso}",
+        "-C",
+        "always",
+        "--color-v4",
+        "green",
+        "--color-v6",
+        "208",
+    ];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "\x1b[1;32mGB\x1b[0;0m \x1b[1;38;5;208mUS\x1b[0;0m\n"
+    );
+}
+
+#[test]
+fn color_scope_ip() {
+    let input = "81.2.69.205 2001:480::1\n";
+    let args = [
+        "-t",
+        "<{ip}|{country_iso}>",
+        "-C",
+        "always",
+        "--color-scope",
+        "ip",
+    ];
+    let output_str = run_geoipsed(input, &args).unwrap();
+    assert_eq!(
+        output_str,
+        "<\x1b[1;31m81.2.69.205\x1b[0;0m|GB> <\x1b[1;36m2001:480::1\x1b[0;0m|US>\n"
+    );
+
+    let args = ["--strip", "-t", "<{ip}|{country_iso}>"];
+    assert_eq!(run_geoipsed(&output_str, &args).unwrap(), input);
+}
+
+#[test]
+fn jsonl_and_csv_output() {
+    let input = "a 81.2.69.205\nb 214.78.0.40\n";
+    let output_str = run_geoipsed(input, &["--output", "jsonl"]).unwrap();
+    let records: Vec<serde_json::Value> = output_str
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["line_no"], 1);
+    assert_eq!(records[0]["offset"], 2);
+    assert_eq!(records[0]["country_iso"], "GB");
+    assert_eq!(records[1]["asnorg"], "DoD Network Information Center");
+    // numbers and true/false fields are typed, unknown values null
+    assert_eq!(records[1]["asnnum"], 721);
+    assert_eq!(records[0]["latitude"], 51.5142);
+    assert_eq!(records[0]["is_anycast"], false);
+    assert!(records[0]["asnnum"].is_null());
+    assert!(records[0]["metro_code"].is_null());
+
+    let output_str = run_geoipsed(input, &["--output", "csv"]).unwrap();
+    let mut lines = output_str.lines();
+    let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+    assert_eq!(header[..5], ["source", "line_no", "offset", "ip", "asnnum"]);
+    let row = lines.next().unwrap();
+    assert!(row.starts_with("-,1,2,81.2.69.205,,"), "{row}");
+    assert!(row.contains(",GB,United Kingdom,"), "{row}");
+    assert_eq!(lines.count(), 1);
+}
@@ -0,0 +1,38 @@
+use geoipsed::countrydb::{CountryTable, ParseCountryError};
+
+const CSV: &str = "\
+start_ip,end_ip,country,country_name,continent,continent_name
+81.2.69.0,81.2.69.255,GB,United Kingdom,EU,Europe
+1.0.0.0,1.0.0.255,au,Australia,OC,Oceania
+2001:480::,2001:480:ffff:ffff:ffff:ffff:ffff:ffff,US,United States,NA,North America
+";
+
+#[test]
+fn lookup_and_round_trip() {
+    let table = CountryTable::from_csv(CSV).unwrap();
+    assert_eq!(table.len(), 3);
+    let lookup =
+        |table: &CountryTable, ip: &str| table.lookup(ip.parse().unwrap()).map(str::to_string);
+    assert_eq!(lookup(&table, "81.2.69.205").as_deref(), Some("GB"));
+    assert_eq!(lookup(&table, "1.0.0.0").as_deref(), Some("AU"));
+    assert_eq!(lookup(&table, "::ffff:1.0.0.255").as_deref(), Some("AU"));
+    assert_eq!(lookup(&table, "2001:480::1").as_deref(), Some("US"));
+    assert_eq!(lookup(&table, "1.0.1.0"), None);
+    assert_eq!(lookup(&table, "0.0.0.1"), None);
+
+    let bytes = table.to_bytes();
+    assert_eq!(CountryTable::from_bytes(&bytes), Some(table));
+    assert_eq!(CountryTable::from_bytes(&bytes[..bytes.len() - 1]), None);
+}
+
+#[test]
+fn invalid_lines() {
+    assert_eq!(
+        CountryTable::from_csv("1.0.0.0,1.0.0.255,AU\n1.0.1.0,::1,CN\n"),
+        Err(ParseCountryError { line: 2 })
+    );
+    assert_eq!(
+        CountryTable::from_csv("1.0.0.0,1.0.0.255,AU\n1.0.1.0,1.0.1.255,China\n"),
+        Err(ParseCountryError { line: 2 })
+    );
+}
@@ -0,0 +1,42 @@
+use geoipsed::domain::{is_domain, PublicSuffixList, Resolver};
+use std::time::Duration;
+
+/// Test suffixes are found by the longest matching rule, including
+/// wildcards and their exceptions
+#[test]
+fn public_suffix_rules() {
+    let psl = PublicSuffixList::parse("// comment\ncom\nuk\nco.uk\n*.ck\n!www.ck\n");
+    assert_eq!(psl.public_suffix("www.example.com"), Some("com"));
+    assert_eq!(psl.public_suffix("www.example.CO.UK"), Some("CO.UK"));
+    assert_eq!(psl.public_suffix("shop.example.ck"), Some("example.ck"));
+    assert_eq!(psl.public_suffix("www.ck"), Some("ck"));
+    assert_eq!(psl.public_suffix("example.invalid"), None);
+
+    assert_eq!(
+        psl.registrable_domain("a.b.example.co.uk"),
+        Some("example.co.uk")
+    );
+    assert_eq!(psl.registrable_domain("example.com."), Some("example.com"));
+    assert_eq!(psl.registrable_domain("www.ck"), Some("www.ck"));
+    assert_eq!(psl.registrable_domain("co.uk"), None);
+}
+
+/// Test only names under a known public suffix are domains
+#[test]
+fn embedded_list() {
+    assert!(is_domain(b"example.com"));
+    assert!(is_domain(b"user.github.io"));
+    assert!(!is_domain(b"com"));
+    assert!(!is_domain(b"github.io"));
+    assert!(!is_domain(b"report.docx"));
+}
+
+/// Test lookups resolve through the system resolver and are cached
+#[test]
+fn resolver() {
+    let resolver = Resolver::new(Duration::from_secs(5), 2);
+    resolver.prefetch(["localhost", "localhost", "127.0.0.1"]);
+    let ip = resolver.resolve("localhost").expect("localhost resolves");
+    assert!(ip.is_loopback());
+    assert_eq!(resolver.resolve("127.0.0.1"), "127.0.0.1".parse().ok());
+}
@@ -0,0 +1,23 @@
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+use geoipsed::encoding::sniff;
+
+/// Test UTF-16 is recognized by its BOM or its NUL bytes
+#[test]
+fn sniffs_utf16() {
+    let utf16le: Vec<u8> = "src=10.0.0.1\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let utf16be: Vec<u8> = "src=10.0.0.1\n"
+        .encode_utf16()
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    assert_eq!(sniff(&utf16le), UTF_16LE);
+    assert_eq!(sniff(&utf16be), UTF_16BE);
+    assert_eq!(sniff(&[&[0xff, 0xfe][..], &utf16le].concat()), UTF_16LE);
+    assert_eq!(sniff(&[&[0xfe, 0xff][..], &utf16be].concat()), UTF_16BE);
+
+    assert_eq!(sniff(b"src=10.0.0.1\n"), UTF_8);
+    assert_eq!(sniff(b""), UTF_8);
+    assert_eq!(sniff(b"\0\0\0\0binary\0\0"), UTF_8);
+}
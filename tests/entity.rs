@@ -0,0 +1,389 @@
+use geoipsed::entity::{
+    BuildError, EntityKind, ExtractScratch, Extractor, PatternSet, Rejection, SearchProfile,
+    MAC_PATTERN,
+};
+use geoipsed::geoip::REGEX_PATTERN;
+use geoipsed::mac::parse_mac;
+use proptest::prelude::*;
+
+/// Test ips and macs are found in one pass with their kinds
+#[test]
+fn mixed_entities() {
+    let extractor = Extractor::new(&[
+        (EntityKind::Ip, REGEX_PATTERN),
+        (EntityKind::Mac, MAC_PATTERN),
+    ])
+    .unwrap();
+    let line = b"dhcp ack 10.0.0.7 to 00:1a:2b:3c:4d:5e via 001a.2b3c.4d5f";
+
+    let found: Vec<(EntityKind, &[u8])> = extractor
+        .find_iter(line)
+        .map(|m| (m.kind().clone(), m.as_bytes()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (EntityKind::Ip, &b"10.0.0.7"[..]),
+            (EntityKind::Mac, &b"00:1a:2b:3c:4d:5e"[..]),
+            (EntityKind::Mac, &b"001a.2b3c.4d5f"[..]),
+        ]
+    );
+    let m = extractor.find_iter(line).next().unwrap();
+    assert_eq!(&line[m.range()], b"10.0.0.7");
+}
+
+/// Test mac notations parse to the same address and malformed ones do not
+#[test]
+fn mac_notations() {
+    let mac = Some([0x00, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e]);
+    assert_eq!(parse_mac(b"00:1a:2b:3c:4d:5e"), mac);
+    assert_eq!(parse_mac(b"00-1A-2B-3C-4D-5E"), mac);
+    assert_eq!(parse_mac(b"001a.2b3c.4d5e"), mac);
+    assert_eq!(parse_mac(b"00:1a:2b:3c:4d"), None);
+    assert_eq!(parse_mac(b"00:1a:2b:3c:4d:5g"), None);
+}
+
+/// Test custom patterns share the scan with built-in kinds and that
+/// candidates rejected by their validator are skipped
+#[test]
+fn custom_patterns() {
+    let email = EntityKind::Custom("email".to_string());
+    let extractor = PatternSet::new()
+        .kind(EntityKind::Ip)
+        .validated(email.clone(), r"\b[\w.+-]+@[\w-]+(?:\.[\w-]+)+\b", |m| {
+            !m.ends_with(b".invalid")
+        })
+        .build()
+        .unwrap();
+    let line = b"login alice@example.com from 192.0.2.1, bob@host.invalid from 192.0.2.2";
+
+    let found: Vec<(EntityKind, &[u8])> = extractor
+        .find_iter(line)
+        .map(|m| (m.kind().clone(), m.as_bytes()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (email.clone(), &b"alice@example.com"[..]),
+            (EntityKind::Ip, &b"192.0.2.1"[..]),
+            (EntityKind::Ip, &b"192.0.2.2"[..]),
+        ]
+    );
+    assert_eq!(
+        extractor.kinds().collect::<Vec<_>>(),
+        [&EntityKind::Ip, &email]
+    );
+}
+
+/// Test build failures are told apart
+#[test]
+fn build_errors() {
+    assert!(matches!(
+        PatternSet::new().build(),
+        Err(BuildError::NoPatterns)
+    ));
+    // custom kinds have no built-in pattern
+    assert!(matches!(
+        PatternSet::new()
+            .kind(EntityKind::Custom("md5".to_string()))
+            .build(),
+        Err(BuildError::NoPatterns)
+    ));
+    let err = Extractor::new(&[(EntityKind::Ip, "(unclosed")])
+        .err()
+        .unwrap();
+    assert!(matches!(err, BuildError::Regex(_)));
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+/// Test a scratch moved between extractors of different kinds finds what
+/// find_iter does with each
+#[test]
+fn scratch_between_extractors() {
+    let ip = PatternSet::new().kind(EntityKind::Ip).build().unwrap();
+    let mixed = PatternSet::new()
+        .kind(EntityKind::Mac)
+        .kind(EntityKind::Ip)
+        .build()
+        .unwrap();
+    let line = b"10.0.0.7 is 00:1a:2b:3c:4d:5e, 310.0.0.1 is not";
+    let mut scratch = ExtractScratch::new();
+    for extractor in [&mixed, &ip, &mixed] {
+        let found: Vec<_> = extractor
+            .find_iter(line)
+            .map(|m| (m.kind().clone(), m.range()))
+            .collect();
+        let reused: Vec<_> = extractor
+            .find_iter_with(line, &mut scratch)
+            .map(|m| (m.kind().clone(), m.range()))
+            .collect();
+        assert_eq!(found, reused);
+    }
+}
+
+/// Test the throughput profile finds the built-in kinds as the default
+/// does, treats non-ASCII letters as non-word characters, and rejects
+/// Unicode classes
+#[test]
+fn search_profiles() {
+    let extractor = |profile| {
+        PatternSet::new()
+            .search_profile(profile)
+            .kind(EntityKind::Ip)
+            .kind(EntityKind::Mac)
+            .kind(EntityKind::Domain)
+            .build()
+            .unwrap()
+    };
+    let (latency, throughput) = (
+        extractor(SearchProfile::Latency),
+        extractor(SearchProfile::Throughput),
+    );
+    let found = |extractor: &Extractor, line: &[u8]| -> Vec<Vec<u8>> {
+        extractor
+            .find_iter(line)
+            .map(|m| m.as_bytes().to_vec())
+            .collect()
+    };
+
+    let line = "usuário 10.0.0.7 at 00:1a:2b:3c:4d:5e via example.com".as_bytes();
+    assert_eq!(found(&latency, line), found(&throughput, line));
+    assert_eq!(found(&throughput, line).len(), 3);
+    let line = "é00:1a:2b:3c:4d:5e".as_bytes();
+    assert!(found(&latency, line).is_empty());
+    assert_eq!(found(&throughput, line), [b"00:1a:2b:3c:4d:5e"]);
+
+    let letters = PatternSet::new().search_profile(SearchProfile::Throughput);
+    let letters = letters.pattern(EntityKind::Custom("word".to_string()), r"\p{L}+");
+    assert!(matches!(letters.build(), Err(BuildError::Regex(_))));
+}
+
+/// Test strict extractors skip addresses that are not globally routable,
+/// of any notation, and leave other kinds alone
+#[test]
+fn strict_and_permissive() {
+    let line = b"10.0.0.7 127.0.0.1 255.255.255.255 81.2.69.205 fe80::1 2001:db8::1 2a02:ec0::1 00:1a:2b:3c:4d:5e";
+    let found = |set: PatternSet| -> Vec<Vec<u8>> {
+        let extractor = set
+            .kind(EntityKind::Ip)
+            .kind(EntityKind::Mac)
+            .build()
+            .unwrap();
+        extractor
+            .find_iter(line)
+            .map(|m| m.as_bytes().to_vec())
+            .collect()
+    };
+
+    assert_eq!(found(PatternSet::permissive()).len(), 8);
+    assert_eq!(found(PatternSet::new()), found(PatternSet::permissive()));
+    assert_eq!(
+        found(PatternSet::strict()),
+        [&b"81.2.69.205"[..], b"2a02:ec0::1", b"00:1a:2b:3c:4d:5e"]
+    );
+    assert_eq!(
+        found(PatternSet::new().public_only(true)),
+        found(PatternSet::strict())
+    );
+}
+
+/// Test greedy recovery extracts addresses glued to longer tokens only
+/// when it is on
+#[test]
+fn greedy_recovery() {
+    let found = |recovery: bool, line: &[u8]| -> Vec<Vec<u8>> {
+        let extractor = PatternSet::new()
+            .greedy_recovery(recovery)
+            .kind(EntityKind::Ip)
+            .build()
+            .unwrap();
+        extractor
+            .find_iter(line)
+            .map(|m| m.as_bytes().to_vec())
+            .collect()
+    };
+
+    assert!(found(false, b"x 1.2.3.4.5 y").is_empty());
+    assert_eq!(found(true, b"x 1.2.3.4.5 y"), [b"1.2.3.4"]);
+    assert_eq!(found(true, b"x 310.0.0.0 y"), [b"10.0.0.0"]);
+    // delimited addresses are extracted the same either way
+    let line = b"1.2.3.4:80 and 2001:db8::1";
+    assert_eq!(found(true, line), found(false, line));
+}
+
+/// Test rejected candidates name the check that excluded them
+#[test]
+fn candidate_rejections() {
+    let rejections = |set: PatternSet, line: &[u8]| -> Vec<(Vec<u8>, Option<Rejection>)> {
+        let extractor = set.build().unwrap();
+        extractor
+            .candidates(line)
+            .map(|(m, rejection)| (m.as_bytes().to_vec(), rejection))
+            .collect()
+    };
+
+    let ips = || PatternSet::new().kind(EntityKind::Ip);
+    assert_eq!(
+        rejections(ips(), b"v1.2.3.4 8.8.8.8"),
+        [
+            (b"1.2.3.4".to_vec(), Some(Rejection::Boundary)),
+            (b"8.8.8.8".to_vec(), None),
+        ]
+    );
+    assert_eq!(
+        rejections(ips().public_only(true), b"10.0.0.1 8.8.8.8"),
+        [
+            (b"10.0.0.1".to_vec(), Some(Rejection::PublicOnly)),
+            (b"8.8.8.8".to_vec(), None),
+        ]
+    );
+    let odd = PatternSet::new().validated(EntityKind::Custom("odd".to_string()), r"\b\d\b", |d| {
+        (d[0] - b'0') % 2 == 1
+    });
+    assert_eq!(
+        rejections(odd, b"1 2"),
+        [
+            (b"1".to_vec(), None),
+            (b"2".to_vec(), Some(Rejection::Validator)),
+        ]
+    );
+}
+
+/// Test obfuscated ipv4 notations are matched and canonicalized only when
+/// asked for
+#[test]
+fn obfuscated_ipv4() {
+    let line = b"http://3595436072/ 0xd64e0028 0326.0116.0.050 010.1.1.1 81.2.69.205";
+    let found = |obfuscated: bool| -> Vec<(String, Option<String>)> {
+        let extractor = PatternSet::new()
+            .obfuscated_ipv4(obfuscated)
+            .kind(EntityKind::Ip)
+            .build()
+            .unwrap();
+        extractor
+            .find_iter(line)
+            .map(|m| {
+                let text = String::from_utf8(m.as_bytes().to_vec()).unwrap();
+                (text, m.ip().map(|ip| ip.to_string()))
+            })
+            .collect()
+    };
+    let entity = |text: &str, ip: Option<&str>| (text.to_string(), ip.map(str::to_string));
+
+    assert_eq!(
+        found(false),
+        [
+            entity("010.1.1.1", None),
+            entity("81.2.69.205", Some("81.2.69.205")),
+        ]
+    );
+    assert_eq!(
+        found(true),
+        [
+            entity("3595436072", Some("214.78.0.40")),
+            entity("0xd64e0028", Some("214.78.0.40")),
+            entity("0326.0116.0.050", Some("214.78.0.40")),
+            entity("010.1.1.1", Some("8.1.1.1")),
+            entity("81.2.69.205", Some("81.2.69.205")),
+        ]
+    );
+
+    let extractor = PatternSet::new()
+        .obfuscated_ipv4(true)
+        .kind(EntityKind::Ip)
+        .build()
+        .unwrap();
+    let m = extractor.find_iter(b"0xd64e0028").next().unwrap();
+    assert_eq!(m.canonical_text().unwrap(), "214.78.0.40");
+}
+
+/// Test the url an address is part of is found only with url context
+#[test]
+fn url_context() {
+    let found = |url_context: bool, line: &[u8]| -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+        let extractor = PatternSet::new()
+            .url_context(url_context)
+            .kind(EntityKind::Ip)
+            .build()
+            .unwrap();
+        extractor
+            .find_iter(line)
+            .map(|m| (m.as_bytes().to_vec(), m.url().map(<[u8]>::to_vec)))
+            .collect()
+    };
+
+    let line = b"GET http://1.2.3.4:8080/path, see (https://[2001:db8::1]/). from 5.6.7.8";
+    assert_eq!(
+        found(true, line),
+        [
+            (
+                b"1.2.3.4".to_vec(),
+                Some(b"http://1.2.3.4:8080/path".to_vec())
+            ),
+            (
+                b"2001:db8::1".to_vec(),
+                Some(b"https://[2001:db8::1]/".to_vec())
+            ),
+            (b"5.6.7.8".to_vec(), None),
+        ]
+    );
+    assert!(found(false, line).iter().all(|(_, url)| url.is_none()));
+    assert_eq!(
+        found(true, b"\"ftp://user@10.0.0.1/x\"")[0].1.as_deref(),
+        Some(&b"ftp://user@10.0.0.1/x"[..])
+    );
+}
+
+proptest! {
+    /// Test any bytes, including invalid UTF-8 and NULs, yield ordered,
+    /// non-overlapping, in-bounds entities without panicking
+    #[test]
+    fn find_iter_any_bytes(
+        haystack in prop::collection::vec(
+            prop_oneof![any::<u8>(), prop::sample::select(&b"0123456789abcdef.:-\0 "[..])],
+            0..256,
+        ),
+    ) {
+        let extractor = PatternSet::new()
+            .kind(EntityKind::Ip)
+            .kind(EntityKind::Mac)
+            .kind(EntityKind::Domain)
+            .build()
+            .unwrap();
+        let mut end = 0;
+        for entity in extractor.find_iter(&haystack) {
+            prop_assert!(end <= entity.start() && entity.start() < entity.end());
+            prop_assert_eq!(&haystack[entity.range()], entity.as_bytes());
+            end = entity.end();
+        }
+    }
+
+    /// Test find_iter_with yields what find_iter does while reusing one
+    /// scratch across haystacks
+    #[test]
+    fn find_iter_with_any_bytes(
+        haystacks in prop::collection::vec(
+            prop::collection::vec(
+                prop_oneof![any::<u8>(), prop::sample::select(&b"0123456789abcdef.:-\0 "[..])],
+                0..128,
+            ),
+            1..4,
+        ),
+    ) {
+        let extractor = PatternSet::new()
+            .kind(EntityKind::Ip)
+            .kind(EntityKind::Mac)
+            .kind(EntityKind::Domain)
+            .build()
+            .unwrap();
+        let mut scratch = ExtractScratch::new();
+        for haystack in &haystacks {
+            let found: Vec<_> = extractor.find_iter(haystack).map(|m| m.range()).collect();
+            let reused: Vec<_> = extractor
+                .find_iter_with(haystack, &mut scratch)
+                .map(|m| m.range())
+                .collect();
+            prop_assert_eq!(found, reused);
+        }
+    }
+}
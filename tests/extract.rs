@@ -0,0 +1,42 @@
+use geoipsed::entity::{EntityKind, PatternSet};
+use geoipsed::ipparse::parse_ip_bytes;
+use proptest::prelude::*;
+use std::net::IpAddr;
+
+/// Bytes that separate tokens in the generated lines
+const DELIMITERS: &[u8] = b" \t,;|=/\"'()[]<>";
+
+/// Valid addresses in the forms std prints, and near misses: out of range
+/// or extra octets, too few groups, and other words
+fn token() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<IpAddr>().prop_map(|ip| ip.to_string()),
+        any::<std::net::Ipv4Addr>().prop_map(|ip| ip.to_string()),
+        r"[0-9]{1,4}(\.[0-9]{1,4}){2,4}",
+        r"[0-9a-f]{0,4}(:[0-9a-f]{0,4}){1,8}",
+        r"[a-z0-9]{1,8}",
+    ]
+}
+
+/// Test extraction finds exactly the delimited tokens std parses as IPs
+#[test]
+fn matches_std_oracle() {
+    let extractor = PatternSet::new().kind(EntityKind::Ip).build().unwrap();
+    let line = prop::collection::vec((token(), prop::sample::select(DELIMITERS)), 0..8);
+    proptest!(|(tokens in line)| {
+        let mut haystack = Vec::new();
+        for (token, delimiter) in &tokens {
+            haystack.extend_from_slice(token.as_bytes());
+            haystack.push(*delimiter);
+        }
+        let expected: Vec<IpAddr> = tokens
+            .iter()
+            .filter_map(|(token, _)| token.parse().ok())
+            .collect();
+        let extracted: Vec<IpAddr> = extractor
+            .find_iter(&haystack)
+            .filter_map(|m| parse_ip_bytes(m.as_bytes()))
+            .collect();
+        prop_assert_eq!(extracted, expected, "{}", String::from_utf8_lossy(&haystack));
+    });
+}
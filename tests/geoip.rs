@@ -0,0 +1,88 @@
+use geoipsed::geoip::{Capabilities, GeoIPSed, IpColors, SpacePolicy};
+use std::path::PathBuf;
+use termcolor::ColorChoice;
+
+fn open(pfx2as: Option<PathBuf>, template: &str) -> GeoIPSed {
+    GeoIPSed::new(
+        Some("tests/maxmind".into()),
+        pfx2as.map(|path| path.try_into().unwrap()),
+        Some(template.to_string()),
+        ColorChoice::Never,
+        IpColors::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn capabilities() {
+    let geoipdb = open(None, "{ip} {asnorg} {isp} {city}");
+    let capabilities = geoipdb.capabilities();
+    assert_eq!(
+        capabilities,
+        Capabilities {
+            asn: true,
+            asn_org: true,
+            city: true,
+            country: false,
+            isp: false,
+            connection_type: false,
+        }
+    );
+    assert!(capabilities.provides("country_iso"));
+    assert!(!capabilities.provides("organization"));
+    assert_eq!(geoipdb.unavailable_fields(), ["isp"]);
+
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("capabilities_pfx2as.txt");
+    std::fs::write(&path, "81.2.69.0/24 200\n").unwrap();
+    let geoipdb = open(Some(path), "{asnnum} {asnorg}");
+    assert!(geoipdb.capabilities().asn);
+    assert!(!geoipdb.capabilities().asn_org);
+    assert_eq!(geoipdb.unavailable_fields(), ["asnorg"]);
+}
+
+/// Test decorations written straight to a writer match those returned
+#[test]
+fn lookup_write() {
+    let mut geoipdb = open(None, "<{ip}|{asnnum} {asnorg}|{latitude}>");
+    for policy in [
+        SpacePolicy::Underscore,
+        SpacePolicy::Keep,
+        SpacePolicy::Quote,
+    ] {
+        geoipdb.space_policy = policy;
+        for ip in [
+            "214.78.0.40",
+            "81.2.69.205",
+            "8.8.8.8",
+            "2001:480::1",
+            "not.an.ip",
+        ] {
+            let mut out = Vec::new();
+            let found = geoipdb.lookup_write(ip, &mut out).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), geoipdb.lookup(ip));
+            assert_eq!(found, geoipdb.lookup_found(ip).1);
+            assert_eq!(
+                geoipdb.lookup_with_json(ip),
+                (geoipdb.lookup(ip), geoipdb.lookup_json(ip))
+            );
+        }
+    }
+    let mut out = Vec::new();
+    assert!(geoipdb.lookup_write("214.78.0.40", &mut out).unwrap());
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "<214.78.0.40|721 \"DoD Network Information Center\"|32.6783>"
+    );
+    assert!(!geoipdb.lookup_write("8.8.8.8", &mut Vec::new()).unwrap());
+
+    // the country is looked up along with a template without country fields
+    let mut out = Vec::new();
+    let country = geoipdb.lookup_write_country("214.78.0.40", &mut out);
+    assert_eq!(country.unwrap(), (true, Some("US".to_string())));
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        geoipdb.lookup("214.78.0.40")
+    );
+    let country = geoipdb.lookup_write_country("not.an.ip", &mut Vec::new());
+    assert_eq!(country.unwrap(), (false, None));
+}
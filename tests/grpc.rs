@@ -0,0 +1,71 @@
+#![cfg(feature = "grpc")]
+
+use geoipsed::grpc::{DecorateRequest, EnrichmentClient, LookupRequest};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tokio_stream::StreamExt;
+
+/// Test the streaming Decorate and Lookup RPCs of the grpc subcommand
+#[test]
+fn grpc_streams() {
+    let mut maxmind_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    maxmind_dir.push("tests/maxmind");
+    let mut server = Command::new(env!("CARGO_BIN_EXE_geoipsed"))
+        .env("MAXMIND_MMDB_DIR", maxmind_dir.as_os_str())
+        .args(["grpc", "--max-db-age", "0", "--listen", "127.0.0.1:0"])
+        .args(["-t", "<{ip}|{country_iso}>"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let addr = banner.trim().rsplit("grpc://").next().unwrap().to_string();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(async {
+        let mut client = EnrichmentClient::connect(format!("http://{addr}"))
+            .await
+            .unwrap();
+
+        let texts = ["src 81.2.69.205\n", "dst 89.160.20.135 and 2001:480::1\n"];
+        let requests = texts.map(|text| DecorateRequest {
+            text: text.as_bytes().to_vec(),
+        });
+        let decorated: Vec<String> = client
+            .decorate(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(|r| String::from_utf8(r.unwrap().text).unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            decorated,
+            [
+                "src <81.2.69.205|GB>\n",
+                "dst <89.160.20.135|SE> and <2001:480::1|US>\n"
+            ]
+        );
+
+        let requests = ["81.2.69.205", "nope"].map(|ip| LookupRequest { ip: ip.into() });
+        let records: Vec<_> = client
+            .lookup(tokio_stream::iter(requests))
+            .await
+            .unwrap()
+            .into_inner()
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert!(records[0].found);
+        assert_eq!(records[0].city, "London");
+        assert_eq!(records[0].country_iso, "GB");
+        assert!(!records[1].found);
+        assert_eq!(records[1].ip, "nope");
+    });
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
@@ -0,0 +1,138 @@
+use geoipsed::ipparse::{
+    parse_ip_bytes, parse_ipv4_bytes, parse_ipv6_bytes, parse_obfuscated_ipv4,
+};
+use proptest::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const IPV4: &[&str] = &[
+    "0.0.0.0",
+    "1.2.3.4",
+    "255.255.255.255",
+    "192.168.10.1",
+    "256.1.1.1",
+    "1.2.3",
+    "1.2.3.4.5",
+    "01.2.3.4",
+    "1.2.3.04",
+    "1..3.4",
+    "1.2.3.4.",
+    "1.2.3.-4",
+    "1.2.3.4a",
+    "1000.2.3.4",
+    "",
+];
+
+const IPV6: &[&str] = &[
+    "::",
+    "::1",
+    "1::",
+    "2001:db8::1",
+    "2001:0db8:0000:0000:0000:ff00:0042:8329",
+    "2001:DB8::FF00:42:8329",
+    "1:2:3:4:5:6:7:8",
+    "1:2:3:4:5:6:7::",
+    "::2:3:4:5:6:7:8",
+    "1:2:3:4:5:6:1.2.3.4",
+    "::ffff:192.0.2.1",
+    "64:ff9b::192.0.2.33",
+    "1:2:3:4:5:6:7:8:9",
+    "1:2:3:4:5:6:7",
+    "1:2:3:4::5:6:7:8",
+    "1::2::3",
+    ":::",
+    ":1::",
+    "1:",
+    ":1",
+    "12345::",
+    "g::1",
+    "::1.2.3.4.5",
+    "::1.2.3.256",
+    "1.2.3.4::",
+    "::1.2.3.4:1",
+    "1:2:3:4:5:6:7:1.2.3.4",
+    "fe80::1%eth0",
+    "",
+];
+
+/// Test the byte parsers agree with std on valid and invalid input
+#[test]
+fn agrees_with_std() {
+    for s in IPV4 {
+        assert_eq!(
+            parse_ipv4_bytes(s.as_bytes()),
+            s.parse::<Ipv4Addr>().ok(),
+            "{s}"
+        );
+    }
+    for s in IPV6 {
+        assert_eq!(
+            parse_ipv6_bytes(s.as_bytes()),
+            s.parse::<Ipv6Addr>().ok(),
+            "{s}"
+        );
+    }
+    for s in IPV4.iter().chain(IPV6) {
+        assert_eq!(
+            parse_ip_bytes(s.as_bytes()),
+            s.parse::<IpAddr>().ok(),
+            "{s}"
+        );
+    }
+}
+
+/// Test compressed and ipv4-embedded forms expand to the right segments
+#[test]
+fn ipv6_forms() {
+    let ip = |s: &str| parse_ipv6_bytes(s.as_bytes()).unwrap().segments();
+    assert_eq!(ip("::"), [0; 8]);
+    assert_eq!(ip("2001:db8::1"), [0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(ip("1:2:3:4:5:6:7::"), [1, 2, 3, 4, 5, 6, 7, 0]);
+    assert_eq!(
+        ip("::ffff:192.0.2.1"),
+        [0, 0, 0, 0, 0, 0xffff, 0xc000, 0x0201]
+    );
+    assert!(parse_ipv6_bytes(b"1::2::3").is_none());
+}
+
+/// Test decimal, hex, and octal ipv4 notations are canonicalized
+#[test]
+fn obfuscated_ipv4() {
+    let ip = |s: &str| parse_obfuscated_ipv4(s.as_bytes());
+    let expected = Some(Ipv4Addr::new(192, 168, 1, 1));
+    assert_eq!(ip("3232235777"), expected);
+    assert_eq!(ip("0xC0A80101"), expected);
+    assert_eq!(ip("030052000401"), expected);
+    assert_eq!(ip("0300.0250.0001.0001"), expected);
+    assert_eq!(ip("0xc0.0xa8.1.01"), expected);
+    assert_eq!(ip("192.168.1.1"), expected);
+
+    assert_eq!(ip("4294967296"), None);
+    assert_eq!(ip("0x1C0A80101"), None);
+    assert_eq!(ip("0400.1.1.1"), None);
+    assert_eq!(ip("08.1.1.1"), None);
+    assert_eq!(ip("1.2.3"), None);
+    assert_eq!(ip("0x.1.1.1"), None);
+}
+
+proptest! {
+    /// Test the byte parsers agree with std on arbitrary bytes, and on
+    /// strings shaped like addresses, which arbitrary bytes rarely are
+    #[test]
+    fn agrees_with_std_on_any_input(
+        bytes in prop::collection::vec(any::<u8>(), 0..48),
+        v4 in r"[0-9]{0,4}(\.[0-9]{0,4}){0,4}",
+        v6 in r"[0-9a-fA-F:]{0,40}(\.[0-9]{1,3}){0,3}",
+    ) {
+        let std = |b: &[u8]| std::str::from_utf8(b).ok().and_then(|s| s.parse::<IpAddr>().ok());
+        for input in [&bytes[..], v4.as_bytes(), v6.as_bytes()] {
+            prop_assert_eq!(parse_ip_bytes(input), std(input), "{:?}", input);
+        }
+    }
+
+    /// Test valid addresses of every form round trip through the parsers
+    #[test]
+    fn parses_valid(ip in any::<IpAddr>()) {
+        let s = ip.to_string();
+        prop_assert_eq!(parse_ip_bytes(s.as_bytes()), Some(ip));
+    }
+}
@@ -0,0 +1,69 @@
+use geoipsed::ipset::{IpRange, IpSet};
+use std::net::IpAddr;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+fn set(cidrs: &[&str]) -> IpSet {
+    cidrs
+        .iter()
+        .map(|c| c.parse::<IpRange>().unwrap())
+        .collect()
+}
+
+/// Test membership across IPv4 and IPv6 networks
+#[test]
+fn contains() {
+    let s = set(&["10.0.0.0/8", "192.168.1.1", "2001:db8::/32"]);
+
+    assert!(s.contains(ip("10.0.0.0")));
+    assert!(s.contains(ip("10.255.255.255")));
+    assert!(!s.contains(ip("11.0.0.0")));
+    assert!(s.contains(ip("192.168.1.1")));
+    assert!(!s.contains(ip("192.168.1.2")));
+    assert!(s.contains(ip("2001:db8:ffff::1")));
+    assert!(!s.contains(ip("2001:db9::1")));
+    // ipv4-mapped ipv6 addresses are the same member as the ipv4 address
+    assert!(s.contains(ip("::ffff:10.1.2.3")));
+}
+
+/// Test overlapping and adjacent ranges are coalesced
+#[test]
+fn coalesce() {
+    let s = set(&["10.0.0.0/24", "10.0.1.0/24", "10.0.0.128/25", "10.0.3.0/24"]);
+
+    let ranges: Vec<(IpAddr, IpAddr)> = s.ranges().iter().map(|r| (r.start(), r.end())).collect();
+    assert_eq!(
+        ranges,
+        [
+            (ip("10.0.0.0"), ip("10.0.1.255")),
+            (ip("10.0.3.0"), ip("10.0.3.255"))
+        ]
+    );
+}
+
+/// Test union and intersection
+#[test]
+fn set_operations() {
+    let a = set(&["10.0.0.0/16", "172.16.0.0/12"]);
+    let b = set(&["10.0.128.0/17", "172.20.0.0/16", "192.168.0.0/16"]);
+
+    let union = a.union(&b);
+    assert!(union.contains(ip("10.0.0.1")));
+    assert!(union.contains(ip("192.168.5.5")));
+    assert_eq!(union.ranges().len(), 3);
+
+    let intersection = a.intersection(&b);
+    assert_eq!(intersection, set(&["10.0.128.0/17", "172.20.0.0/16"]));
+    assert!(a.intersection(&IpSet::new()).is_empty());
+}
+
+/// Test invalid networks are rejected
+#[test]
+fn parse_errors() {
+    assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+    assert!("2001:db8::/129".parse::<IpRange>().is_err());
+    assert!("not an ip".parse::<IpRange>().is_err());
+    assert!("10.0.0.0/x".parse::<IpRange>().is_err());
+}
@@ -0,0 +1,76 @@
+use geoipsed::mmdb::MmdbWriter;
+use serde_json::{json, Value};
+use std::net::IpAddr;
+
+fn lookup(reader: &maxminddb::Reader<Vec<u8>>, ip: &str) -> Option<Value> {
+    reader.lookup::<Value>(ip.parse::<IpAddr>().unwrap()).ok()
+}
+
+#[test]
+fn build_and_read_back() {
+    let mut writer = MmdbWriter::new("Internal-City").description("test networks");
+    writer
+        .insert_csv(
+            "start_ip,end_ip,country.iso_code,location.latitude,is_vpn,postal\n\
+             10.0.0.0,10.0.2.255,GB,51.5,true,01234\n\
+             2001:db8::,2001:db8::ffff,US,,false,\n",
+        )
+        .unwrap();
+    writer.insert(
+        "10.0.1.0/24".parse().unwrap(),
+        json!({"site": "lab"}).as_object().unwrap(),
+    );
+    // 10.0.0.0/23 and 10.0.2.0/24, the IPv6 range, and 10.0.1.0/24
+    assert_eq!(writer.len(), 4);
+    let mut db = Vec::new();
+    writer.write(&mut db).unwrap();
+
+    let reader = maxminddb::Reader::from_source(db).unwrap();
+    assert_eq!(reader.metadata.database_type, "Internal-City");
+    assert_eq!(reader.metadata.ip_version, 6);
+    assert_eq!(reader.metadata.description["en"], "test networks");
+    let gb = json!({
+        "country": {"iso_code": "GB"},
+        "location": {"latitude": 51.5},
+        "is_vpn": true,
+        "postal": "01234",
+    });
+    assert_eq!(lookup(&reader, "10.0.0.1"), Some(gb.clone()));
+    assert_eq!(lookup(&reader, "10.0.2.255"), Some(gb));
+    assert_eq!(lookup(&reader, "10.0.1.7"), Some(json!({"site": "lab"})));
+    assert_eq!(
+        lookup(&reader, "2001:db8::abcd"),
+        Some(json!({"country": {"iso_code": "US"}, "is_vpn": false}))
+    );
+    assert_eq!(lookup(&reader, "10.0.3.0"), None);
+    assert_eq!(lookup(&reader, "2001:db8::1:0"), None);
+}
+
+#[test]
+fn invalid_csv() {
+    let mut writer = MmdbWriter::new("Internal-ASN");
+    assert!(writer.insert_csv("ip,asn\n10.0.0.1,1\n").is_err());
+    let err = writer
+        .insert_csv("network,asn\n10.0.0.0/8,1\n10.0.0.0/33,2\n")
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 3:"), "{err}");
+}
+
+#[test]
+fn null_array_elements() {
+    let mut writer = MmdbWriter::new("Internal-Tags");
+    writer.insert(
+        "10.0.0.0/8".parse().unwrap(),
+        json!({"tags": ["a", null], "site": "lab"})
+            .as_object()
+            .unwrap(),
+    );
+    let mut db = Vec::new();
+    writer.write(&mut db).unwrap();
+
+    let reader = maxminddb::Reader::from_source(db).unwrap();
+    assert_eq!(
+        lookup(&reader, "10.1.2.3"),
+        Some(json!({"tags": ["a"], "site": "lab"}))
+    );
+}
@@ -0,0 +1,32 @@
+use geoipsed::ordered::{OrderedWriter, ReorderWindow};
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn writes_in_sequence() {
+    let mut out = OrderedWriter::new(Vec::new());
+    assert_eq!(out.push(2, b"c".to_vec()).unwrap(), 0);
+    assert_eq!(out.push(1, b"b".to_vec()).unwrap(), 0);
+    assert_eq!(out.pending(), 2);
+    assert_eq!(out.push(0, b"a".to_vec()).unwrap(), 3);
+    assert_eq!(out.pending(), 0);
+    assert_eq!(out.push(3, b"d".to_vec()).unwrap(), 4);
+    assert_eq!(out.into_inner(), b"abcd");
+}
+
+#[test]
+fn window_bounds_producers() {
+    let window = Arc::new(ReorderWindow::new(2));
+    window.enter(0);
+    window.enter(1);
+    let producer = {
+        let window = Arc::clone(&window);
+        thread::spawn(move || window.enter(3))
+    };
+    window.advance(1);
+    window.advance(2);
+    producer.join().unwrap();
+
+    window.close();
+    window.enter(u64::MAX / 2);
+}
@@ -0,0 +1,138 @@
+use geoipsed::geoip::{GeoIPSed, IpColors};
+use geoipsed::ordered::OrderedWriter;
+use geoipsed::pipeline::{Cache, Pipeline, Totals};
+use geoipsed::sink::jsonl::JsonLinesSink;
+use geoipsed::sink::text::TextSink;
+use geoipsed::sink::{LineWriter, Occurrence, Sink};
+use termcolor::ColorChoice;
+
+fn geoipdb(template: &str) -> GeoIPSed {
+    GeoIPSed::new(
+        Some("tests/maxmind".into()),
+        None,
+        Some(template.to_string()),
+        ColorChoice::Never,
+        IpColors::default(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn decorates_lines() {
+    let geoipdb = geoipdb("<{ip}|{country_iso}>");
+    let mut out = Vec::new();
+    let totals = Pipeline::new()
+        .enricher(&geoipdb)
+        .sink(TextSink::new(&mut out))
+        .run(&b"a 81.2.69.205\nno ips\n214.78.0.40 999.1.1.1"[..])
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "a <81.2.69.205|GB>\nno ips\n<214.78.0.40|US> 999.1.1.1"
+    );
+    assert_eq!(
+        totals,
+        Totals {
+            lines: 3,
+            matches: 2
+        }
+    );
+}
+
+/// Test the default extractor finds obfuscated ipv4 addresses when asked
+#[test]
+fn obfuscated_ipv4() {
+    let geoipdb = geoipdb("<{ip}|{country_iso}>");
+    let run = |obfuscated: bool| {
+        let mut out = Vec::new();
+        Pipeline::new()
+            .enricher(&geoipdb)
+            .obfuscated_ipv4(obfuscated)
+            .sink(TextSink::new(&mut out))
+            .run(&b"http://3595436072/ 81.2.69.205"[..])
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    };
+    assert_eq!(run(false), "http://3595436072/ <81.2.69.205|GB>");
+    assert_eq!(run(true), "http://<214.78.0.40|US>/ <81.2.69.205|GB>");
+}
+
+#[test]
+fn writes_records() {
+    let geoipdb = geoipdb("{ip}");
+    let mut out = Vec::new();
+    let mut pipeline = Pipeline::new()
+        .enricher(&geoipdb)
+        .cache(Cache::with_capacity(1))
+        .sink(JsonLinesSink::new(&mut out));
+    pipeline.process("first", &b"81.2.69.205\n"[..]).unwrap();
+    pipeline
+        .process("second", &b"x 214.78.0.40 81.2.69.205\n"[..])
+        .unwrap();
+    assert_eq!(pipeline.finish().unwrap().matches, 3);
+
+    let records: Vec<serde_json::Value> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0]["source"], "first");
+    assert_eq!(records[1]["source"], "second");
+    assert_eq!(records[1]["offset"], 2);
+    assert_eq!(records[1]["country_iso"], "US");
+    assert_eq!(records[2]["country_iso"], "GB");
+}
+
+/// Sink keeping the lines written to it
+#[derive(Default)]
+struct Lines(Vec<Vec<u8>>);
+
+impl Sink for Lines {
+    fn write_line(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        self.0.push(line.to_vec());
+        Ok(())
+    }
+
+    fn write_record(&mut self, _occurrence: &Occurrence) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Test reordered chunks of whole lines reach a sink a line at a time
+#[test]
+fn line_writer() {
+    let mut sink = Lines::default();
+    let mut ordered = OrderedWriter::new(LineWriter::new(&mut sink, b'\n'));
+    ordered.push(1, b"c\nd".to_vec()).unwrap();
+    ordered.push(0, b"a\nb\n".to_vec()).unwrap();
+    drop(ordered);
+    assert_eq!(sink.0, [&b"a\n"[..], b"b\n", b"c\n", b"d"]);
+}
+
+#[test]
+fn needs_an_enricher() {
+    assert!(Pipeline::new().run(&b"81.2.69.205\n"[..]).is_err());
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn runs_on_tokio_streams() {
+    let geoipdb = geoipdb("<{ip}|{country_iso}>");
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut out = Vec::new();
+    let totals = runtime
+        .block_on(
+            Pipeline::new()
+                .enricher(&geoipdb)
+                .run_async(&b"a 81.2.69.205\nb 2001:480::1\n"[..], &mut out),
+        )
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "a <81.2.69.205|GB>\nb <2001:480::1|US>\n"
+    );
+    assert_eq!(totals.matches, 2);
+}
@@ -0,0 +1,66 @@
+use geoipsed::prefixtrie::PrefixTrie;
+use std::net::IpAddr;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+/// Test the longest matching prefix wins, whatever the insertion order
+#[test]
+fn longest_prefix_match() {
+    let mut trie = PrefixTrie::new();
+    trie.insert_cidr("10.1.2.0/24", "lab").unwrap();
+    trie.insert_cidr("0.0.0.0/0", "internet").unwrap();
+    trie.insert_cidr("10.0.0.0/8", "corp").unwrap();
+    trie.insert_cidr("10.1.2.3", "printer").unwrap();
+    trie.insert_cidr("10.1.0.0/16", "office").unwrap();
+
+    assert_eq!(trie.len(), 5);
+    assert_eq!(trie.lookup(ip("10.1.2.3")), Some(&"printer"));
+    assert_eq!(trie.lookup(ip("10.1.2.4")), Some(&"lab"));
+    assert_eq!(trie.lookup(ip("10.1.3.1")), Some(&"office"));
+    assert_eq!(trie.lookup(ip("10.2.0.0")), Some(&"corp"));
+    assert_eq!(trie.lookup(ip("11.0.0.0")), Some(&"internet"));
+    // the ipv4 default route does not cover ipv6 addresses
+    assert_eq!(trie.lookup(ip("2001:db8::1")), None);
+}
+
+/// Test ipv4 and ipv6 prefixes share one trie
+#[test]
+fn mixed_families() {
+    let trie: PrefixTrie<u16> = [
+        (ip("192.0.2.0"), 24, 10),
+        (ip("2001:db8::"), 32, 20),
+        (ip("2001:db8:1::"), 48, 30),
+    ]
+    .into_iter()
+    .collect();
+
+    assert_eq!(trie.lookup(ip("192.0.2.200")), Some(&10));
+    assert_eq!(trie.lookup(ip("::ffff:192.0.2.1")), Some(&10));
+    assert_eq!(trie.lookup(ip("2001:db8:2::1")), Some(&20));
+    assert_eq!(trie.lookup(ip("2001:db8:1:ffff::1")), Some(&30));
+    assert_eq!(trie.lookup(ip("2001:db9::1")), None);
+}
+
+/// Test re-inserting a prefix replaces its value
+#[test]
+fn replace() {
+    let mut trie = PrefixTrie::new();
+    assert_eq!(trie.insert(ip("10.0.0.0"), 8, "a"), None);
+    // host bits beyond the prefix length are ignored
+    assert_eq!(trie.insert(ip("10.9.9.9"), 8, "b"), Some("a"));
+    assert_eq!(trie.len(), 1);
+    assert_eq!(trie.lookup(ip("10.0.0.1")), Some(&"b"));
+}
+
+/// Test malformed networks are rejected
+#[test]
+fn parse_errors() {
+    let mut trie = PrefixTrie::new();
+    assert!(trie.insert_cidr("10.0.0.0/33", ()).is_err());
+    assert!(trie.insert_cidr("2001:db8::/129", ()).is_err());
+    assert!(trie.insert_cidr("10.0.0/8", ()).is_err());
+    assert!(trie.insert_cidr("example.com", ()).is_err());
+    assert!(trie.is_empty());
+}
@@ -0,0 +1,36 @@
+use geoipsed::entity::{EntityKind, PatternSet};
+use geoipsed::geoip::{GeoIPSed, IpColors};
+use std::sync::Arc;
+use std::thread;
+use termcolor::ColorChoice;
+
+#[test]
+fn shared_across_threads() {
+    let geoipdb = GeoIPSed::new(
+        Some("tests/maxmind".into()),
+        None,
+        Some("{country_iso}".to_string()),
+        ColorChoice::Never,
+        IpColors::default(),
+    )
+    .unwrap();
+    let extractor = PatternSet::new().kind(EntityKind::Ip).build().unwrap();
+    let shared = Arc::new((geoipdb, extractor));
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let (geoipdb, extractor) = &*shared;
+                let line = b"81.2.69.205 -> 214.78.0.40";
+                extractor
+                    .find_iter(line)
+                    .map(|m| geoipdb.lookup(std::str::from_utf8(m.as_bytes()).unwrap()))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), ["GB", "US"]);
+    }
+}
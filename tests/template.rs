@@ -0,0 +1,115 @@
+use geoipsed::template::{closest_field, Fields, Template, TemplateError};
+use std::borrow::Cow;
+
+struct Host;
+
+impl Fields for Host {
+    fn field(&self, name: &str) -> Option<Cow<'_, str>> {
+        match name {
+            "name" => Some("gw".into()),
+            "vlan" => Some(42.to_string().into()),
+            _ => None,
+        }
+    }
+}
+
+/// Test fields are substituted and literal text is kept
+#[test]
+fn render() {
+    let template = Template::new("<{name}|vlan{vlan}|{unknown}>");
+    assert_eq!(template.render(&Host), "<gw|vlan42|>");
+    assert_eq!(
+        template.field_names().collect::<Vec<_>>(),
+        ["name", "vlan", "unknown"]
+    );
+
+    assert_eq!(Template::new("{name}{vlan}").render(&Host), "gw42");
+    assert_eq!(Template::new("no fields").render(&Host), "no fields");
+    assert_eq!(Template::new("").render(&Host), "");
+
+    let mut out = Vec::new();
+    template.write(&Host, &mut out).unwrap();
+    assert_eq!(out, b"<gw|vlan42|>");
+}
+
+struct Counter(u32);
+
+impl Fields for Counter {
+    fn field(&self, _name: &str) -> Option<Cow<'_, str>> {
+        None
+    }
+
+    fn write_field_to(&self, name: &str, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        match name {
+            "n" => write!(out, "{}", self.0),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Test renders and writes both format fields with `write_field_to`
+#[test]
+fn write_field_to() {
+    let template = Template::new("n={n}{other}");
+    assert_eq!(template.render(&Counter(7)), "n=7");
+
+    let mut out = String::from("count ");
+    template.render_into(&Counter(8), &mut out);
+    assert_eq!(out, "count n=8");
+
+    let mut out = Vec::new();
+    template.write(&Counter(9), &mut out).unwrap();
+    assert_eq!(out, b"n=9");
+}
+
+/// Test an unterminated field is kept as literal text
+#[test]
+fn unterminated() {
+    let template = Template::new("{name} {vlan");
+    assert_eq!(template.render(&Host), "gw {vlan");
+    assert_eq!(template.field_names().collect::<Vec<_>>(), ["name"]);
+}
+
+/// Test the inverse pattern matches renders and captures the field
+#[test]
+fn inverse_pattern() {
+    let template = Template::new("<{name}|vlan{vlan}|{name}>");
+    let pattern = template.inverse_pattern("name", "[a-z]+").unwrap();
+    let re = regex::Regex::new(&pattern).unwrap();
+    let line = format!(
+        "x {} y {} z",
+        template.render(&Host),
+        template.render(&Host)
+    );
+    assert_eq!(re.replace_all(&line, "$name"), "x gw y gw z");
+    // literal text is matched literally
+    assert!(!re.is_match("<gw|vlan42|gw|"));
+
+    assert_eq!(template.inverse_pattern("missing", ".*"), None);
+}
+
+/// Test compile rejects malformed templates that new keeps as literals
+#[test]
+fn compile() {
+    assert_eq!(
+        Template::compile("<{name}|{vlan}>").unwrap(),
+        Template::new("<{name}|{vlan}>")
+    );
+    assert_eq!(
+        Template::compile("{name} {vlan"),
+        Err(TemplateError::Unclosed(7))
+    );
+    assert_eq!(
+        Template::compile("{name}{}"),
+        Err(TemplateError::EmptyField(6))
+    );
+}
+
+/// Test typos of field names are matched to the closest field
+#[test]
+fn closest_fields() {
+    let fields = ["asnnum", "asnorg", "country_iso", "country_full"];
+    assert_eq!(closest_field("asn_org", fields), Some("asnorg"));
+    assert_eq!(closest_field("countryiso", fields), Some("country_iso"));
+    assert_eq!(closest_field("hostname", fields), None);
+}